@@ -4,6 +4,21 @@
 -- for testing the C implementation.
 --
 
+-- The value of an int stat. Sums are exact integers (numeric here, i128 in
+-- Rust): a whole number in float notation like 150.0 counts as 150, and a
+-- fractional value raises instead of being truncated.
+CREATE OR REPLACE FUNCTION jsonb_stats_int_value_plpgsql(value text)
+RETURNS numeric
+LANGUAGE plpgsql
+AS $$
+BEGIN
+    IF value::numeric <> trunc(value::numeric) THEN
+        RAISE EXCEPTION 'jsonb_stats: ''value'' must be an integer, got %', value;
+    END IF;
+    RETURN trunc(value::numeric);
+END;
+$$;
+
 -- Accumulation function (Level 2: stats -> stats_agg)
 CREATE OR REPLACE FUNCTION jsonb_stats_accum_plpgsql(state jsonb, stats jsonb)
 RETURNS jsonb
@@ -20,6 +35,7 @@ DECLARE
     new_summary jsonb;
     -- For integer summary
     sum_val numeric; count_val int; mean_val numeric; min_val numeric; max_val numeric; sum_sq_diff_val numeric; delta numeric;
+    m2_old numeric; m3_old numeric; m4_old numeric; m3_val numeric; m4_val numeric; n_old numeric;
     -- For text/boolean summary
     counts jsonb; val_key text; current_count int;
     -- For array summary
@@ -31,25 +47,41 @@ BEGIN
         current_summary := new_state->stat_key;
         stat_type := stat_obj->>'type';
         stat_val_str := stat_obj->>'value';
+        IF stat_type = 'uuid' THEN
+            stat_val_str := lower(stat_val_str);
+        END IF;
 
-        IF current_summary IS NULL THEN
+        IF stat_type = 'str' AND jsonb_typeof(stat_obj->'value') = 'array' THEN
+            -- Multi-value str: each element counts as one observation
+            new_summary := COALESCE(current_summary, jsonb_build_object('type', 'str_agg', 'counts', '{}'::jsonb));
+            FOR element IN SELECT jsonb_array_elements_text(stat_obj->'value') LOOP
+                current_count := COALESCE((new_summary->'counts'->>element)::int, 0);
+                new_summary := jsonb_set(new_summary, ARRAY['counts', element], to_jsonb(current_count + 1))
+                    || jsonb_build_object(
+                        'min', LEAST(new_summary->>'min' COLLATE "C", element),
+                        'max', GREATEST(new_summary->>'max' COLLATE "C", element));
+            END LOOP;
+        ELSIF current_summary IS NULL THEN
             -- INIT PATH
             IF stat_type = 'int' THEN
-                stat_val_numeric := stat_val_str::numeric;
+                stat_val_numeric := jsonb_stats_int_value_plpgsql(stat_val_str);
                 new_summary := jsonb_build_object(
                     'type', 'int_agg', 'count', 1, 'sum', stat_val_numeric, 'min', stat_val_numeric, 'max', stat_val_numeric,
-                    'mean', stat_val_numeric, 'sum_sq_diff', 0
+                    'min_count', 1, 'max_count', 1, 'mean', stat_val_numeric, 'sum_sq_diff', 0, 'm3', 0, 'm4', 0
                 );
-            ELSIF stat_type = 'str' OR stat_type = 'bool' THEN
+            ELSIF stat_type IN ('str', 'bool', 'uuid') THEN
                  new_summary := jsonb_build_object(
                     'type', stat_type || '_agg', 'counts', jsonb_build_object(stat_val_str, 1)
                 );
+                IF stat_type = 'str' THEN
+                    new_summary := new_summary || jsonb_build_object('min', stat_val_str, 'max', stat_val_str);
+                END IF;
             ELSIF stat_type = 'arr' THEN
                 new_summary := jsonb_build_object(
                     'type', 'arr_agg', 'count', 1, 'counts', '{}'::jsonb
                 );
                 IF stat_val_str != '{}' THEN
-                    FOR element IN SELECT unnest(string_to_array(trim(stat_val_str, '{}'), ',')) LOOP
+                    FOR element IN SELECT e FROM unnest(stat_val_str::text[]) e WHERE e IS NOT NULL LOOP
                         new_summary := jsonb_set(new_summary, ARRAY['counts', element], to_jsonb(1));
                     END LOOP;
                 END IF;
@@ -58,27 +90,48 @@ BEGIN
             -- UPDATE PATH
             new_summary := current_summary;
             IF stat_type = 'int' THEN
-                stat_val_numeric := stat_val_str::numeric;
+                stat_val_numeric := jsonb_stats_int_value_plpgsql(stat_val_str);
                 sum_val := (current_summary->>'sum')::numeric + stat_val_numeric;
                 count_val := (current_summary->>'count')::int + 1;
                 mean_val := (current_summary->>'mean')::numeric;
                 delta := stat_val_numeric - mean_val;
+                n_old := count_val - 1;
+                m2_old := (current_summary->>'sum_sq_diff')::numeric;
+                m3_old := COALESCE((current_summary->>'m3')::numeric, 0);
+                m4_old := COALESCE((current_summary->>'m4')::numeric, 0);
+                m4_val := m4_old + delta^4 * n_old * (n_old^2 - n_old + 1) / count_val^3
+                    + 6 * delta^2 * m2_old / count_val^2 - 4 * delta * m3_old / count_val;
+                m3_val := m3_old + delta^3 * n_old * (n_old - 1) / count_val^2 - 3 * delta * m2_old / count_val;
                 mean_val := mean_val + delta / count_val;
                 min_val := LEAST((current_summary->>'min')::numeric, stat_val_numeric);
                 max_val := GREATEST((current_summary->>'max')::numeric, stat_val_numeric);
                 sum_sq_diff_val := (current_summary->>'sum_sq_diff')::numeric + delta * (stat_val_numeric - mean_val);
                 new_summary := jsonb_build_object(
                     'type', 'int_agg', 'count', count_val, 'sum', sum_val, 'min', min_val, 'max', max_val,
-                    'mean', mean_val, 'sum_sq_diff', sum_sq_diff_val
+                    'min_count', CASE
+                        WHEN stat_val_numeric < (current_summary->>'min')::numeric THEN 1
+                        WHEN stat_val_numeric = (current_summary->>'min')::numeric THEN (current_summary->>'min_count')::int + 1
+                        ELSE (current_summary->>'min_count')::int END,
+                    'max_count', CASE
+                        WHEN stat_val_numeric > (current_summary->>'max')::numeric THEN 1
+                        WHEN stat_val_numeric = (current_summary->>'max')::numeric THEN (current_summary->>'max_count')::int + 1
+                        ELSE (current_summary->>'max_count')::int END,
+                    'mean', mean_val, 'sum_sq_diff', sum_sq_diff_val, 'm3', m3_val, 'm4', m4_val
                 );
-            ELSIF stat_type = 'str' OR stat_type = 'bool' THEN
+            ELSIF stat_type IN ('str', 'bool', 'uuid') THEN
                 counts := current_summary->'counts';
                 current_count := COALESCE((counts->>stat_val_str)::int, 0);
                 new_summary := jsonb_set(new_summary, ARRAY['counts', stat_val_str], to_jsonb(current_count + 1));
+                IF stat_type = 'str' THEN
+                    -- Byte-order comparison, matching Rust String ordering
+                    new_summary := new_summary || jsonb_build_object(
+                        'min', LEAST(current_summary->>'min' COLLATE "C", stat_val_str),
+                        'max', GREATEST(current_summary->>'max' COLLATE "C", stat_val_str));
+                END IF;
             ELSIF stat_type = 'arr' THEN
                 new_summary := new_summary || jsonb_build_object('count', (current_summary->>'count')::int + 1);
                 IF stat_val_str != '{}' THEN
-                    FOR element IN SELECT unnest(string_to_array(trim(stat_val_str, '{}'), ',')) LOOP
+                    FOR element IN SELECT e FROM unnest(stat_val_str::text[]) e WHERE e IS NOT NULL LOOP
                         current_count := COALESCE((new_summary->'counts'->>element)::int, 0);
                         new_summary := jsonb_set(new_summary, ARRAY['counts', element], to_jsonb(current_count + 1));
                     END LOOP;
@@ -101,11 +154,15 @@ DECLARE
     summary_key text; summary_a jsonb; summary_b jsonb; merged_summary jsonb; type_a text;
     -- Integer summary
     count_a numeric; count_b numeric; total_count numeric; mean_a numeric; mean_b numeric; delta numeric;
+    m2_a numeric; m2_b numeric; m3_a numeric; m3_b numeric;
     -- Text/boolean/array summary
     counts_a jsonb; counts_b jsonb; k text; v jsonb;
 BEGIN
     FOR summary_key, summary_b IN SELECT * FROM jsonb_each(b) LOOP
-        IF summary_key = 'type' THEN CONTINUE; END IF;
+        IF summary_key = 'type' THEN
+            IF NOT merged_state ? 'type' THEN merged_state := merged_state || jsonb_build_object('type', summary_b); END IF;
+            CONTINUE;
+        END IF;
         summary_a := merged_state->summary_key;
         IF summary_a IS NULL THEN
             merged_summary := summary_b;
@@ -114,16 +171,35 @@ BEGIN
             IF type_a = 'int_agg' THEN
                 count_a := (summary_a->>'count')::numeric; count_b := (summary_b->>'count')::numeric; total_count := count_a + count_b;
                 mean_a := (summary_a->>'mean')::numeric; mean_b := (summary_b->>'mean')::numeric; delta := mean_b - mean_a;
+                m2_a := (summary_a->>'sum_sq_diff')::numeric; m2_b := (summary_b->>'sum_sq_diff')::numeric;
+                m3_a := COALESCE((summary_a->>'m3')::numeric, 0); m3_b := COALESCE((summary_b->>'m3')::numeric, 0);
                 merged_summary := jsonb_build_object(
                     'type', type_a, 'count', total_count,
                     'sum', (summary_a->>'sum')::numeric + (summary_b->>'sum')::numeric,
                     'min', LEAST((summary_a->>'min')::numeric, (summary_b->>'min')::numeric),
                     'max', GREATEST((summary_a->>'max')::numeric, (summary_b->>'max')::numeric),
                     'mean', mean_a + (delta * count_b / total_count),
-                    'sum_sq_diff', (summary_a->>'sum_sq_diff')::numeric + (summary_b->>'sum_sq_diff')::numeric + (delta^2 * count_a * count_b) / total_count
+                    'sum_sq_diff', (summary_a->>'sum_sq_diff')::numeric + (summary_b->>'sum_sq_diff')::numeric + (delta^2 * count_a * count_b) / total_count,
+                    'm3', m3_a + m3_b + delta^3 * count_a * count_b * (count_a - count_b) / total_count^2
+                        + 3 * delta * (count_a * m2_b - count_b * m2_a) / total_count,
+                    'm4', COALESCE((summary_a->>'m4')::numeric, 0) + COALESCE((summary_b->>'m4')::numeric, 0)
+                        + delta^4 * count_a * count_b * (count_a^2 - count_a * count_b + count_b^2) / total_count^3
+                        + 6 * delta^2 * (count_a^2 * m2_b + count_b^2 * m2_a) / total_count^2
+                        + 4 * delta * (count_a * m3_b - count_b * m3_a) / total_count
                 );
-            ELSIF type_a IN ('str_agg', 'bool_agg', 'arr_agg') THEN
-                merged_summary := summary_a;
+                -- Observations at the min/max; left out when unknown
+                merged_summary := merged_summary || jsonb_strip_nulls(jsonb_build_object(
+                    'min_count', CASE
+                        WHEN (summary_a->>'min')::numeric < (summary_b->>'min')::numeric THEN (summary_a->>'min_count')::numeric
+                        WHEN (summary_b->>'min')::numeric < (summary_a->>'min')::numeric THEN (summary_b->>'min_count')::numeric
+                        ELSE (summary_a->>'min_count')::numeric + (summary_b->>'min_count')::numeric END,
+                    'max_count', CASE
+                        WHEN (summary_a->>'max')::numeric > (summary_b->>'max')::numeric THEN (summary_a->>'max_count')::numeric
+                        WHEN (summary_b->>'max')::numeric > (summary_a->>'max')::numeric THEN (summary_b->>'max_count')::numeric
+                        ELSE (summary_a->>'max_count')::numeric + (summary_b->>'max_count')::numeric END));
+            ELSIF type_a IN ('str_agg', 'bool_agg', 'arr_agg', 'uuid_agg') THEN
+                merged_summary := summary_a - ARRAY['mode', 'mode_count', 'distinct', 'total_elements', 'mean_elements_per_row',
+                    'entropy_bits', 'normalized_entropy', 'gini', 'top_share', 'min_numeric', 'max_numeric', 'min_count', 'max_count', 'weighted_median', 'weighted_mean', 'total', 'true_ratio', 'first', 'last'];
                 IF type_a = 'arr_agg' THEN
                     merged_summary := merged_summary || jsonb_build_object('count', (summary_a->>'count')::int + (summary_b->>'count')::int);
                 END IF;
@@ -133,6 +209,11 @@ BEGIN
                     counts_a := jsonb_set(counts_a, ARRAY[k], to_jsonb(COALESCE((counts_a->>k)::int, 0) + (v->>0)::int));
                 END LOOP;
                 merged_summary := jsonb_set(merged_summary, '{counts}', counts_a);
+                IF type_a = 'str_agg' THEN
+                    merged_summary := merged_summary || jsonb_strip_nulls(jsonb_build_object(
+                        'min', LEAST(summary_a->>'min' COLLATE "C", summary_b->>'min'),
+                        'max', GREATEST(summary_a->>'max' COLLATE "C", summary_b->>'max')));
+                END IF;
             END IF;
         END IF;
         merged_state := jsonb_set(merged_state, ARRAY[summary_key], merged_summary, true);
@@ -150,6 +231,10 @@ DECLARE
     key text; summary jsonb; result jsonb;
     -- For integer summary
     count_val numeric; mean_val numeric; sum_sq_diff_val numeric; variance numeric; stddev numeric; cv_pct numeric;
+    m3_val numeric; m4_val numeric; skewness numeric; kurtosis numeric;
+    -- For categorical summary
+    mode_key text; mode_count numeric; entropy numeric; n_distinct numeric;
+    gini numeric; top_share numeric;
 BEGIN
     result := jsonb_build_object('type', 'stats_agg');
     FOR key, summary IN SELECT * FROM jsonb_each(state) LOOP
@@ -161,13 +246,88 @@ BEGIN
             variance := CASE WHEN count_val > 1 THEN sum_sq_diff_val / (count_val - 1) ELSE NULL END;
             stddev := CASE WHEN variance IS NOT NULL AND variance >= 0 THEN sqrt(variance) ELSE NULL END;
             cv_pct := CASE WHEN stddev IS NOT NULL AND mean_val != 0 THEN (stddev / mean_val) * 100 ELSE NULL END;
+            m3_val := COALESCE((summary->>'m3')::numeric, 0);
+            m4_val := COALESCE((summary->>'m4')::numeric, 0);
+            skewness := CASE WHEN count_val >= 3 AND sum_sq_diff_val > 0
+                THEN sqrt(count_val) * m3_val / sum_sq_diff_val^1.5 * sqrt(count_val * (count_val - 1)) / (count_val - 2) END;
+            kurtosis := CASE WHEN count_val >= 4 AND sum_sq_diff_val > 0
+                THEN ((count_val + 1) * (count_val * m4_val / sum_sq_diff_val^2 - 3) + 6) * (count_val - 1)
+                    / ((count_val - 2) * (count_val - 3)) END;
             summary := summary
                 || jsonb_build_object('variance', variance, 'stddev', stddev, 'coefficient_of_variation_pct', cv_pct)
+                || jsonb_build_object('range', round((summary->>'max')::numeric - (summary->>'min')::numeric, 2))
+                || jsonb_build_object('relative_range_pct', CASE WHEN mean_val != 0
+                    THEN round(((summary->>'max')::numeric - (summary->>'min')::numeric) / mean_val * 100, 2) END)
                 || jsonb_build_object('mean', round(mean_val, 2))
-                || jsonb_build_object('sum_sq_diff', round(sum_sq_diff_val, 2))
+                || jsonb_build_object('sum_sq_diff', sum_sq_diff_val)
+                || jsonb_build_object('m3', round(m3_val, 2), 'm4', round(m4_val, 2))
+                || jsonb_build_object('skewness', round(skewness, 2), 'excess_kurtosis', round(kurtosis, 2))
                 || jsonb_build_object('variance', round(variance, 2))
                 || jsonb_build_object('stddev', round(stddev, 2))
                 || jsonb_build_object('coefficient_of_variation_pct', round(cv_pct, 2));
+        ELSIF summary->>'type' = 'arr_agg' THEN
+            summary := summary || jsonb_build_object(
+                'distinct', (SELECT count(*) FROM jsonb_object_keys(summary->'counts')),
+                'total_elements', (SELECT COALESCE(sum(e.value::numeric), 0) FROM jsonb_each_text(summary->'counts') AS e),
+                'mean_elements_per_row', CASE WHEN (summary->>'count')::numeric > 0
+                    THEN round((SELECT COALESCE(sum(e.value::numeric), 0) FROM jsonb_each_text(summary->'counts') AS e)
+                        / (summary->>'count')::numeric, 2) END);
+        ELSIF summary->>'type' IN ('str_agg', 'bool_agg', 'date_agg', 'uuid_agg') THEN
+            summary := summary || jsonb_build_object(
+                'distinct', (SELECT count(*) FROM jsonb_object_keys(summary->'counts')));
+            -- Mode: highest count, ties broken by smallest key in byte order
+            mode_key := NULL;
+            SELECT e.key, e.value::numeric INTO mode_key, mode_count
+            FROM jsonb_each_text(summary->'counts') AS e
+            ORDER BY e.value::numeric DESC, e.key COLLATE "C"
+            LIMIT 1;
+            IF mode_key IS NOT NULL THEN
+                summary := summary || jsonb_build_object('mode', mode_key, 'mode_count', mode_count);
+            END IF;
+            IF summary->>'type' = 'bool_agg' THEN
+                summary := summary || jsonb_build_object(
+                    'total', COALESCE((summary->'counts'->>'true')::numeric, 0) + COALESCE((summary->'counts'->>'false')::numeric, 0),
+                    'true_ratio', round(COALESCE((summary->'counts'->>'true')::numeric, 0)
+                        / NULLIF(COALESCE((summary->'counts'->>'true')::numeric, 0) + COALESCE((summary->'counts'->>'false')::numeric, 0), 0), 2));
+            END IF;
+            -- How many observations equal the min/max
+            IF summary->>'type' IN ('str_agg', 'date_agg') THEN
+                summary := summary || jsonb_strip_nulls(jsonb_build_object(
+                    'min_count', summary->'counts'->(summary->>'min'),
+                    'max_count', summary->'counts'->(summary->>'max')));
+            END IF;
+            -- Numeric range when every str_agg key is a plain decimal number
+            IF summary->>'type' = 'str_agg' AND mode_key IS NOT NULL AND NOT EXISTS (
+                SELECT 1 FROM jsonb_object_keys(summary->'counts') AS k
+                WHERE k !~ '^[+-]?([0-9]+\.?[0-9]*|\.[0-9]+)([eE][+-]?[0-9]+)?$'
+            ) THEN
+                summary := summary || (SELECT jsonb_build_object('min_numeric', min(k::float8), 'max_numeric', max(k::float8))
+                    FROM jsonb_object_keys(summary->'counts') AS k);
+                -- Count-weighted median (midpoint of the two middle values) and mean
+                summary := summary || (SELECT jsonb_build_object(
+                        'weighted_median', (min(v) FILTER (WHERE cum > floor((total - 1) / 2))
+                            + min(v) FILTER (WHERE cum > floor(total / 2))) / 2,
+                        'weighted_mean', round((sum(v::numeric * c) / sum(c))::numeric, 2))
+                    FROM (SELECT v, c, sum(c) OVER (ORDER BY v) AS cum, sum(c) OVER () AS total
+                          FROM (SELECT e.key::float8 AS v, e.value::numeric AS c
+                                FROM jsonb_each_text(summary->'counts') AS e) kc) ranked);
+            END IF;
+        END IF;
+        IF summary->>'type' IN ('str_agg', 'bool_agg', 'arr_agg', 'uuid_agg') THEN
+            -- Shannon entropy in bits, and relative to the maximum log2(distinct)
+            SELECT -sum(p * log(2, p)), count(*) INTO entropy, n_distinct
+            FROM (SELECT e.value::numeric / sum(e.value::numeric) OVER () AS p
+                  FROM jsonb_each_text(summary->'counts') AS e) probs;
+            summary := summary || jsonb_build_object(
+                'entropy_bits', round(entropy, 2),
+                'normalized_entropy', CASE WHEN n_distinct > 1 THEN round(entropy / log(2, n_distinct), 2) END);
+        END IF;
+        IF summary->>'type' IN ('str_agg', 'bool_agg', 'arr_agg', 'date_agg', 'uuid_agg') THEN
+            -- Concentration: Gini impurity and the most frequent key's share
+            SELECT 1 - sum(p^2), max(p) INTO gini, top_share
+            FROM (SELECT e.value::numeric / sum(e.value::numeric) OVER () AS p
+                  FROM jsonb_each_text(summary->'counts') AS e) probs;
+            summary := summary || jsonb_build_object('gini', round(gini, 2), 'top_share', round(top_share, 2));
         END IF;
         result := jsonb_set(result, ARRAY[key], summary);
     END LOOP;
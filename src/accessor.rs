@@ -0,0 +1,515 @@
+use pgrx::prelude::*;
+use pgrx::JsonB;
+use serde_json::{json, Map, Value};
+
+use crate::digest::{exact_cdf, exact_quantile, TDigest};
+use crate::guc;
+use crate::helpers::*;
+use crate::histogram::Histogram;
+use crate::state::NumFields;
+
+/// Metrics readable through `stats_value`.
+const NUM_METRICS: &[&str] = &[
+    "count",
+    "sum",
+    "min",
+    "max",
+    "mean",
+    "sum_sq_diff",
+    "variance",
+    "stddev",
+    "coefficient_of_variation_pct",
+    "range",
+    "relative_range_pct",
+    "median",
+    "p25",
+    "p75",
+    "p95",
+    "iqr",
+    "quartile_coefficient_of_dispersion",
+    "mad",
+];
+
+/// Extract one numeric metric from a finalized `stats_agg`:
+/// `stats_value(agg, 'num', 'mean')` is `(agg->'num'->>'mean')::float8`.
+/// Returns NULL when `key` is absent or the metric is NULL (e.g. variance at count 1).
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_value(agg: JsonB, key: &str, metric: &str) -> Option<f64> {
+    let obj = expect_stats_agg(&agg, "stats_value");
+    if !NUM_METRICS.contains(&metric) {
+        pgrx::error!(
+            "jsonb_stats: unknown metric '{}'. Expected: {}",
+            metric,
+            NUM_METRICS.join(", ")
+        );
+    }
+
+    opt_f64(numeric_entry(obj, key, "stats_value")?, metric)
+}
+
+/// The numeric metrics of one key of a finalized `stats_agg` as a typed
+/// single-row set: `SELECT * FROM stats_to_record(agg, 'num')`. No row when
+/// `key` is absent; min and max are NULL under `jsonb_stats.track_extremes = off`.
+#[pg_extern(immutable, parallel_safe, strict)]
+#[allow(clippy::type_complexity)]
+pub fn stats_to_record(
+    agg: JsonB,
+    key: &str,
+) -> TableIterator<
+    'static,
+    (
+        name!(count, Option<i64>),
+        name!(mean, Option<f64>),
+        name!(stddev, Option<f64>),
+        name!(min, Option<f64>),
+        name!(max, Option<f64>),
+    ),
+> {
+    let obj = expect_stats_agg(&agg, "stats_to_record");
+    let row = numeric_entry(obj, key, "stats_to_record").map(|entry| {
+        (
+            opt_f64(entry, "count").map(|c| c as i64),
+            opt_f64(entry, "mean"),
+            opt_f64(entry, "stddev"),
+            opt_f64(entry, "min"),
+            opt_f64(entry, "max"),
+        )
+    });
+    TableIterator::new(row)
+}
+
+/// The summary of `key` in a `stats_agg`, which must be a numeric aggregate;
+/// None when the key is absent.
+fn numeric_entry<'a>(obj: &'a Map<String, Value>, key: &str, func: &str) -> Option<&'a Map<String, Value>> {
+    let entry = match obj.get(key) {
+        Some(Value::Object(e)) => e,
+        _ => return None,
+    };
+    let agg_type = get_type(entry);
+    if !matches!(agg_type, "int_agg" | "float_agg" | "dec2_agg" | "nat_agg" | "interval_agg") {
+        pgrx::error!(
+            "jsonb_stats: {} key '{}' is {}, expected a numeric aggregate",
+            func,
+            key,
+            agg_type
+        );
+    }
+    Some(entry)
+}
+
+/// The value below which a fraction `p` (0..=1) of `key`'s values fall,
+/// read from the summary's raw values or t-digest (`jsonb_stats.percentiles`)
+/// or else its histogram (`jsonb_stats.hist_bins`): `stats_percentile(agg, 'num', 0.9)`.
+/// NULL when `key` is absent.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_percentile(agg: JsonB, key: &str, p: f64) -> Option<f64> {
+    if !(0.0..=1.0).contains(&p) {
+        pgrx::error!("jsonb_stats: stats_percentile p must be between 0 and 1, got {}", p);
+    }
+    let entry = numeric_entry(expect_stats_agg(&agg, "stats_percentile"), key, "stats_percentile")?;
+    Some(match entry_sketch(entry, key, "stats_percentile") {
+        Sketch::Exact(sorted) => exact_quantile(&sorted, p),
+        Sketch::Digest(d) => d.quantile(p),
+        Sketch::Hist(h) => h.quantile(p),
+    })
+}
+
+/// The estimated fraction of `key`'s values below `x`, from the same sketch
+/// as `stats_percentile`. NULL when `key` is absent.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_cdf(agg: JsonB, key: &str, x: f64) -> Option<f64> {
+    let entry = numeric_entry(expect_stats_agg(&agg, "stats_cdf"), key, "stats_cdf")?;
+    Some(match entry_sketch(entry, key, "stats_cdf") {
+        Sketch::Exact(sorted) => exact_cdf(&sorted, x),
+        Sketch::Digest(d) => d.cdf(x),
+        Sketch::Hist(h) => h.cdf(x),
+    })
+}
+
+/// The `n` most frequent values of a categorical `key` (str, bool, arr,
+/// date, ts, time, uuid, inet) as `[{"value", "count"}, ...]`, most frequent first
+/// and ties broken by value. The `__other__` bucket of pruned values is not
+/// a value and is left out. NULL when `key` is absent.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_top(agg: JsonB, key: &str, n: i32) -> Option<JsonB> {
+    if n < 0 {
+        pgrx::error!("jsonb_stats: stats_top n must be >= 0, got {}", n);
+    }
+    let entry = match expect_stats_agg(&agg, "stats_top").get(key) {
+        Some(Value::Object(e)) => e,
+        _ => return None,
+    };
+    let counts = match entry.get("counts") {
+        Some(Value::Object(c)) => c,
+        _ => pgrx::error!(
+            "jsonb_stats: stats_top key '{}' is {}, expected an aggregate with counts",
+            key,
+            get_type(entry)
+        ),
+    };
+    let mut top: Vec<(&String, i64)> = counts
+        .keys()
+        .filter(|k| k.as_str() != OTHER_KEY)
+        .map(|k| (k, get_count(counts, k)))
+        .collect();
+    // Keys iterate in order, so a stable sort by count keeps ties by value
+    top.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    top.truncate(n as usize);
+    Some(JsonB(Value::Array(
+        top.into_iter()
+            .map(|(value, count)| json!({"value": value, "count": count}))
+            .collect(),
+    )))
+}
+
+enum Sketch {
+    /// The summary's raw values, ascending.
+    Exact(Vec<f64>),
+    Digest(TDigest),
+    Hist(Histogram),
+}
+
+/// The summary's raw values while it keeps them, else its t-digest, else
+/// its histogram.
+fn entry_sketch(entry: &Map<String, Value>, key: &str, func: &str) -> Sketch {
+    if let Some(sorted) = NumFields::from_json(entry).sorted_raw_values().filter(|v| !v.is_empty()) {
+        Sketch::Exact(sorted)
+    } else if let Some(d) = entry.get("digest") {
+        Sketch::Digest(TDigest::from_json(d))
+    } else if let Some(h) = entry.get("histogram") {
+        Sketch::Hist(Histogram::from_json(h))
+    } else {
+        pgrx::error!(
+            "jsonb_stats: {} key '{}' has no digest or histogram; enable jsonb_stats.percentiles or jsonb_stats.hist_bins",
+            func,
+            key
+        )
+    }
+}
+
+/// Explode a finalized `stats_agg` into one row per key. Numeric metrics are
+/// NULL for summaries that lack them; `min_text`/`max_text` hold numeric,
+/// date, timestamp, time or string bounds as text.
+#[pg_extern(immutable, parallel_safe, strict)]
+#[allow(clippy::type_complexity)]
+pub fn stats_agg_unnest(
+    agg: JsonB,
+) -> TableIterator<
+    'static,
+    (
+        name!(key, String),
+        name!(agg_type, String),
+        name!(count, Option<i64>),
+        name!(mean, Option<f64>),
+        name!(stddev, Option<f64>),
+        name!(min_text, Option<String>),
+        name!(max_text, Option<String>),
+    ),
+> {
+    let obj = expect_stats_agg(&agg, "stats_agg_unnest");
+    let rows: Vec<_> = obj
+        .iter()
+        .filter(|(key, v)| !is_agg_metadata(key, v))
+        .filter_map(|(key, summary)| match summary {
+            Value::Object(s) => Some((
+                key.clone(),
+                get_type(s).to_string(),
+                opt_f64(s, "count").map(|c| c as i64),
+                opt_f64(s, "mean"),
+                opt_f64(s, "stddev"),
+                opt_text(s, "min"),
+                opt_text(s, "max"),
+            )),
+            _ => None,
+        })
+        .collect();
+    TableIterator::new(rows)
+}
+
+/// The type of every key of a `stats` or `stats_agg` object as a flat
+/// `{key: type}` object: stat types for `stats`, agg types for `stats_agg`.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_types(input: JsonB) -> JsonB {
+    let types = key_types(&input, "stats_types")
+        .map(|(key, ty)| (key.clone(), json!(ty)))
+        .collect();
+    JsonB(Value::Object(types))
+}
+
+/// How many keys of a `stats` or `stats_agg` have each type, for schema
+/// monitoring: `{"int_agg": 3, "str_agg": 2}`.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_profile(input: JsonB) -> JsonB {
+    let mut profile = Map::new();
+    for (_, ty) in key_types(&input, "stats_profile") {
+        let n = profile.entry(ty).or_insert(json!(0));
+        *n = json!(n.as_i64().unwrap_or(0) + 1);
+    }
+    JsonB(Value::Object(profile))
+}
+
+/// The `(key, type)` pairs of a `stats` or `stats_agg`, skipping the type
+/// tag and watermark.
+fn key_types<'a>(input: &'a JsonB, fname: &'a str) -> impl Iterator<Item = (&'a String, &'a str)> {
+    let obj = match &input.0 {
+        Value::Object(m) if matches!(get_type(m), "stats" | "stats_agg") => m,
+        Value::Object(m) => pgrx::error!(
+            "jsonb_stats: {} expects a stats or stats_agg, got type '{}'",
+            fname,
+            get_type(m)
+        ),
+        other => pgrx::error!("jsonb_stats: {} expects a stats or stats_agg object, got {}", fname, other),
+    };
+    obj.iter()
+        .filter(|(key, v)| !is_agg_metadata(key, v))
+        .map(move |(key, entry)| match entry {
+            Value::Object(e) => (key, get_type(e)),
+            other => pgrx::error!("jsonb_stats: {} key '{}' is not an object: {}", fname, key, other),
+        })
+}
+
+/// Flatten a finalized `stats_agg` into one level of dotted keys for pivoting
+/// into a wide row: `{"num.mean": 52.3, "num.stddev": 11.2, "ind.tech": 600}`.
+/// Scalar metrics become `key.metric`, each `counts` value becomes
+/// `key.value`; sketches (digest, histogram, hll, raw values) are left out.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_flatten(agg: JsonB) -> JsonB {
+    let obj = expect_stats_agg(&agg, "stats_flatten");
+    let mut flat = Map::new();
+    flatten_into(&mut flat, "", obj);
+    JsonB(Value::Object(flat))
+}
+
+/// Add the summaries of `obj` to `flat`, their keys prefixed by `prefix`.
+/// A nested stats_agg adds its own keys one level further down.
+fn flatten_into(flat: &mut Map<String, Value>, prefix: &str, obj: &Map<String, Value>) {
+    for (key, summary) in obj.iter().filter(|(key, v)| !is_agg_metadata(key, v)) {
+        let key = format!("{}{}", prefix, key);
+        let s = match summary {
+            Value::Object(s) if get_type(s) == "stats_agg" => {
+                flatten_into(flat, &format!("{}.", key), s);
+                continue;
+            }
+            Value::Object(s) => s,
+            other => pgrx::error!("jsonb_stats: stats_flatten key '{}' is not a summary: {}", key, other),
+        };
+        for (field, v) in s.iter().filter(|(field, _)| field.as_str() != "type") {
+            match (field.as_str(), v) {
+                ("counts", Value::Object(counts)) => {
+                    for (value, count) in counts {
+                        flat.insert(format!("{}.{}", key, value), count.clone());
+                    }
+                }
+                (_, Value::Object(_) | Value::Array(_)) => {}
+                _ => {
+                    flat.insert(format!("{}.{}", key, field), v.clone());
+                }
+            }
+        }
+    }
+}
+
+/// Keys listed per categorical summary by `stats_explain`.
+const EXPLAIN_TOP: usize = 3;
+
+/// Render a finalized `stats_agg` as one line for logs, keys in order:
+/// `num: n=1000 mean=52.30 sd=11.20; ind: tech=600 finance=400`.
+/// Numeric summaries show count, mean and stddev (when defined); count-based
+/// summaries show their most frequent keys; `nulls=` is added when any were counted.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_explain(agg: JsonB) -> String {
+    explain_entries(expect_stats_agg(&agg, "stats_explain"))
+}
+
+/// The `stats_explain` line for each key of a stats_agg object.
+fn explain_entries(obj: &Map<String, Value>) -> String {
+    let parts: Vec<String> = obj
+        .iter()
+        .filter(|(key, v)| !is_agg_metadata(key, v))
+        .map(|(key, summary)| match summary {
+            Value::Object(s) => format!("{}: {}", key, explain_summary(key, s)),
+            other => pgrx::error!("jsonb_stats: stats_explain key '{}' is not a summary: {}", key, other),
+        })
+        .collect();
+    parts.join("; ")
+}
+
+fn explain_summary(key: &str, s: &Map<String, Value>) -> String {
+    let mut fields = Vec::new();
+    match get_type(s) {
+        "stats_agg" => return format!("{{{}}}", explain_entries(s)),
+        "int_agg" | "float_agg" | "dec2_agg" | "nat_agg" | "interval_agg" => {
+            fields.push(format!("n={}", get_i64(s, "count")));
+            for (label, field) in [("mean", "mean"), ("sd", "stddev")] {
+                if let Some(Value::Number(n)) = s.get(field) {
+                    fields.push(format!("{}={}", label, n));
+                }
+            }
+        }
+        agg_type @ ("str_agg" | "bool_agg" | "arr_agg" | "date_agg" | "ts_agg" | "time_agg"
+        | "uuid_agg" | "inet_agg") => {
+            if agg_type == "arr_agg" {
+                fields.push(format!("n={}", get_i64(s, "count")));
+            }
+            let mut counts: Vec<(&str, i64)> = match s.get("counts") {
+                Some(Value::Object(c)) => c
+                    .keys()
+                    .filter(|k| k.as_str() != OTHER_KEY)
+                    .map(|k| (k.as_str(), get_i64(c, k)))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            counts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+            fields.extend(counts.iter().take(EXPLAIN_TOP).map(|(k, n)| format!("{}={}", k, n)));
+        }
+        other => pgrx::error!("jsonb_stats: stats_explain key '{}' has unknown type '{}'", key, other),
+    }
+    let null_count = get_i64(s, "null_count");
+    if null_count > 0 {
+        fields.push(format!("nulls={}", null_count));
+    }
+    fields.join(" ")
+}
+
+/// Compare two finalized `stats_agg` objects, `b` relative to `a`
+/// (e.g. today against yesterday). Per key present on both sides:
+/// - numeric summaries: `count_delta`, `mean_delta`, `stddev_delta`
+///   (null when either side has no stddev);
+/// - count-based summaries: `count_deltas` for the values whose count
+///   changed, and the values that `appeared` in or `disappeared` from `counts`.
+///
+/// Keys on one side only are flagged `{"only_in": "a"}` or `{"only_in": "b"}`;
+/// keys whose summary type differs are flagged `{"type_changed": {"a", "b"}}`.
+#[pg_extern(stable, parallel_safe, strict)]
+pub fn stats_diff(a: JsonB, b: JsonB) -> JsonB {
+    let a = expect_stats_agg(&a, "stats_diff");
+    let b = expect_stats_agg(&b, "stats_diff");
+    let digits = guc::ROUND_DIGITS.get() as usize;
+
+    let mut result = Map::new();
+    for key in a.keys().chain(b.keys()) {
+        if key == "type" || result.contains_key(key) {
+            continue;
+        }
+        if a.get(key).or(b.get(key)).is_some_and(|v| is_watermark(key, v)) {
+            continue;
+        }
+        let diff = match (a.get(key), b.get(key)) {
+            (Some(Value::Object(sa)), Some(Value::Object(sb))) => diff_summaries(sa, sb, digits),
+            (Some(_), None) => json!({"only_in": "a"}),
+            (None, Some(_)) => json!({"only_in": "b"}),
+            _ => pgrx::error!("jsonb_stats: stats_diff key '{}' is not a summary", key),
+        };
+        result.insert(key.clone(), diff);
+    }
+    JsonB(Value::Object(result))
+}
+
+fn diff_summaries(a: &Map<String, Value>, b: &Map<String, Value>, digits: usize) -> Value {
+    let agg_type = get_type(a);
+    if agg_type != get_type(b) {
+        return json!({"type_changed": {"a": agg_type, "b": get_type(b)}});
+    }
+    let mut d = Map::new();
+    d.insert("type".to_string(), json!(agg_type));
+    match agg_type {
+        "int_agg" | "float_agg" | "dec2_agg" | "nat_agg" | "interval_agg" => {
+            d.insert("count_delta".to_string(), json!(get_i64(b, "count") - get_i64(a, "count")));
+            for (field, name) in [("mean", "mean_delta"), ("stddev", "stddev_delta")] {
+                let delta = match (opt_f64(a, field), opt_f64(b, field)) {
+                    (Some(x), Some(y)) => round_to(y - x, digits),
+                    _ => Value::Null,
+                };
+                d.insert(name.to_string(), delta);
+            }
+        }
+        "str_agg" | "bool_agg" | "arr_agg" | "date_agg" | "ts_agg" | "time_agg" | "uuid_agg" | "inet_agg" => {
+            let counts = |s: &Map<String, Value>| {
+                s.get("counts").and_then(Value::as_object).cloned().unwrap_or_default()
+            };
+            let (ca, cb) = (counts(a), counts(b));
+            let mut deltas = Map::new();
+            for value in ca.keys().chain(cb.keys()) {
+                let delta = get_i64(&cb, value) - get_i64(&ca, value);
+                if delta != 0 {
+                    deltas.insert(value.clone(), json!(delta));
+                }
+            }
+            let appeared: Vec<&String> = cb.keys().filter(|v| !ca.contains_key(*v)).collect();
+            let disappeared: Vec<&String> = ca.keys().filter(|v| !cb.contains_key(*v)).collect();
+            d.insert("count_deltas".to_string(), Value::Object(deltas));
+            d.insert("appeared".to_string(), json!(appeared));
+            d.insert("disappeared".to_string(), json!(disappeared));
+        }
+        other => pgrx::error!("jsonb_stats: stats_diff cannot compare unknown aggregate type '{}'", other),
+    }
+    Value::Object(d)
+}
+
+/// Whether two finalized `stats_agg` objects hold the same statistics:
+/// the same keys and structure, strings, booleans and nulls equal, and
+/// numbers within `tol` of each other, so `100`, `100.0` and `100.004`
+/// match at `tol => 0.01`. Key order never matters in JSONB.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_agg_eq(a: JsonB, b: JsonB, tol: f64) -> bool {
+    if tol.is_nan() || tol < 0.0 {
+        pgrx::error!("jsonb_stats: stats_agg_eq tolerance must be >= 0, got {}", tol);
+    }
+    let a = expect_stats_agg(&a, "stats_agg_eq");
+    let b = expect_stats_agg(&b, "stats_agg_eq");
+    objects_eq(a, b, tol)
+}
+
+fn objects_eq(a: &Map<String, Value>, b: &Map<String, Value>, tol: f64) -> bool {
+    a.len() == b.len()
+        && a.iter().all(|(k, va)| b.get(k).is_some_and(|vb| values_eq(va, vb, tol)))
+}
+
+fn values_eq(a: &Value, b: &Value, tol: f64) -> bool {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => objects_eq(a, b, tol),
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len() && a.iter().zip(b).all(|(x, y)| values_eq(x, y, tol))
+        }
+        (Value::Number(x), Value::Number(y)) => {
+            let (x, y) = (x.to_string(), y.to_string());
+            // Integers subtract exactly: past 2^53 they would collapse in f64
+            if let (Ok(x), Ok(y)) = (x.parse::<i128>(), y.parse::<i128>()) {
+                return x.abs_diff(y) as f64 <= tol;
+            }
+            match (x.parse::<f64>(), y.parse::<f64>()) {
+                (Ok(x), Ok(y)) => (x - y).abs() <= tol,
+                _ => false,
+            }
+        }
+        _ => a == b,
+    }
+}
+
+/// The object behind `agg`, or an error naming `func` if it isn't a stats_agg.
+fn expect_stats_agg<'a>(agg: &'a JsonB, func: &str) -> &'a Map<String, Value> {
+    match &agg.0 {
+        Value::Object(m) if get_type(m) == "stats_agg" => m,
+        Value::Object(m) => pgrx::error!(
+            "jsonb_stats: {} expects a stats_agg, got type '{}'",
+            func,
+            get_type(m)
+        ),
+        other => pgrx::error!("jsonb_stats: {} expects a stats_agg object, got {}", func, other),
+    }
+}
+
+fn opt_f64(obj: &Map<String, Value>, key: &str) -> Option<f64> {
+    match obj.get(key) {
+        Some(Value::Number(_)) => Some(get_f64(obj, key)),
+        _ => None,
+    }
+}
+
+fn opt_text(obj: &Map<String, Value>, key: &str) -> Option<String> {
+    match obj.get(key) {
+        Some(Value::Number(n)) => Some(n.to_string()),
+        Some(Value::String(s)) => Some(s.clone()),
+        _ => None,
+    }
+}
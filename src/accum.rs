@@ -1,21 +1,27 @@
 use std::collections::HashMap;
 
 use pgrx::prelude::*;
-use pgrx::{Internal, JsonB};
+use pgrx::{AnyElement, Internal, JsonB};
 use serde_json::{json, Map, Number, Value};
 
+use crate::guc::{self, OnUnknownType};
 use crate::helpers::*;
-use crate::state::{AggEntry, NumFields, StatsState};
+use crate::hll::Hll;
+use crate::stat::{order_position, stat_json, timestamptz_text};
+use crate::state::{AggEntry, FirstLast, NumFields, StatsState};
 
 /// Accumulate a single stats object into the running state (stats -> stats_agg).
 ///
 /// For each key in `stats` (skipping "type"):
 /// - INIT path: create a new *_agg summary from the stat value
 /// - UPDATE path: update the existing summary with the new value
+/// - NULL path: a JSON null `value` only increments the summary's "null_count"
 ///
 /// Spec: dev/reference_plpgsql.sql lines 8-92
-#[pg_extern(immutable, parallel_safe, strict)]
+#[pg_extern(stable, parallel_safe, strict)]
 pub fn jsonb_stats_accum(state: JsonB, stats: JsonB) -> JsonB {
+    check_object_input(&state.0);
+    check_object_input(&stats.0);
     let mut new_state: Map<String, Value> = match state.0 {
         Value::Object(m) => m,
         _ => Map::new(),
@@ -26,22 +32,50 @@ pub fn jsonb_stats_accum(state: JsonB, stats: JsonB) -> JsonB {
         _ => return JsonB(Value::Object(new_state)),
     };
 
+    check_key_limit(&stats_map);
     for (key, stat_obj) in stats_map {
-        if key == "type" {
+        if is_type_tag(&key, &stat_obj) {
             continue;
         }
+        let key = field_key(key);
 
         let stat_map = match stat_obj {
             Value::Object(m) => m,
             _ => continue,
         };
+        check_array_limit(&key, &stat_map);
 
-        let stat_type = match stat_map.get("type") {
-            Some(Value::String(s)) => s.as_str(),
-            _ => continue,
+        let stat_type = match stat_type_of(&stat_map) {
+            Some(t) => t,
+            None => continue,
         };
+        if skip_unknown_type(stat_type) {
+            continue;
+        }
+        if let Some(current) = new_state.get(&key) {
+            let agg_type = current.get("type").and_then(Value::as_str).unwrap_or("");
+            check_stat_type(&key, agg_type, stat_type);
+        }
+
+        if stat_type == "stats" {
+            // A nested stats object: accumulate its keys into a nested stats_agg
+            let current = new_state
+                .remove(&key)
+                .unwrap_or_else(|| json!({"type": "stats_agg"}));
+            let nested = jsonb_stats_accum(JsonB(current), JsonB(Value::Object(stat_map))).0;
+            new_state.insert(key, nested);
+            continue;
+        }
+
+        let existing_unit = new_state.get(&key).and_then(unit_of).map(str::to_string);
+        let unit = merged_unit(&key, existing_unit, get_str(&stat_map, "unit"));
 
-        let summary = if let Some(current) = new_state.remove(&key) {
+        let mut summary = if stat_map.get("value") == Some(&Value::Null) {
+            let summary = new_state
+                .remove(&key)
+                .unwrap_or_else(|| empty_summary(stat_type));
+            count_null(summary)
+        } else if let Some(current) = new_state.remove(&key) {
             // UPDATE path
             update_summary(current, &stat_map, stat_type)
         } else {
@@ -49,63 +83,86 @@ pub fn jsonb_stats_accum(state: JsonB, stats: JsonB) -> JsonB {
             init_summary(&stat_map, stat_type)
         };
 
+        keep_unit(&mut summary, unit);
         new_state.insert(key, summary);
     }
 
     JsonB(Value::Object(new_state))
 }
 
+/// Fold a single value into `agg` under `key`, typed as `stat(value)` would
+/// type it: `stats_add_value(agg, 'num_employees', 42)` is
+/// `jsonb_stats_accum(agg, jsonb_build_object('num_employees', stat(42)))`.
+/// For keeping a running aggregate current from a trigger.
+#[pg_extern(stable, parallel_safe, strict)]
+pub fn stats_add_value(agg: JsonB, key: &str, value: AnyElement) -> JsonB {
+    let stat = unsafe { stat_json(value.oid(), value.datum()) };
+    let mut stats = Map::new();
+    stats.insert(key.to_string(), stat);
+    jsonb_stats_accum(agg, JsonB(Value::Object(stats)))
+}
+
 /// Initialize a new aggregate summary from a single stat value.
 fn init_summary(stat: &Map<String, Value>, stat_type: &str) -> Value {
     match stat_type {
-        "int" | "float" | "dec2" => init_num_agg(stat, stat_type),
+        "int" | "float" | "dec2" | "interval" => init_num_agg(stat, stat_type),
         "nat" => {
-            let val = get_f64(stat, "value");
+            let val = stat_number(stat, "nat");
             if val < 0.0 {
                 pgrx::error!("jsonb_stats: nat value must be >= 0, got {}", val);
             }
             init_num_agg(stat, "nat")
         }
-        "str" | "bool" => init_str_or_bool_agg(stat, stat_type),
+        "str" | "bool" | "uuid" | "inet" => init_str_or_bool_agg(stat_type, stat),
         "arr" => init_arr_agg(stat),
-        "date" => init_date_agg(stat),
+        "date" | "ts" | "time" => init_date_agg(stat, stat_type),
         other => pgrx::error!(
-            "jsonb_stats: unknown stat type '{}'. Expected: int, float, dec2, nat, str, bool, arr, date",
+            "jsonb_stats: unknown stat type '{}'. Expected: int, float, dec2, nat, str, bool, arr, date, ts, time, uuid, inet, interval, stats",
             other
         ),
     }
 }
 
-fn init_num_agg(stat: &Map<String, Value>, stat_type: &str) -> Value {
-    let val = get_f64(stat, "value");
+/// A summary of no values, for a key whose first stat has a null value.
+fn empty_summary(stat_type: &str) -> Value {
     let agg_type = format!("{}_agg", stat_type);
-    let mut result = Map::new();
-    result.insert("type".to_string(), json!(agg_type));
-    result.insert("count".to_string(), Value::Number(Number::from(1)));
-    result.insert("sum".to_string(), num_value(val));
-    result.insert("min".to_string(), num_value(val));
-    result.insert("max".to_string(), num_value(val));
-    result.insert("mean".to_string(), num_value(val));
-    result.insert("sum_sq_diff".to_string(), Value::Number(Number::from(0)));
-    Value::Object(result)
+    match stat_type {
+        "int" | "float" | "dec2" | "nat" | "interval" => NumFields::empty().to_json(&agg_type),
+        "arr" => json!({"type": agg_type, "count": 0, "counts": {}}),
+        "str" if guc::HLL.get() => json!({"type": agg_type, "counts": {}, "hll": Hll::new().to_json()}),
+        "str" | "bool" | "date" | "ts" | "time" | "uuid" | "inet" => json!({"type": agg_type, "counts": {}}),
+        other => pgrx::error!(
+            "jsonb_stats: unknown stat type '{}'. Expected: int, float, dec2, nat, str, bool, arr, date, ts, time, uuid, inet, interval, stats",
+            other
+        ),
+    }
 }
 
-fn init_str_or_bool_agg(stat: &Map<String, Value>, stat_type: &str) -> Value {
-    let val_str = match stat.get("value") {
-        Some(Value::String(s)) => s.clone(),
-        Some(Value::Bool(b)) => b.to_string(),
-        Some(Value::Number(n)) => n.to_string(),
-        _ => pgrx::error!("jsonb_stats: stat of type '{}' has missing or invalid 'value'", stat_type),
+/// Increment a summary's "null_count".
+fn count_null(summary: Value) -> Value {
+    let mut obj = match summary {
+        Value::Object(m) => m,
+        other => return other,
     };
+    let null_count = get_i64(&obj, "null_count") + 1;
+    insert_null_count(&mut obj, null_count);
+    Value::Object(obj)
+}
 
+fn init_num_agg(stat: &Map<String, Value>, stat_type: &str) -> Value {
+    let val = stat_number(stat, stat_type);
     let agg_type = format!("{}_agg", stat_type);
-    let mut counts = Map::new();
-    counts.insert(val_str, Value::Number(Number::from(1)));
+    NumFields::init(val, exact_value(stat, stat_type)).to_json(&agg_type)
+}
 
+fn init_str_or_bool_agg(stat_type: &str, stat: &Map<String, Value>) -> Value {
     let mut result = Map::new();
-    result.insert("type".to_string(), json!(agg_type));
-    result.insert("counts".to_string(), Value::Object(counts));
-    Value::Object(result)
+    result.insert("type".to_string(), json!(format!("{}_agg", stat_type)));
+    result.insert("counts".to_string(), Value::Object(Map::new()));
+    if stat_type == "str" && guc::HLL.get() {
+        result.insert("hll".to_string(), Hll::new().to_json());
+    }
+    update_str_or_bool_agg(result, stat)
 }
 
 fn init_arr_agg(stat: &Map<String, Value>) -> Value {
@@ -131,19 +188,15 @@ fn init_arr_agg(stat: &Map<String, Value>) -> Value {
         }
     } else if let Some(Value::String(s)) = stat.get("value") {
         // PostgreSQL array text format: {a,b,c}
-        let trimmed = s.trim_matches(|c| c == '{' || c == '}');
-        if !trimmed.is_empty() {
-            for elem in trimmed.split(',') {
-                let elem = elem.trim();
-                let existing: i64 = counts
-                    .get(elem)
-                    .and_then(|v| match v {
-                        Value::Number(n) => n.to_string().parse().ok(),
-                        _ => None,
-                    })
-                    .unwrap_or(0);
-                counts.insert(elem.to_string(), Value::Number(Number::from(existing + 1)));
-            }
+        for elem in parse_array_literal(s) {
+            let existing: i64 = counts
+                .get(&elem)
+                .and_then(|v| match v {
+                    Value::Number(n) => n.to_string().parse().ok(),
+                    _ => None,
+                })
+                .unwrap_or(0);
+            counts.insert(elem, Value::Number(Number::from(existing + 1)));
         }
     }
 
@@ -154,17 +207,15 @@ fn init_arr_agg(stat: &Map<String, Value>) -> Value {
     Value::Object(result)
 }
 
-fn init_date_agg(stat: &Map<String, Value>) -> Value {
-    let date_str = match stat.get("value") {
-        Some(Value::String(s)) => s.clone(),
-        _ => pgrx::error!("jsonb_stats: date stat requires a string 'value'"),
-    };
+/// Initialize a date_agg, ts_agg or time_agg: counts plus lexical min/max of ISO strings.
+fn init_date_agg(stat: &Map<String, Value>, stat_type: &str) -> Value {
+    let date_str = iso_value(stat, stat_type);
 
     let mut counts = Map::new();
     counts.insert(date_str.clone(), Value::Number(Number::from(1)));
 
     let mut result = Map::new();
-    result.insert("type".to_string(), json!("date_agg"));
+    result.insert("type".to_string(), json!(format!("{}_agg", stat_type)));
     result.insert("counts".to_string(), Value::Object(counts));
     result.insert("min".to_string(), json!(date_str));
     result.insert("max".to_string(), json!(date_str));
@@ -179,62 +230,44 @@ fn update_summary(current: Value, stat: &Map<String, Value>, stat_type: &str) ->
     };
 
     match stat_type {
-        "int" | "float" | "dec2" => update_num_agg(current_obj, stat),
+        "int" | "float" | "dec2" | "interval" => update_num_agg(current_obj, stat),
         "nat" => {
-            let val = get_f64(stat, "value");
+            let val = stat_number(stat, "nat");
             if val < 0.0 {
                 pgrx::error!("jsonb_stats: nat value must be >= 0, got {}", val);
             }
             update_num_agg(current_obj, stat)
         }
-        "str" | "bool" => update_str_or_bool_agg(current_obj, stat),
+        "str" | "bool" | "uuid" | "inet" => update_str_or_bool_agg(current_obj, stat),
         "arr" => update_arr_agg(current_obj, stat),
-        "date" => update_date_agg(current_obj, stat),
+        "date" | "ts" | "time" => update_date_agg(current_obj, stat, stat_type),
         other => pgrx::error!(
-            "jsonb_stats: unknown stat type '{}'. Expected: int, float, dec2, nat, str, bool, arr, date",
+            "jsonb_stats: unknown stat type '{}'. Expected: int, float, dec2, nat, str, bool, arr, date, ts, time, uuid, inet, interval, stats",
             other
         ),
     }
 }
 
 /// Welford single-value update for any numeric agg type.
-fn update_num_agg(mut obj: Map<String, Value>, stat: &Map<String, Value>) -> Value {
-    let val = get_f64(stat, "value");
-    let count = get_f64(&obj, "count") + 1.0;
-    let old_mean = get_f64(&obj, "mean");
-    let delta = val - old_mean;
-    let new_mean = old_mean + delta / count;
-    let new_ssd = get_f64(&obj, "sum_sq_diff") + delta * (val - new_mean);
-
+fn update_num_agg(obj: Map<String, Value>, stat: &Map<String, Value>) -> Value {
     // Preserve the existing type tag
-    obj.insert("count".to_string(), num_value(count));
-    obj.insert(
-        "sum".to_string(),
-        num_value(get_f64(&obj, "sum") + val),
-    );
-    obj.insert(
-        "min".to_string(),
-        num_value(get_f64(&obj, "min").min(val)),
-    );
-    obj.insert(
-        "max".to_string(),
-        num_value(get_f64(&obj, "max").max(val)),
-    );
-    obj.insert("mean".to_string(), num_value(new_mean));
-    obj.insert("sum_sq_diff".to_string(), num_value(new_ssd));
-    Value::Object(obj)
+    let type_tag = get_type(&obj);
+    let stat_type = type_tag.trim_end_matches("_agg");
+    let val = stat_number(stat, stat_type);
+    let mut f = NumFields::from_json(&obj);
+    f.update(val, exact_value(stat, stat_type));
+    f.to_json(type_tag)
 }
 
-/// Increment count for str_agg or bool_agg.
+/// Increment count for str_agg, bool_agg, uuid_agg or inet_agg; str_agg also tracks
+/// lexical min/max. A str stat with an array value counts each element.
 fn update_str_or_bool_agg(mut obj: Map<String, Value>, stat: &Map<String, Value>) -> Value {
-    let val_str = match stat.get("value") {
-        Some(Value::String(s)) => s.clone(),
-        Some(Value::Bool(b)) => b.to_string(),
-        Some(Value::Number(n)) => n.to_string(),
-        _ => {
-            let stat_type = get_type(&obj).trim_end_matches("_agg");
-            pgrx::error!("jsonb_stats: stat of type '{}' has missing or invalid 'value'", stat_type);
-        }
+    let stat_type = get_type(&obj).trim_end_matches("_agg").to_string();
+    let values = match stat_type.as_str() {
+        "uuid" => vec![uuid_value(stat)],
+        "inet" => vec![inet_value(stat)],
+        "str" => str_values(stat, &stat_type),
+        _ => vec![scalar_value(stat, &stat_type)],
     };
 
     let mut counts: Map<String, Value> = obj
@@ -244,17 +277,35 @@ fn update_str_or_bool_agg(mut obj: Map<String, Value>, stat: &Map<String, Value>
             _ => None,
         })
         .unwrap_or_default();
+    let mut hll = obj.get("hll").map(Hll::from_json);
 
-    let current: i64 = counts
-        .get(&val_str)
-        .and_then(|v| match v {
-            Value::Number(n) => n.to_string().parse().ok(),
-            _ => None,
-        })
-        .unwrap_or(0);
-    counts.insert(val_str, Value::Number(Number::from(current + 1)));
+    for val_str in values {
+        if let Some(h) = &mut hll {
+            h.add(&val_str);
+        }
+        let current: i64 = counts
+            .get(&val_str)
+            .and_then(|v| match v {
+                Value::Number(n) => n.to_string().parse().ok(),
+                _ => None,
+            })
+            .unwrap_or(0);
+
+        if stat_type == "str" {
+            if get_str(&obj, "min").is_none_or(|cur| val_str.as_str() < cur) {
+                obj.insert("min".to_string(), json!(val_str));
+            }
+            if get_str(&obj, "max").is_none_or(|cur| val_str.as_str() > cur) {
+                obj.insert("max".to_string(), json!(val_str));
+            }
+        }
 
+        counts.insert(val_str, Value::Number(Number::from(current + 1)));
+    }
     obj.insert("counts".to_string(), Value::Object(counts));
+    if let Some(h) = hll {
+        obj.insert("hll".to_string(), h.to_json());
+    }
     Value::Object(obj)
 }
 
@@ -292,19 +343,15 @@ fn update_arr_agg(mut obj: Map<String, Value>, stat: &Map<String, Value>) -> Val
             counts.insert(key, Value::Number(Number::from(existing + 1)));
         }
     } else if let Some(Value::String(s)) = stat.get("value") {
-        let trimmed = s.trim_matches(|c| c == '{' || c == '}');
-        if !trimmed.is_empty() {
-            for elem in trimmed.split(',') {
-                let elem = elem.trim();
-                let existing: i64 = counts
-                    .get(elem)
-                    .and_then(|v| match v {
-                        Value::Number(n) => n.to_string().parse().ok(),
-                        _ => None,
-                    })
-                    .unwrap_or(0);
-                counts.insert(elem.to_string(), Value::Number(Number::from(existing + 1)));
-            }
+        for elem in parse_array_literal(s) {
+            let existing: i64 = counts
+                .get(&elem)
+                .and_then(|v| match v {
+                    Value::Number(n) => n.to_string().parse().ok(),
+                    _ => None,
+                })
+                .unwrap_or(0);
+            counts.insert(elem, Value::Number(Number::from(existing + 1)));
         }
     }
 
@@ -312,12 +359,13 @@ fn update_arr_agg(mut obj: Map<String, Value>, stat: &Map<String, Value>) -> Val
     Value::Object(obj)
 }
 
-/// Update date_agg: increment count for date string, update min/max.
-fn update_date_agg(mut obj: Map<String, Value>, stat: &Map<String, Value>) -> Value {
-    let date_str = match stat.get("value") {
-        Some(Value::String(s)) => s.clone(),
-        _ => pgrx::error!("jsonb_stats: date stat requires a string 'value'"),
-    };
+/// Update date_agg/ts_agg/time_agg: increment count for the ISO string, update min/max.
+fn update_date_agg(
+    mut obj: Map<String, Value>,
+    stat: &Map<String, Value>,
+    stat_type: &str,
+) -> Value {
+    let date_str = iso_value(stat, stat_type);
 
     // Update counts
     let mut counts: Map<String, Value> = obj
@@ -338,19 +386,201 @@ fn update_date_agg(mut obj: Map<String, Value>, stat: &Map<String, Value>) -> Va
     counts.insert(date_str.clone(), Value::Number(Number::from(current + 1)));
     obj.insert("counts".to_string(), Value::Object(counts));
 
-    // Update min/max via string compare (ISO dates sort lexicographically)
-    if let Some(Value::String(cur_min)) = obj.get("min") {
-        if date_str < *cur_min {
-            obj.insert("min".to_string(), json!(date_str));
+    // Update min/max in range_cmp order. Both are absent while the summary
+    // has only seen nulls.
+    if get_str(&obj, "min").is_none_or(|cur| range_cmp(get_type(&obj), &date_str, cur).is_lt()) {
+        obj.insert("min".to_string(), json!(date_str));
+    }
+    if get_str(&obj, "max").is_none_or(|cur| range_cmp(get_type(&obj), &date_str, cur).is_gt()) {
+        obj.insert("max".to_string(), json!(date_str));
+    }
+
+    Value::Object(obj)
+}
+
+// ── Inverse transition for moving-window aggregates ──
+
+/// Remove a single stats object from a running state: the inverse of
+/// `jsonb_stats_accum`, usable as the `minvfunc` of a moving aggregate.
+///
+/// Numeric summaries apply the reverse Welford update. Their min/max cannot
+/// be reversed: removing the current extreme turns it NULL for the rest of
+/// the frame. Count-map summaries decrement counts, dropping keys that reach
+/// zero, and recompute str/date/ts/time min/max from the remaining keys. A summary
+/// left with no values and no nulls is dropped from the state.
+#[pg_extern(stable, parallel_safe, strict)]
+pub fn jsonb_stats_accum_inverse(state: JsonB, stats: JsonB) -> JsonB {
+    let mut new_state: Map<String, Value> = match state.0 {
+        Value::Object(m) => m,
+        _ => Map::new(),
+    };
+
+    let stats_map = match stats.0 {
+        Value::Object(m) => m,
+        _ => return JsonB(Value::Object(new_state)),
+    };
+
+    for (key, stat_obj) in stats_map {
+        if is_type_tag(&key, &stat_obj) {
+            continue;
+        }
+        let key = field_key(key);
+
+        let stat_map = match stat_obj {
+            Value::Object(m) => m,
+            _ => continue,
+        };
+
+        let stat_type = match stat_type_of(&stat_map) {
+            Some(t) => t,
+            None => continue,
+        };
+        if skip_unknown_type(stat_type) {
+            continue;
+        }
+
+        let mut current = match new_state.remove(&key) {
+            Some(Value::Object(m)) => m,
+            _ => pgrx::error!("jsonb_stats: cannot remove key '{}': not in state", key),
+        };
+        let agg_type = format!("{}_agg", stat_type);
+        if get_type(&current) != agg_type {
+            pgrx::error!(
+                "jsonb_stats: type mismatch for key '{}': state has {} but stat is {}",
+                key,
+                get_type(&current),
+                stat_type
+            );
+        }
+
+        if stat_type == "stats" {
+            current = match jsonb_stats_accum_inverse(JsonB(Value::Object(current)), JsonB(Value::Object(stat_map))).0 {
+                Value::Object(m) => m,
+                _ => Map::new(),
+            };
+        } else if stat_map.get("value") == Some(&Value::Null) {
+            let null_count = get_i64(&current, "null_count");
+            if null_count == 0 {
+                pgrx::error!("jsonb_stats: cannot remove null from key '{}': no nulls counted", key);
+            }
+            insert_null_count(&mut current, null_count - 1);
+        } else {
+            match stat_type {
+                "int" | "float" | "dec2" | "nat" | "interval" => {
+                    remove_num_value(&mut current, &stat_map, &key)
+                }
+                "str" | "bool" | "date" | "ts" | "time" | "uuid" | "inet" => {
+                    let values = match stat_type {
+                        "date" | "ts" | "time" => vec![iso_value(&stat_map, stat_type)],
+                        "uuid" => vec![uuid_value(&stat_map)],
+                        "inet" => vec![inet_value(&stat_map)],
+                        "str" => str_values(&stat_map, stat_type),
+                        _ => vec![scalar_value(&stat_map, stat_type)],
+                    };
+                    for val_str in values {
+                        remove_counted_value(&mut current, &val_str, &key);
+                    }
+                    // A sketch cannot forget a value
+                    current.remove("hll");
+                }
+                "arr" => remove_arr_value(&mut current, &stat_map, &key),
+                other => pgrx::error!(
+                    "jsonb_stats: unknown stat type '{}'. Expected: int, float, dec2, nat, str, bool, arr, date, ts, time, uuid, inet, interval, stats",
+                    other
+                ),
+            }
         }
+
+        if !is_empty_summary(&current) {
+            new_state.insert(key, Value::Object(current));
+        }
+    }
+
+    JsonB(Value::Object(new_state))
+}
+
+fn remove_num_value(obj: &mut Map<String, Value>, stat: &Map<String, Value>, key: &str) {
+    let type_tag = get_type(obj).to_string();
+    let stat_type = type_tag.trim_end_matches("_agg");
+    let mut f = NumFields::from_json(obj);
+    if f.count == 0 {
+        pgrx::error!("jsonb_stats: cannot remove a value from key '{}': no values counted", key);
+    }
+    f.remove(stat_number(stat, stat_type), exact_value(stat, stat_type));
+    if let Value::Object(m) = f.to_json(&type_tag) {
+        *obj = m;
     }
-    if let Some(Value::String(cur_max)) = obj.get("max") {
-        if date_str > *cur_max {
-            obj.insert("max".to_string(), json!(date_str));
+}
+
+/// Decrement `val_str` in a str/bool/date/ts/time/uuid/inet summary and refresh min/max.
+fn remove_counted_value(obj: &mut Map<String, Value>, val_str: &str, key: &str) {
+    let mut counts = match obj.remove("counts") {
+        Some(Value::Object(m)) => m,
+        _ => Map::new(),
+    };
+    decrement_count(&mut counts, val_str, 1, key);
+    let agg_type = get_type(obj).to_string();
+    if !matches!(agg_type.as_str(), "bool_agg" | "uuid_agg" | "inet_agg") {
+        let order = |a: &&String, b: &&String| range_cmp(&agg_type, a, b);
+        match (counts.keys().min_by(order), counts.keys().max_by(order)) {
+            (Some(min), Some(max)) => {
+                obj.insert("min".to_string(), json!(min));
+                obj.insert("max".to_string(), json!(max));
+            }
+            _ => {
+                obj.remove("min");
+                obj.remove("max");
+            }
         }
     }
+    obj.insert("counts".to_string(), Value::Object(counts));
+}
 
-    Value::Object(obj)
+fn remove_arr_value(obj: &mut Map<String, Value>, stat: &Map<String, Value>, key: &str) {
+    let count = get_i64(obj, "count");
+    if count == 0 {
+        pgrx::error!("jsonb_stats: cannot remove a value from key '{}': no values counted", key);
+    }
+    obj.insert("count".to_string(), Value::Number(Number::from(count - 1)));
+
+    let mut elems = HashMap::new();
+    collect_arr_counts(stat, &mut elems, 1);
+    let mut counts = match obj.remove("counts") {
+        Some(Value::Object(m)) => m,
+        _ => Map::new(),
+    };
+    for (elem, n) in elems {
+        decrement_count(&mut counts, &elem, n, key);
+    }
+    obj.insert("counts".to_string(), Value::Object(counts));
+}
+
+/// Decrement one count by `n`, dropping the entry at zero. Removing more
+/// than was counted is an error: the window frame is out of sync.
+fn decrement_count(counts: &mut Map<String, Value>, val: &str, n: i64, key: &str) {
+    let current = get_i64(counts, val);
+    if current < n {
+        pgrx::error!("jsonb_stats: cannot remove '{}' from key '{}': not counted", val, key);
+    }
+    if current == n {
+        counts.remove(val);
+    } else {
+        counts.insert(val.to_string(), Value::Number(Number::from(current - n)));
+    }
+}
+
+/// Whether a running summary holds neither values nor nulls.
+fn is_empty_summary(obj: &Map<String, Value>) -> bool {
+    if get_i64(obj, "null_count") > 0 {
+        return false;
+    }
+    match get_type(obj) {
+        "int_agg" | "float_agg" | "dec2_agg" | "nat_agg" | "interval_agg" | "arr_agg" => {
+            get_i64(obj, "count") == 0
+        }
+        "stats_agg" => obj.keys().all(|k| k == "type"),
+        _ => matches!(obj.get("counts"), Some(Value::Object(c)) if c.is_empty()),
+    }
 }
 
 // ── Internal-state sfunc for the aggregate (avoids serde_json round-trip per row) ──
@@ -358,7 +588,8 @@ fn update_date_agg(mut obj: Map<String, Value>, stat: &Map<String, Value>) -> Va
 /// Aggregate sfunc using pgrx Internal state. The state is a native Rust
 /// StatsState allocated on the Rust heap (Box), avoiding both JSONB
 /// serialization per row and PostgreSQL memory context lifetime issues.
-#[pg_extern(immutable, parallel_safe)]
+/// A SQL NULL `stats` row, like a JSON `null`, leaves the state unchanged.
+#[pg_extern(stable, parallel_safe)]
 pub unsafe fn jsonb_stats_accum_sfunc(
     internal: Internal,
     stats: Option<pgrx::JsonB>,
@@ -376,156 +607,640 @@ pub unsafe fn jsonb_stats_accum_sfunc(
     };
 
     let state = unsafe { &mut *state_ptr };
+    accum_into(state, stats.0, 1);
 
-    let stats_map = match stats.0 {
-        Value::Object(m) => m,
-        _ => {
-            return Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)));
+    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+}
+
+/// Weighted sfunc for `jsonb_stats_wagg`: each stat counts as `weight`
+/// identical observations, e.g. when aggregating pre-summarized buckets.
+/// A NULL or zero weight contributes nothing.
+#[pg_extern(stable, parallel_safe)]
+pub unsafe fn jsonb_stats_waccum_sfunc(
+    internal: Internal,
+    stats: Option<pgrx::JsonB>,
+    weight: Option<f64>,
+) -> Internal {
+    let state_ptr: *mut StatsState = match internal.unwrap() {
+        Some(datum) => datum.cast_mut_ptr::<StatsState>(),
+        None => Box::into_raw(Box::new(StatsState::default())),
+    };
+
+    let w = weight.map(observation_weight).unwrap_or(0);
+    if let Some(stats) = stats.filter(|_| w > 0) {
+        let state = unsafe { &mut *state_ptr };
+        accum_into(state, stats.0, w);
+    }
+
+    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+}
+
+/// Sfunc for `jsonb_stats_scalar_agg(code, value)`: folds `stat(value)`
+/// under `code` straight into the native state, without building a JSONB
+/// stats object per row. A NULL code or value contributes nothing, like
+/// `jsonb_stats_agg(jsonb_build_object(code, stat(value)))`.
+#[pg_extern(stable, parallel_safe)]
+pub unsafe fn jsonb_stats_scalar_sfunc(
+    internal: Internal,
+    code: Option<String>,
+    value: Option<AnyElement>,
+) -> Internal {
+    let state_ptr: *mut StatsState = match internal.unwrap() {
+        Some(datum) => datum.cast_mut_ptr::<StatsState>(),
+        None => Box::into_raw(Box::new(StatsState::default())),
+    };
+
+    if let (Some(code), Some(value)) = (code, value) {
+        let state = unsafe { &mut *state_ptr };
+        if let Value::Object(stat_map) = unsafe { stat_json(value.oid(), value.datum()) } {
+            accum_stat(state, field_key(code), &stat_map, 1);
+        }
+    }
+
+    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+}
+
+/// Sfunc for `jsonb_stats_ordered_agg`: accumulates like `jsonb_stats_agg`
+/// and also keeps, per categorical key (str, bool, date, ts, time, uuid, inet), the
+/// value at the smallest and largest `order_key`, whatever the input order.
+/// Rows with a NULL order key are summarized but take no position.
+#[pg_extern(stable, parallel_safe)]
+pub unsafe fn jsonb_stats_ordered_sfunc(
+    internal: Internal,
+    order_key: Option<AnyElement>,
+    stats: Option<pgrx::JsonB>,
+) -> Internal {
+    let state_ptr: *mut StatsState = match internal.unwrap() {
+        Some(datum) => datum.cast_mut_ptr::<StatsState>(),
+        None => Box::into_raw(Box::new(StatsState::default())),
+    };
+
+    if let Some(stats) = stats {
+        let state = unsafe { &mut *state_ptr };
+        if let (Some(order_key), Value::Object(stats_map)) = (order_key, &stats.0) {
+            let position = order_position(&order_key);
+            for (key, stat) in stats_map {
+                let value = match stat.as_object().and_then(stat_type_of) {
+                    Some("str" | "bool" | "date" | "ts" | "time" | "uuid" | "inet") => &stat["value"],
+                    _ => continue,
+                };
+                if value.is_null() {
+                    continue;
+                }
+                let key = field_key(key.clone());
+                match state.ordered.get_mut(&key) {
+                    Some(fl) => fl.observe(position.clone(), value.clone()),
+                    None => {
+                        state.ordered.insert(key, FirstLast::new(position.clone(), value.clone()));
+                    }
+                }
+            }
         }
+        accum_into(state, stats.0, 1);
+    }
+
+    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+}
+
+/// Sfunc for `jsonb_stats_agg_watermarked(ts, stats)`: accumulates like
+/// `jsonb_stats_agg` and keeps the latest `ts` seen as the state's
+/// watermark, so consumers can tell how fresh an aggregate is. A NULL `ts`
+/// row is summarized but does not move the watermark.
+#[pg_extern(stable, parallel_safe)]
+pub unsafe fn jsonb_stats_watermark_sfunc(
+    internal: Internal,
+    ts: Option<pgrx::datum::TimestampWithTimeZone>,
+    stats: Option<pgrx::JsonB>,
+) -> Internal {
+    let state_ptr: *mut StatsState = match internal.unwrap() {
+        Some(datum) => datum.cast_mut_ptr::<StatsState>(),
+        None => Box::into_raw(Box::new(StatsState::default())),
+    };
+
+    let state = unsafe { &mut *state_ptr };
+    if let Some(ts) = ts {
+        state.observe_watermark(&timestamptz_text(ts));
+    }
+    if let Some(stats) = stats {
+        accum_into(state, stats.0, 1);
+    }
+
+    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+}
+
+/// Weights are observation counts, so they must be whole and non-negative.
+fn observation_weight(weight: f64) -> i64 {
+    if !weight.is_finite() || weight < 0.0 || weight.fract() != 0.0 || weight >= i64::MAX as f64 {
+        pgrx::error!(
+            "jsonb_stats: weight must be a non-negative whole number, got {}",
+            weight
+        );
+    }
+    weight as i64
+}
+
+/// Fold one stats object into the state, each stat counting `w` times.
+pub(crate) fn accum_into(state: &mut StatsState, stats: Value, w: i64) {
+    let stats_map = match stats {
+        Value::Object(m) => m,
+        _ => return,
     };
 
+    check_key_limit(&stats_map);
     for (key, stat_obj) in stats_map {
-        if key == "type" {
-            continue;
+        if let Value::Object(stat_map) = stat_obj {
+            accum_stat(state, field_key(key), &stat_map, w);
         }
+    }
+}
 
-        let stat_map = match stat_obj {
-            Value::Object(m) => m,
-            _ => continue,
-        };
+/// Fold one `stat` object under `key` into the native state.
+pub(crate) fn accum_stat(state: &mut StatsState, key: String, stat_map: &Map<String, Value>, w: i64) {
+    let stat_type = match stat_type_of(stat_map) {
+        Some(t) => t.to_string(),
+        None => return,
+    };
+    if skip_unknown_type(&stat_type) {
+        return;
+    }
+    check_array_limit(&key, stat_map);
+    if let Some(entry) = state.entries.get(&key) {
+        check_stat_type(&key, entry.type_tag(), &stat_type);
+    }
 
-        let stat_type = match stat_map.get("type") {
-            Some(Value::String(s)) => s.clone(),
-            _ => continue,
-        };
+    if stat_type == "stats" {
+        let entry = state
+            .entries
+            .entry(key)
+            .or_insert_with(|| AggEntry::NestedAgg(Box::default()));
+        if let AggEntry::NestedAgg(nested) = entry {
+            accum_into(nested, Value::Object(stat_map.clone()), w);
+        }
+        return;
+    }
 
-        if let Some(entry) = state.entries.get_mut(&key) {
-            update_entry(entry, &stat_map, &stat_type);
-        } else {
-            state.entries.insert(key, init_entry(&stat_map, &stat_type));
+    state.note_unit(&key, get_str(stat_map, "unit"));
+
+    if stat_map.get("value") == Some(&Value::Null) {
+        let entry = state
+            .entries
+            .entry(key)
+            .or_insert_with(|| empty_entry(&stat_type));
+        if let Some(nulls) = entry.null_count_mut() {
+            *nulls = add_counts(*nulls, w);
         }
+        return;
     }
 
-    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+    if let Some(entry) = state.entries.get_mut(&key) {
+        update_entry(entry, stat_map, &stat_type, w);
+    } else {
+        state.entries.insert(key, init_entry(stat_map, &stat_type, w));
+    }
 }
 
-fn init_entry(stat: &Map<String, Value>, stat_type: &str) -> AggEntry {
+/// Enforce `jsonb_stats.max_keys` on one stats object. The `"type": "stats"`
+/// marker is not a data key and does not count.
+fn check_key_limit(stats_map: &Map<String, Value>) {
+    let limit = guc::MAX_KEYS.get();
+    if limit <= 0 {
+        return;
+    }
+    let keys = stats_map.iter().filter(|(key, v)| !is_type_tag(key, v)).count();
+    if keys > limit as usize {
+        pgrx::error!(
+            "jsonb_stats: stats object has {} keys, more than jsonb_stats.max_keys ({})",
+            keys,
+            limit
+        );
+    }
+}
+
+/// Enforce `jsonb_stats.max_array_elements` on the value of one stat.
+fn check_array_limit(key: &str, stat: &Map<String, Value>) {
+    let limit = guc::MAX_ARRAY_ELEMENTS.get();
+    if limit <= 0 {
+        return;
+    }
+    let len = match stat.get("value") {
+        Some(Value::Array(elems)) => elems.len(),
+        Some(Value::String(s)) if stat_type_of(stat) == Some("arr") => parse_array_literal(s).len(),
+        _ => return,
+    };
+    if len > limit as usize {
+        pgrx::error!(
+            "jsonb_stats: value of '{}' has {} elements, more than jsonb_stats.max_array_elements ({})",
+            key,
+            len,
+            limit
+        );
+    }
+}
+
+/// An entry of no values, for a key whose first stat has a null value.
+fn empty_entry(stat_type: &str) -> AggEntry {
+    match stat_type {
+        "int" => AggEntry::IntAgg(NumFields::empty()),
+        "float" => AggEntry::FloatAgg(NumFields::empty()),
+        "dec2" => AggEntry::Dec2Agg(NumFields::empty()),
+        "nat" => AggEntry::NatAgg(NumFields::empty()),
+        "interval" => AggEntry::IntervalAgg(NumFields::empty()),
+        "str" => AggEntry::StrAgg {
+            counts: HashMap::new(),
+            min: None,
+            max: None,
+            null_count: 0,
+            hll: guc::HLL.get().then(Hll::new),
+        },
+        "bool" => AggEntry::BoolAgg {
+            counts: HashMap::new(),
+            null_count: 0,
+        },
+        "arr" => AggEntry::ArrAgg {
+            count: 0,
+            counts: HashMap::new(),
+            null_count: 0,
+        },
+        "date" => AggEntry::DateAgg {
+            counts: HashMap::new(),
+            min_date: None,
+            max_date: None,
+            null_count: 0,
+        },
+        "ts" => AggEntry::TsAgg {
+            counts: HashMap::new(),
+            min_ts: None,
+            max_ts: None,
+            null_count: 0,
+        },
+        "time" => AggEntry::TimeAgg {
+            counts: HashMap::new(),
+            min_time: None,
+            max_time: None,
+            null_count: 0,
+        },
+        "uuid" => AggEntry::UuidAgg {
+            counts: HashMap::new(),
+            null_count: 0,
+        },
+        "inet" => AggEntry::InetAgg {
+            counts: HashMap::new(),
+            null_count: 0,
+        },
+        other => pgrx::error!(
+            "jsonb_stats: unknown stat type '{}'. Expected: int, float, dec2, nat, str, bool, arr, date, ts, time, uuid, inet, interval, stats",
+            other
+        ),
+    }
+}
+
+fn init_entry(stat: &Map<String, Value>, stat_type: &str, w: i64) -> AggEntry {
     match stat_type {
         "int" => {
-            let val = get_f64(stat, "value");
-            AggEntry::IntAgg(NumFields::init(val))
+            let val = stat_number(stat, "int");
+            AggEntry::IntAgg(NumFields::init_weighted(val, exact_value(stat, "int"), w))
         }
         "float" => {
-            let val = get_f64(stat, "value");
-            AggEntry::FloatAgg(NumFields::init(val))
+            let val = stat_number(stat, "float");
+            AggEntry::FloatAgg(NumFields::init_weighted(val, 0, w))
         }
         "dec2" => {
-            let val = get_f64(stat, "value");
-            AggEntry::Dec2Agg(NumFields::init(val))
+            let val = stat_number(stat, "dec2");
+            AggEntry::Dec2Agg(NumFields::init_weighted(val, exact_value(stat, "dec2"), w))
         }
         "nat" => {
-            let val = get_f64(stat, "value");
+            let val = stat_number(stat, "nat");
             if val < 0.0 {
                 pgrx::error!("jsonb_stats: nat value must be >= 0, got {}", val);
             }
-            AggEntry::NatAgg(NumFields::init(val))
+            AggEntry::NatAgg(NumFields::init_weighted(val, exact_value(stat, "nat"), w))
+        }
+        "interval" => {
+            let val = stat_number(stat, "interval");
+            AggEntry::IntervalAgg(NumFields::init_weighted(val, 0, w))
         }
         "str" => {
-            let val_str = value_to_string(stat)
-                .unwrap_or_else(|| pgrx::error!("jsonb_stats: stat of type 'str' has missing or invalid 'value'"));
-            let mut counts = HashMap::new();
-            counts.insert(val_str, 1);
-            AggEntry::StrAgg { counts }
+            let mut entry = empty_entry("str");
+            update_entry(&mut entry, stat, "str", w);
+            entry
         }
         "bool" => {
-            let val_str = value_to_string(stat)
-                .unwrap_or_else(|| pgrx::error!("jsonb_stats: stat of type 'bool' has missing or invalid 'value'"));
             let mut counts = HashMap::new();
-            counts.insert(val_str, 1);
-            AggEntry::BoolAgg { counts }
+            counts.insert(scalar_value(stat, "bool"), w);
+            AggEntry::BoolAgg {
+                counts,
+                null_count: 0,
+            }
         }
         "arr" => {
             let mut counts = HashMap::new();
-            collect_arr_counts(stat, &mut counts);
-            AggEntry::ArrAgg { count: 1, counts }
+            collect_arr_counts(stat, &mut counts, w);
+            AggEntry::ArrAgg {
+                count: w,
+                counts,
+                null_count: 0,
+            }
         }
         "date" => {
-            let date_str = match stat.get("value") {
-                Some(Value::String(s)) => s.clone(),
-                _ => pgrx::error!("jsonb_stats: date stat requires a string 'value'"),
-            };
+            let date_str = iso_value(stat, "date");
             let mut counts = HashMap::new();
-            counts.insert(date_str.clone(), 1);
+            counts.insert(date_str.clone(), w);
             AggEntry::DateAgg {
                 counts,
                 min_date: Some(date_str.clone()),
                 max_date: Some(date_str),
+                null_count: 0,
+            }
+        }
+        "ts" => {
+            let ts_str = iso_value(stat, "ts");
+            let mut counts = HashMap::new();
+            counts.insert(ts_str.clone(), w);
+            AggEntry::TsAgg {
+                counts,
+                min_ts: Some(ts_str.clone()),
+                max_ts: Some(ts_str),
+                null_count: 0,
+            }
+        }
+        "time" => {
+            let time_str = iso_value(stat, "time");
+            let mut counts = HashMap::new();
+            counts.insert(time_str.clone(), w);
+            AggEntry::TimeAgg {
+                counts,
+                min_time: Some(time_str.clone()),
+                max_time: Some(time_str),
+                null_count: 0,
+            }
+        }
+        "uuid" => {
+            let mut counts = HashMap::new();
+            counts.insert(uuid_value(stat), w);
+            AggEntry::UuidAgg {
+                counts,
+                null_count: 0,
+            }
+        }
+        "inet" => {
+            let mut counts = HashMap::new();
+            counts.insert(inet_value(stat), w);
+            AggEntry::InetAgg {
+                counts,
+                null_count: 0,
             }
         }
         other => pgrx::error!(
-            "jsonb_stats: unknown stat type '{}'. Expected: int, float, dec2, nat, str, bool, arr, date",
+            "jsonb_stats: unknown stat type '{}'. Expected: int, float, dec2, nat, str, bool, arr, date, ts, time, uuid, inet, interval, stats",
             other
         ),
     }
 }
 
-fn update_entry(entry: &mut AggEntry, stat: &Map<String, Value>, stat_type: &str) {
+fn update_entry(entry: &mut AggEntry, stat: &Map<String, Value>, stat_type: &str, w: i64) {
+    let agg_type = entry.type_tag();
     match entry {
-        AggEntry::IntAgg(f) | AggEntry::FloatAgg(f) | AggEntry::Dec2Agg(f) => {
-            let val = get_f64(stat, "value");
-            f.update(val);
+        AggEntry::IntAgg(f) => {
+            let val = stat_number(stat, "int");
+            f.update_weighted(val, exact_value(stat, "int"), w);
+        }
+        AggEntry::FloatAgg(f) => {
+            let val = stat_number(stat, "float");
+            f.update_weighted(val, 0, w);
+        }
+        AggEntry::Dec2Agg(f) => {
+            let val = stat_number(stat, "dec2");
+            f.update_weighted(val, exact_value(stat, "dec2"), w);
         }
         AggEntry::NatAgg(f) => {
-            let val = get_f64(stat, "value");
+            let val = stat_number(stat, "nat");
             if val < 0.0 {
                 pgrx::error!("jsonb_stats: nat value must be >= 0, got {}", val);
             }
-            f.update(val);
+            f.update_weighted(val, exact_value(stat, "nat"), w);
+        }
+        AggEntry::IntervalAgg(f) => {
+            let val = stat_number(stat, "interval");
+            f.update_weighted(val, 0, w);
+        }
+        AggEntry::StrAgg {
+            counts,
+            min,
+            max,
+            hll,
+            ..
+        } => {
+            for val_str in str_values(stat, stat_type) {
+                if let Some(h) = hll {
+                    h.add(&val_str);
+                }
+                if min.as_ref().is_none_or(|cur| val_str < *cur) {
+                    *min = Some(val_str.clone());
+                }
+                if max.as_ref().is_none_or(|cur| val_str > *cur) {
+                    *max = Some(val_str.clone());
+                }
+                add_weight(counts, val_str, w);
+            }
+        }
+        AggEntry::BoolAgg { counts, .. } => {
+            add_weight(counts, scalar_value(stat, stat_type), w);
+        }
+        AggEntry::UuidAgg { counts, .. } => {
+            add_weight(counts, uuid_value(stat), w);
         }
-        AggEntry::StrAgg { counts } | AggEntry::BoolAgg { counts } => {
-            let val_str = value_to_string(stat).unwrap_or_else(|| {
-                pgrx::error!("jsonb_stats: stat of type '{}' has missing or invalid 'value'", stat_type)
-            });
-            *counts.entry(val_str).or_insert(0) += 1;
+        AggEntry::InetAgg { counts, .. } => {
+            add_weight(counts, inet_value(stat), w);
         }
-        AggEntry::ArrAgg { count, counts } => {
-            *count += 1;
-            collect_arr_counts(stat, counts);
+        AggEntry::ArrAgg { count, counts, .. } => {
+            *count = add_counts(*count, w);
+            collect_arr_counts(stat, counts, w);
         }
         AggEntry::DateAgg {
             counts,
             min_date,
             max_date,
+            ..
+        }
+        | AggEntry::TsAgg {
+            counts,
+            min_ts: min_date,
+            max_ts: max_date,
+            ..
+        }
+        | AggEntry::TimeAgg {
+            counts,
+            min_time: min_date,
+            max_time: max_date,
+            ..
         } => {
-            let date_str = match stat.get("value") {
-                Some(Value::String(s)) => s,
-                _ => pgrx::error!("jsonb_stats: date stat requires a string 'value'"),
-            };
-            *counts.entry(date_str.clone()).or_insert(0) += 1;
+            let date_str = iso_value(stat, stat_type);
+            add_weight(counts, date_str.clone(), w);
             match min_date {
-                Some(cur) if date_str < cur => *min_date = Some(date_str.clone()),
+                Some(cur) if range_cmp(agg_type, &date_str, cur).is_lt() => *min_date = Some(date_str.clone()),
                 None => *min_date = Some(date_str.clone()),
                 _ => {}
             }
             match max_date {
-                Some(cur) if date_str > cur => *max_date = Some(date_str.clone()),
+                Some(cur) if range_cmp(agg_type, &date_str, cur).is_gt() => *max_date = Some(date_str.clone()),
                 None => *max_date = Some(date_str.clone()),
                 _ => {}
             }
         }
+        AggEntry::NestedAgg(nested) => accum_into(nested, Value::Object(stat.clone()), w),
     }
 }
 
-fn value_to_string(stat: &Map<String, Value>) -> Option<String> {
-    match stat.get("value") {
-        Some(Value::String(s)) => Some(s.clone()),
-        Some(Value::Bool(b)) => Some(b.to_string()),
-        Some(Value::Number(n)) => Some(n.to_string()),
+/// The type of a stat: its "type", or under `jsonb_stats.infer_type` one
+/// inferred from the kind of its "value" when "type" is absent (number ->
+/// float, string -> str, boolean -> bool, array -> arr). None skips the stat.
+fn stat_type_of(stat: &Map<String, Value>) -> Option<&str> {
+    match stat.get("type") {
+        Some(Value::String(s)) => Some(s.as_str()),
+        None if guc::INFER_TYPE.get() => match stat.get("value")? {
+            Value::Number(_) => Some("float"),
+            Value::String(_) => Some("str"),
+            Value::Bool(_) => Some("bool"),
+            Value::Array(_) => Some("arr"),
+            _ => None,
+        },
         _ => None,
     }
 }
 
-fn collect_arr_counts(stat: &Map<String, Value>, counts: &mut HashMap<String, i64>) {
+/// Whether to pass over a stat of unknown type (`jsonb_stats.on_unknown_type
+/// = skip`) rather than let accumulation raise on it.
+fn skip_unknown_type(stat_type: &str) -> bool {
+    !STAT_TYPES.contains(&stat_type) && guc::ON_UNKNOWN_TYPE.get() == OnUnknownType::Skip
+}
+
+/// A key keeps the type of its first stat: folding e.g. a str into an
+/// int_agg would otherwise update the summary by its own type and give
+/// silently wrong results.
+fn check_stat_type(key: &str, agg_type: &str, stat_type: &str) {
+    if agg_type.strip_suffix("_agg") != Some(stat_type) {
+        pgrx::error!(
+            "jsonb_stats: type mismatch for key '{}': {} vs {}",
+            key,
+            agg_type,
+            stat_type
+        );
+    }
+}
+
+/// The string 'value' of a date/ts/time stat. Dates must be ISO-8601 and
+/// times are brought to their `HH:MM:SS.ffffff` form, so that lexical
+/// min/max ordering is chronological.
+fn iso_value(stat: &Map<String, Value>, stat_type: &str) -> String {
+    let s = match stat.get("value") {
+        Some(Value::String(s)) => s,
+        _ => pgrx::error!("jsonb_stats: {} stat requires a string 'value'", stat_type),
+    };
+    if stat_type == "date" && !is_iso_date(s) {
+        pgrx::error!(
+            "jsonb_stats: date stat requires an ISO-8601 (YYYY-MM-DD) value, got '{}'",
+            s
+        );
+    }
+    if stat_type == "time" {
+        return iso_time(s).unwrap_or_else(|| {
+            pgrx::error!("jsonb_stats: time stat requires an HH:MM:SS[.ffffff] value, got '{}'", s)
+        });
+    }
+    s.clone()
+}
+
+/// The 'value' of a uuid stat in canonical lowercase hyphenated form, so
+/// that differently cased spellings of one UUID share a count.
+fn uuid_value(stat: &Map<String, Value>) -> String {
+    match stat.get("value") {
+        Some(Value::String(s)) if is_uuid(s) => s.to_ascii_lowercase(),
+        other => pgrx::error!(
+            "jsonb_stats: uuid stat requires a hyphenated UUID string 'value', got {}",
+            other.unwrap_or(&Value::Null)
+        ),
+    }
+}
+
+/// The 'value' of an inet stat in canonical form (IPv6 lowercase and
+/// compressed), keeping a prefix length when one is given.
+fn inet_value(stat: &Map<String, Value>) -> String {
+    match stat.get("value") {
+        Some(Value::String(s)) => match parse_inet(s) {
+            Some((addr, Some(prefix))) => format!("{}/{}", addr, prefix),
+            Some((addr, None)) => addr.to_string(),
+            None => pgrx::error!(
+                "jsonb_stats: inet stat requires an IP address 'value', got '{}'",
+                s
+            ),
+        },
+        other => pgrx::error!(
+            "jsonb_stats: inet stat requires an IP address string 'value', got {}",
+            other.unwrap_or(&Value::Null)
+        ),
+    }
+}
+
+/// Exact scaled form of a numeric stat value for `NumFields::sum_int`:
+/// whole units for int/nat, cents for dec2. Float and interval contribute 0.
+fn exact_value(stat: &Map<String, Value>, stat_type: &str) -> i128 {
+    match stat_type {
+        "int" | "nat" => get_i128(stat, "value"),
+        "dec2" => get_cents(stat, "value"),
+        _ => 0,
+    }
+}
+
+/// The value of a numeric stat: a JSON number, or a number written as a
+/// string. Any other kind raises rather than silently adding 0 to the sum.
+fn stat_number(stat: &Map<String, Value>, stat_type: &str) -> f64 {
+    match stat.get("value") {
+        Some(Value::Number(_) | Value::String(_)) => get_f64(stat, "value"),
+        _ => pgrx::error!("jsonb_stats: stat of type '{}' has missing or invalid 'value'", stat_type),
+    }
+}
+
+/// The count key of a str or bool stat. The value must be of the stat's own
+/// kind: counts are keyed by text, so a number 1 would otherwise share the
+/// key of the string "1", and a bool true that of the string "true".
+fn scalar_value(stat: &Map<String, Value>, stat_type: &str) -> String {
+    match (stat_type, stat.get("value")) {
+        (_, None) => pgrx::error!("jsonb_stats: stat of type '{}' has missing or invalid 'value'", stat_type),
+        ("bool", Some(Value::Bool(b))) => b.to_string(),
+        ("bool", Some(v)) => pgrx::error!("jsonb_stats: bool stat requires a boolean 'value', got {}", v),
+        (_, Some(Value::String(s))) => s.clone(),
+        (_, Some(v)) => pgrx::error!("jsonb_stats: {} stat requires a string 'value', got {}", stat_type, v),
+    }
+}
+
+/// The count keys of a str stat. A JSON array value counts each element as
+/// one observation (`["a","a","b"]` adds a:2, b:1), unlike an `arr` stat,
+/// which also counts the row itself in `count`.
+fn str_values(stat: &Map<String, Value>, stat_type: &str) -> Vec<String> {
+    match stat.get("value") {
+        Some(Value::Array(elems)) => elems
+            .iter()
+            .map(|e| match e {
+                Value::String(s) => s.clone(),
+                _ => pgrx::error!("jsonb_stats: str stat array elements must be strings, got {}", e),
+            })
+            .collect(),
+        _ => vec![scalar_value(stat, stat_type)],
+    }
+}
+
+/// Add weight `w` to the count of `key`, raising instead of wrapping past
+/// the i64 range.
+fn add_weight(counts: &mut HashMap<String, i64>, key: String, w: i64) {
+    match counts.get_mut(&key) {
+        Some(n) => *n = add_key_counts(&key, *n, w),
+        None => {
+            counts.insert(key, w);
+        }
+    }
+}
+
+fn collect_arr_counts(stat: &Map<String, Value>, counts: &mut HashMap<String, i64>, w: i64) {
     if let Some(Value::Array(arr)) = stat.get("value") {
         for elem in arr {
             let key = match elem {
@@ -534,14 +1249,11 @@ fn collect_arr_counts(stat: &Map<String, Value>, counts: &mut HashMap<String, i6
                 Value::Bool(b) => b.to_string(),
                 _ => continue,
             };
-            *counts.entry(key).or_insert(0) += 1;
+            add_weight(counts, key, w);
         }
     } else if let Some(Value::String(s)) = stat.get("value") {
-        let trimmed = s.trim_matches(|c| c == '{' || c == '}');
-        if !trimmed.is_empty() {
-            for elem in trimmed.split(',') {
-                *counts.entry(elem.trim().to_string()).or_insert(0) += 1;
-            }
+        for elem in parse_array_literal(s) {
+            add_weight(counts, elem, w);
         }
     }
 }
@@ -0,0 +1,139 @@
+use pgrx::prelude::*;
+use pgrx::{Internal, JsonB};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Number, Value};
+
+use crate::guc;
+use crate::helpers::round_to;
+use crate::parallel::{decode, encode};
+
+/// Running state of `jsonb_stats_corr_agg`: each marginal's Welford mean and
+/// sum of squared deviations, plus the co-moment Σ(x − mean_x)(y − mean_y).
+#[derive(Default, Clone, Serialize, Deserialize)]
+pub struct CorrState {
+    pub n: i64,
+    pub mean_x: f64,
+    pub mean_y: f64,
+    pub m2_x: f64,
+    pub m2_y: f64,
+    pub c_xy: f64,
+}
+
+impl CorrState {
+    /// Welford update with one (x, y) pair.
+    pub fn update(&mut self, x: f64, y: f64) {
+        self.n += 1;
+        let n = self.n as f64;
+        let dx = x - self.mean_x;
+        self.mean_x += dx / n;
+        let dy = y - self.mean_y;
+        self.mean_y += dy / n;
+        // The co-moment pairs the old x deviation with the new y deviation
+        self.c_xy += dx * (y - self.mean_y);
+        self.m2_x += dx * (x - self.mean_x);
+        self.m2_y += dy * (y - self.mean_y);
+    }
+
+    /// Pairwise merge of two partial states.
+    pub fn merge(&mut self, other: &CorrState) {
+        if other.n == 0 {
+            return;
+        }
+        if self.n == 0 {
+            *self = other.clone();
+            return;
+        }
+        let (na, nb) = (self.n as f64, other.n as f64);
+        let n = na + nb;
+        let dx = other.mean_x - self.mean_x;
+        let dy = other.mean_y - self.mean_y;
+        self.c_xy += other.c_xy + dx * dy * na * nb / n;
+        self.m2_x += other.m2_x + dx * dx * na * nb / n;
+        self.m2_y += other.m2_y + dy * dy * na * nb / n;
+        self.mean_x += dx * nb / n;
+        self.mean_y += dy * nb / n;
+        self.n += other.n;
+    }
+
+    /// `{"correlation", "covariance", "n"}`: the Pearson correlation and the
+    /// sample covariance, each NULL while undefined (fewer than two pairs, or
+    /// a constant side for the correlation).
+    pub fn to_json(&self, digits: usize) -> Value {
+        let defined = |v: f64| if v.is_finite() { round_to(v, digits) } else { Value::Null };
+        let (correlation, covariance) = if self.n >= 2 {
+            let denom = (self.m2_x * self.m2_y).sqrt();
+            let r = if denom > 0.0 { (self.c_xy / denom).clamp(-1.0, 1.0) } else { f64::NAN };
+            (defined(r), defined(self.c_xy / (self.n - 1) as f64))
+        } else {
+            (Value::Null, Value::Null)
+        };
+        let mut obj = Map::new();
+        obj.insert("correlation".to_string(), correlation);
+        obj.insert("covariance".to_string(), covariance);
+        obj.insert("n".to_string(), Value::Number(Number::from(self.n)));
+        Value::Object(obj)
+    }
+}
+
+/// Sfunc for `jsonb_stats_corr_agg(x, y)`. Rows where either side is NULL
+/// are skipped, as by PostgreSQL's `corr`.
+#[pg_extern(immutable, parallel_safe)]
+pub unsafe fn jsonb_stats_corr_sfunc(internal: Internal, x: Option<f64>, y: Option<f64>) -> Internal {
+    let state_ptr: *mut CorrState = match internal.unwrap() {
+        Some(datum) => datum.cast_mut_ptr::<CorrState>(),
+        None => Box::into_raw(Box::new(CorrState::default())),
+    };
+
+    if let (Some(x), Some(y)) = (x, y) {
+        if !x.is_finite() || !y.is_finite() {
+            pgrx::error!("jsonb_stats: jsonb_stats_corr_agg values must be finite, got ({}, {})", x, y);
+        }
+        let state = unsafe { &mut *state_ptr };
+        state.update(x, y);
+    }
+
+    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+}
+
+/// Combine two partial correlation states; state2 is freed, as in
+/// `jsonb_stats_combine`.
+#[pg_extern(immutable, parallel_safe)]
+pub unsafe fn jsonb_stats_corr_combine(state1: Internal, state2: Internal) -> Internal {
+    let ptr1 = state1.unwrap().map(|d| d.cast_mut_ptr::<CorrState>());
+    let ptr2 = state2.unwrap().map(|d| d.cast_mut_ptr::<CorrState>());
+    let ptr = match (ptr1, ptr2) {
+        (None, None) => Box::into_raw(Box::new(CorrState::default())),
+        (Some(p), None) | (None, Some(p)) => p,
+        (Some(p1), Some(p2)) => {
+            let s2 = unsafe { Box::from_raw(p2) };
+            unsafe { &mut *p1 }.merge(&s2);
+            p1
+        }
+    };
+    Internal::from(Some(pgrx::pg_sys::Datum::from(ptr as usize)))
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub unsafe fn jsonb_stats_corr_serial(internal: Internal) -> Vec<u8> {
+    match internal.unwrap() {
+        Some(datum) => encode(unsafe { &*datum.cast_mut_ptr::<CorrState>() }),
+        None => encode(&CorrState::default()),
+    }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub unsafe fn jsonb_stats_corr_deserial(bytes: Vec<u8>, _internal: Internal) -> Internal {
+    let ptr = Box::into_raw(Box::new(decode::<CorrState>(&bytes)));
+    Internal::from(Some(pgrx::pg_sys::Datum::from(ptr as usize)))
+}
+
+/// Finalfunc for `jsonb_stats_corr_agg`, rounded to `jsonb_stats.round_digits`.
+/// Borrows the state, as `jsonb_stats_final_internal` does.
+#[pg_extern(stable, parallel_safe)]
+pub unsafe fn jsonb_stats_corr_final(internal: Internal) -> JsonB {
+    let digits = guc::ROUND_DIGITS.get() as usize;
+    match internal.unwrap() {
+        Some(datum) => JsonB(unsafe { &*datum.cast_mut_ptr::<CorrState>() }.to_json(digits)),
+        None => JsonB(json!({"correlation": null, "covariance": null, "n": 0})),
+    }
+}
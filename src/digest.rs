@@ -0,0 +1,216 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Compression parameter δ: a compressed digest keeps at most about δ/2 centroids.
+const COMPRESSION: f64 = 100.0;
+
+/// Centroids buffered before a compression pass.
+const BUFFER_SIZE: usize = 5 * COMPRESSION as usize;
+
+/// A centroid `[mean, weight]`, serialized as a two-element array.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+pub struct Centroid(pub f64, pub u64);
+
+/// Mergeable t-digest (Dunning, "merging" variant) for approximate quantiles.
+///
+/// New values are appended as weight-1 centroids and folded together in a
+/// compression pass once the buffer fills. Centroids near the median may
+/// absorb many values; centroids near the tails stay small, so extreme
+/// quantiles remain accurate.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TDigest {
+    pub min: f64,
+    pub max: f64,
+    pub centroids: Vec<Centroid>,
+}
+
+impl TDigest {
+    /// A digest of `weight` copies of `val`.
+    pub fn new(val: f64, weight: u64) -> Self {
+        TDigest {
+            min: val,
+            max: val,
+            centroids: vec![Centroid(val, weight)],
+        }
+    }
+
+    pub fn add(&mut self, val: f64, weight: u64) {
+        self.min = self.min.min(val);
+        self.max = self.max.max(val);
+        self.centroids.push(Centroid(val, weight));
+        if self.centroids.len() > BUFFER_SIZE {
+            self.compress();
+        }
+    }
+
+    pub fn merge(&mut self, other: &TDigest) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.centroids.extend_from_slice(&other.centroids);
+        if self.centroids.len() > BUFFER_SIZE {
+            self.compress();
+        }
+    }
+
+    /// Sort centroids and merge neighbours while each spans at most one unit
+    /// of the k1 scale function k(q) = δ/(2π)·asin(2q−1), which keeps
+    /// centroids small near q = 0 and q = 1.
+    pub fn compress(&mut self) {
+        if self.centroids.len() <= 1 {
+            return;
+        }
+        self.centroids.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let total: f64 = self.centroids.iter().map(|c| c.1 as f64).sum();
+
+        let mut merged: Vec<Centroid> = Vec::with_capacity(COMPRESSION as usize * 2);
+        let k = |q: f64| COMPRESSION / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).asin();
+        let mut cur = self.centroids[0];
+        let mut weight_before = 0.0;
+        for &next in &self.centroids[1..] {
+            let proposed = (cur.1 + next.1) as f64;
+            let q_start = weight_before / total;
+            let q_end = ((weight_before + proposed) / total).min(1.0);
+            if k(q_end) - k(q_start) <= 1.0 {
+                cur.0 += (next.0 - cur.0) * next.1 as f64 / proposed;
+                cur.1 += next.1;
+            } else {
+                weight_before += cur.1 as f64;
+                merged.push(cur);
+                cur = next;
+            }
+        }
+        merged.push(cur);
+        self.centroids = merged;
+    }
+
+    /// Estimate the value at quantile `q` (0..=1) by interpolating between
+    /// centroid centres, anchored at the exact min and max.
+    pub fn quantile(&self, q: f64) -> f64 {
+        let mut sorted = self.centroids.clone();
+        sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let total: f64 = sorted.iter().map(|c| c.1 as f64).sum();
+        let target = q.clamp(0.0, 1.0) * total;
+
+        let first = sorted[0];
+        let last = sorted[sorted.len() - 1];
+        if target <= first.1 as f64 / 2.0 {
+            return interpolate(self.min, first.0, target / (first.1 as f64 / 2.0));
+        }
+        if target >= total - last.1 as f64 / 2.0 {
+            let span = last.1 as f64 / 2.0;
+            return interpolate(last.0, self.max, (target - (total - span)) / span);
+        }
+
+        let mut cumulative = 0.0;
+        for pair in sorted.windows(2) {
+            let (a, b) = (pair[0], pair[1]);
+            let centre_a = cumulative + a.1 as f64 / 2.0;
+            let centre_b = cumulative + a.1 as f64 + b.1 as f64 / 2.0;
+            if target <= centre_b {
+                return interpolate(a.0, b.0, (target - centre_a) / (centre_b - centre_a));
+            }
+            cumulative += a.1 as f64;
+        }
+        self.max
+    }
+
+    /// Estimate the fraction of values below `x`, the inverse of `quantile`:
+    /// the same piecewise-linear rank curve through the centroid centres.
+    pub fn cdf(&self, x: f64) -> f64 {
+        if x < self.min {
+            return 0.0;
+        }
+        if x >= self.max {
+            return 1.0;
+        }
+        let mut sorted = self.centroids.clone();
+        sorted.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let total: f64 = sorted.iter().map(|c| c.1 as f64).sum();
+
+        // (value, rank) knots: min, each centroid centre, max
+        let mut knots = vec![(self.min, 0.0)];
+        let mut cumulative = 0.0;
+        for c in &sorted {
+            knots.push((c.0, cumulative + c.1 as f64 / 2.0));
+            cumulative += c.1 as f64;
+        }
+        knots.push((self.max, total));
+        for pair in knots.windows(2) {
+            let ((xa, ra), (xb, rb)) = (pair[0], pair[1]);
+            if x < xb {
+                let t = if xb > xa { (x - xa) / (xb - xa) } else { 1.0 };
+                return interpolate(ra, rb, t) / total;
+            }
+        }
+        1.0
+    }
+
+    /// Estimate the median absolute deviation, the median of |x − median|.
+    /// Each centroid stands for its weight of values at its mean distance
+    /// from the median, so the deviations form a second digest to query.
+    pub fn mad(&self) -> f64 {
+        let median = self.quantile(0.5);
+        let centroids: Vec<Centroid> = self
+            .centroids
+            .iter()
+            .map(|c| Centroid((c.0 - median).abs(), c.1))
+            .collect();
+        let min = centroids.iter().map(|c| c.0).fold(f64::INFINITY, f64::min);
+        let deviations = TDigest {
+            min,
+            max: (self.min - median).abs().max((self.max - median).abs()),
+            centroids,
+        };
+        deviations.quantile(0.5)
+    }
+
+    pub fn to_json(&self) -> Value {
+        serde_json::to_value(self)
+            .unwrap_or_else(|e| pgrx::error!("jsonb_stats: digest serialization failed: {}", e))
+    }
+
+    pub fn from_json(v: &Value) -> Self {
+        let digest: TDigest = serde_json::from_value(v.clone())
+            .unwrap_or_else(|e| pgrx::error!("jsonb_stats: invalid digest: {}", e));
+        if digest.centroids.is_empty() {
+            pgrx::error!("jsonb_stats: invalid digest: no centroids");
+        }
+        digest
+    }
+}
+
+/// The exact value at quantile `q` (0..=1) of ascending `sorted` values,
+/// interpolating between neighbours like PostgreSQL's `percentile_cont`.
+pub fn exact_quantile(sorted: &[f64], q: f64) -> f64 {
+    let pos = q.clamp(0.0, 1.0) * (sorted.len() - 1) as f64;
+    let lo = pos.floor() as usize;
+    interpolate(sorted[lo], sorted[pos.ceil() as usize], pos - lo as f64)
+}
+
+/// The exact fraction of ascending `sorted` values below `x`, the inverse
+/// of `exact_quantile`.
+pub fn exact_cdf(sorted: &[f64], x: f64) -> f64 {
+    let n = sorted.len();
+    if x < sorted[0] {
+        return 0.0;
+    }
+    if x >= sorted[n - 1] {
+        return 1.0;
+    }
+    // The last value <= x; x is below the maximum, so a next one exists
+    let i = sorted.partition_point(|v| *v <= x) - 1;
+    let (a, b) = (sorted[i], sorted[i + 1]);
+    (i as f64 + (x - a) / (b - a)) / (n - 1) as f64
+}
+
+/// The exact median absolute deviation of ascending `sorted` values.
+pub fn exact_mad(sorted: &[f64]) -> f64 {
+    let median = exact_quantile(sorted, 0.5);
+    let mut deviations: Vec<f64> = sorted.iter().map(|v| (v - median).abs()).collect();
+    deviations.sort_by(f64::total_cmp);
+    exact_quantile(&deviations, 0.5)
+}
+
+fn interpolate(a: f64, b: f64, t: f64) -> f64 {
+    a + (b - a) * t.clamp(0.0, 1.0)
+}
@@ -1,21 +1,84 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
 use pgrx::prelude::*;
 use pgrx::{Internal, JsonB};
 use serde_json::{json, Map, Number, Value};
 
+use crate::digest::{exact_mad, exact_quantile};
+use crate::guc::{self, CvZeroMean, DateBucket};
 use crate::helpers::*;
-use crate::state::{AggEntry, NumFields, StatsState};
+use crate::hll::Hll;
+use crate::state::{has_log_means, raw_values_json, AggEntry, NumFields, StatsState};
+
+/// Output options for finalization, read once per finalfunc call so that
+/// every entry in a result is finalized consistently.
+pub(crate) struct FinalOptions {
+    /// Delta degrees of freedom: 1 for sample variance, 0 for population.
+    ddof: i64,
+    /// Decimal places for derived fields (`jsonb_stats.round_digits`).
+    digits: usize,
+    /// Maximum keys kept in categorical counts (`jsonb_stats.max_counts`), 0 = unlimited.
+    max_counts: usize,
+    /// CV is NULL below this absolute mean (`jsonb_stats.cv_min_abs_mean`).
+    cv_min_abs_mean: f64,
+    /// CV for a mean of exactly 0 (`jsonb_stats.cv_zero_mean`).
+    cv_zero_mean: CvZeroMean,
+    /// Report the moment-based fields (`jsonb_stats.compute_moments`).
+    moments: bool,
+    /// Granularity of date_agg counts (`jsonb_stats.date_bucket`).
+    date_bucket: DateBucket,
+    /// Count inet_agg addresses per network (`jsonb_stats.inet_rollup`).
+    inet_rollup: bool,
+}
+
+impl FinalOptions {
+    pub(crate) fn from_gucs(ddof: i64) -> Self {
+        FinalOptions {
+            ddof,
+            digits: guc::ROUND_DIGITS.get() as usize,
+            max_counts: guc::MAX_COUNTS.get() as usize,
+            cv_min_abs_mean: guc::CV_MIN_ABS_MEAN.get(),
+            cv_zero_mean: guc::CV_ZERO_MEAN.get(),
+            moments: guc::COMPUTE_MOMENTS.get(),
+            date_bucket: guc::DATE_BUCKET.get(),
+            inet_rollup: guc::INET_ROLLUP.get(),
+        }
+    }
+}
 
 /// Compute derived statistics (variance, stddev, cv_pct) for numeric agg summaries,
-/// add "type": "stats_agg" to the result, and round numeric fields to 2 decimal places.
+/// add "type": "stats_agg" to the result, and round numeric fields to
+/// `jsonb_stats.round_digits` decimal places (default 2).
 ///
 /// Spec: dev/reference_plpgsql.sql lines 145-176
-#[pg_extern(immutable, parallel_safe, strict)]
+#[pg_extern(stable, parallel_safe, strict)]
 pub fn jsonb_stats_final(state: JsonB) -> JsonB {
+    finalize_state(state, 1)
+}
+
+/// Like `jsonb_stats_final(state)`, but `population => true` divides
+/// `sum_sq_diff` by `count` (population variance) instead of `count - 1`.
+/// In population mode a single value has variance 0 rather than NULL.
+#[pg_extern(stable, parallel_safe, strict, name = "jsonb_stats_final")]
+pub fn jsonb_stats_final_population(state: JsonB, population: bool) -> JsonB {
+    finalize_state(state, if population { 0 } else { 1 })
+}
+
+/// Finalize a JSONB running state, using `count - ddof` as the variance divisor.
+fn finalize_state(state: JsonB, ddof: i64) -> JsonB {
+    check_object_input(&state.0);
     let state_map = match state.0 {
         Value::Object(m) => m,
         _ => return state,
     };
+    let opts = FinalOptions::from_gucs(ddof);
+    JsonB(Value::Object(finalize_map(state_map, &opts)))
+}
 
+/// Finalize every summary of a JSONB running state, recursing into nested
+/// stats_agg summaries.
+fn finalize_map(state_map: Map<String, Value>, opts: &FinalOptions) -> Map<String, Value> {
     let mut result = Map::new();
     result.insert("type".to_string(), json!("stats_agg"));
 
@@ -24,77 +87,104 @@ pub fn jsonb_stats_final(state: JsonB) -> JsonB {
             continue;
         }
 
-        let finalized = match summary {
+        let unit = unit_of(&summary).map(str::to_string);
+        let mut finalized = match summary {
             Value::Object(obj)
                 if matches!(
                     get_type(&obj),
-                    "int_agg" | "float_agg" | "dec2_agg" | "nat_agg"
+                    "int_agg" | "float_agg" | "dec2_agg" | "nat_agg" | "interval_agg"
                 ) =>
             {
-                finalize_num_agg(obj)
+                finalize_num_agg(obj, opts)
+            }
+            Value::Object(obj) if get_type(&obj) == "stats_agg" => Value::Object(finalize_map(obj, opts)),
+            Value::Object(mut obj)
+                if matches!(
+                    get_type(&obj),
+                    "str_agg"
+                        | "bool_agg"
+                        | "arr_agg"
+                        | "date_agg"
+                        | "ts_agg"
+                        | "time_agg"
+                        | "uuid_agg"
+                        | "inet_agg"
+                ) =>
+            {
+                let mut counts = match obj.remove("counts") {
+                    Some(Value::Object(c)) => c,
+                    _ => Map::new(),
+                };
+                if get_type(&obj) == "date_agg" && opts.date_bucket != DateBucket::Day {
+                    let bucketed = bucket_date_counts(
+                        counts.keys().map(|k| (k.as_str(), get_count(&counts, k))),
+                        opts.date_bucket,
+                    );
+                    counts = bucketed
+                        .into_iter()
+                        .map(|(k, v)| (k, Value::Number(Number::from(v))))
+                        .collect();
+                }
+                if get_type(&obj) == "inet_agg" && opts.inet_rollup {
+                    let rolled = rollup_inet_counts(
+                        counts.keys().map(|k| (k.as_str(), get_count(&counts, k))),
+                    );
+                    counts = rolled
+                        .into_iter()
+                        .map(|(k, v)| (k, Value::Number(Number::from(v))))
+                        .collect();
+                }
+                insert_count_stats(
+                    &mut obj,
+                    counts
+                        .iter()
+                        .map(|(k, v)| (k.as_str(), v.to_string().parse().unwrap_or(0))),
+                    opts.digits,
+                );
+                if get_type(&obj) == "inet_agg" {
+                    insert_inet_family_counts(&mut obj, counts.keys().map(|k| (k.as_str(), get_count(&counts, k))));
+                }
+                obj.insert(
+                    "counts".to_string(),
+                    Value::Object(prune_counts(counts, opts.max_counts)),
+                );
+                if let Some(h) = obj.get("hll").map(Hll::from_json) {
+                    insert_distinct_estimate(&mut obj, &h);
+                }
+                Value::Object(obj)
             }
             other => other,
         };
 
+        keep_unit(&mut finalized, unit);
         result.insert(key, finalized);
     }
 
-    JsonB(Value::Object(result))
+    result
 }
 
+/// Running-state fields of a numeric summary that finalization rebuilds or
+/// leaves out on purpose (moments under `compute_moments = off`, raw values
+/// no longer kept), so a stale copy must not pass through.
+const NUM_STATE_FIELDS: &[&str] = &["sum_sq_diff", "m3", "m4", "raw_values"];
+
 /// Add derived stats to a numeric agg summary and round numeric fields.
-/// Preserves the original type tag.
-fn finalize_num_agg(mut obj: Map<String, Value>) -> Value {
-    let count = get_f64(&obj, "count");
-    let mean = get_f64(&obj, "mean");
-    let ssd = get_f64(&obj, "sum_sq_diff");
-
-    // variance = sum_sq_diff / (count - 1), NULL if count <= 1
-    let (variance, stddev, cv_pct) = if count > 1.0 {
-        let var = ssd / (count - 1.0);
-        let sd = if var >= 0.0 { var.sqrt() } else { f64::NAN };
-        let cv = if mean != 0.0 {
-            (sd / mean) * 100.0
-        } else {
-            f64::NAN
-        };
-        (
-            if var.is_finite() {
-                round2(var)
-            } else {
-                Value::Null
-            },
-            if sd.is_finite() {
-                round2(sd)
-            } else {
-                Value::Null
-            },
-            if cv.is_finite() {
-                round2(cv)
-            } else {
-                Value::Null
-            },
-        )
-    } else {
-        (Value::Null, Value::Null, Value::Null)
+/// Preserves the original type tag and passes unrecognized fields through.
+fn finalize_num_agg(obj: Map<String, Value>, opts: &FinalOptions) -> Value {
+    let Value::Object(mut finalized) = finalize_num_entry(get_type(&obj), &NumFields::from_json(&obj), opts) else {
+        unreachable!("finalize_num_entry builds an object")
     };
-
-    // Round mean and sum_sq_diff
-    obj.insert("mean".to_string(), round2(mean));
-    obj.insert("sum_sq_diff".to_string(), round2(ssd));
-    obj.insert("variance".to_string(), variance);
-    obj.insert("stddev".to_string(), stddev);
-    obj.insert(
-        "coefficient_of_variation_pct".to_string(),
-        cv_pct,
-    );
-
-    Value::Object(obj)
+    for (key, value) in obj {
+        if !NUM_STATE_FIELDS.contains(&key.as_str()) {
+            finalized.entry(key).or_insert(value);
+        }
+    }
+    Value::Object(finalized)
 }
 
 // ── Internal-state finalfunc: converts StatsState → finalized JsonB ──
 
-#[pg_extern(immutable, parallel_safe)]
+#[pg_extern(stable, parallel_safe)]
 pub unsafe fn jsonb_stats_final_internal(internal: Internal) -> JsonB {
     let state_ptr: *mut StatsState = match internal.unwrap() {
         Some(datum) => datum.cast_mut_ptr::<StatsState>(),
@@ -110,37 +200,82 @@ pub unsafe fn jsonb_stats_final_internal(internal: Internal) -> JsonB {
     // jsonb_stats_combine's Box::from_raw on state2, or leaked until the
     // aggregate memory context is reset at end-of-query.
     let state = unsafe { &*state_ptr };
+    let opts = FinalOptions::from_gucs(1);
+    JsonB(Value::Object(finalize_entries(state, &opts)))
+}
 
+/// Finalize every entry of a native state, recursing into nested stats.
+pub(crate) fn finalize_entries(state: &StatsState, opts: &FinalOptions) -> Map<String, Value> {
     let mut result = Map::new();
     result.insert("type".to_string(), json!("stats_agg"));
+    insert_watermark(&mut result, state);
 
     for (key, entry) in &state.entries {
         let val = match entry {
             AggEntry::IntAgg(f)
             | AggEntry::FloatAgg(f)
             | AggEntry::Dec2Agg(f)
-            | AggEntry::NatAgg(f) => finalize_num_entry(entry.type_tag(), f),
-            AggEntry::StrAgg { counts } => {
+            | AggEntry::NatAgg(f)
+            | AggEntry::IntervalAgg(f) => finalize_num_entry(entry.type_tag(), f, opts),
+            AggEntry::NestedAgg(nested) => Value::Object(finalize_entries(nested, opts)),
+            AggEntry::StrAgg {
+                counts,
+                min,
+                max,
+                null_count,
+                hll,
+            } => {
                 let mut m = Map::new();
                 m.insert("type".to_string(), json!("str_agg"));
                 let mut c = Map::new();
                 for (k, v) in counts {
                     c.insert(k.clone(), Value::Number(Number::from(*v)));
                 }
-                m.insert("counts".to_string(), Value::Object(c));
+                m.insert("counts".to_string(), Value::Object(prune_counts(c, opts.max_counts)));
+                if let Some(min) = min {
+                    m.insert("min".to_string(), json!(min));
+                }
+                if let Some(max) = max {
+                    m.insert("max".to_string(), json!(max));
+                }
+                insert_count_stats(&mut m, counts.iter().map(|(k, v)| (k.as_str(), *v)), opts.digits);
+                insert_null_count(&mut m, *null_count);
+                if let Some(h) = hll {
+                    insert_distinct_estimate(&mut m, h);
+                    // Kept so finalized summaries stay mergeable at the next level
+                    m.insert("hll".to_string(), h.to_json());
+                }
                 Value::Object(m)
             }
-            AggEntry::BoolAgg { counts } => {
+            AggEntry::BoolAgg { counts, null_count }
+            | AggEntry::UuidAgg { counts, null_count }
+            | AggEntry::InetAgg { counts, null_count } => {
+                let rolled;
+                let counts = if entry.type_tag() == "inet_agg" && opts.inet_rollup {
+                    rolled = rollup_inet_counts(counts.iter().map(|(k, v)| (k.as_str(), *v)));
+                    &rolled
+                } else {
+                    counts
+                };
                 let mut m = Map::new();
-                m.insert("type".to_string(), json!("bool_agg"));
+                m.insert("type".to_string(), json!(entry.type_tag()));
                 let mut c = Map::new();
                 for (k, v) in counts {
                     c.insert(k.clone(), Value::Number(Number::from(*v)));
                 }
-                m.insert("counts".to_string(), Value::Object(c));
+                m.insert("counts".to_string(), Value::Object(prune_counts(c, opts.max_counts)));
+                insert_count_stats(&mut m, counts.iter().map(|(k, v)| (k.as_str(), *v)), opts.digits);
+                if entry.type_tag() == "inet_agg" {
+                    insert_inet_family_counts(&mut m, counts.iter().map(|(k, v)| (k.as_str(), *v)));
+                }
+                insert_null_count(&mut m, *null_count);
                 Value::Object(m)
             }
-            AggEntry::ArrAgg { count, counts } => {
+            AggEntry::ArrAgg {
+                count,
+                counts,
+                null_count,
+            } => {
                 let mut m = Map::new();
                 m.insert("type".to_string(), json!("arr_agg"));
                 m.insert("count".to_string(), Value::Number(Number::from(*count)));
@@ -148,66 +283,551 @@ pub unsafe fn jsonb_stats_final_internal(internal: Internal) -> JsonB {
                 for (k, v) in counts {
                     c.insert(k.clone(), Value::Number(Number::from(*v)));
                 }
-                m.insert("counts".to_string(), Value::Object(c));
+                m.insert("counts".to_string(), Value::Object(prune_counts(c, opts.max_counts)));
+                insert_count_stats(&mut m, counts.iter().map(|(k, v)| (k.as_str(), *v)), opts.digits);
+                insert_null_count(&mut m, *null_count);
                 Value::Object(m)
             }
             AggEntry::DateAgg {
                 counts,
                 min_date,
                 max_date,
+                null_count,
+            }
+            | AggEntry::TsAgg {
+                counts,
+                min_ts: min_date,
+                max_ts: max_date,
+                null_count,
+            }
+            | AggEntry::TimeAgg {
+                counts,
+                min_time: min_date,
+                max_time: max_date,
+                null_count,
             } => {
+                let bucketed;
+                let counts = if entry.type_tag() == "date_agg" && opts.date_bucket != DateBucket::Day {
+                    bucketed = bucket_date_counts(counts.iter().map(|(k, v)| (k.as_str(), *v)), opts.date_bucket);
+                    &bucketed
+                } else {
+                    counts
+                };
                 let mut m = Map::new();
-                m.insert("type".to_string(), json!("date_agg"));
+                m.insert("type".to_string(), json!(entry.type_tag()));
                 let mut c = Map::new();
                 for (k, v) in counts {
                     c.insert(k.clone(), Value::Number(Number::from(*v)));
                 }
-                m.insert("counts".to_string(), Value::Object(c));
+                m.insert("counts".to_string(), Value::Object(prune_counts(c, opts.max_counts)));
                 if let Some(min) = min_date {
                     m.insert("min".to_string(), json!(min));
                 }
                 if let Some(max) = max_date {
                     m.insert("max".to_string(), json!(max));
                 }
+                insert_count_stats(&mut m, counts.iter().map(|(k, v)| (k.as_str(), *v)), opts.digits);
+                insert_null_count(&mut m, *null_count);
+                Value::Object(m)
+            }
+        };
+        let mut val = match (val, state.ordered.get(key)) {
+            (Value::Object(mut m), Some(fl)) => {
+                m.insert("first".to_string(), fl.first.clone());
+                m.insert("last".to_string(), fl.last.clone());
                 Value::Object(m)
             }
+            (val, _) => val,
         };
+        keep_unit(&mut val, state.units.get(key).cloned());
         result.insert(key.clone(), val);
     }
 
-    JsonB(Value::Object(result))
+    result
+}
+
+/// Finalfunc for `jsonb_stats_raw_agg`: the running state as JSONB, with
+/// no derived statistics, ready to store and merge later.
+#[pg_extern(stable, parallel_safe)]
+pub unsafe fn jsonb_stats_raw_final_internal(internal: Internal) -> JsonB {
+    let state_ptr: *mut StatsState = match internal.unwrap() {
+        Some(datum) => datum.cast_mut_ptr::<StatsState>(),
+        None => return JsonB(json!({})),
+    };
+    // Borrowed, not owned: see jsonb_stats_final_internal
+    let state = unsafe { &*state_ptr };
+    let mut obj = state.entries_json();
+    insert_watermark(&mut obj, state);
+    JsonB(Value::Object(obj))
+}
+
+/// Add a state's "_watermark", when it has one.
+fn insert_watermark(obj: &mut Map<String, Value>, state: &StatsState) {
+    if let Some(w) = &state.watermark {
+        obj.insert(WATERMARK_KEY.to_string(), json!(w));
+    }
+}
+
+/// Add the sketch's "distinct_estimate", which unlike "distinct" still
+/// counts the keys pruned from `counts`.
+fn insert_distinct_estimate(obj: &mut Map<String, Value>, hll: &Hll) {
+    obj.insert(
+        "distinct_estimate".to_string(),
+        Value::Number(Number::from(hll.estimate().round() as i64)),
+    );
+}
+
+/// Add inet_agg "ipv4_count" and "ipv6_count": the counts of the keys of
+/// each address family. Keys that are not addresses, such as `"__other__"`,
+/// are in neither.
+fn insert_inet_family_counts<'a>(obj: &mut Map<String, Value>, counts: impl Iterator<Item = (&'a str, i64)>) {
+    let (mut v4, mut v6) = (0i64, 0i64);
+    for (addr, count) in counts {
+        match parse_inet(addr) {
+            Some((IpAddr::V4(_), _)) => v4 = add_counts(v4, count),
+            Some((IpAddr::V6(_), _)) => v6 = add_counts(v6, count),
+            None => {}
+        }
+    }
+    obj.insert("ipv4_count".to_string(), Value::Number(Number::from(v4)));
+    obj.insert("ipv6_count".to_string(), Value::Number(Number::from(v6)));
+}
+
+/// Roll date_agg counts up to `bucket`: each `YYYY-MM-DD` key becomes the
+/// first date of its ISO week, month or year. Keys that are not ISO dates,
+/// such as `"__other__"`, are kept as they are.
+fn bucket_date_counts<'a>(
+    counts: impl Iterator<Item = (&'a str, i64)>,
+    bucket: DateBucket,
+) -> HashMap<String, i64> {
+    let mut bucketed: HashMap<String, i64> = HashMap::new();
+    for (date, count) in counts {
+        let key = if is_iso_date(date) {
+            truncate_date(date, bucket)
+        } else {
+            date.to_string()
+        };
+        let c = bucketed.entry(key).or_insert(0);
+        *c = add_counts(*c, count);
+    }
+    bucketed
+}
+
+/// The first date of the bucket holding the valid ISO date `date`.
+fn truncate_date(date: &str, bucket: DateBucket) -> String {
+    let (y, m, d): (i64, i64, i64) = (
+        date[0..4].parse().unwrap_or(0),
+        date[5..7].parse().unwrap_or(1),
+        date[8..10].parse().unwrap_or(1),
+    );
+    match bucket {
+        DateBucket::Day => date.to_string(),
+        DateBucket::Month => format!("{:04}-{:02}-01", y, m),
+        DateBucket::Year => format!("{:04}-01-01", y),
+        DateBucket::Week => {
+            let days = days_from_civil(y, m, d);
+            // 1970-01-01 was a Thursday, three days after a Monday
+            let monday = days - (days + 3).rem_euclid(7);
+            let (y, m, d) = civil_from_days(monday);
+            format!("{:04}-{:02}-{:02}", y, m, d)
+        }
+    }
+}
+
+/// Roll inet_agg counts up to networks: each address becomes its /24
+/// (IPv4) or /48 (IPv6) network. Keys already naming a network at least
+/// that wide, and keys that are not addresses such as `"__other__"`, are
+/// kept as they are.
+fn rollup_inet_counts<'a>(counts: impl Iterator<Item = (&'a str, i64)>) -> HashMap<String, i64> {
+    let mut rolled: HashMap<String, i64> = HashMap::new();
+    for (addr, count) in counts {
+        let key = inet_network(addr).unwrap_or_else(|| addr.to_string());
+        let c = rolled.entry(key).or_insert(0);
+        *c = add_counts(*c, count);
+    }
+    rolled
+}
+
+/// The /24 or /48 network holding `addr`, or None if `addr` is not an
+/// address or its prefix is already that short.
+fn inet_network(addr: &str) -> Option<String> {
+    let (ip, prefix) = parse_inet(addr)?;
+    let (network, bits): (IpAddr, u8) = match ip {
+        IpAddr::V4(v4) => (Ipv4Addr::from(u32::from(v4) & 0xffff_ff00).into(), 24),
+        IpAddr::V6(v6) => (Ipv6Addr::from(u128::from(v6) & !(u128::MAX >> 48)).into(), 48),
+    };
+    if prefix.is_some_and(|p| p <= bits) {
+        return None;
+    }
+    Some(format!("{}/{}", network, bits))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (H. Hinnant's algorithm).
+fn days_from_civil(y: i64, m: i64, d: i64) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((m + 9) % 12) + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    (if m <= 2 { yoe + era * 400 + 1 } else { yoe + era * 400 }, m, d)
+}
+
+/// The most frequent key and its count. Ties go to the lexicographically
+/// smallest key (byte order) so the result does not depend on map order.
+fn mode_of<'a>(counts: impl Iterator<Item = (&'a str, i64)>) -> Option<(String, i64)> {
+    counts
+        .fold(None, |best, (k, v)| match best {
+            Some((bk, bv)) if bv > v || (bv == v && bk <= k) => Some((bk, bv)),
+            _ => Some((k, v)),
+        })
+        .map(|(k, v)| (k.to_string(), v))
+}
+
+/// Add the fields derived from a categorical summary's counts: "distinct"
+/// for every count-based type, "total_elements" and "mean_elements_per_row"
+/// for arr_agg,
+/// "mode"/"mode_count" for str/bool/date/ts/time/uuid/inet (omitted when counts
+/// are empty), "entropy_bits"/"normalized_entropy" for str/bool/arr/uuid/inet, and
+/// "gini"/"top_share" for every count-based type,
+/// "min_numeric"/"max_numeric" and the count-weighted
+/// "weighted_median"/"weighted_mean" for a str_agg whose keys are all numbers, and
+/// "total"/"true_ratio" for bool_agg, and "min_count"/"max_count" for
+/// str/date/ts/time when `counts` still holds the key of "min"/"max" (not
+/// once bucketed or pruned away).
+fn insert_count_stats<'a>(
+    obj: &mut Map<String, Value>,
+    counts: impl Iterator<Item = (&'a str, i64)> + Clone,
+    digits: usize,
+) {
+    let distinct = counts.clone().filter(|(k, _)| *k != OTHER_KEY);
+    obj.insert(
+        "distinct".to_string(),
+        Value::Number(Number::from(distinct.clone().count())),
+    );
+    if get_type(obj) == "arr_agg" {
+        let total: i64 = counts.map(|(_, v)| v).sum();
+        obj.insert("total_elements".to_string(), Value::Number(Number::from(total)));
+        let rows = get_count(obj, "count");
+        obj.insert(
+            "mean_elements_per_row".to_string(),
+            if rows > 0 { round_to(total as f64 / rows as f64, digits) } else { Value::Null },
+        );
+    } else if let Some((key, count)) = mode_of(distinct.clone()) {
+        obj.insert("mode".to_string(), json!(key));
+        obj.insert("mode_count".to_string(), Value::Number(Number::from(count)));
+    }
+    if matches!(get_type(obj), "str_agg" | "bool_agg" | "arr_agg" | "uuid_agg" | "inet_agg") {
+        let n = distinct.clone().count();
+        let entropy = entropy_bits(distinct.clone().map(|(_, v)| v));
+        obj.insert(
+            "entropy_bits".to_string(),
+            entropy.map_or(Value::Null, |e| round_to(e, digits)),
+        );
+        obj.insert(
+            "normalized_entropy".to_string(),
+            match entropy {
+                Some(e) if n > 1 => round_to(e / (n as f64).log2(), digits),
+                _ => Value::Null,
+            },
+        );
+    }
+    if get_type(obj) == "bool_agg" {
+        let count_of = |key: &str| distinct.clone().filter(|(k, _)| *k == key).map(|(_, v)| v).sum::<i64>();
+        let (trues, total) = (count_of("true"), count_of("true") + count_of("false"));
+        obj.insert("total".to_string(), Value::Number(Number::from(total)));
+        obj.insert(
+            "true_ratio".to_string(),
+            if total > 0 { round_to(trues as f64 / total as f64, digits) } else { Value::Null },
+        );
+    }
+    if matches!(get_type(obj), "str_agg" | "date_agg" | "ts_agg" | "time_agg") {
+        for (bound, field) in [("min", "min_count"), ("max", "max_count")] {
+            let ties = get_str(obj, bound).and_then(|b| distinct.clone().find(|(k, _)| *k == b)).map(|(_, c)| c);
+            if let Some(c) = ties {
+                obj.insert(field.to_string(), Value::Number(Number::from(c)));
+            }
+        }
+    }
+    if get_type(obj) == "str_agg" {
+        if let Some((min, max)) = numeric_key_range(distinct.clone().map(|(k, _)| k)) {
+            obj.insert("min_numeric".to_string(), num_value(min));
+            obj.insert("max_numeric".to_string(), num_value(max));
+            if let Some((median, mean)) = weighted_center(distinct.clone()) {
+                obj.insert("weighted_median".to_string(), num_value(median));
+                obj.insert("weighted_mean".to_string(), round_to(mean, digits));
+            }
+        }
+    }
+    let (gini, top_share) = match concentration(distinct.map(|(_, v)| v)) {
+        Some((g, t)) => (round_to(g, digits), round_to(t, digits)),
+        None => (Value::Null, Value::Null),
+    };
+    obj.insert("gini".to_string(), gini);
+    obj.insert("top_share".to_string(), top_share);
+}
+
+/// The median and mean of numeric keys weighted by their counts, as if each
+/// key were repeated `count` times: `{"1": 1, "2": 2, "3": 1}` has median 2.
+/// An even total takes the midpoint of the two middle values. None when
+/// nothing was counted; the caller has checked that every key is numeric.
+fn weighted_center<'a>(counts: impl Iterator<Item = (&'a str, i64)>) -> Option<(f64, f64)> {
+    let mut values: Vec<(f64, i64)> = counts
+        .filter_map(|(k, c)| Some((k.parse::<f64>().ok()?, c)))
+        .filter(|(_, c)| *c > 0)
+        .collect();
+    let total: i64 = values.iter().map(|(_, c)| c).sum();
+    if total == 0 {
+        return None;
+    }
+    values.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let at_rank = |rank: i64| {
+        let mut cum = 0;
+        values
+            .iter()
+            .find(|(_, c)| {
+                cum += c;
+                cum > rank
+            })
+            .map_or(0.0, |(v, _)| *v)
+    };
+    let median = (at_rank((total - 1) / 2) + at_rank(total / 2)) / 2.0;
+    let mean = values.iter().map(|(v, c)| v * *c as f64).sum::<f64>() / total as f64;
+    Some((median, mean))
+}
+
+/// The numeric min and max of the keys when every key is a plain decimal
+/// number (`02`, `-1.5`, `1e3`; not `inf` or `0x1f`), else None. Lets a
+/// str_agg of zip codes or similar report a range by value, not lexically.
+fn numeric_key_range<'a>(keys: impl Iterator<Item = &'a str>) -> Option<(f64, f64)> {
+    let mut range: Option<(f64, f64)> = None;
+    for key in keys {
+        if !key.bytes().all(|b| b.is_ascii_digit() || matches!(b, b'+' | b'-' | b'.' | b'e' | b'E')) {
+            return None;
+        }
+        let v = key.parse::<f64>().ok().filter(|v| v.is_finite())?;
+        range = Some(match range {
+            Some((lo, hi)) => (lo.min(v), hi.max(v)),
+            None => (v, v),
+        });
+    }
+    range
+}
+
+/// Gini impurity `1 - Σ p²` and the share of the most frequent key, or None
+/// when nothing was counted. Keys pruned into `OTHER_KEY` are not included.
+fn concentration(counts: impl Iterator<Item = i64> + Clone) -> Option<(f64, f64)> {
+    let total: i64 = counts.clone().sum();
+    if total <= 0 {
+        return None;
+    }
+    let share = |c: i64| c as f64 / total as f64;
+    let sum_sq: f64 = counts.clone().map(|c| share(c) * share(c)).sum();
+    let top = counts.max().unwrap_or(0);
+    Some((1.0 - sum_sq, share(top)))
+}
+
+/// Shannon entropy `-Σ p·log2(p)` of a count distribution, in bits; None
+/// when nothing was counted. Keys pruned into `OTHER_KEY` are not included.
+fn entropy_bits(counts: impl Iterator<Item = i64> + Clone) -> Option<f64> {
+    let total: i64 = counts.clone().sum();
+    if total <= 0 {
+        return None;
+    }
+    // p·log2(1/p) rather than -p·log2(p), so a single key gives 0 and not -0
+    Some(
+        counts
+            .filter(|&c| c > 0)
+            .map(|c| {
+                let p = c as f64 / total as f64;
+                p * (1.0 / p).log2()
+            })
+            .sum(),
+    )
+}
+
+/// Keep the `max` most frequent keys (ties by smallest key) and sum the rest
+/// into `OTHER_KEY`. `max == 0` keeps everything.
+fn prune_counts(counts: Map<String, Value>, max: usize) -> Map<String, Value> {
+    if max == 0 || counts.len() <= max {
+        return counts;
+    }
+    let mut other: i64 = 0;
+    let mut entries: Vec<(String, i64)> = Vec::with_capacity(counts.len());
+    for (k, v) in counts {
+        let n: i64 = v.to_string().parse().unwrap_or(0);
+        if k == OTHER_KEY {
+            other += n;
+        } else {
+            entries.push((k, n));
+        }
+    }
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    if entries.len() > max {
+        other += entries[max..].iter().map(|(_, n)| n).sum::<i64>();
+        entries.truncate(max);
+    }
+
+    let mut pruned: Map<String, Value> = entries
+        .into_iter()
+        .map(|(k, n)| (k, Value::Number(Number::from(n))))
+        .collect();
+    pruned.insert(OTHER_KEY.to_string(), Value::Number(Number::from(other)));
+    pruned
+}
+
+/// Sample skewness G1 (count >= 3) and sample excess kurtosis G2
+/// (count >= 4), the bias-adjusted estimators used by SAS, Excel and
+/// `scipy.stats` with `bias=False`. Both are undefined for constant values,
+/// and unknown when the moments were not maintained.
+fn shape_stats(f: &NumFields) -> (Option<f64>, Option<f64>) {
+    let n = f.count as f64;
+    let m2 = f.sum_sq_diff;
+    if !f.moments || m2 <= 0.0 {
+        return (None, None);
+    }
+    let skewness = (f.count >= 3).then(|| {
+        let g1 = n.sqrt() * f.m3 / m2.powf(1.5);
+        g1 * (n * (n - 1.0)).sqrt() / (n - 2.0)
+    });
+    let kurtosis = (f.count >= 4).then(|| {
+        let g2 = n * f.m4 / (m2 * m2) - 3.0;
+        ((n + 1.0) * g2 + 6.0) * (n - 1.0) / ((n - 2.0) * (n - 3.0))
+    });
+    (skewness, kurtosis)
 }
 
-fn finalize_num_entry(type_tag: &str, f: &NumFields) -> Value {
+/// Build the finalized numeric summary. Variance uses `count - opts.ddof` as
+/// divisor; variance, stddev and cv_pct are NULL unless `count > opts.ddof`.
+fn finalize_num_entry(type_tag: &str, f: &NumFields, opts: &FinalOptions) -> Value {
+    let ddof = opts.ddof;
+    let round = |v: f64| round_to(v, opts.digits);
+    // Fourth powers overflow f64 long before the values themselves do
+    let round_finite = |v: f64| if v.is_finite() { round(v) } else { Value::Null };
     let mut obj = Map::new();
     obj.insert("type".to_string(), json!(type_tag));
     obj.insert("count".to_string(), Value::Number(Number::from(f.count)));
-    obj.insert("sum".to_string(), num_value(f.sum));
-    obj.insert("min".to_string(), num_value(f.min));
-    obj.insert("max".to_string(), num_value(f.max));
-    obj.insert("mean".to_string(), round2(f.mean));
-    obj.insert("sum_sq_diff".to_string(), round2(f.sum_sq_diff));
-
-    if f.count > 1 {
-        let var = f.sum_sq_diff / (f.count as f64 - 1.0);
+    obj.insert("sum".to_string(), f.sum_value(type_tag));
+    if f.extremes {
+        f.insert_extremes(&mut obj);
+    }
+    if f.count > 0 {
+        if f.extremes {
+            obj.insert("range".to_string(), round_finite(f.max - f.min));
+        }
+        obj.insert("mean".to_string(), round(f.mean));
+        if f.extremes {
+            let relative = if f.mean != 0.0 { round_finite((f.max - f.min) / f.mean * 100.0) } else { Value::Null };
+            obj.insert("relative_range_pct".to_string(), relative);
+        }
+        if type_tag == "interval_agg" {
+            obj.insert("mean_interval".to_string(), json!(format_hms(f.mean)));
+        }
+        if opts.moments && f.moments {
+            // Unrounded, so finalized summaries still merge exactly; round_to
+            // still raises the overflow error for a non-finite value
+            let ssd = if f.sum_sq_diff.is_finite() {
+                num_value(f.sum_sq_diff)
+            } else {
+                round(f.sum_sq_diff)
+            };
+            obj.insert("sum_sq_diff".to_string(), ssd);
+            obj.insert("m3".to_string(), round_finite(f.m3));
+            obj.insert("m4".to_string(), round_finite(f.m4));
+        }
+    } else {
+        // Only nulls seen: no value to describe
+        let fields: &[&str] = if opts.moments {
+            &["range", "relative_range_pct", "mean", "sum_sq_diff", "m3", "m4"]
+        } else {
+            &["range", "relative_range_pct", "mean"]
+        };
+        let extreme_fields = ["range", "relative_range_pct"];
+        for field in fields.iter().filter(|field| f.extremes || !extreme_fields.contains(field)) {
+            obj.insert(field.to_string(), Value::Null);
+        }
+    }
+    insert_null_count(&mut obj, f.null_count);
+    if has_log_means(type_tag) {
+        f.insert_log_sums(&mut obj);
+        let (geo, harm) = match f.log_means() {
+            Some((g, h)) => (round(g), round(h)),
+            None => (Value::Null, Value::Null),
+        };
+        obj.insert("geometric_mean".to_string(), geo);
+        obj.insert("harmonic_mean".to_string(), harm);
+    }
+    // Exact from the raw values while they are kept, else from the digest
+    const QUANTILES: [(&str, f64); 4] = [("p25", 0.25), ("median", 0.5), ("p75", 0.75), ("p95", 0.95)];
+    let raw = f.sorted_raw_values().filter(|v| !v.is_empty());
+    let digest = f.digest.clone().map(|mut d| {
+        d.compress();
+        d
+    });
+    let quantiles = match (&raw, &digest) {
+        (Some(sorted), _) => Some((QUANTILES.map(|(_, q)| exact_quantile(sorted, q)), exact_mad(sorted))),
+        (None, Some(d)) => Some((QUANTILES.map(|(_, q)| d.quantile(q)), d.mad())),
+        (None, None) => None,
+    };
+    if let Some((values, mad)) = quantiles {
+        for ((name, _), v) in QUANTILES.iter().zip(values) {
+            obj.insert(name.to_string(), round(v));
+        }
+        obj.insert("iqr".to_string(), round(values[2] - values[0]));
+        let (p25, p75) = (values[0], values[2]);
+        let qcd = if p75 + p25 != 0.0 { round_finite((p75 - p25) / (p75 + p25)) } else { Value::Null };
+        obj.insert("quartile_coefficient_of_dispersion".to_string(), qcd);
+        obj.insert("mad".to_string(), round(mad));
+    }
+    // Kept so finalized summaries stay mergeable at the next level
+    if let Some(d) = &digest {
+        obj.insert("digest".to_string(), d.to_json());
+    }
+    if let Some(sorted) = &raw {
+        obj.insert("raw_values".to_string(), raw_values_json(sorted));
+    }
+    if let Some(h) = &f.hist {
+        obj.insert("histogram".to_string(), h.finalized_json());
+    }
+
+    if !opts.moments {
+        return Value::Object(obj);
+    }
+
+    if f.count > ddof && f.moments {
+        let var = f.sum_sq_diff / (f.count - ddof) as f64;
         let sd = if var >= 0.0 { var.sqrt() } else { f64::NAN };
-        let cv = if f.mean != 0.0 {
+        let cv = if f.mean != 0.0 && f.mean.abs() >= opts.cv_min_abs_mean {
             (sd / f.mean) * 100.0
+        } else if f.mean == 0.0 && opts.cv_zero_mean == CvZeroMean::Zero && sd.is_finite() {
+            0.0
         } else {
             f64::NAN
         };
 
         obj.insert(
             "variance".to_string(),
-            if var.is_finite() { round2(var) } else { Value::Null },
+            if var.is_finite() { round(var) } else { Value::Null },
         );
         obj.insert(
             "stddev".to_string(),
-            if sd.is_finite() { round2(sd) } else { Value::Null },
+            if sd.is_finite() { round(sd) } else { Value::Null },
         );
         obj.insert(
             "coefficient_of_variation_pct".to_string(),
-            if cv.is_finite() { round2(cv) } else { Value::Null },
+            if cv.is_finite() { round(cv) } else { Value::Null },
         );
     } else {
         obj.insert("variance".to_string(), Value::Null);
@@ -215,5 +835,22 @@ fn finalize_num_entry(type_tag: &str, f: &NumFields) -> Value {
         obj.insert("coefficient_of_variation_pct".to_string(), Value::Null);
     }
 
+    let (skewness, kurtosis) = shape_stats(f);
+    obj.insert("skewness".to_string(), skewness.map_or(Value::Null, round_finite));
+    obj.insert("excess_kurtosis".to_string(), kurtosis.map_or(Value::Null, round_finite));
+
     Value::Object(obj)
 }
+
+/// A duration in seconds as `[-]hh:mm:ss[.ffffff]`, to the microsecond.
+/// Hours are not folded into days, so the text casts back to `interval`.
+fn format_hms(secs: f64) -> String {
+    let micros = (secs.abs() * 1e6).round() as u64;
+    let (whole, frac) = (micros / 1_000_000, micros % 1_000_000);
+    let sign = if secs < 0.0 && micros > 0 { "-" } else { "" };
+    let mut s = format!("{}{:02}:{:02}:{:02}", sign, whole / 3600, whole / 60 % 60, whole % 60);
+    if frac > 0 {
+        s.push_str(format!(".{:06}", frac).trim_end_matches('0'));
+    }
+    s
+}
@@ -0,0 +1,329 @@
+use pgrx::guc::{GucContext, GucFlags, GucRegistry, GucSetting, PostgresGucEnum};
+
+/// `jsonb_stats.round_digits`: decimal places for derived numeric fields
+/// (mean, variance, stddev, cv_pct) in finalized output.
+pub static ROUND_DIGITS: GucSetting<i32> = GucSetting::<i32>::new(2);
+
+/// How the finalfunc writes rounded display fields.
+#[derive(PostgresGucEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum NumberFormat {
+    /// `round_digits` decimal places, whatever the magnitude.
+    #[name = c"fixed"]
+    Fixed,
+    /// `round_digits` significant decimals in scientific notation for
+    /// magnitudes outside [1e-4, 1e15], fixed otherwise.
+    #[name = c"auto"]
+    Auto,
+}
+
+/// `jsonb_stats.number_format`: `fixed` (default) or `auto`.
+pub static NUMBER_FORMAT: GucSetting<NumberFormat> = GucSetting::<NumberFormat>::new(NumberFormat::Fixed);
+
+/// `jsonb_stats.max_counts`: keep only this many most frequent keys in the
+/// `counts` of finalized categorical summaries (0 = unlimited).
+pub static MAX_COUNTS: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/// `jsonb_stats.percentiles`: keep a t-digest per numeric aggregate and
+/// report median, p25, p75 and p95 in finalized output.
+pub static PERCENTILES: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/// `jsonb_stats.exact_quantile_limit`: numeric aggregates tracked under
+/// `jsonb_stats.percentiles` also keep their raw values up to this count and
+/// report exact quantiles from them (0 = always use the digest).
+pub static EXACT_QUANTILE_LIMIT: GucSetting<i32> = GucSetting::<i32>::new(10000);
+
+/// `jsonb_stats.hll`: keep a HyperLogLog sketch per str aggregate and report
+/// `distinct_estimate` in finalized output.
+pub static HLL: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/// `jsonb_stats.hist_bins`: number of equal-width histogram bins kept per
+/// numeric aggregate (0 = no histogram).
+pub static HIST_BINS: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/// `jsonb_stats.cv_min_abs_mean`: report `coefficient_of_variation_pct` as
+/// NULL when |mean| is below this, instead of a misleadingly huge value.
+pub static CV_MIN_ABS_MEAN: GucSetting<f64> = GucSetting::<f64>::new(0.0);
+
+/// What the finalfunc reports as `coefficient_of_variation_pct` when the
+/// mean is exactly 0.
+#[derive(PostgresGucEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CvZeroMean {
+    /// NULL: the ratio is undefined.
+    #[name = c"null"]
+    Null,
+    /// 0, for tools that cannot handle a NULL CV.
+    #[name = c"zero"]
+    Zero,
+}
+
+/// `jsonb_stats.cv_zero_mean`: `null` (default) or `zero` CV for a zero mean.
+pub static CV_ZERO_MEAN: GucSetting<CvZeroMean> = GucSetting::<CvZeroMean>::new(CvZeroMean::Null);
+
+/// `jsonb_stats.compute_moments`: maintain `sum_sq_diff`, `m3` and `m4` per
+/// numeric value and report the moment-based fields (variance, stddev, cv,
+/// skewness, kurtosis). Off, numeric summaries keep only counts, sums,
+/// min/max and mean.
+pub static COMPUTE_MOMENTS: GucSetting<bool> = GucSetting::<bool>::new(true);
+
+/// `jsonb_stats.track_extremes`: maintain `min`/`max` per numeric value and
+/// report them with `range`. Off, numeric summaries started while it is off
+/// leave these fields out.
+pub static TRACK_EXTREMES: GucSetting<bool> = GucSetting::<bool>::new(true);
+
+/// `jsonb_stats.merge_prune_min`: after each `jsonb_stats_merge_agg` step,
+/// fold `str_agg`/`bool_agg`/`arr_agg` keys whose merged count is below this
+/// into `"__other__"` (0 = off). Never applied by parallel combine.
+pub static MERGE_PRUNE_MIN: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/// `jsonb_stats.numeric_merge_coerce`: merge int/float/dec2/nat summaries of
+/// one key into a float_agg instead of raising a type mismatch.
+pub static NUMERIC_MERGE_COERCE: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/// `jsonb_stats.strict_input`: raise on a non-object `stats`, state or
+/// `stats_agg` instead of passing it through unchanged.
+pub static STRICT_INPUT: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/// `jsonb_stats.max_keys`: most keys one `stats` object may have when
+/// accumulated (0 = unlimited), so a pathological row cannot exhaust memory.
+pub static MAX_KEYS: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/// `jsonb_stats.max_array_elements`: most elements an array `value` may have
+/// when accumulated (0 = unlimited).
+pub static MAX_ARRAY_ELEMENTS: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/// `jsonb_stats.max_serial_bytes`: largest aggregate state the serialfunc
+/// may send between parallel workers (0 = unlimited).
+pub static MAX_SERIAL_BYTES: GucSetting<i32> = GucSetting::<i32>::new(0);
+
+/// `jsonb_stats.inet_rollup`: report `inet_agg` counts per /24 (IPv4) or
+/// /48 (IPv6) network instead of per address.
+pub static INET_ROLLUP: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/// `jsonb_stats.infer_type`: accumulate a stat without a "type" as the kind
+/// of its "value" suggests, instead of skipping it.
+pub static INFER_TYPE: GucSetting<bool> = GucSetting::<bool>::new(false);
+
+/// Granularity the finalfunc rolls `date_agg` counts up to.
+#[derive(PostgresGucEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DateBucket {
+    /// One key per date, as accumulated.
+    #[name = c"day"]
+    Day,
+    /// The Monday starting the ISO week.
+    #[name = c"week"]
+    Week,
+    /// The first of the month.
+    #[name = c"month"]
+    Month,
+    /// January 1st.
+    #[name = c"year"]
+    Year,
+}
+
+/// `jsonb_stats.date_bucket`: `day` (default), `week`, `month` or `year`.
+pub static DATE_BUCKET: GucSetting<DateBucket> = GucSetting::<DateBucket>::new(DateBucket::Day);
+
+/// What accumulation does with a stat whose `type` it does not know.
+#[derive(PostgresGucEnum, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OnUnknownType {
+    /// Raise an error, aborting the statement.
+    #[name = c"error"]
+    Error,
+    /// Ignore that key and keep going.
+    #[name = c"skip"]
+    Skip,
+}
+
+/// `jsonb_stats.on_unknown_type`: `error` (default) or `skip` stats of an
+/// unknown type during accumulation, so one malformed row need not abort a batch.
+pub static ON_UNKNOWN_TYPE: GucSetting<OnUnknownType> = GucSetting::<OnUnknownType>::new(OnUnknownType::Error);
+
+/// Register all jsonb_stats GUCs. Called once from `_PG_init`.
+pub fn init() {
+    GucRegistry::define_int_guc(
+        c"jsonb_stats.round_digits",
+        c"Decimal places for derived statistics in finalized stats_agg output.",
+        c"Applies to mean, variance, stddev and coefficient_of_variation_pct; sum_sq_diff is never rounded.",
+        &ROUND_DIGITS,
+        0,
+        15,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_enum_guc(
+        c"jsonb_stats.number_format",
+        c"Format of rounded derived statistics: fixed or auto.",
+        c"auto writes magnitudes below 1e-4 or above 1e15 in scientific notation with round_digits decimals, so tiny means do not round to 0. Mergeable fields such as sum, min, max and sum_sq_diff always keep full precision.",
+        &NUMBER_FORMAT,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"jsonb_stats.max_counts",
+        c"Maximum number of keys kept in finalized str/bool/arr/date/ts counts.",
+        c"The most frequent keys are kept; the rest are summed into an \"__other__\" bucket. 0 means unlimited.",
+        &MAX_COUNTS,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"jsonb_stats.percentiles",
+        c"Track approximate quantiles for numeric aggregates.",
+        c"Numeric aggregates started while enabled carry a t-digest and report median, p25, p75 and p95. Merging with a summary that has no digest drops it.",
+        &PERCENTILES,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"jsonb_stats.exact_quantile_limit",
+        c"Largest count for which numeric aggregates report exact quantiles.",
+        c"With jsonb_stats.percentiles on, numeric aggregates keep their raw values until the count passes this limit and report exact median, p25, p75, p95 and mad from them; past it they fall back to the t-digest. 0 always uses the digest.",
+        &EXACT_QUANTILE_LIMIT,
+        0,
+        1_000_000,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"jsonb_stats.hll",
+        c"Estimate distinct values of str aggregates with a HyperLogLog sketch.",
+        c"str aggregates started while enabled carry a fixed-size sketch and report distinct_estimate, which stays accurate when counts are pruned. Merging with a summary that has no sketch drops it.",
+        &HLL,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"jsonb_stats.hist_bins",
+        c"Number of equal-width histogram bins for numeric aggregates (0 = off).",
+        c"The bin range is fixed from the first 100 values and widened as needed. Merging with a summary that has no histogram drops it.",
+        &HIST_BINS,
+        0,
+        1000,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_float_guc(
+        c"jsonb_stats.cv_min_abs_mean",
+        c"Smallest absolute mean for which coefficient_of_variation_pct is reported.",
+        c"Below it the CV is NULL: near a zero mean it grows without bound and says little. 0 reports it for any nonzero mean.",
+        &CV_MIN_ABS_MEAN,
+        0.0,
+        f64::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_enum_guc(
+        c"jsonb_stats.cv_zero_mean",
+        c"coefficient_of_variation_pct for a mean of exactly 0: null or zero.",
+        c"zero reports 0 instead of NULL whenever stddev is defined, for tools that cannot handle a NULL CV.",
+        &CV_ZERO_MEAN,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"jsonb_stats.compute_moments",
+        c"Compute variance, stddev, cv, skewness and kurtosis for numeric aggregates.",
+        c"Off skips the per-value moment updates and leaves these fields out of finalized output. Set it for both accumulation and finalization.",
+        &COMPUTE_MOMENTS,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"jsonb_stats.track_extremes",
+        c"Track min, max and range for numeric aggregates.",
+        c"Off skips the per-value min/max comparisons; summaries started while it is off leave min, max and range out, and merging with one that has them drops them.",
+        &TRACK_EXTREMES,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"jsonb_stats.merge_prune_min",
+        c"Minimum merged count a categorical key needs to keep its own entry (0 = off).",
+        c"Applied by jsonb_stats_merge_agg to str_agg, bool_agg and arr_agg count maps after each input is merged, never by parallel combine; rarer keys are summed into an \"__other__\" bucket.",
+        &MERGE_PRUNE_MIN,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"jsonb_stats.numeric_merge_coerce",
+        c"Merge differing numeric summaries (int, float, dec2, nat) of a key as float_agg.",
+        c"Off, such a merge raises a type mismatch. The merged summary keeps exact Welford moments but loses geometric and harmonic means that an int or nat side did not track. interval_agg never coerces.",
+        &NUMERIC_MERGE_COERCE,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"jsonb_stats.strict_input",
+        c"Raise on non-object input to jsonb_stats_accum, jsonb_stats_merge and jsonb_stats_final.",
+        c"Off, such input is passed through or ignored, which can hide a malformed pipeline.",
+        &STRICT_INPUT,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"jsonb_stats.max_keys",
+        c"Maximum number of keys in one stats object passed to accumulation (0 = unlimited).",
+        c"Accumulation raises on a larger stats object, including a nested one, instead of building a summary for every key.",
+        &MAX_KEYS,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"jsonb_stats.max_array_elements",
+        c"Maximum number of elements in an array stat value passed to accumulation (0 = unlimited).",
+        c"Applies to arr stats, in JSON or PostgreSQL array form, and to str stats with an array value.",
+        &MAX_ARRAY_ELEMENTS,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"jsonb_stats.max_serial_bytes",
+        c"Maximum size in bytes of an aggregate state serialized for parallel aggregation (0 = unlimited).",
+        c"Serialization stops and raises as soon as the state grows past it, instead of handing an oversized payload to the leader.",
+        &MAX_SERIAL_BYTES,
+        0,
+        i32::MAX,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_enum_guc(
+        c"jsonb_stats.date_bucket",
+        c"Granularity of date_agg counts in finalized output: day, week, month or year.",
+        c"Each date key is truncated to the start of its week (Monday), month or year and the counts summed; min and max keep the exact dates.",
+        &DATE_BUCKET,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"jsonb_stats.inet_rollup",
+        c"Roll inet_agg counts up to /24 (IPv4) and /48 (IPv6) networks in finalized output.",
+        c"Each address is replaced by its network and the counts summed; addresses already in a wider network keep their key.",
+        &INET_ROLLUP,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"jsonb_stats.infer_type",
+        c"Infer the type of a stat that has none from its value.",
+        c"A number is accumulated as float, a string as str, a boolean as bool and an array as arr. Off, stats without a type are skipped.",
+        &INFER_TYPE,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_enum_guc(
+        c"jsonb_stats.on_unknown_type",
+        c"What accumulation does with a stat of unknown type: error or skip.",
+        c"error raises, aborting the statement; skip ignores that key of the stats object and keeps the rest.",
+        &ON_UNKNOWN_TYPE,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+}
@@ -1,14 +1,112 @@
+use std::cmp::Ordering;
+use std::net::IpAddr;
+
 use serde_json::{Number, Value};
 
+use crate::guc;
+
 type Map = serde_json::Map<String, Value>;
 
+/// Every stat type the accumulators understand: those `stat()` builds, and
+/// `stats` for a nested object of stats.
+pub const STAT_TYPES: &[&str] = &[
+    "int", "float", "dec2", "nat", "str", "bool", "arr", "date", "ts", "time", "uuid", "inet",
+    "interval", "stats",
+];
+
+/// Counts key that collects the keys pruned by `jsonb_stats.max_counts`.
+pub const OTHER_KEY: &str = "__other__";
+
+/// Key under which a data field literally named "type" is kept: "type"
+/// itself is the tag of every stats, stats_agg and summary object.
+pub const TYPE_FIELD_KEY: &str = "__type__";
+
+/// Whether `key` is an object's type tag rather than a data field named
+/// "type": the tag is a string, while a field holds a stat or summary object.
+pub fn is_type_tag(key: &str, value: &Value) -> bool {
+    key == "type" && !value.is_object()
+}
+
+/// Top-level key of `jsonb_stats_agg_watermarked` output: the latest input
+/// timestamp, as UTC ISO text.
+pub const WATERMARK_KEY: &str = "_watermark";
+
+/// Whether `key` is a stats_agg's watermark rather than a summary: the
+/// watermark is a string, while a summary is an object.
+pub fn is_watermark(key: &str, value: &Value) -> bool {
+    key == WATERMARK_KEY && value.is_string()
+}
+
+/// Whether a top-level stats_agg entry is metadata (type tag or watermark)
+/// rather than a summary.
+pub fn is_agg_metadata(key: &str, value: &Value) -> bool {
+    is_type_tag(key, value) || is_watermark(key, value)
+}
+
+/// Chronological order of two ISO timestamps. Fractional seconds are
+/// compared as digits, so `10:30:00.5Z` is later than `10:30:00Z` although
+/// '.' sorts before 'Z'; a naive timestamp orders as if it were UTC.
+pub fn iso_ts_cmp(a: &str, b: &str) -> Ordering {
+    let parts = |s: &str| {
+        let s = s.trim_end_matches('Z');
+        let (whole, frac) = s.split_once('.').unwrap_or((s, ""));
+        (whole.to_string(), format!("{:0<9}", frac))
+    };
+    parts(a).cmp(&parts(b))
+}
+
+/// Whether UTC ISO timestamp `a` is later than `b`, by `iso_ts_cmp`.
+pub fn iso_ts_later(a: &str, b: &str) -> bool {
+    iso_ts_cmp(a, b).is_gt()
+}
+
+/// Order of two min/max candidates of a `<type>_agg`: ts_agg values by
+/// `iso_ts_cmp`, everything else bytewise (which is chronological for ISO
+/// dates and canonical times).
+pub fn range_cmp(agg_type: &str, a: &str, b: &str) -> Ordering {
+    if agg_type == "ts_agg" {
+        iso_ts_cmp(a, b)
+    } else {
+        a.cmp(b)
+    }
+}
+
+/// The key a data field is stored under: "type" becomes `TYPE_FIELD_KEY`.
+pub fn field_key(key: String) -> String {
+    if key == "type" {
+        TYPE_FIELD_KEY.to_string()
+    } else {
+        key
+    }
+}
+
 /// Extract an f64 from a JSON object by key.
 /// With `arbitrary_precision`, Number::as_f64() returns None,
-/// so we parse from the string representation.
+/// so we parse from the string representation. A quoted number is accepted.
 pub fn get_f64(obj: &Map, key: &str) -> f64 {
-    match obj.get(key) {
-        Some(Value::Number(n)) => n.to_string().parse::<f64>().unwrap_or(0.0),
-        _ => 0.0,
+    number_text(obj, key).and_then(|s| s.parse::<f64>().ok()).unwrap_or(0.0)
+}
+
+/// A number written as a JSON string, like the "150" in
+/// `{"type": "int", "value": "150"}` that some upstream systems emit.
+pub fn quoted_number(v: &Value) -> Option<Number> {
+    match serde_json::from_str::<Value>(v.as_str()?.trim()) {
+        Ok(Value::Number(n)) => Some(n),
+        _ => None,
+    }
+}
+
+/// The text of a number field, quoted or not; None when absent or of another
+/// JSON kind. A string that does not hold a number raises rather than
+/// silently reading as 0.
+fn number_text(obj: &Map, key: &str) -> Option<String> {
+    match obj.get(key)? {
+        Value::Number(n) => Some(n.to_string()),
+        v @ Value::String(s) => match quoted_number(v) {
+            Some(n) => Some(n.to_string()),
+            None => pgrx::error!("jsonb_stats: '{}' must be a number, got \"{}\"", key, s),
+        },
+        _ => None,
     }
 }
 
@@ -20,6 +118,102 @@ pub fn get_i64(obj: &Map, key: &str) -> i64 {
     }
 }
 
+/// Extract an f64 from an aggregate summary field. Unlike `get_f64`, a field
+/// that is present but not a number raises instead of reading as 0, so a
+/// corrupt state is not silently merged; absent or null reads as 0.
+pub fn get_checked_f64(obj: &Map, key: &str) -> f64 {
+    match obj.get(key) {
+        None | Some(Value::Null) => 0.0,
+        Some(Value::Number(n)) => n.to_string().parse().unwrap_or_else(|_| corrupt_field(key)),
+        Some(_) => corrupt_field(key),
+    }
+}
+
+/// Extract a count (a non-negative whole number) from an aggregate summary
+/// field, raising on anything else; absent or null reads as 0.
+pub fn get_count(obj: &Map, key: &str) -> i64 {
+    let n = match obj.get(key) {
+        None | Some(Value::Null) => return 0,
+        Some(Value::Number(n)) => n.to_string(),
+        Some(_) => corrupt_field(key),
+    };
+    let count = n.parse::<i64>().ok().or_else(|| {
+        // A whole number in float notation (e.g. 3.0) is still a count
+        let f = n.parse::<f64>().ok()?;
+        (f.fract() == 0.0 && f.abs() < i64::MAX as f64).then_some(f as i64)
+    });
+    match count {
+        Some(c) if c >= 0 => c,
+        _ => corrupt_field(key),
+    }
+}
+
+fn corrupt_field(key: &str) -> ! {
+    pgrx::error!("jsonb_stats: corrupt agg field '{}'", key)
+}
+
+/// Add two counts, raising instead of wrapping past the i64 range.
+pub fn add_counts(a: i64, b: i64) -> i64 {
+    a.checked_add(b)
+        .unwrap_or_else(|| pgrx::error!("jsonb_stats: count overflow: {} + {} exceeds the i64 range", a, b))
+}
+
+/// Add two counts of the count-map key `key`, raising instead of wrapping
+/// past the i64 range.
+pub fn add_key_counts(key: &str, a: i64, b: i64) -> i64 {
+    a.checked_add(b)
+        .unwrap_or_else(|| pgrx::error!("jsonb_stats: count overflow for key '{}'", key))
+}
+
+/// Extract an exact integer from a JSON object by key.
+/// Parses the number text directly so values beyond 2^53 keep full precision.
+/// A whole number written in float notation (e.g. 150.0) is accepted;
+/// a fractional value raises an error instead of being truncated.
+pub fn get_i128(obj: &Map, key: &str) -> i128 {
+    match number_text(obj, key) {
+        Some(s) => s.parse::<i128>().unwrap_or_else(|_| {
+            let f = s.parse::<f64>().unwrap_or(0.0);
+            if f.fract() != 0.0 {
+                pgrx::error!("jsonb_stats: '{}' must be an integer, got {}", key, s);
+            }
+            f as i128
+        }),
+        None => 0,
+    }
+}
+
+/// Extract a decimal as an exact count of hundredths (cents) for dec2.
+/// Parses the number text directly; digits beyond the second decimal are
+/// rounded half away from zero, matching PostgreSQL's round(numeric, 2).
+pub fn get_cents(obj: &Map, key: &str) -> i128 {
+    let s = match number_text(obj, key) {
+        Some(s) => s,
+        None => return 0,
+    };
+    if s.contains(['e', 'E']) {
+        // Exponent notation: no exact text to work from
+        return (s.parse::<f64>().unwrap_or(0.0) * 100.0).round() as i128;
+    }
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.as_str()),
+    };
+    let (int_part, frac_part) = digits.split_once('.').unwrap_or((digits, ""));
+    let mut frac = frac_part.bytes().map(|b| (b - b'0') as i128);
+    let units: i128 = int_part
+        .parse()
+        .unwrap_or_else(|_| pgrx::error!("jsonb_stats: invalid dec2 number {}", s));
+    let mut cents = units * 100 + frac.next().unwrap_or(0) * 10 + frac.next().unwrap_or(0);
+    if frac.next().unwrap_or(0) >= 5 {
+        cents += 1;
+    }
+    if negative {
+        -cents
+    } else {
+        cents
+    }
+}
+
 /// Get the "type" string from a JSON object.
 pub fn get_type(obj: &Map) -> &str {
     match obj.get("type") {
@@ -28,30 +222,260 @@ pub fn get_type(obj: &Map) -> &str {
     }
 }
 
+/// The JSON kind of a value, for error messages.
+pub fn json_kind(v: &Value) -> &'static str {
+    match v {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Under `jsonb_stats.strict_input`, raise unless `v` is a JSON object.
+pub fn check_object_input(v: &Value) {
+    if !v.is_object() && crate::guc::STRICT_INPUT.get() {
+        pgrx::error!("jsonb_stats: expected a JSON object, got {}", json_kind(v));
+    }
+}
+
 /// Create a JSON number from f64, using integer representation when the value is exact.
 /// This matches PostgreSQL's numeric behavior where 100.0 is stored as 100.
+/// Whole numbers past the i64 range keep all their digits; only non-finite
+/// values (the unset min/max of an empty summary) become null.
 pub fn num_value(v: f64) -> Value {
-    if v.fract() == 0.0 && v.abs() < (i64::MAX as f64) {
-        Value::Number(Number::from(v as i64))
-    } else {
-        Number::from_f64(v)
+    if !v.is_finite() {
+        return Value::Null;
+    }
+    if v.fract() == 0.0 && v.abs() < i128::MAX as f64 {
+        return int_value(v as i128);
+    }
+    Number::from_f64(v)
+        .map(Value::Number)
+        .unwrap_or_else(|| pgrx::error!("jsonb_stats: cannot represent {} as a JSON number", v))
+}
+
+/// Create a JSON number from an exact integer without going through f64.
+pub fn int_value(v: i128) -> Value {
+    match i64::try_from(v) {
+        Ok(i) => Value::Number(Number::from(i)),
+        Err(_) => v
+            .to_string()
+            .parse::<Number>()
             .map(Value::Number)
-            .unwrap_or(Value::Null)
+            .unwrap_or_else(|e| pgrx::error!("jsonb_stats: int_value failed for {}: {}", v, e)),
     }
 }
 
-/// Round f64 to 2 decimal places, preserving exact representation via arbitrary_precision.
-/// E.g. round2(100.0) produces the JSON number 100.00 (not 100 or 100.0).
-pub fn round2(v: f64) -> Value {
+/// Create a JSON number with exactly two decimals from a count of cents.
+pub fn cents_value(cents: i128) -> Value {
+    let sign = if cents < 0 { "-" } else { "" };
+    let abs = cents.unsigned_abs();
+    decimal_number(&format!("{}{}.{:02}", sign, abs / 100, abs % 100))
+}
+
+/// Set "null_count" on a summary, omitting it while no nulls were seen.
+pub fn insert_null_count(obj: &mut Map, null_count: i64) {
+    if null_count > 0 {
+        obj.insert("null_count".to_string(), Value::Number(Number::from(null_count)));
+    } else {
+        obj.remove("null_count");
+    }
+}
+
+/// Round f64 to `digits` decimal places, preserving exact representation via
+/// `decimal_number`. E.g. round_to(100.0, 2) produces the JSON number 100.00
+/// (not 100 or 100.0). Under `jsonb_stats.number_format = auto` a magnitude
+/// outside [1e-4, 1e15] keeps `digits` decimals of its mantissa instead
+/// (1e-9 → 1.00e-9), so it does not round to 0.
+pub fn round_to(v: f64, digits: usize) -> Value {
     if !v.is_finite() {
         pgrx::error!(
-            "jsonb_stats: non-finite value in round2 ({}). Input data likely caused numeric overflow.",
+            "jsonb_stats: non-finite value in round_to ({}). Input data likely caused numeric overflow.",
             v
         );
     }
-    // format!("{:.2}", v) always produces exactly 2 decimal places
-    serde_json::from_str(&format!("{:.2}", v))
-        .unwrap_or_else(|e| pgrx::error!("jsonb_stats: round2 failed for {}: {}", v, e))
+    let scientific = v != 0.0
+        && !(1e-4..=1e15).contains(&v.abs())
+        && guc::NUMBER_FORMAT.get() == guc::NumberFormat::Auto;
+    // Both forms write exactly `digits` decimal places, of the value or of its mantissa
+    let text = if scientific {
+        format!("{:.*e}", digits, v)
+    } else {
+        format!("{:.*}", digits, v)
+    };
+    decimal_number(&text)
+}
+
+/// A JSON number written exactly as `text`, trailing zeros included. Only
+/// serde_json's `arbitrary_precision` keeps them: without it `Number` holds
+/// an f64 and "100.00" would silently come out as 100.0. Cargo.toml always
+/// enables the feature and features only add up, so this raises only if
+/// the manifest itself is changed.
+pub fn decimal_number(text: &str) -> Value {
+    let n: Number = text
+        .parse()
+        .unwrap_or_else(|e| pgrx::error!("jsonb_stats: invalid decimal '{}': {}", text, e));
+    if n.to_string() != text {
+        pgrx::error!(
+            "jsonb_stats: decimal '{}' came out as '{}'; serde_json must be built with arbitrary_precision",
+            text,
+            n
+        );
+    }
+    Value::Number(n)
+}
+
+/// The unit a summary keeps when `incoming` meets its `existing` one: the
+/// first seen wins, and a different one only raises a WARNING, since the
+/// values still aggregate.
+pub fn merged_unit(key: &str, existing: Option<String>, incoming: Option<&str>) -> Option<String> {
+    match (existing, incoming) {
+        (Some(a), Some(b)) if a != b => {
+            pgrx::warning!("jsonb_stats: unit mismatch for key '{}': '{}' vs '{}', keeping '{}'", key, a, b, a);
+            Some(a)
+        }
+        (Some(a), _) => Some(a),
+        (None, b) => b.map(str::to_string),
+    }
+}
+
+/// The unit label of a stat or summary, if any.
+pub fn unit_of(v: &Value) -> Option<&str> {
+    v.get("unit").and_then(Value::as_str)
+}
+
+/// Carry a summary's unit over to its rebuilt form, which numeric
+/// summaries lose when parsed into `NumFields` and written back.
+pub fn keep_unit(summary: &mut Value, unit: Option<String>) {
+    if let (Value::Object(obj), Some(unit)) = (summary, unit) {
+        obj.insert("unit".to_string(), Value::String(unit));
+    }
+}
+
+/// Check that `s` is a valid calendar date in `YYYY-MM-DD` form.
+pub fn is_iso_date(s: &str) -> bool {
+    let b = s.as_bytes();
+    if b.len() != 10 || b[4] != b'-' || b[7] != b'-' {
+        return false;
+    }
+    let num = |r: std::ops::Range<usize>| -> Option<u32> {
+        b[r].iter().try_fold(0u32, |acc, c| {
+            c.is_ascii_digit().then(|| acc * 10 + (c - b'0') as u32)
+        })
+    };
+    let (Some(year), Some(month), Some(day)) = (num(0..4), num(5..7), num(8..10)) else {
+        return false;
+    };
+    let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    let days_in_month = match month {
+        1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+        4 | 6 | 9 | 11 => 30,
+        2 if leap => 29,
+        2 => 28,
+        _ => return false,
+    };
+    (1..=days_in_month).contains(&day)
+}
+
+/// The canonical `HH:MM:SS.ffffff` form of a time of day given as
+/// `HH:MM:SS` with up to six fractional digits, or None if `s` is not one.
+/// The fraction is always padded to six digits so that equal times share a
+/// count key and lexical order is chronological.
+pub fn iso_time(s: &str) -> Option<String> {
+    let (hms, frac) = s.split_once('.').unwrap_or((s, ""));
+    let b = hms.as_bytes();
+    if b.len() != 8 || b[2] != b':' || b[5] != b':' || frac.len() > 6 {
+        return None;
+    }
+    let num = |r: std::ops::Range<usize>| -> Option<u32> {
+        b[r].iter().try_fold(0u32, |acc, c| {
+            c.is_ascii_digit().then(|| acc * 10 + (c - b'0') as u32)
+        })
+    };
+    let (h, m, sec) = (num(0..2)?, num(3..5)?, num(6..8)?);
+    if !frac.bytes().all(|c| c.is_ascii_digit()) || m > 59 || sec > 59 {
+        return None;
+    }
+    let micros: u32 = format!("{:0<6}", frac).parse().ok()?;
+    // 24:00:00 is the one valid time past 23:59:59.999999, as in PostgreSQL
+    if h > 24 || (h == 24 && (m, sec, micros) != (0, 0, 0)) {
+        return None;
+    }
+    Some(format!("{}.{:06}", hms, micros))
+}
+
+/// Check that `s` is a UUID in the hyphenated 8-4-4-4-12 hex form.
+pub fn is_uuid(s: &str) -> bool {
+    let b = s.as_bytes();
+    b.len() == 36
+        && b.iter().enumerate().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => *c == b'-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Parse an `inet`/`cidr` text value, `address` or `address/prefix`, into
+/// the address and its prefix length (None when absent).
+pub fn parse_inet(s: &str) -> Option<(IpAddr, Option<u8>)> {
+    let (addr, prefix) = match s.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix.parse::<u8>().ok()?)),
+        None => (s, None),
+    };
+    let addr: IpAddr = addr.parse().ok()?;
+    let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+    if prefix.is_some_and(|p| p > max_prefix) {
+        return None;
+    }
+    Some((addr, prefix))
+}
+
+/// Split PostgreSQL array text (`{a,"b,c",NULL}`) into its elements.
+/// Double-quoted elements keep commas, braces and surrounding spaces, with
+/// backslash escapes resolved; `""` is a real empty string. Unquoted elements
+/// are trimmed, and empty ones (`{a,,b}`) and unquoted `NULL` are skipped, as
+/// JSON nulls are. Nested arrays are flattened.
+pub fn parse_array_literal(s: &str) -> Vec<String> {
+    let mut elems = Vec::new();
+    let mut chars = s.chars().peekable();
+    let mut cur = String::new();
+    let mut quoted = false;
+    // Push the pending element at a delimiter (',', '{', '}' or end of input)
+    let flush = |cur: &mut String, quoted: &mut bool, elems: &mut Vec<String>| {
+        let elem = if *quoted { cur.clone() } else { cur.trim().to_string() };
+        if *quoted || !(elem.is_empty() || elem.eq_ignore_ascii_case("NULL")) {
+            elems.push(elem);
+        }
+        cur.clear();
+        *quoted = false;
+    };
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                // Text before the quote on the same element is whitespace
+                cur.clear();
+                quoted = true;
+                while let Some(q) = chars.next() {
+                    match q {
+                        '\\' => cur.extend(chars.next()),
+                        '"' => break,
+                        _ => cur.push(q),
+                    }
+                }
+                // Anything after the closing quote up to the delimiter is ignored
+                while chars.peek().is_some_and(|&n| !matches!(n, ',' | '{' | '}')) {
+                    chars.next();
+                }
+            }
+            '\\' if !quoted => cur.extend(chars.next()),
+            ',' | '{' | '}' => flush(&mut cur, &mut quoted, &mut elems),
+            _ => cur.push(c),
+        }
+    }
+    flush(&mut cur, &mut quoted, &mut elems);
+    elems
 }
 
 /// Extract a string from a JSON object by key.
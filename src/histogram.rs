@@ -0,0 +1,255 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::helpers::*;
+
+/// Values buffered before the bin range is fixed.
+const WARMUP: usize = 100;
+
+/// Mergeable equal-width histogram for numeric aggregates.
+///
+/// The first `WARMUP` values are buffered as `[value, weight]` pairs; the bin
+/// range is then fixed to their min..max. A later value outside the range
+/// widens it (at least doubling the span) and re-bins the existing counts by
+/// bin midpoint, as does merging histograms with different ranges, so counts
+/// are exact only while the range stays put.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Histogram {
+    pub bins: usize,
+    pub pending: Vec<(f64, u64)>,
+    pub lo: f64,
+    pub hi: f64,
+    /// One count per bin; empty while warming up.
+    pub counts: Vec<u64>,
+}
+
+impl Histogram {
+    pub fn new(bins: usize, val: f64, weight: u64) -> Self {
+        Histogram {
+            bins,
+            pending: vec![(val, weight)],
+            lo: 0.0,
+            hi: 0.0,
+            counts: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, val: f64, weight: u64) {
+        if self.counts.is_empty() {
+            self.pending.push((val, weight));
+            if self.pending.len() >= WARMUP {
+                self.fix_range();
+            }
+            return;
+        }
+        if val < self.lo || val > self.hi {
+            self.widen(val);
+        }
+        let i = self.bin_of(val);
+        self.counts[i] += weight;
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        if other.counts.is_empty() {
+            for &(v, w) in &other.pending {
+                self.add(v, w);
+            }
+            return;
+        }
+        if self.counts.is_empty() {
+            let pending = std::mem::take(&mut self.pending);
+            *self = other.clone();
+            for (v, w) in pending {
+                self.add(v, w);
+            }
+            return;
+        }
+        if (self.lo, self.hi, self.bins) != (other.lo, other.hi, other.bins) {
+            self.rebin(self.lo.min(other.lo), self.hi.max(other.hi));
+        }
+        for (i, &c) in other.counts.iter().enumerate() {
+            if c > 0 {
+                let j = self.bin_of(other.midpoint(i));
+                self.counts[j] += c;
+            }
+        }
+    }
+
+    /// Fix the bin range to the buffered values. A constant sample gets
+    /// unit-width bins starting at the value.
+    fn fix_range(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        let lo = pending.iter().map(|p| p.0).fold(f64::INFINITY, f64::min);
+        let hi = pending.iter().map(|p| p.0).fold(f64::NEG_INFINITY, f64::max);
+        self.lo = lo;
+        self.hi = if hi > lo { hi } else { lo + self.bins as f64 };
+        self.counts = vec![0; self.bins];
+        for (v, w) in pending {
+            let i = self.bin_of(v);
+            self.counts[i] += w;
+        }
+    }
+
+    fn widen(&mut self, val: f64) {
+        let span = self.hi - self.lo;
+        if val < self.lo {
+            self.rebin(val.min(self.lo - span), self.hi);
+        } else {
+            self.rebin(self.lo, val.max(self.hi + span));
+        }
+    }
+
+    fn rebin(&mut self, lo: f64, hi: f64) {
+        let bins = self.bins;
+        let old = std::mem::replace(
+            self,
+            Histogram {
+                bins,
+                pending: Vec::new(),
+                lo,
+                hi,
+                counts: vec![0; bins],
+            },
+        );
+        for (i, &c) in old.counts.iter().enumerate() {
+            if c > 0 {
+                let j = self.bin_of(old.midpoint(i));
+                self.counts[j] += c;
+            }
+        }
+    }
+
+    fn width(&self) -> f64 {
+        (self.hi - self.lo) / self.bins as f64
+    }
+
+    fn midpoint(&self, i: usize) -> f64 {
+        self.lo + (i as f64 + 0.5) * self.width()
+    }
+
+    /// The bin holding `val`; `hi` itself falls in the last bin.
+    fn bin_of(&self, val: f64) -> usize {
+        let i = ((val - self.lo) / self.width()).floor();
+        (i.max(0.0) as usize).min(self.bins - 1)
+    }
+
+    /// Estimate the value at quantile `q` (0..=1), assuming values spread
+    /// evenly within each bin.
+    pub fn quantile(&self, q: f64) -> f64 {
+        let h = self.with_fixed_range();
+        let total: u64 = h.counts.iter().sum();
+        let target = q.clamp(0.0, 1.0) * total as f64;
+        let mut cumulative = 0.0;
+        for (i, &c) in h.counts.iter().enumerate() {
+            let c = c as f64;
+            if c > 0.0 && cumulative + c >= target {
+                let lo = h.lo + i as f64 * h.width();
+                let hi = if i + 1 == h.bins { h.hi } else { lo + h.width() };
+                return lo + (hi - lo) * ((target - cumulative) / c).clamp(0.0, 1.0);
+            }
+            cumulative += c;
+        }
+        h.hi
+    }
+
+    /// Estimate the fraction of values below `x`, spreading each bin's count
+    /// evenly over its width.
+    pub fn cdf(&self, x: f64) -> f64 {
+        let h = self.with_fixed_range();
+        let total: u64 = h.counts.iter().sum();
+        if total == 0 || x < h.lo {
+            return 0.0;
+        }
+        if x >= h.hi {
+            return 1.0;
+        }
+        let i = h.bin_of(x);
+        let below: u64 = h.counts[..i].iter().sum();
+        let lo = h.lo + i as f64 * h.width();
+        let within = if h.width() > 0.0 { (x - lo) / h.width() } else { 0.0 };
+        (below as f64 + h.counts[i] as f64 * within.clamp(0.0, 1.0)) / total as f64
+    }
+
+    /// This histogram, with the bin range fixed if it is still warming up.
+    fn with_fixed_range(&self) -> std::borrow::Cow<'_, Histogram> {
+        if self.counts.is_empty() {
+            let mut h = self.clone();
+            h.fix_range();
+            std::borrow::Cow::Owned(h)
+        } else {
+            std::borrow::Cow::Borrowed(self)
+        }
+    }
+
+    /// Running-state form: the `{lo, hi, count}` bins once the range is
+    /// fixed, or `{bins, pending}` while warming up.
+    pub fn to_json(&self) -> Value {
+        if self.counts.is_empty() {
+            json!({"bins": self.bins, "pending": self.pending})
+        } else {
+            self.bins_json()
+        }
+    }
+
+    /// Finalized form: always `{lo, hi, count}` bins.
+    pub fn finalized_json(&self) -> Value {
+        self.with_fixed_range().bins_json()
+    }
+
+    fn bins_json(&self) -> Value {
+        // Shared edges are computed once so adjacent bins agree exactly
+        let edge = |i: usize| {
+            if i == self.bins {
+                self.hi
+            } else {
+                self.lo + i as f64 * self.width()
+            }
+        };
+        Value::Array(
+            self.counts
+                .iter()
+                .enumerate()
+                .map(|(i, &c)| json!({"lo": num_value(edge(i)), "hi": num_value(edge(i + 1)), "count": c}))
+                .collect(),
+        )
+    }
+
+    pub fn from_json(v: &Value) -> Self {
+        match v {
+            Value::Array(arr) if !arr.is_empty() => {
+                let bins: Vec<&Map<String, Value>> = arr
+                    .iter()
+                    .map(|b| {
+                        b.as_object()
+                            .unwrap_or_else(|| pgrx::error!("jsonb_stats: invalid histogram bin: {}", b))
+                    })
+                    .collect();
+                Histogram {
+                    bins: bins.len(),
+                    pending: Vec::new(),
+                    lo: get_f64(bins[0], "lo"),
+                    hi: get_f64(bins[bins.len() - 1], "hi"),
+                    counts: bins.iter().map(|b| get_i64(b, "count") as u64).collect(),
+                }
+            }
+            Value::Object(m) => {
+                let bins = get_i64(m, "bins");
+                let pending: Vec<(f64, u64)> = m
+                    .get("pending")
+                    .and_then(|p| serde_json::from_value(p.clone()).ok())
+                    .unwrap_or_else(|| pgrx::error!("jsonb_stats: invalid histogram: missing 'pending'"));
+                if bins <= 0 || pending.is_empty() {
+                    pgrx::error!("jsonb_stats: invalid histogram: {}", v);
+                }
+                Histogram {
+                    bins: bins as usize,
+                    pending,
+                    lo: 0.0,
+                    hi: 0.0,
+                    counts: Vec::new(),
+                }
+            }
+            _ => pgrx::error!("jsonb_stats: invalid histogram: {}", v),
+        }
+    }
+}
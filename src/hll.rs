@@ -0,0 +1,117 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+
+/// Register index bits: 2^12 registers, a standard error of about
+/// 1.04/√4096 ≈ 1.6%.
+const PRECISION: u32 = 12;
+const REGISTERS: usize = 1 << PRECISION;
+
+/// HyperLogLog sketch (Flajolet et al.) for the number of distinct strings,
+/// in a fixed 4 KiB whatever the cardinality. Each register keeps the
+/// longest run of leading zeros seen among the hashes routed to it; merging
+/// takes the register-wise maximum, so sketches merge exactly.
+///
+/// Serialized as a hex string of its registers.
+#[derive(Clone)]
+pub struct Hll {
+    registers: Vec<u8>,
+}
+
+impl Hll {
+    pub fn new() -> Self {
+        Hll {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    pub fn add(&mut self, s: &str) {
+        let h = hash64(s);
+        let index = (h >> (64 - PRECISION)) as usize;
+        let rank = ((h << PRECISION).leading_zeros().min(64 - PRECISION) + 1) as u8;
+        if rank > self.registers[index] {
+            self.registers[index] = rank;
+        }
+    }
+
+    pub fn merge(&mut self, other: &Hll) {
+        for (a, b) in self.registers.iter_mut().zip(&other.registers) {
+            *a = (*a).max(*b);
+        }
+    }
+
+    /// Estimated number of distinct strings added, with linear counting
+    /// for small cardinalities where the raw estimate is biased.
+    pub fn estimate(&self) -> f64 {
+        let m = REGISTERS as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-(r as i32))).sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        if raw <= 2.5 * m && zeros > 0 {
+            m * (m / zeros as f64).ln()
+        } else {
+            raw
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        Value::String(self.to_hex())
+    }
+
+    pub fn from_json(v: &Value) -> Self {
+        match v.as_str().map(Hll::from_hex) {
+            Some(Ok(hll)) => hll,
+            Some(Err(e)) => pgrx::error!("jsonb_stats: invalid hll: {}", e),
+            None => pgrx::error!("jsonb_stats: invalid hll: expected a hex string, got {}", v),
+        }
+    }
+
+    fn to_hex(&self) -> String {
+        self.registers.iter().map(|r| format!("{:02x}", r)).collect()
+    }
+
+    fn from_hex(s: &str) -> Result<Self, String> {
+        if s.len() != REGISTERS * 2 || !s.is_ascii() {
+            return Err(format!("expected {} hex digits, got {}", REGISTERS * 2, s.len()));
+        }
+        let registers = (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<u8>, String>>()?;
+        Ok(Hll { registers })
+    }
+}
+
+impl Default for Hll {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serialize for Hll {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for Hll {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        Hll::from_hex(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// 64-bit FNV-1a with a SplitMix64 finalizer to spread the bits. Fixed,
+/// unlike std's hashers, so sketches from other sessions and workers agree.
+fn hash64(s: &str) -> u64 {
+    let mut h: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in s.bytes() {
+        h ^= b as u64;
+        h = h.wrapping_mul(0x0100_0000_01b3);
+    }
+    h ^= h >> 30;
+    h = h.wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    h ^= h >> 27;
+    h = h.wrapping_mul(0x94d0_49bb_1331_11eb);
+    h ^ (h >> 31)
+}
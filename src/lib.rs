@@ -2,20 +2,54 @@ use pgrx::prelude::*;
 
 pg_module_magic!();
 
+#[pg_guard]
+pub extern "C-unwind" fn _PG_init() {
+    guc::init();
+}
+
+mod accessor;
 mod accum;
+mod corr;
+mod digest;
 mod final_fn;
+mod guc;
 mod helpers;
+mod histogram;
+mod hll;
 mod merge;
 mod parallel;
+mod pct;
 mod stat;
 mod state;
+mod validate;
 
 // Re-export all pg_extern functions so pgrx can discover them
-pub use accum::{jsonb_stats_accum, jsonb_stats_accum_sfunc};
-pub use final_fn::{jsonb_stats_final, jsonb_stats_final_internal};
-pub use merge::{jsonb_stats_merge, jsonb_stats_merge_sfunc};
+pub use accessor::{
+    stats_agg_eq, stats_agg_unnest, stats_cdf, stats_diff, stats_explain, stats_flatten, stats_percentile,
+    stats_profile, stats_to_record, stats_top, stats_types, stats_value,
+};
+pub use accum::{
+    jsonb_stats_accum, jsonb_stats_accum_inverse, jsonb_stats_accum_sfunc, jsonb_stats_ordered_sfunc,
+    jsonb_stats_scalar_sfunc, jsonb_stats_waccum_sfunc, jsonb_stats_watermark_sfunc,
+};
+pub use corr::{
+    jsonb_stats_corr_combine, jsonb_stats_corr_deserial, jsonb_stats_corr_final, jsonb_stats_corr_serial,
+    jsonb_stats_corr_sfunc,
+};
+pub use final_fn::{
+    jsonb_stats_final, jsonb_stats_final_internal, jsonb_stats_final_population, jsonb_stats_raw_final_internal,
+};
+pub use merge::{
+    jsonb_stats_merge, jsonb_stats_merge_all, jsonb_stats_merge_sfunc, jsonb_stats_merge_source_sfunc,
+    jsonb_stats_unify_sfunc, stats_rename, stats_strip, stats_subtract,
+};
 pub use parallel::{jsonb_stats_combine, jsonb_stats_deserial, jsonb_stats_serial};
-pub use stat::{jsonb_stats_sfunc, stat, stats_from_jsonb};
+pub use pct::{
+    jsonb_stats_pct_combine, jsonb_stats_pct_deserial, jsonb_stats_pct_final, jsonb_stats_pct_serial,
+    jsonb_stats_pct_sfunc,
+};
+pub use stat::{jsonb_stats_sfunc, stat, stat_as, stat_with_unit, stats_build, stats_from_jsonb, stats_row};
+pub use validate::stats_validate;
 
 // Aggregate definitions using extension_sql!
 // These must come after all function definitions (enforced by `requires`).
@@ -33,6 +67,54 @@ CREATE AGGREGATE jsonb_stats_agg(jsonb) (
     parallel = safe
 );
 
+-- stats -> running state (no derived stats), for storing and merging later
+CREATE AGGREGATE jsonb_stats_raw_agg(jsonb) (
+    sfunc = jsonb_stats_accum_sfunc,
+    stype = internal,
+    finalfunc = jsonb_stats_raw_final_internal,
+    finalfunc_modify = read_write,
+    combinefunc = jsonb_stats_combine,
+    serialfunc = jsonb_stats_serial,
+    deserialfunc = jsonb_stats_deserial,
+    parallel = safe
+);
+
+-- (stats, weight) -> stats_agg: each stat counts as `weight` observations
+CREATE AGGREGATE jsonb_stats_wagg(jsonb, float8) (
+    sfunc = jsonb_stats_waccum_sfunc,
+    stype = internal,
+    finalfunc = jsonb_stats_final_internal,
+    finalfunc_modify = read_write,
+    combinefunc = jsonb_stats_combine,
+    serialfunc = jsonb_stats_serial,
+    deserialfunc = jsonb_stats_deserial,
+    parallel = safe
+);
+
+-- (order_key, stats) -> stats_agg with "first"/"last" per categorical key
+CREATE AGGREGATE jsonb_stats_ordered_agg(anyelement, jsonb) (
+    sfunc = jsonb_stats_ordered_sfunc,
+    stype = internal,
+    finalfunc = jsonb_stats_final_internal,
+    finalfunc_modify = read_write,
+    combinefunc = jsonb_stats_combine,
+    serialfunc = jsonb_stats_serial,
+    deserialfunc = jsonb_stats_deserial,
+    parallel = safe
+);
+
+-- (ts, stats) -> stats_agg with "_watermark", the latest ts seen
+CREATE AGGREGATE jsonb_stats_agg_watermarked(timestamptz, jsonb) (
+    sfunc = jsonb_stats_watermark_sfunc,
+    stype = internal,
+    finalfunc = jsonb_stats_final_internal,
+    finalfunc_modify = read_write,
+    combinefunc = jsonb_stats_combine,
+    serialfunc = jsonb_stats_serial,
+    deserialfunc = jsonb_stats_deserial,
+    parallel = safe
+);
+
 -- stats_agg -> stats_agg (parallel-safe with Internal state)
 CREATE AGGREGATE jsonb_stats_merge_agg(jsonb) (
     sfunc = jsonb_stats_merge_sfunc,
@@ -45,6 +127,66 @@ CREATE AGGREGATE jsonb_stats_merge_agg(jsonb) (
     parallel = safe
 );
 
+-- (stats_agg, source) -> stats_agg, raising when a source ID is merged twice
+CREATE AGGREGATE jsonb_stats_merge_agg(jsonb, text) (
+    sfunc = jsonb_stats_merge_source_sfunc,
+    stype = internal,
+    finalfunc = jsonb_stats_final_internal,
+    finalfunc_modify = read_write,
+    combinefunc = jsonb_stats_combine,
+    serialfunc = jsonb_stats_serial,
+    deserialfunc = jsonb_stats_deserial,
+    parallel = safe
+);
+
+-- stats and/or stats_agg -> stats_agg (raw stats accumulated, summaries merged)
+CREATE AGGREGATE jsonb_stats_unify_agg(jsonb) (
+    sfunc = jsonb_stats_unify_sfunc,
+    stype = internal,
+    finalfunc = jsonb_stats_final_internal,
+    finalfunc_modify = read_write,
+    combinefunc = jsonb_stats_combine,
+    serialfunc = jsonb_stats_serial,
+    deserialfunc = jsonb_stats_deserial,
+    parallel = safe
+);
+
+-- (code, scalar) -> stats_agg, without wrapping each value in stat()
+CREATE AGGREGATE jsonb_stats_scalar_agg(text, anyelement) (
+    sfunc = jsonb_stats_scalar_sfunc,
+    stype = internal,
+    finalfunc = jsonb_stats_final_internal,
+    finalfunc_modify = read_write,
+    combinefunc = jsonb_stats_combine,
+    serialfunc = jsonb_stats_serial,
+    deserialfunc = jsonb_stats_deserial,
+    parallel = safe
+);
+
+-- (x, y) -> {"correlation", "covariance", "n"} between two numeric columns
+CREATE AGGREGATE jsonb_stats_corr_agg(float8, float8) (
+    sfunc = jsonb_stats_corr_sfunc,
+    stype = internal,
+    finalfunc = jsonb_stats_corr_final,
+    finalfunc_modify = read_write,
+    combinefunc = jsonb_stats_corr_combine,
+    serialfunc = jsonb_stats_corr_serial,
+    deserialfunc = jsonb_stats_corr_deserial,
+    parallel = safe
+);
+
+-- value -> {"p50", "p90", "p95", "p99"} from a t-digest alone
+CREATE AGGREGATE jsonb_stats_pct_agg(float8) (
+    sfunc = jsonb_stats_pct_sfunc,
+    stype = internal,
+    finalfunc = jsonb_stats_pct_final,
+    finalfunc_modify = read_write,
+    combinefunc = jsonb_stats_pct_combine,
+    serialfunc = jsonb_stats_pct_serial,
+    deserialfunc = jsonb_stats_pct_deserial,
+    parallel = safe
+);
+
 -- (code, stat) -> stats (convenience aggregate)
 CREATE AGGREGATE jsonb_stats_agg(text, jsonb) (
     sfunc = jsonb_stats_sfunc,
@@ -56,25 +198,42 @@ CREATE AGGREGATE jsonb_stats_agg(text, jsonb) (
 CREATE FUNCTION stats(code text, val anyelement)
 RETURNS jsonb
 AS $$ SELECT stats(jsonb_build_object(code, stat(val))) $$
-LANGUAGE SQL IMMUTABLE STRICT PARALLEL SAFE;
+LANGUAGE SQL STABLE STRICT PARALLEL SAFE;
 
 -- Convert a single stats row to stats_agg (for merging with existing aggregates)
 CREATE FUNCTION jsonb_stats_to_agg(stats jsonb)
 RETURNS jsonb
 AS $$ SELECT jsonb_stats_final(jsonb_stats_accum('{}'::jsonb, stats)) $$
-LANGUAGE SQL IMMUTABLE STRICT PARALLEL SAFE;
+LANGUAGE SQL STABLE STRICT PARALLEL SAFE;
 "#,
     name = "aggregates",
     requires = [
         jsonb_stats_accum,
         jsonb_stats_accum_sfunc,
+        jsonb_stats_waccum_sfunc,
+        jsonb_stats_ordered_sfunc,
+        jsonb_stats_watermark_sfunc,
+        jsonb_stats_scalar_sfunc,
         jsonb_stats_merge,
         jsonb_stats_merge_sfunc,
+        jsonb_stats_merge_source_sfunc,
+        jsonb_stats_unify_sfunc,
         jsonb_stats_final,
         jsonb_stats_final_internal,
+        jsonb_stats_raw_final_internal,
         jsonb_stats_combine,
         jsonb_stats_serial,
         jsonb_stats_deserial,
+        jsonb_stats_corr_sfunc,
+        jsonb_stats_corr_combine,
+        jsonb_stats_corr_serial,
+        jsonb_stats_corr_deserial,
+        jsonb_stats_corr_final,
+        jsonb_stats_pct_sfunc,
+        jsonb_stats_pct_combine,
+        jsonb_stats_pct_serial,
+        jsonb_stats_pct_deserial,
+        jsonb_stats_pct_final,
         jsonb_stats_sfunc,
         stats_from_jsonb,
         stat
@@ -136,6 +295,62 @@ mod tests {
         assert_eq!(val["foo"]["type"], "int");
     }
 
+    #[pg_test(error = "jsonb_stats: stats() expects a JSON object of code->stat entries")]
+    fn test_stats_rejects_array() {
+        Spi::run("SELECT stats('[1, 2]'::jsonb)").unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: stats() entry 'x' must be a stat object with a \"type\", got 5")]
+    fn test_stats_rejects_non_stat_entry() {
+        Spi::run("SELECT stats('{\"x\": 5}'::jsonb)").unwrap();
+    }
+
+    #[pg_test]
+    fn test_stats_accepts_null_entry() {
+        let val = Spi::get_one::<pgrx::JsonB>("SELECT stats(jsonb_build_object('n', stat(NULL::int)))")
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(val, serde_json::json!({"type": "stats", "n": null}));
+    }
+
+    // ── stats_row() tests ──
+
+    #[pg_test]
+    fn test_stats_row_composite() {
+        Spi::run("CREATE TYPE company_row AS (num_employees int, industry text, is_profitable bool)").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT stats_row(ROW(150, 'tech', true)::company_row)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(
+            val,
+            serde_json::json!({
+                "type": "stats",
+                "num_employees": {"type": "int", "value": 150},
+                "industry": {"type": "str", "value": "tech"},
+                "is_profitable": {"type": "bool", "value": true}
+            })
+        );
+
+        // NULL columns are left out
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT stats_row(ROW(150, NULL, true)::company_row)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert!(val.get("industry").is_none());
+        assert_eq!(val["num_employees"]["value"], 150);
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_row expects a row of a composite type")]
+    fn test_stats_row_rejects_scalar() {
+        Spi::run("SELECT stats_row(42)").unwrap();
+    }
+
     // ── jsonb_stats_sfunc tests ──
 
     #[pg_test]
@@ -166,6 +381,143 @@ mod tests {
         assert_eq!(val["b"]["value"], 2);
     }
 
+    // ── jsonb_stats_scalar_agg tests ──
+
+    #[pg_test]
+    fn test_scalar_agg_matches_wrapped_stat() {
+        let (scalar, wrapped) = Spi::get_two::<pgrx::JsonB, pgrx::JsonB>(
+            "WITH t(v) AS (VALUES (3), (NULL), (7), (7))
+             SELECT (SELECT jsonb_stats_scalar_agg('n', v) FROM t),
+                    (SELECT jsonb_stats_agg(jsonb_build_object('n', stat(v))) FROM t)",
+        )
+        .unwrap();
+        let scalar = scalar.unwrap().0;
+        assert_eq!(scalar, wrapped.unwrap().0);
+        assert_eq!(scalar["n"]["type"], "int_agg");
+        assert_eq!(scalar["n"]["count"], 3);
+    }
+
+    #[pg_test]
+    fn test_scalar_agg_multiple_codes() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_scalar_agg(code, v)
+             FROM (VALUES ('a', 'x'::text), ('b', 'y'), ('a', 'y'), (NULL, 'z')) AS t(code, v)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["type"], "stats_agg");
+        assert_eq!(val["a"]["counts"], serde_json::json!({"x": 1, "y": 1}));
+        assert_eq!(val["b"]["counts"], serde_json::json!({"y": 1}));
+    }
+
+    // ── jsonb_stats_corr_agg tests ──
+
+    #[pg_test]
+    fn test_corr_agg_perfectly_correlated() {
+        let (val, covar) = Spi::get_two::<pgrx::JsonB, f64>(
+            "SELECT jsonb_stats_corr_agg(g, 2 * g + 1), covar_samp(g, 2 * g + 1)
+             FROM generate_series(1, 100) AS g",
+        )
+        .unwrap();
+        let val = val.unwrap().0;
+        assert_eq!(val["correlation"].to_string(), "1.00");
+        assert_eq!(val["covariance"].as_f64(), Some((covar.unwrap() * 100.0).round() / 100.0));
+        assert_eq!(val["n"], 100);
+    }
+
+    #[pg_test]
+    fn test_corr_agg_matches_corr_and_skips_nulls() {
+        let (val, r) = Spi::get_two::<pgrx::JsonB, f64>(
+            "WITH data(x, y) AS (VALUES (1.0, 3.0), (2.0, 1.0), (3.0, 4.0), (4.0, 8.0), (NULL, 5.0), (5.0, NULL))
+             SELECT jsonb_stats_corr_agg(x, y), corr(x, y) FROM data",
+        )
+        .unwrap();
+        let val = val.unwrap().0;
+        assert_eq!(val["n"], 4);
+        assert_eq!(val["correlation"].as_f64(), Some((r.unwrap() * 100.0).round() / 100.0));
+    }
+
+    #[pg_test]
+    fn test_corr_agg_undefined_below_two_pairs() {
+        let val = Spi::get_one::<pgrx::JsonB>("SELECT jsonb_stats_corr_agg(1.0, 2.0)")
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(val, serde_json::json!({"correlation": null, "covariance": null, "n": 1}));
+    }
+
+    #[pg_test]
+    fn test_corr_state_merge_matches_sequential() {
+        use crate::corr::CorrState;
+
+        let pairs: Vec<(f64, f64)> = (0..50)
+            .map(|i| (i as f64, ((i * 7) % 13) as f64 + 0.5 * i as f64))
+            .collect();
+        let mut sequential = CorrState::default();
+        let (mut left, mut right) = (CorrState::default(), CorrState::default());
+        for (i, &(x, y)) in pairs.iter().enumerate() {
+            sequential.update(x, y);
+            if i < 20 {
+                left.update(x, y);
+            } else {
+                right.update(x, y);
+            }
+        }
+        left.merge(&right);
+        assert_eq!(left.n, sequential.n);
+        for (a, b) in [
+            (left.c_xy, sequential.c_xy),
+            (left.m2_x, sequential.m2_x),
+            (left.m2_y, sequential.m2_y),
+            (left.mean_y, sequential.mean_y),
+        ] {
+            assert!((a - b).abs() < 1e-9 * b.abs().max(1.0), "{} vs {}", a, b);
+        }
+    }
+
+    // ── jsonb_stats_pct_agg tests ──
+
+    #[pg_test]
+    fn test_pct_agg_skewed_distribution() {
+        // 990 values in 1..=10, and a tail of 10 values from 1000 up
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_pct_agg(v) FROM (
+                 SELECT (g % 10 + 1)::float8 AS v FROM generate_series(1, 990) AS g
+                 UNION ALL SELECT 1000.0 * g FROM generate_series(1, 10) AS g) AS t",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let p50 = val["p50"].as_f64().unwrap();
+        let p99 = val["p99"].as_f64().unwrap();
+        assert!((1.0..=10.0).contains(&p50), "p50 = {}", p50);
+        assert!(p99 > 100.0 * p50, "p99 = {}, p50 = {}", p99, p50);
+        assert!(val["p90"].as_f64().unwrap() <= val["p95"].as_f64().unwrap());
+    }
+
+    #[pg_test]
+    fn test_pct_agg_empty_and_merge() {
+        let val = Spi::get_one::<pgrx::JsonB>("SELECT jsonb_stats_pct_agg(NULL::float8)")
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(val, serde_json::json!({"p50": null, "p90": null, "p95": null, "p99": null}));
+
+        use crate::pct::PctState;
+        let (mut left, mut right, mut sequential) = (PctState::default(), PctState::default(), PctState::default());
+        for i in 1..=200 {
+            sequential.update(i as f64);
+            if i % 2 == 0 {
+                left.update(i as f64);
+            } else {
+                right.update(i as f64);
+            }
+        }
+        left.merge(right);
+        assert_eq!(left.to_json(2), sequential.to_json(2));
+    }
+
     // ── jsonb_stats_accum tests ──
 
     #[pg_test]
@@ -260,6 +612,32 @@ mod tests {
         assert_eq!(ok, Ok(Some(true)));
     }
 
+    #[pg_test]
+    fn test_exact_int_sum_matches_plpgsql() {
+        load_plpgsql_reference();
+        let ok = Spi::get_one::<bool>(
+            "WITH data(s) AS (VALUES
+                ('{\"n\": {\"type\": \"int\", \"value\": 9007199254740993}}'::jsonb),
+                ('{\"n\": {\"type\": \"int\", \"value\": 1}}'::jsonb),
+                ('{\"n\": {\"type\": \"int\", \"value\": 1.0}}'::jsonb))
+            SELECT (SELECT jsonb_stats_agg(s) FROM data)->'n'->'sum'
+                 = (SELECT jsonb_stats_agg_plpgsql(s) FROM data)->'n'->'sum'
+               AND (SELECT jsonb_stats_agg(s) FROM data)->'n'->>'sum' = '9007199254740995'",
+        );
+        assert_eq!(ok, Ok(Some(true)));
+    }
+
+    #[pg_test]
+    fn test_final_keeps_unrecognized_num_fields() {
+        let val = crate::jsonb_stats_final(pgrx::JsonB(serde_json::json!({
+            "x": {"type": "int_agg", "count": 2, "sum": 3, "min": 1, "max": 2, "mean": 1.5,
+                  "sum_sq_diff": 0.5, "m3": 0, "m4": 0.125, "source": "legacy-import"}
+        })))
+        .0;
+        assert_eq!(val["x"]["source"], "legacy-import");
+        assert_eq!(val["x"]["variance"].to_string(), "0.50");
+    }
+
     // ── jsonb_stats_merge tests ──
 
     #[pg_test]
@@ -304,6 +682,38 @@ mod tests {
         assert_eq!(val["b"]["counts"]["y"], 1);
     }
 
+    #[pg_test]
+    fn test_merge_all_three_aggs() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_merge_all(
+                '{\"type\": \"stats_agg\", \"num\": {\"type\": \"int_agg\", \"count\": 2, \"sum\": 200, \"min\": 50, \"max\": 150, \"mean\": 100, \"sum_sq_diff\": 5000}}'::jsonb,
+                NULL,
+                '{\"type\": \"stats_agg\", \"num\": {\"type\": \"int_agg\", \"count\": 1, \"sum\": 2500, \"min\": 2500, \"max\": 2500, \"mean\": 2500, \"sum_sq_diff\": 0}}'::jsonb,
+                '{\"type\": \"stats_agg\", \"num\": {\"type\": \"int_agg\", \"count\": 1, \"sum\": 300, \"min\": 300, \"max\": 300, \"mean\": 300, \"sum_sq_diff\": 0}}'::jsonb
+            )",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["type"], "stats_agg");
+        assert_eq!(val["num"]["count"], 4);
+        assert_eq!(val["num"]["sum"], 3000);
+        assert_eq!(val["num"]["min"], 50);
+        assert_eq!(val["num"]["max"], 2500);
+        assert_eq!(val["num"]["mean"], 750);
+    }
+
+    #[pg_test]
+    fn test_merge_all_empty_array() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_merge_all(VARIADIC ARRAY[]::jsonb[])",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val, serde_json::json!({}));
+    }
+
     #[pg_test]
     fn test_merge_matches_plpgsql() {
         load_plpgsql_reference();
@@ -343,6 +753,40 @@ mod tests {
         assert_eq!(num["mean"].to_string(), "100.00");
     }
 
+    #[pg_test]
+    fn test_final_range() {
+        let val = crate::jsonb_stats_final(pgrx::JsonB(serde_json::json!({
+            "n": {"type": "int_agg", "count": 3, "sum": 60, "min": -15, "max": 45, "mean": 20, "sum_sq_diff": 1950},
+            "f": {"type": "float_agg", "count": 2, "sum": 0.4, "min": 0.1, "max": 0.3, "mean": 0.2, "sum_sq_diff": 0.02}
+        })))
+        .0;
+        assert_eq!(val["n"]["range"].to_string(), "60.00");
+        assert_eq!(val["f"]["range"].to_string(), "0.20");
+    }
+
+    #[pg_test]
+    fn test_final_relative_range_pct() {
+        let val = crate::jsonb_stats_final(pgrx::JsonB(serde_json::json!({
+            "n": {"type": "int_agg", "count": 3, "sum": 60, "min": -15, "max": 45, "mean": 20, "sum_sq_diff": 1950},
+            "z": {"type": "int_agg", "count": 2, "sum": 0, "min": -5, "max": 5, "mean": 0, "sum_sq_diff": 50}
+        })))
+        .0;
+        // (45 - -15) / 20 * 100
+        assert_eq!(val["n"]["relative_range_pct"].to_string(), "300.00");
+        assert!(val["z"]["relative_range_pct"].is_null());
+    }
+
+    #[pg_test]
+    fn test_final_quartile_coefficient_of_dispersion() {
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>("SELECT jsonb_stats_agg(stats('n', v)) FROM unnest(ARRAY[1, 2, 3, 4]) AS v")
+            .unwrap()
+            .unwrap()
+            .0;
+        // Exact quartiles 1.75 and 3.25: 1.5 / 5
+        assert_eq!(val["n"]["quartile_coefficient_of_dispersion"].to_string(), "0.30");
+    }
+
     #[pg_test]
     fn test_final_single_count_nulls() {
         let result = Spi::get_one::<pgrx::JsonB>(
@@ -369,6 +813,147 @@ mod tests {
         assert_eq!(ok, Ok(Some(true)));
     }
 
+    #[pg_test]
+    fn test_final_sample_vs_population() {
+        // values 2, 4, 4, 6: mean 4, sum_sq_diff 8
+        let state = "'{\"num\": {\"type\": \"int_agg\", \"count\": 4, \"sum\": 16, \"min\": 2, \"max\": 6, \"mean\": 4, \"sum_sq_diff\": 8}}'::jsonb";
+
+        let sample = Spi::get_one::<pgrx::JsonB>(&format!(
+            "SELECT jsonb_stats_final({state}, false)"
+        ))
+        .unwrap()
+        .unwrap()
+        .0;
+        // 8 / 3 = 2.666...
+        assert_eq!(sample["num"]["variance"].to_string(), "2.67");
+        assert_eq!(sample["num"]["stddev"].to_string(), "1.63");
+        assert_eq!(sample["num"]["coefficient_of_variation_pct"].to_string(), "40.82");
+
+        // One-argument form defaults to sample variance
+        let default = Spi::get_one::<bool>(&format!(
+            "SELECT jsonb_stats_final({state}) = jsonb_stats_final({state}, false)"
+        ));
+        assert_eq!(default, Ok(Some(true)));
+
+        let population = Spi::get_one::<pgrx::JsonB>(&format!(
+            "SELECT jsonb_stats_final({state}, true)"
+        ))
+        .unwrap()
+        .unwrap()
+        .0;
+        // 8 / 4 = 2
+        assert_eq!(population["num"]["variance"].to_string(), "2.00");
+        assert_eq!(population["num"]["stddev"].to_string(), "1.41");
+        assert_eq!(population["num"]["coefficient_of_variation_pct"].to_string(), "35.36");
+    }
+
+    #[pg_test]
+    fn test_final_population_single_count_zero() {
+        let result = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_final(
+                '{\"num\": {\"type\": \"int_agg\", \"count\": 1, \"sum\": 100, \"min\": 100, \"max\": 100, \"mean\": 100, \"sum_sq_diff\": 0}}'::jsonb,
+                true
+            )",
+        );
+        let val = result.unwrap().unwrap().0;
+        assert_eq!(val["num"]["variance"].to_string(), "0.00");
+        assert_eq!(val["num"]["stddev"].to_string(), "0.00");
+        assert_eq!(val["num"]["coefficient_of_variation_pct"].to_string(), "0.00");
+    }
+
+    #[pg_test]
+    fn test_final_round_digits_guc() {
+        Spi::run("SET LOCAL jsonb_stats.round_digits = 5").unwrap();
+        let state = "'{\"num\": {\"type\": \"int_agg\", \"count\": 4, \"sum\": 16, \"min\": 2, \"max\": 6, \"mean\": 4, \"sum_sq_diff\": 8}}'::jsonb";
+        let val = Spi::get_one::<pgrx::JsonB>(&format!("SELECT jsonb_stats_final({state})"))
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(val["num"]["mean"].to_string(), "4.00000");
+        // 8 / 3 = 2.666666...
+        assert_eq!(val["num"]["variance"].to_string(), "2.66667");
+        assert_eq!(val["num"]["stddev"].to_string(), "1.63299");
+
+        // Internal-state path honours the same setting
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(s) FROM (VALUES
+                ('{\"x\": {\"type\": \"int\", \"value\": 1}}'::jsonb),
+                ('{\"x\": {\"type\": \"int\", \"value\": 2}}'::jsonb)) AS t(s)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["x"]["mean"].to_string(), "1.50000");
+        assert_eq!(val["x"]["stddev"].to_string(), "0.70711");
+    }
+
+    // ── stats_add_value ──
+
+    #[pg_test]
+    fn test_stats_add_value_folds_ints() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT stats_add_value(stats_add_value(
+                 '{\"num\": {\"type\": \"int_agg\", \"count\": 2, \"sum\": 200, \"min\": 50, \"max\": 150, \"mean\": 100, \"sum_sq_diff\": 5000},
+                   \"ind\": {\"type\": \"str_agg\", \"counts\": {\"tech\": 2}}}'::jsonb,
+                 'num', 10), 'num', 2500)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let num = &val["num"];
+        assert_eq!(num["type"], "int_agg");
+        assert_eq!(num["count"], 4);
+        assert_eq!(num["sum"], 2710);
+        assert_eq!(num["min"], 10);
+        assert_eq!(num["max"], 2500);
+        assert_eq!(val["ind"]["counts"], serde_json::json!({"tech": 2}));
+    }
+
+    #[pg_test(error = "jsonb_stats: type mismatch for key 'num': int_agg vs str")]
+    fn test_stats_add_value_type_mismatch() {
+        Spi::run(
+            "SELECT stats_add_value('{\"num\": {\"type\": \"int_agg\", \"count\": 1, \"sum\": 1, \"min\": 1, \"max\": 1, \"mean\": 1, \"sum_sq_diff\": 0}}'::jsonb,
+                                    'num', 'x'::text)",
+        )
+        .unwrap();
+    }
+
+    // ── Data field named "type" ──
+
+    #[pg_test]
+    fn test_data_key_named_type_survives_pipeline() {
+        let (merged, jsonb) = Spi::get_two::<pgrx::JsonB, pgrx::JsonB>(
+            "WITH data(grp, kind, n) AS (VALUES (1, 'retail', 1), (1, 'wholesale', 2), (2, 'retail', 3)),
+                  by_grp AS (SELECT jsonb_stats_agg(stats(jsonb_build_object('type', stat(kind), 'n', stat(n)))) AS agg
+                             FROM data GROUP BY grp)
+             SELECT (SELECT jsonb_stats_merge_agg(agg) FROM by_grp),
+                    (SELECT jsonb_stats_final(jsonb_stats_accum(jsonb_stats_accum('{}',
+                         jsonb_build_object('type', stat('retail'::text))),
+                         jsonb_build_object('type', stat('wholesale'::text)))))",
+        )
+        .unwrap();
+        let (merged, jsonb) = (merged.unwrap().0, jsonb.unwrap().0);
+        assert_eq!(merged["type"], "stats_agg");
+        assert_eq!(merged["__type__"]["type"], "str_agg");
+        assert_eq!(merged["__type__"]["counts"], serde_json::json!({"retail": 2, "wholesale": 1}));
+        assert_eq!(merged["n"]["count"], 3);
+        assert_eq!(jsonb["type"], "stats_agg");
+        assert_eq!(jsonb["__type__"]["counts"], serde_json::json!({"retail": 1, "wholesale": 1}));
+    }
+
+    #[pg_test]
+    fn test_stats_row_column_named_type() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT stats_row(t) FROM (SELECT 'retail'::text AS type, 7 AS n) t",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["type"], "stats");
+        assert_eq!(val["__type__"], serde_json::json!({"type": "str", "value": "retail"}));
+        assert_eq!(val["n"], serde_json::json!({"type": "int", "value": 7}));
+    }
+
     // ── Full pipeline: jsonb_stats_agg (accum + final) ──
 
     #[pg_test]
@@ -407,26 +992,410 @@ mod tests {
         assert_eq!(ok, Ok(Some(true)));
     }
 
-    // ── End-to-end test matching sql/001 scenario ──
-
     #[pg_test]
-    fn test_end_to_end_three_companies() {
-        load_plpgsql_reference();
+    fn test_finalized_output_remerges_exactly() {
+        // Group a (1, 2, 4) has mean 2.33... and sum_sq_diff 4.66..., neither
+        // of which survives rounding; merging with b (10) must still give the
+        // same result as aggregating 1, 2, 4, 10 directly (sum_sq_diff 48.75)
+        let (merged, direct) = Spi::get_two::<pgrx::JsonB, pgrx::JsonB>(
+            "WITH data(grp, v) AS (VALUES ('a', 1), ('a', 2), ('a', 4), ('b', 10)),
+                  by_grp AS (SELECT jsonb_stats_agg(jsonb_build_object('n', stat(v))) AS agg
+                             FROM data GROUP BY grp)
+             SELECT (SELECT jsonb_stats_merge_agg(agg) FROM by_grp),
+                    (SELECT jsonb_stats_agg(jsonb_build_object('n', stat(v))) FROM data)",
+        )
+        .unwrap();
+        let (merged, direct) = (merged.unwrap().0, direct.unwrap().0);
+        let ssd = merged["n"]["sum_sq_diff"].as_f64().unwrap();
+        assert!((ssd - 48.75).abs() < 1e-9, "sum_sq_diff {}", ssd);
+        for field in ["count", "sum", "mean", "variance", "stddev"] {
+            assert_eq!(merged["n"][field], direct["n"][field], "field {}", field);
+        }
+    }
 
-        // Build stats for 3 companies, aggregate by region, then merge globally
+    #[pg_test(error = "jsonb_stats: corrupt nat_agg (negative value)")]
+    fn test_merge_rejects_negative_nat_agg() {
+        Spi::run(
+            r#"SELECT jsonb_stats_merge_agg(agg) FROM (VALUES
+                 ('{"type": "stats_agg", "n": {"type": "nat_agg", "count": 2, "sum": 3, "min": -1, "max": 4,
+                   "mean": 1.5, "sum_sq_diff": 12.5}}'::jsonb)) AS t(agg)"#,
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: corrupt nat_agg (negative value)")]
+    fn test_jsonb_merge_rejects_negative_nat_agg() {
+        crate::jsonb_stats_merge(
+            pgrx::JsonB(serde_json::json!({"n": {"type": "nat_agg", "count": 1, "sum": 2, "min": 2, "max": 2,
+                "mean": 2, "sum_sq_diff": 0}})),
+            pgrx::JsonB(serde_json::json!({"n": {"type": "nat_agg", "count": 2, "sum": 3, "min": -1, "max": 4,
+                "mean": 1.5, "sum_sq_diff": 12.5}})),
+        );
+    }
+
+    // ── Merge provenance ──
+
+    #[pg_test]
+    fn test_merge_agg_with_sources_matches_plain_merge() {
         let ok = Spi::get_one::<bool>(
-            "WITH
-            -- Company stats as JSONB
-            company_stats(region, stats) AS (
-                VALUES
-                    ('EU', '{\"num_employees\": {\"type\": \"int\", \"value\": 150}, \"industry\": {\"type\": \"str\", \"value\": \"tech\"}, \"is_profitable\": {\"type\": \"bool\", \"value\": true}}'::jsonb),
-                    ('US', '{\"num_employees\": {\"type\": \"int\", \"value\": 2500}, \"industry\": {\"type\": \"str\", \"value\": \"finance\"}, \"is_profitable\": {\"type\": \"bool\", \"value\": true}}'::jsonb),
-                    ('EU', '{\"num_employees\": {\"type\": \"int\", \"value\": 50}, \"industry\": {\"type\": \"str\", \"value\": \"tech\"}, \"is_profitable\": {\"type\": \"bool\", \"value\": false}}'::jsonb)
-            ),
-            -- Level 2: aggregate by region
-            by_region_rust AS (
-                SELECT region, jsonb_stats_agg(stats) AS agg FROM company_stats GROUP BY region
-            ),
+            "WITH groups AS (
+                 SELECT 'region-' || g AS source, jsonb_stats_agg(jsonb_build_object('n', stat(v))) AS agg
+                 FROM generate_series(1, 30) AS v, LATERAL (SELECT v % 3 AS g) AS x
+                 GROUP BY g)
+             SELECT jsonb_stats_merge_agg(agg, source) = jsonb_stats_merge_agg(agg) FROM groups",
+        );
+        assert_eq!(ok, Ok(Some(true)));
+    }
+
+    #[pg_test(error = "jsonb_stats: duplicate source 'region-1' in merge")]
+    fn test_merge_agg_rejects_duplicate_source() {
+        Spi::run(
+            "WITH groups AS (
+                 SELECT 'region-' || g AS source, jsonb_stats_agg(jsonb_build_object('n', stat(v))) AS agg
+                 FROM generate_series(1, 30) AS v, LATERAL (SELECT v % 3 AS g) AS x
+                 GROUP BY g)
+             SELECT jsonb_stats_merge_agg(agg, source)
+             FROM (SELECT * FROM groups UNION ALL SELECT * FROM groups WHERE source = 'region-1') AS twice",
+        )
+        .unwrap();
+    }
+
+    #[pg_test]
+    fn test_merge_agg_null_source_is_unchecked() {
+        let count = Spi::get_one::<i64>(
+            "SELECT (jsonb_stats_merge_agg(agg, NULL::text) -> 'n' ->> 'count')::int8
+             FROM (VALUES (jsonb_stats_to_agg(stats('n', 1))), (jsonb_stats_to_agg(stats('n', 1)))) AS t(agg)",
+        );
+        assert_eq!(count, Ok(Some(2)));
+    }
+
+    // ── Merge order independence ──
+
+    /// Four per-group stats_aggs over the same keys, with "odd" present only
+    /// in groups 1 and 3, ordered by group.
+    fn merge_order_parts() -> Vec<serde_json::Value> {
+        let parts = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_agg(agg ORDER BY g) FROM (
+                 SELECT i % 4 AS g, jsonb_stats_agg(
+                     jsonb_build_object(
+                         'n', stat(i * 7 % 13),
+                         'x', stat(i * 0.37::float8),
+                         'c', stat('v' || i % 5),
+                         'b', stat(i % 3 = 0),
+                         'day', stat(date '2024-01-01' + i),
+                         'a', jsonb_build_object('type', 'arr', 'value', to_jsonb(ARRAY['t' || i % 3])))
+                     || CASE WHEN i % 2 = 1 THEN jsonb_build_object('odd', stat(i)) ELSE '{}' END) AS agg
+                 FROM generate_series(1, 40) AS i GROUP BY i % 4) s",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        parts.as_array().unwrap().clone()
+    }
+
+    fn merge_json(a: &serde_json::Value, b: &serde_json::Value) -> serde_json::Value {
+        Spi::get_one::<pgrx::JsonB>(&format!(
+            "SELECT jsonb_stats_merge('{}'::jsonb, '{}'::jsonb)",
+            a.to_string().replace('\'', "''"),
+            b.to_string().replace('\'', "''")
+        ))
+        .unwrap()
+        .unwrap()
+        .0
+    }
+
+    /// Finalize, rounding the unrounded float sum and sum_sq_diff so that
+    /// summation order cannot show through in the last bits.
+    fn final_rounded(agg: &serde_json::Value) -> serde_json::Value {
+        let mut val = Spi::get_one::<pgrx::JsonB>(&format!(
+            "SELECT jsonb_stats_final('{}'::jsonb)",
+            agg.to_string().replace('\'', "''")
+        ))
+        .unwrap()
+        .unwrap()
+        .0;
+        round_sums(&mut val);
+        val
+    }
+
+    fn round_sums(agg: &mut serde_json::Value) {
+        for summary in agg.as_object_mut().unwrap().values_mut() {
+            for field in ["sum", "sum_sq_diff"] {
+                if let Some(v) = summary.get(field).and_then(|v| v.as_f64()) {
+                    summary[field] = serde_json::json!(format!("{:.6}", v));
+                }
+            }
+        }
+    }
+
+    #[pg_test]
+    fn test_merge_order_independent_left_folds() {
+        let parts = merge_order_parts();
+        let mut expected: Option<serde_json::Value> = None;
+        // Every permutation of the four parts, folded left to right
+        for p in 0..24usize {
+            let mut pool: Vec<usize> = (0..4).collect();
+            let mut order = Vec::new();
+            let mut k = p;
+            for n in (1..=4).rev() {
+                order.push(pool.remove(k % n));
+                k /= n;
+            }
+            let mut acc = parts[order[0]].clone();
+            for &i in &order[1..] {
+                acc = merge_json(&acc, &parts[i]);
+            }
+            let got = final_rounded(&acc);
+            match &expected {
+                None => expected = Some(got),
+                Some(e) => assert_eq!(&got, e, "order {:?}", order),
+            }
+        }
+    }
+
+    #[pg_test]
+    fn test_merge_order_independent_groupings() {
+        let parts = merge_order_parts();
+        let left = merge_json(&merge_json(&merge_json(&parts[0], &parts[1]), &parts[2]), &parts[3]);
+        let right = merge_json(&parts[0], &merge_json(&parts[1], &merge_json(&parts[2], &parts[3])));
+        let tree = merge_json(&merge_json(&parts[3], &parts[1]), &merge_json(&parts[2], &parts[0]));
+        let expected = final_rounded(&left);
+        assert_eq!(final_rounded(&right), expected);
+        assert_eq!(final_rounded(&tree), expected);
+
+        // The aggregate and the variadic form agree with the pairwise folds
+        let parts_sql = serde_json::Value::Array(parts).to_string().replace('\'', "''");
+        let (asc, desc, all) = Spi::get_three::<pgrx::JsonB, pgrx::JsonB, pgrx::JsonB>(&format!(
+            "WITH p AS (SELECT agg, ord FROM jsonb_array_elements('{parts_sql}'::jsonb) WITH ORDINALITY AS e(agg, ord))
+             SELECT (SELECT jsonb_stats_merge_agg(agg ORDER BY ord) FROM p),
+                    (SELECT jsonb_stats_merge_agg(agg ORDER BY ord DESC) FROM p),
+                    (SELECT jsonb_stats_merge_all(VARIADIC array_agg(agg ORDER BY ord DESC)) FROM p)"
+        ))
+        .unwrap();
+        assert_eq!(final_rounded(&asc.unwrap().0), expected);
+        assert_eq!(final_rounded(&desc.unwrap().0), expected);
+        assert_eq!(final_rounded(&all.unwrap().0), expected);
+    }
+
+    #[pg_test]
+    fn test_merge_commutative_with_stale_derived_fields() {
+        // Finalized count summaries carry derived fields; whichever side is
+        // `a`, none of them may survive into the merged state
+        let a = serde_json::json!({"type": "stats_agg", "c": {"type": "str_agg", "counts": {"x": 3},
+            "entropy_bits": 0, "gini": 0, "top_share": 1, "first": "x", "last": "x"}});
+        let b = serde_json::json!({"c": {"type": "str_agg", "counts": {"x": 1, "y": 2},
+            "entropy_bits": 0.92, "normalized_entropy": 0.92, "gini": 0.44, "top_share": 0.67}});
+        let ab = merge_json(&a, &b);
+        assert_eq!(ab, merge_json(&b, &a));
+        assert_eq!(ab["type"], "stats_agg");
+        assert_eq!(
+            ab["c"],
+            serde_json::json!({"type": "str_agg", "counts": {"x": 4, "y": 2}})
+        );
+    }
+
+    // ── jsonb_stats_raw_agg ──
+
+    #[pg_test]
+    fn test_raw_agg_returns_running_state() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_raw_agg(jsonb_build_object('n', stat(v), 'c', stat('v' || v)))
+             FROM unnest(ARRAY[1, 2, 2, NULL]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert!(val.get("type").is_none());
+        assert_eq!(val["n"]["type"], "int_agg");
+        assert_eq!(val["n"]["count"], 3);
+        assert_eq!(val["n"]["null_count"], 1);
+        for field in ["variance", "stddev", "coefficient_of_variation_pct", "range"] {
+            assert!(val["n"].get(field).is_none(), "n.{}", field);
+        }
+        assert_eq!(
+            val["c"],
+            serde_json::json!({"type": "str_agg", "counts": {"v1": 1, "v2": 2}, "min": "v1", "max": "v2", "null_count": 1})
+        );
+    }
+
+    #[pg_test]
+    fn test_raw_agg_feeds_merge_agg() {
+        let (merged, direct) = Spi::get_two::<pgrx::JsonB, pgrx::JsonB>(
+            "WITH rows AS (
+                 SELECT i % 3 AS g, jsonb_build_object(
+                     'n', stat(i * 7 % 11), 'x', stat(i * 0.37::float8), 'c', stat('v' || i % 4),
+                     'b', stat(i % 5 = 0), 'a', jsonb_build_object('type', 'arr', 'value', to_jsonb(ARRAY[i % 2, i % 3])),
+                     'd', stat(date '2024-01-01' + i)) AS s
+                 FROM generate_series(1, 60) AS i),
+             by_g AS (SELECT jsonb_stats_raw_agg(s) AS raw FROM rows GROUP BY g)
+             SELECT (SELECT jsonb_stats_merge_agg(raw) FROM by_g),
+                    (SELECT jsonb_stats_agg(s) FROM rows)",
+        )
+        .unwrap();
+        let (mut merged, mut direct) = (merged.unwrap().0, direct.unwrap().0);
+        round_sums(&mut merged);
+        round_sums(&mut direct);
+        assert_eq!(merged, direct);
+    }
+
+    // ── stats_strip ──
+
+    #[pg_test]
+    fn test_stats_strip_remerges_like_finalized() {
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        let (a, b) = Spi::get_two::<pgrx::JsonB, pgrx::JsonB>(
+            "WITH rows AS (
+                 SELECT i % 2 AS g, jsonb_build_object(
+                     'n', stat(i * 7 % 11), 'x', stat(i * 0.5::float8), 'c', stat('v' || i % 3),
+                     'b', stat(i % 4 = 0), 'd', stat(date '2024-01-01' + i)) AS s
+                 FROM generate_series(1, 30) AS i)
+             SELECT (SELECT jsonb_stats_agg(s) FROM rows WHERE g = 0),
+                    (SELECT jsonb_stats_agg(s) FROM rows WHERE g = 1)",
+        )
+        .unwrap();
+        let (a, b) = (a.unwrap().0, b.unwrap().0);
+
+        let sa = crate::stats_strip(pgrx::JsonB(a.clone())).0;
+        assert!(sa.get("type").is_none());
+        for field in ["variance", "stddev", "coefficient_of_variation_pct", "median", "range"] {
+            assert!(sa["n"].get(field).is_none(), "n.{}", field);
+        }
+        for field in ["mode", "mode_count", "distinct", "gini", "entropy_bits"] {
+            assert!(sa["c"].get(field).is_none(), "c.{}", field);
+        }
+        assert!(sa["n"]["digest"].is_object());
+        assert_eq!(sa["c"]["counts"], a["c"]["counts"]);
+        assert!(sa.to_string().len() < a.to_string().len());
+
+        let sb = crate::stats_strip(pgrx::JsonB(b.clone())).0;
+        let merged = |x: &serde_json::Value, y: &serde_json::Value| {
+            crate::jsonb_stats_final(crate::jsonb_stats_merge(pgrx::JsonB(x.clone()), pgrx::JsonB(y.clone()))).0
+        };
+        assert_eq!(merged(&sa, &sb), merged(&a, &b));
+        // Nothing the finalfunc reads was stripped
+        assert_eq!(crate::jsonb_stats_final(pgrx::JsonB(sa)).0, crate::jsonb_stats_final(pgrx::JsonB(a)).0);
+    }
+
+    #[pg_test(error = "jsonb_stats: merge received a raw stat for key 'n'; did you mean jsonb_stats_accum?")]
+    fn test_stats_strip_rejects_raw_stats() {
+        crate::stats_strip(pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": 1}})));
+    }
+
+    // ── stats_subtract ──
+
+    #[pg_test]
+    fn test_stats_subtract_matches_aggregating_the_rest() {
+        let (all, part, rest) = Spi::get_three::<pgrx::JsonB, pgrx::JsonB, pgrx::JsonB>(
+            "WITH rows AS (
+                 SELECT i, jsonb_build_object('n', stat(i * 7 % 11), 'c', stat('v' || i % 3)) AS s
+                 FROM generate_series(1, 30) AS i)
+             SELECT (SELECT jsonb_stats_agg(s) FROM rows),
+                    (SELECT jsonb_stats_agg(s) FROM rows WHERE i <= 9),
+                    (SELECT jsonb_stats_agg(s) FROM rows WHERE i > 9)",
+        )
+        .unwrap();
+        let (all, part, rest) = (all.unwrap().0, part.unwrap().0, rest.unwrap().0);
+
+        let diff = crate::stats_subtract(pgrx::JsonB(all), pgrx::JsonB(part)).0;
+        assert_eq!(diff["type"], "stats_agg");
+        assert_eq!(diff["c"]["counts"], rest["c"]["counts"]);
+        for field in ["count", "sum", "mean", "variance", "min", "max"] {
+            assert_eq!(diff["n"][field], rest["n"][field], "n.{}", field);
+        }
+    }
+
+    #[pg_test]
+    fn test_stats_subtract_drops_emptied_keys() {
+        let a = serde_json::json!({
+            "c": {"type": "str_agg", "counts": {"a": 3, "b": 1}, "min": "a", "max": "b"},
+            "only_a": {"type": "bool_agg", "counts": {"true": 2}}
+        });
+        let b = serde_json::json!({
+            "c": {"type": "str_agg", "counts": {"b": 2, "z": 1}, "min": "b", "max": "z"},
+            "only_b": {"type": "bool_agg", "counts": {"false": 1}}
+        });
+        let diff = crate::stats_subtract(pgrx::JsonB(a), pgrx::JsonB(b)).0;
+        assert_eq!(diff["c"]["counts"], serde_json::json!({"a": 3}));
+        assert_eq!(diff["c"]["max"], "a");
+        assert_eq!(diff["only_a"]["counts"], serde_json::json!({"true": 2}));
+        assert!(diff.get("only_b").is_none());
+    }
+
+    #[pg_test(error = "jsonb_stats: type mismatch for key 'n': existing int_agg vs removed str_agg")]
+    fn test_stats_subtract_rejects_type_mismatch() {
+        crate::stats_subtract(
+            pgrx::JsonB(serde_json::json!({"n": {"type": "int_agg", "count": 1, "sum": 1, "min": 1, "max": 1, "mean": 1, "sum_sq_diff": 0}})),
+            pgrx::JsonB(serde_json::json!({"n": {"type": "str_agg", "counts": {"a": 1}}})),
+        );
+    }
+
+    // ── stats_rename ──
+
+    #[pg_test]
+    fn test_stats_rename_stats_and_agg() {
+        let (stats, agg) = Spi::get_two::<pgrx::JsonB, pgrx::JsonB>(
+            r#"SELECT stats_rename(stats(jsonb_build_object('emp', stat(150), 'ind', stat('tech'::text))), '{"emp": "employees"}'),
+                      stats_rename(jsonb_stats_agg(stats('emp', v)), '{"emp": "employees"}')
+               FROM unnest(ARRAY[10, 20]) AS v"#,
+        )
+        .unwrap();
+        let (stats, agg) = (stats.unwrap().0, agg.unwrap().0);
+        assert_eq!(stats["type"], "stats");
+        assert_eq!(stats["employees"], serde_json::json!({"type": "int", "value": 150}));
+        assert_eq!(stats["ind"]["value"], "tech");
+        assert!(stats.get("emp").is_none());
+        assert_eq!(agg["type"], "stats_agg");
+        assert_eq!(agg["employees"]["count"], 2);
+        assert!(agg.get("emp").is_none());
+    }
+
+    #[pg_test]
+    fn test_stats_rename_merges_summaries_onto_one_key() {
+        let renamed = crate::stats_rename(
+            pgrx::JsonB(serde_json::json!({
+                "type": "stats_agg",
+                "emp": {"type": "int_agg", "count": 1, "sum": 10, "min": 10, "max": 10, "mean": 10, "sum_sq_diff": 0},
+                "employees": {"type": "int_agg", "count": 1, "sum": 30, "min": 30, "max": 30, "mean": 30, "sum_sq_diff": 0}
+            })),
+            pgrx::JsonB(serde_json::json!({"emp": "employees"})),
+        )
+        .0;
+        assert_eq!(renamed["employees"]["count"], 2);
+        assert_eq!(renamed["employees"]["mean"].as_f64(), Some(20.0));
+        assert!(renamed.get("emp").is_none());
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_rename maps more than one stat to 'employees'")]
+    fn test_stats_rename_rejects_colliding_raw_stats() {
+        crate::stats_rename(
+            pgrx::JsonB(serde_json::json!({
+                "emp": {"type": "int", "value": 1},
+                "employees": {"type": "int", "value": 2}
+            })),
+            pgrx::JsonB(serde_json::json!({"emp": "employees"})),
+        );
+    }
+
+    // ── End-to-end test matching sql/001 scenario ──
+
+    #[pg_test]
+    fn test_end_to_end_three_companies() {
+        load_plpgsql_reference();
+
+        // Build stats for 3 companies, aggregate by region, then merge globally
+        let ok = Spi::get_one::<bool>(
+            "WITH
+            -- Company stats as JSONB
+            company_stats(region, stats) AS (
+                VALUES
+                    ('EU', '{\"num_employees\": {\"type\": \"int\", \"value\": 150}, \"industry\": {\"type\": \"str\", \"value\": \"tech\"}, \"is_profitable\": {\"type\": \"bool\", \"value\": true}}'::jsonb),
+                    ('US', '{\"num_employees\": {\"type\": \"int\", \"value\": 2500}, \"industry\": {\"type\": \"str\", \"value\": \"finance\"}, \"is_profitable\": {\"type\": \"bool\", \"value\": true}}'::jsonb),
+                    ('EU', '{\"num_employees\": {\"type\": \"int\", \"value\": 50}, \"industry\": {\"type\": \"str\", \"value\": \"tech\"}, \"is_profitable\": {\"type\": \"bool\", \"value\": false}}'::jsonb)
+            ),
+            -- Level 2: aggregate by region
+            by_region_rust AS (
+                SELECT region, jsonb_stats_agg(stats) AS agg FROM company_stats GROUP BY region
+            ),
             by_region_plpgsql AS (
                 SELECT region, jsonb_stats_agg_plpgsql(stats) AS agg FROM company_stats GROUP BY region
             ),
@@ -635,24 +1604,31 @@ mod tests {
         use std::collections::HashMap;
 
         let mut state = StatsState::default();
-        state.entries.insert("i".to_string(), AggEntry::IntAgg(NumFields::init(100.0)));
-        state.entries.insert("f".to_string(), AggEntry::FloatAgg(NumFields::init(3.14)));
-        state.entries.insert("d".to_string(), AggEntry::Dec2Agg(NumFields::init(99.99)));
-        state.entries.insert("n".to_string(), AggEntry::NatAgg(NumFields::init(42.0)));
+        state.entries.insert("i".to_string(), AggEntry::IntAgg(NumFields::init(100.0, 100)));
+        state.entries.insert("f".to_string(), AggEntry::FloatAgg(NumFields::init(3.14, 0)));
+        state.entries.insert("d".to_string(), AggEntry::Dec2Agg(NumFields::init(99.99, 9999)));
+        state.entries.insert("n".to_string(), AggEntry::NatAgg(NumFields::init(42.0, 42)));
         state.entries.insert("s".to_string(), AggEntry::StrAgg {
             counts: HashMap::from([("tech".to_string(), 2), ("finance".to_string(), 1)]),
+            min: Some("finance".to_string()),
+            max: Some("tech".to_string()),
+            null_count: 0,
+            hll: None,
         });
         state.entries.insert("b".to_string(), AggEntry::BoolAgg {
             counts: HashMap::from([("true".to_string(), 3), ("false".to_string(), 1)]),
+            null_count: 1,
         });
         state.entries.insert("a".to_string(), AggEntry::ArrAgg {
             count: 5,
             counts: HashMap::from([("x".to_string(), 3), ("y".to_string(), 2)]),
+            null_count: 0,
         });
         state.entries.insert("dt".to_string(), AggEntry::DateAgg {
             counts: HashMap::from([("2024-01-15".to_string(), 2)]),
             min_date: Some("2024-01-15".to_string()),
             max_date: Some("2024-01-15".to_string()),
+            null_count: 0,
         });
 
         let bytes = serde_json::to_vec(&state).unwrap();
@@ -730,6 +1706,23 @@ mod tests {
         assert_eq!(val["type"], "stats_agg");
     }
 
+    #[pg_test]
+    fn test_accum_agg_mixed_null() {
+        let same = Spi::get_one::<bool>(
+            "WITH data(stats) AS (
+                VALUES
+                    (NULL::jsonb),
+                    ('{\"num\": {\"type\": \"int\", \"value\": 50}}'::jsonb),
+                    ('null'::jsonb),
+                    (NULL::jsonb),
+                    ('{\"num\": {\"type\": \"int\", \"value\": 150}}'::jsonb)
+            )
+            SELECT jsonb_stats_agg(stats) = jsonb_stats_agg(stats) FILTER (WHERE stats IS NOT NULL AND stats <> 'null')
+            FROM data",
+        );
+        assert_eq!(same, Ok(Some(true)));
+    }
+
     // ── stat() with varchar ──
 
     #[pg_test]
@@ -778,19 +1771,26 @@ mod tests {
 
         // agg: multi-row (each pipeline uses its own functions end-to-end)
         let ok = Spi::get_one::<bool>(
-            "SELECT jsonb_stats_agg(stats) = jsonb_stats_agg_plpgsql(stats)
-             FROM comparison_data",
+            "WITH r AS (SELECT jsonb_stats_agg(stats) AS r, jsonb_stats_agg_plpgsql(stats) AS p
+                        FROM comparison_data)
+             SELECT (r #- '{i,sum_sq_diff}') = (p #- '{i,sum_sq_diff}')
+                AND round((r->'i'->>'sum_sq_diff')::numeric, 6) = round((p->'i'->>'sum_sq_diff')::numeric, 6)
+             FROM r",
         );
         assert_eq!(ok, Ok(Some(true)), "agg mismatch");
 
         // merge_agg: independent pipelines (Rust agg→Rust merge_agg vs PL/pgSQL agg→PL/pgSQL merge_agg)
-        // Finalized results match because round2 reconciles f64 vs numeric precision
+        // Finalized results match because round_to reconciles f64 vs numeric precision;
+        // sum_sq_diff is unrounded, so it is compared to 6 decimal places
         let ok = Spi::get_one::<bool>(
             "WITH by_grp_r AS (SELECT jsonb_stats_agg(stats) AS agg FROM comparison_data GROUP BY grp),
                   by_grp_p AS (SELECT jsonb_stats_agg_plpgsql(stats) AS agg FROM comparison_data GROUP BY grp),
                   rust_merged AS (SELECT jsonb_stats_merge_agg(agg) AS result FROM by_grp_r),
                   plpgsql_merged AS (SELECT jsonb_stats_merge_agg_plpgsql(agg) AS result FROM by_grp_p)
-             SELECT r.result = p.result FROM rust_merged r, plpgsql_merged p",
+             SELECT (r.result #- '{i,sum_sq_diff}') = (p.result #- '{i,sum_sq_diff}')
+                AND round((r.result->'i'->>'sum_sq_diff')::numeric, 6)
+                  = round((p.result->'i'->>'sum_sq_diff')::numeric, 6)
+             FROM rust_merged r, plpgsql_merged p",
         );
         assert_eq!(ok, Ok(Some(true)), "merge_agg mismatch");
 
@@ -810,7 +1810,7 @@ mod tests {
     // pgrx::error!() propagates to the #[pg_test(error)] handler.
     // SPI catches PG ERRORs in subtransactions, hiding them from the handler.
 
-    #[pg_test(error = "jsonb_stats: unknown stat type 'foo'. Expected: int, float, dec2, nat, str, bool, arr, date")]
+    #[pg_test(error = "jsonb_stats: unknown stat type 'foo'. Expected: int, float, dec2, nat, str, bool, arr, date, ts, time, uuid, inet, interval, stats")]
     fn test_accum_rejects_unknown_type() {
         crate::jsonb_stats_accum(
             pgrx::JsonB(serde_json::json!({})),
@@ -826,6 +1826,19 @@ mod tests {
         );
     }
 
+    #[pg_test(error = "jsonb_stats: stat of type 'int' has missing or invalid 'value'")]
+    fn test_accum_rejects_int_with_bool_value() {
+        crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({"x": {"type": "int", "value": true}})),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: stat of type 'float' has missing or invalid 'value'")]
+    fn test_agg_rejects_float_with_object_value() {
+        Spi::run(r#"SELECT jsonb_stats_agg('{"x": {"type": "float", "value": {"v": 1}}}'::jsonb)"#).unwrap();
+    }
+
     #[pg_test(error = "jsonb_stats: date stat requires a string 'value'")]
     fn test_accum_rejects_date_with_numeric_value() {
         crate::jsonb_stats_accum(
@@ -842,7 +1855,74 @@ mod tests {
         );
     }
 
-    #[pg_test(error = "jsonb_stats: unknown aggregate type 'foo_agg'. Expected: int_agg, float_agg, dec2_agg, nat_agg, str_agg, bool_agg, arr_agg, date_agg")]
+    #[pg_test(error = "jsonb_stats: type mismatch in merge: 'int_agg' vs 'float_agg'")]
+    fn test_merge_rejects_int_float_mismatch_by_default() {
+        Spi::run(&numeric_coerce_sql("jsonb_stats_merge(i.agg, f.agg)")).unwrap();
+    }
+
+    /// An int_agg of 1, 2, 3 and a float_agg of 4.5, 5.5 for key 'x', merged by `merge_expr`.
+    fn numeric_coerce_sql(merge_expr: &str) -> String {
+        format!(
+            "WITH i AS (SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v))) AS agg FROM unnest(ARRAY[1, 2, 3]) AS v),
+                  f AS (SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v))) AS agg FROM unnest(ARRAY[4.5, 5.5]::float8[]) AS v)
+             SELECT jsonb_stats_final({merge_expr}) FROM i, f"
+        )
+    }
+
+    #[pg_test]
+    fn test_numeric_merge_coerce_int_with_float() {
+        Spi::run("SET LOCAL jsonb_stats.numeric_merge_coerce = on").unwrap();
+        let direct = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v)))
+             FROM unnest(ARRAY[1, 2, 3, 4.5, 5.5]::float8[]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        for merge_expr in [
+            "jsonb_stats_merge(i.agg, f.agg)",
+            "jsonb_stats_merge(f.agg, i.agg)",
+            "(SELECT jsonb_stats_merge_agg(a) FROM (VALUES (i.agg), (f.agg)) AS t(a))",
+        ] {
+            let val = Spi::get_one::<pgrx::JsonB>(&numeric_coerce_sql(merge_expr))
+                .unwrap()
+                .unwrap()
+                .0;
+            let x = &val["x"];
+            assert_eq!(x["type"], "float_agg", "{}", merge_expr);
+            for field in ["count", "sum", "min", "max", "mean", "variance", "stddev", "skewness"] {
+                assert_eq!(x[field], direct["x"][field], "{} {}", merge_expr, field);
+            }
+            // The int side never tracked log sums
+            assert!(x["geometric_mean"].is_null(), "{}", merge_expr);
+            assert!(x["sum_log"].is_null(), "{}", merge_expr);
+        }
+    }
+
+    #[pg_test(error = "jsonb_stats: type mismatch in merge: 'int_agg' vs 'str_agg'")]
+    fn test_numeric_merge_coerce_keeps_categorical_mismatch() {
+        Spi::run("SET LOCAL jsonb_stats.numeric_merge_coerce = on").unwrap();
+        crate::jsonb_stats_merge(
+            pgrx::JsonB(serde_json::json!({"x": {"type": "int_agg", "count": 1, "sum": 1, "min": 1, "max": 1, "mean": 1, "sum_sq_diff": 0}})),
+            pgrx::JsonB(serde_json::json!({"x": {"type": "str_agg", "counts": {"a": 1}}})),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: merge received a raw stat for key 'x'; did you mean jsonb_stats_accum?")]
+    fn test_merge_rejects_raw_stat() {
+        crate::jsonb_stats_merge(
+            pgrx::JsonB(serde_json::json!({"x": {"type": "int_agg", "count": 1, "sum": 1, "min": 1, "max": 1, "mean": 1, "sum_sq_diff": 0}})),
+            pgrx::JsonB(serde_json::json!({"x": {"type": "int", "value": 5}})),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: merge received a raw stat for key 'x'; did you mean jsonb_stats_accum?")]
+    fn test_merge_agg_rejects_raw_stats() {
+        Spi::run("SELECT jsonb_stats_merge_agg(jsonb_build_object('x', stat(v))) FROM unnest(ARRAY[1, 2]) AS v")
+            .unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: unknown aggregate type 'foo_agg'. Expected: int_agg, float_agg, dec2_agg, nat_agg, str_agg, bool_agg, arr_agg, date_agg, ts_agg, time_agg, uuid_agg, inet_agg, interval_agg, stats_agg")]
     fn test_merge_rejects_unknown_agg_type() {
         crate::jsonb_stats_merge(
             pgrx::JsonB(serde_json::json!({"x": {"type": "foo_agg", "count": 1}})),
@@ -850,6 +1930,23 @@ mod tests {
         );
     }
 
+    #[pg_test(error = "jsonb_stats: unknown aggregate type 'foo_agg'. Expected: int_agg, float_agg, dec2_agg, nat_agg, str_agg, bool_agg, arr_agg, date_agg, ts_agg, time_agg, uuid_agg, inet_agg, interval_agg, stats_agg")]
+    fn test_merge_rejects_unknown_agg_type_on_one_side() {
+        // Not silently adopted, as the Internal-state merge would not adopt it either
+        crate::jsonb_stats_merge(
+            pgrx::JsonB(serde_json::json!({"n": {"type": "int_agg", "count": 1, "sum": 1, "min": 1, "max": 1, "mean": 1, "sum_sq_diff": 0}})),
+            pgrx::JsonB(serde_json::json!({"x": {"type": "foo_agg", "count": 1}})),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: unknown aggregate type 'foo_agg'. Expected: int_agg, float_agg, dec2_agg, nat_agg, str_agg, bool_agg, arr_agg, date_agg, ts_agg, time_agg, uuid_agg, inet_agg, interval_agg, stats_agg")]
+    fn test_merge_all_rejects_unknown_agg_type_in_first() {
+        Spi::run(
+            r#"SELECT jsonb_stats_merge_all('{"x": {"type": "foo_agg", "count": 1}}'::jsonb, '{}'::jsonb)"#,
+        )
+        .unwrap();
+    }
+
     // ── Full pipeline with mixed types ──
 
     #[pg_test]
@@ -905,6 +2002,16 @@ mod tests {
         assert_eq!(val["type"], "float");
     }
 
+    #[pg_test(error = "jsonb_stats: float value must be finite")]
+    fn test_stat_float_nan_errors() {
+        Spi::run("SELECT stat('NaN'::float8)").unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: float value must be finite")]
+    fn test_stat_float_infinity_errors() {
+        Spi::run("SELECT stat('Infinity'::float8)").unwrap();
+    }
+
     #[pg_test]
     fn test_stat_empty_string() {
         let result = Spi::get_one::<pgrx::JsonB>("SELECT stat(''::text)");
@@ -1025,6 +2132,69 @@ mod tests {
         assert_eq!(result, Ok(Some(true)), "Merged partial aggregates must match direct aggregate");
     }
 
+    #[pg_test]
+    fn test_serial_roundtrip_large_state() {
+        use crate::parallel::{deserialize_state, serialize_state};
+
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        let mut state = crate::state::StatsState::default();
+        for i in 0..50_000 {
+            crate::accum::accum_into(
+                &mut state,
+                serde_json::json!({
+                    "s": {"type": "str", "value": format!("v{i}")},
+                    "n": {"type": "int", "value": i},
+                    "f": {"type": "float", "value": i as f64 / 7.0},
+                    "ok": {"type": "bool", "value": i % 3 == 0},
+                }),
+                1,
+            );
+        }
+
+        let t0 = std::time::Instant::now();
+        let bytes = serialize_state(&state);
+        let roundtripped = deserialize_state(&bytes);
+        let cbor_ms = t0.elapsed().as_secs_f64() * 1000.0;
+        assert_eq!(
+            serde_json::to_value(&roundtripped).unwrap(),
+            serde_json::to_value(&state).unwrap()
+        );
+
+        let t0 = std::time::Instant::now();
+        let json = serde_json::to_vec(&state).unwrap();
+        let _: crate::state::StatsState = serde_json::from_slice(&json).unwrap();
+        let json_ms = t0.elapsed().as_secs_f64() * 1000.0;
+        log_benchmark(&format!(
+            "BENCHMARK serial 50K-key state: CBOR={}B {:.0}ms, JSON={}B {:.0}ms",
+            bytes.len(),
+            cbor_ms,
+            json.len(),
+            json_ms
+        ));
+        assert!(bytes.len() < json.len());
+    }
+
+    #[pg_test(
+        error = "jsonb_stats: serialized state exceeds jsonb_stats.max_serial_bytes (4096 bytes); prune high-cardinality counts or run the query without parallel workers"
+    )]
+    fn test_serial_rejects_state_over_max_serial_bytes() {
+        let mut state = crate::state::StatsState::default();
+        for i in 0..10_000 {
+            crate::accum::accum_into(&mut state, serde_json::json!({"s": {"type": "str", "value": format!("v{i}")}}), 1);
+        }
+        // Well under the limit first, then the large state trips it
+        Spi::run("SET LOCAL jsonb_stats.max_serial_bytes = 4096").unwrap();
+        crate::parallel::serialize_state(&crate::state::StatsState::default());
+        crate::parallel::serialize_state(&state);
+    }
+
+    #[pg_test(error = "jsonb_stats: serialized state has format version 123, expected 2")]
+    fn test_deserial_rejects_unknown_format_version() {
+        // A JSON payload from before the version byte starts with '{' (123)
+        let json = serde_json::to_vec(&crate::state::StatsState::default()).unwrap();
+        crate::parallel::deserialize_state(&json);
+    }
+
     #[pg_test]
     fn test_parallel_combine_disjoint_keys() {
         // Two workers with non-overlapping keys: combine must adopt both
@@ -1115,7 +2285,58 @@ mod tests {
             "cv_pct should be NULL when mean=0 (0/0 → NaN → guarded to NULL)");
     }
 
-    #[pg_test(error = "jsonb_stats: non-finite value in round2 (inf). Input data likely caused numeric overflow.")]
+    #[pg_test]
+    fn test_cv_min_abs_mean_guc() {
+        // mean 0.0001, stddev ~0.0141: CV ~14142%
+        let query = "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v)))
+                     FROM unnest(ARRAY[-0.0099, 0.0101]::float8[]) AS v";
+        let val = Spi::get_one::<pgrx::JsonB>(query).unwrap().unwrap().0;
+        assert!(val["x"]["coefficient_of_variation_pct"].as_f64().unwrap() > 10000.0);
+
+        Spi::run("SET LOCAL jsonb_stats.cv_min_abs_mean = 0.001").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(query).unwrap().unwrap().0;
+        assert!(val["x"]["coefficient_of_variation_pct"].is_null());
+        assert_eq!(val["x"]["stddev"].to_string(), "0.01");
+
+        // JSONB finalfunc path
+        let val = crate::jsonb_stats_final(pgrx::JsonB(serde_json::json!({
+            "x": {"type": "float_agg", "count": 2, "sum": 0.0002, "min": -0.0099, "max": 0.0101, "mean": 0.0001, "sum_sq_diff": 0.0002}
+        })))
+        .0;
+        assert!(val["x"]["coefficient_of_variation_pct"].is_null());
+    }
+
+    #[pg_test]
+    fn test_cv_zero_mean_guc() {
+        let query = "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v))) FROM unnest(ARRAY[-1, 1]) AS v";
+        let state = serde_json::json!({
+            "x": {"type": "int_agg", "count": 2, "sum": 0, "min": -1, "max": 1, "mean": 0, "sum_sq_diff": 2}
+        });
+
+        // Default: NULL on both paths
+        let val = Spi::get_one::<pgrx::JsonB>(query).unwrap().unwrap().0;
+        assert!(val["x"]["coefficient_of_variation_pct"].is_null());
+        let val = crate::jsonb_stats_final(pgrx::JsonB(state.clone())).0;
+        assert!(val["x"]["coefficient_of_variation_pct"].is_null());
+
+        Spi::run("SET LOCAL jsonb_stats.cv_zero_mean = 'zero'").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(query).unwrap().unwrap().0;
+        assert_eq!(val["x"]["coefficient_of_variation_pct"].to_string(), "0.00");
+        assert_eq!(val["x"]["stddev"].to_string(), "1.41");
+        let val = crate::jsonb_stats_final(pgrx::JsonB(state)).0;
+        assert_eq!(val["x"]["coefficient_of_variation_pct"].to_string(), "0.00");
+
+        // Still NULL while stddev itself is undefined
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(0)))",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert!(val["x"]["coefficient_of_variation_pct"].is_null());
+    }
+
+    #[pg_test(error = "jsonb_stats: non-finite value in round_to (inf). Input data likely caused numeric overflow.")]
     fn test_agg_float_overflow_errors() {
         // Construct Internal state with Inf sum_sq_diff (simulates overflow from extreme values)
         use crate::state::{AggEntry, NumFields, StatsState};
@@ -1124,10 +2345,24 @@ mod tests {
         state.entries.insert("x".to_string(), AggEntry::FloatAgg(NumFields {
             count: 2,
             sum: 0.0,
+            sum_int: 0,
             min: -1e154,
             max: 1e154,
+            min_count: 1,
+            max_count: 1,
+            extremes: true,
             mean: 0.0,
+            moments: true,
             sum_sq_diff: f64::INFINITY,
+            m3: 0.0,
+            m4: 0.0,
+            sum_log: 0.0,
+            sum_reciprocal: 0.0,
+            nonpositive_count: 2,
+            null_count: 0,
+            digest: None,
+            hist: None,
+            raw_values: None,
         }));
 
         let ptr = Box::into_raw(Box::new(state));
@@ -1195,6 +2430,42 @@ mod tests {
         );
     }
 
+    #[pg_test(error = "jsonb_stats: corrupt agg field 'count'")]
+    fn test_merge_rejects_non_numeric_count() {
+        crate::jsonb_stats_merge(
+            pgrx::JsonB(serde_json::json!({"x": {"type": "int_agg", "count": 1, "sum": 1, "min": 1, "max": 1, "mean": 1, "sum_sq_diff": 0}})),
+            pgrx::JsonB(serde_json::json!({"x": {"type": "int_agg", "count": "lots", "sum": 1, "min": 1, "max": 1, "mean": 1, "sum_sq_diff": 0}})),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: corrupt agg field 'b'")]
+    fn test_merge_agg_rejects_non_numeric_counts_entry() {
+        Spi::run(
+            r#"SELECT jsonb_stats_merge_agg(a) FROM (VALUES
+                ('{"x": {"type": "str_agg", "counts": {"a": 1}}}'::jsonb),
+                ('{"x": {"type": "str_agg", "counts": {"a": 1, "b": "two"}}}'::jsonb)) AS t(a)"#,
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: count overflow for key 'true'")]
+    fn test_merge_rejects_count_overflow() {
+        crate::jsonb_stats_merge(
+            pgrx::JsonB(serde_json::json!({"x": {"type": "bool_agg", "counts": {"true": i64::MAX}}})),
+            pgrx::JsonB(serde_json::json!({"x": {"type": "bool_agg", "counts": {"true": 1}}})),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: count overflow for key 'a'")]
+    fn test_merge_agg_rejects_count_overflow() {
+        Spi::run(
+            r#"SELECT jsonb_stats_merge_agg(a) FROM (VALUES
+                ('{"x": {"type": "str_agg", "counts": {"a": 9223372036854775807, "b": 1}}}'::jsonb),
+                ('{"x": {"type": "str_agg", "counts": {"a": 1}}}'::jsonb)) AS t(a)"#,
+        )
+        .unwrap();
+    }
+
     // ── Reproduction: RETURN QUERY + LATERAL + aggregate segfault ──
 
     #[pg_test]
@@ -1236,26 +2507,3203 @@ mod tests {
         assert_eq!(result, Ok(Some(10)), "Should return 10 groups without crashing");
     }
 
-    // ── Benchmarks: Rust vs PL/pgSQL ──
-    //
-    // pgrx tests run inside the PostgreSQL server process, so eprintln/warning
-    // output goes to PG's stderr (invisible to the test runner). We write
-    // benchmark results to /tmp/jsonb_stats_benchmarks.txt so they survive.
+    // ── Exact integer sums ──
 
-    const BENCHMARK_FILE: &str = "/tmp/jsonb_stats_benchmarks.txt";
+    #[pg_test]
+    fn test_int_agg_exact_sum_beyond_2_53() {
+        // 2^53 + 1 is not representable as f64; an f64 running sum would drift
+        let result = Spi::get_one::<pgrx::JsonB>(
+            "WITH data(stats) AS (
+                VALUES
+                    ('{\"n\": {\"type\": \"int\", \"value\": 9007199254740993}}'::jsonb),
+                    ('{\"n\": {\"type\": \"int\", \"value\": 1}}'::jsonb),
+                    ('{\"n\": {\"type\": \"int\", \"value\": 1}}'::jsonb)
+            )
+            SELECT jsonb_stats_agg(stats) FROM data",
+        );
+        let val = result.unwrap().unwrap().0;
+        assert_eq!(val["n"]["sum"].to_string(), "9007199254740995");
+    }
 
-    /// Time a SQL statement by running clock_timestamp() before/after via separate SPI calls.
-    /// Uses SELECT INTO to force materialization of aggregate results.
-    fn time_sql(query: &str) -> f64 {
-        let t1 = Spi::get_one::<f64>(
-            "SELECT extract(epoch from clock_timestamp())::float8",
-        )
-        .unwrap()
-        .unwrap();
-        Spi::run(query).unwrap();
-        let t2 = Spi::get_one::<f64>(
-            "SELECT extract(epoch from clock_timestamp())::float8",
-        )
+    #[pg_test]
+    fn test_nat_agg_exact_sum_jsonb_path() {
+        let result = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_final(jsonb_stats_accum(
+                jsonb_stats_accum('{}'::jsonb,
+                    '{\"n\": {\"type\": \"nat\", \"value\": 9007199254740993}}'::jsonb),
+                '{\"n\": {\"type\": \"nat\", \"value\": 2}}'::jsonb))",
+        );
+        let val = result.unwrap().unwrap().0;
+        assert_eq!(val["n"]["sum"].to_string(), "9007199254740995");
+    }
+
+    #[pg_test]
+    fn test_int_agg_exact_sum_beyond_i64_in_merge() {
+        // Merged sum exceeds i64::MAX; the exact accumulator is i128
+        let agg = "'{\"n\": {\"type\": \"int_agg\", \"count\": 1, \"sum\": 9000000000000000000, \"min\": 9000000000000000000, \"max\": 9000000000000000000, \"mean\": 9000000000000000000, \"sum_sq_diff\": 0}}'::jsonb";
+        let result = Spi::get_one::<pgrx::JsonB>(&format!(
+            "SELECT jsonb_stats_merge({agg}, {agg})"
+        ));
+        let val = result.unwrap().unwrap().0;
+        assert_eq!(val["n"]["sum"].to_string(), "18000000000000000000");
+
+        let result = Spi::get_one::<pgrx::JsonB>(&format!(
+            "SELECT jsonb_stats_merge_agg(agg) FROM (VALUES ({agg}), ({agg})) AS t(agg)"
+        ));
+        let val = result.unwrap().unwrap().0;
+        assert_eq!(val["n"]["sum"].to_string(), "18000000000000000000");
+    }
+
+    // ── Exact dec2 sums ──
+
+    #[pg_test]
+    fn test_dec2_agg_exact_sum_of_cents() {
+        // 0.01 is not representable in binary; 100000 f64 additions drift
+        let result = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('d', jsonb_build_object('type', 'dec2', 'value', 0.01)))
+             FROM generate_series(1, 100000)",
+        );
+        let val = result.unwrap().unwrap().0;
+        assert_eq!(val["d"]["sum"].to_string(), "1000.00");
+    }
+
+    #[pg_test]
+    fn test_dec2_agg_exact_sum_jsonb_path() {
+        let result = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_final(jsonb_stats_accum(
+                jsonb_stats_accum('{}'::jsonb,
+                    '{\"d\": {\"type\": \"dec2\", \"value\": 99.99}}'::jsonb),
+                '{\"d\": {\"type\": \"dec2\", \"value\": 0.01}}'::jsonb))",
+        );
+        let val = result.unwrap().unwrap().0;
+        assert_eq!(val["d"]["sum"].to_string(), "100.00");
+    }
+
+    #[pg_test]
+    fn test_dec2_agg_exact_sum_in_merge() {
+        let agg = "'{\"d\": {\"type\": \"dec2_agg\", \"count\": 1, \"sum\": 0.10, \"min\": 0.10, \"max\": 0.10, \"mean\": 0.10, \"sum_sq_diff\": 0}}'::jsonb";
+        let b = "'{\"d\": {\"type\": \"dec2_agg\", \"count\": 1, \"sum\": -0.30, \"min\": -0.30, \"max\": -0.30, \"mean\": -0.30, \"sum_sq_diff\": 0}}'::jsonb";
+        let result = Spi::get_one::<pgrx::JsonB>(&format!(
+            "SELECT jsonb_stats_merge(jsonb_stats_merge({agg}, {agg}), {b})"
+        ));
+        let val = result.unwrap().unwrap().0;
+        assert_eq!(val["d"]["sum"].to_string(), "-0.10");
+    }
+
+    // ── Quoted numeric values ──
+
+    #[pg_test]
+    fn test_quoted_int_value_matches_number() {
+        let (quoted, plain) = Spi::get_two::<pgrx::JsonB, pgrx::JsonB>(
+            r#"SELECT jsonb_stats_agg(q), jsonb_stats_agg(p)
+               FROM (VALUES ('{"n": {"type": "int", "value": "150"}}'::jsonb, '{"n": {"type": "int", "value": 150}}'::jsonb),
+                            ('{"n": {"type": "int", "value": " 50 "}}', '{"n": {"type": "int", "value": 50}}')) AS t(q, p)"#,
+        )
+        .unwrap();
+        let quoted = quoted.unwrap().0;
+        assert_eq!(quoted["n"]["sum"], 200);
+        assert_eq!(quoted, plain.unwrap().0);
+    }
+
+    #[pg_test]
+    fn test_quoted_values_keep_exact_sums() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT jsonb_stats_accum(jsonb_stats_accum('{}'::jsonb,
+                   '{"big": {"type": "int", "value": "9007199254740993"}, "price": {"type": "dec2", "value": "10.005"}}'),
+                   '{"big": {"type": "int", "value": 1}, "price": {"type": "dec2", "value": "0.10"}}')"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["big"]["sum"].to_string(), "9007199254740994");
+        assert_eq!(val["price"]["sum"].to_string(), "10.11");
+    }
+
+    #[pg_test(error = "jsonb_stats: 'value' must be a number, got \"n/a\"")]
+    fn test_quoted_non_number_errors() {
+        Spi::run(r#"SELECT jsonb_stats_agg('{"n": {"type": "float", "value": "n/a"}}'::jsonb)"#).unwrap();
+    }
+
+    #[pg_test]
+    fn test_validate_accepts_quoted_number() {
+        let val = crate::stats_validate(pgrx::JsonB(serde_json::json!({
+            "type": "stats",
+            "a": {"type": "int", "value": "150"},
+            "b": {"type": "nat", "value": "-1"},
+            "c": {"type": "float", "value": "abc"}
+        })))
+        .0;
+        let errors = val["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].as_str().unwrap().starts_with("key 'b': "));
+        assert!(errors[1].as_str().unwrap().starts_with("key 'c': "));
+    }
+
+    // ── stats_value accessor ──
+
+    fn accessor_agg() -> &'static str {
+        "(SELECT jsonb_stats_agg(s) FROM (VALUES
+            ('{\"num\": {\"type\": \"int\", \"value\": 50}, \"name\": {\"type\": \"str\", \"value\": \"a\"}}'::jsonb),
+            ('{\"num\": {\"type\": \"int\", \"value\": 150}, \"name\": {\"type\": \"str\", \"value\": \"b\"}}'::jsonb)
+        ) AS t(s))"
+    }
+
+    #[pg_test]
+    fn test_stats_value_metrics() {
+        let agg = accessor_agg();
+        let get = |metric: &str| {
+            Spi::get_one::<f64>(&format!("SELECT stats_value({agg}, 'num', '{metric}')")).unwrap()
+        };
+        assert_eq!(get("mean"), Some(100.0));
+        assert_eq!(get("sum"), Some(200.0));
+        assert_eq!(get("min"), Some(50.0));
+        assert_eq!(get("max"), Some(150.0));
+        assert_eq!(get("count"), Some(2.0));
+        assert_eq!(get("variance"), Some(5000.0));
+        assert_eq!(get("stddev"), Some(70.71));
+        assert_eq!(get("coefficient_of_variation_pct"), Some(70.71));
+        assert_eq!(get("range"), Some(100.0));
+    }
+
+    #[pg_test]
+    fn test_stats_value_missing_key_is_null() {
+        let result = Spi::get_one::<f64>(&format!(
+            "SELECT stats_value({}, 'nope', 'mean')",
+            accessor_agg()
+        ));
+        assert_eq!(result, Ok(None));
+    }
+
+    #[pg_test]
+    fn test_stats_value_null_metric_is_null() {
+        // variance is NULL for a single observation
+        let result = Spi::get_one::<f64>(
+            "SELECT stats_value(jsonb_stats_agg(s), 'num', 'variance')
+             FROM (VALUES ('{\"num\": {\"type\": \"int\", \"value\": 1}}'::jsonb)) AS t(s)",
+        );
+        assert_eq!(result, Ok(None));
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_value key 'name' is str_agg, expected a numeric aggregate")]
+    fn test_stats_value_non_numeric_agg_errors() {
+        Spi::get_one::<f64>(&format!("SELECT stats_value({}, 'name', 'mean')", accessor_agg()))
+            .unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_value expects a stats_agg, got type 'stats'")]
+    fn test_stats_value_requires_stats_agg() {
+        crate::stats_value(
+            pgrx::JsonB(serde_json::json!({"type": "stats", "num": {"type": "int", "value": 1}})),
+            "num",
+            "mean",
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: unknown metric 'p99'. Expected: count, sum, min, max, mean, sum_sq_diff, variance, stddev, coefficient_of_variation_pct, range, median, p25, p75, p95, iqr, mad")]
+    fn test_stats_value_unknown_metric_errors() {
+        Spi::get_one::<f64>(&format!("SELECT stats_value({}, 'num', 'p99')", accessor_agg()))
+            .unwrap();
+    }
+
+    #[pg_test]
+    fn test_stats_to_record_int_key() {
+        let row = Spi::get_three::<i64, f64, f64>(&format!(
+            "SELECT count, mean, stddev FROM stats_to_record({}, 'num')",
+            accessor_agg()
+        ))
+        .unwrap();
+        assert_eq!(row, (Some(2), Some(100.0), Some(70.71)));
+        let bounds = Spi::get_two::<f64, f64>(&format!(
+            "SELECT min, max FROM stats_to_record({}, 'num')",
+            accessor_agg()
+        ))
+        .unwrap();
+        assert_eq!(bounds, (Some(50.0), Some(150.0)));
+        let missing = Spi::get_one::<i64>(&format!(
+            "SELECT count(*) FROM stats_to_record({}, 'absent')",
+            accessor_agg()
+        ))
+        .unwrap();
+        assert_eq!(missing, Some(0));
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_to_record key 'name' is str_agg, expected a numeric aggregate")]
+    fn test_stats_to_record_non_numeric_errors() {
+        Spi::run(&format!("SELECT * FROM stats_to_record({}, 'name')", accessor_agg())).unwrap();
+    }
+
+    // ── Mode for categorical aggregates ──
+
+    #[pg_test]
+    fn test_mode_clear_winner() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(s) FROM (VALUES
+                ('{\"c\": {\"type\": \"str\", \"value\": \"apple\"}, \"b\": {\"type\": \"bool\", \"value\": true}, \"d\": {\"type\": \"date\", \"value\": \"2024-01-15\"}}'::jsonb),
+                ('{\"c\": {\"type\": \"str\", \"value\": \"banana\"}, \"b\": {\"type\": \"bool\", \"value\": false}, \"d\": {\"type\": \"date\", \"value\": \"2023-06-01\"}}'::jsonb),
+                ('{\"c\": {\"type\": \"str\", \"value\": \"apple\"}, \"b\": {\"type\": \"bool\", \"value\": true}, \"d\": {\"type\": \"date\", \"value\": \"2024-01-15\"}}'::jsonb)
+            ) AS t(s)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["c"]["mode"], "apple");
+        assert_eq!(val["c"]["mode_count"], 2);
+        assert_eq!(val["b"]["mode"], "true");
+        assert_eq!(val["b"]["mode_count"], 2);
+        assert_eq!(val["d"]["mode"], "2024-01-15");
+        assert_eq!(val["d"]["mode_count"], 2);
+    }
+
+    #[pg_test]
+    fn test_mode_tie_picks_smallest_key() {
+        // "B" < "a" < "b" in byte order
+        let state = "'{\"c\": {\"type\": \"str_agg\", \"counts\": {\"b\": 2, \"a\": 2, \"B\": 2, \"z\": 1}}}'::jsonb";
+        let val = Spi::get_one::<pgrx::JsonB>(&format!("SELECT jsonb_stats_final({state})"))
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(val["c"]["mode"], "B");
+        assert_eq!(val["c"]["mode_count"], 2);
+
+        // Internal-state path agrees
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('c', jsonb_build_object('type', 'str', 'value', v)))
+             FROM unnest(ARRAY['b', 'a', 'B', 'a', 'b', 'B', 'z']) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["c"]["mode"], "B");
+        assert_eq!(val["c"]["mode_count"], 2);
+    }
+
+    #[pg_test]
+    fn test_mode_matches_plpgsql() {
+        load_plpgsql_reference();
+        let state = "'{\"c\": {\"type\": \"str_agg\", \"counts\": {\"b\": 2, \"a\": 2, \"z\": 1}}, \"f\": {\"type\": \"bool_agg\", \"counts\": {\"true\": 1, \"false\": 3}}}'::jsonb";
+        let ok = Spi::get_one::<bool>(&format!(
+            "SELECT jsonb_stats_final({state}) = jsonb_stats_final_plpgsql({state})"
+        ));
+        assert_eq!(ok, Ok(Some(true)));
+    }
+
+    #[pg_test]
+    fn test_merge_drops_stale_mode() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_merge(
+                '{\"c\": {\"type\": \"str_agg\", \"counts\": {\"x\": 1}, \"mode\": \"x\", \"mode_count\": 1}}'::jsonb,
+                '{\"c\": {\"type\": \"str_agg\", \"counts\": {\"y\": 3}}}'::jsonb)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert!(val["c"].get("mode").is_none());
+        assert!(val["c"].get("mode_count").is_none());
+    }
+
+    // ── Distinct cardinality ──
+
+    #[pg_test]
+    fn test_distinct_str_agg_repeated_values() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('c', jsonb_build_object('type', 'str', 'value', v)))
+             FROM unnest(ARRAY['a', 'b', 'a', 'c', 'a', 'b']) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["c"]["distinct"], 3);
+        assert_eq!(val["c"]["mode"], "a");
+
+        // JSONB path agrees
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_final('{\"c\": {\"type\": \"str_agg\", \"counts\": {\"a\": 3, \"b\": 2, \"c\": 1}}}'::jsonb)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["c"]["distinct"], 3);
+    }
+
+    #[pg_test]
+    fn test_distinct_and_total_elements_arr_agg() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('tags', jsonb_build_object('type', 'arr', 'value', to_jsonb(a))))
+             FROM (VALUES (ARRAY[1, 2]), (ARRAY[2, 3]), (ARRAY[3, 4])) AS t(a)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["tags"]["count"], 3);
+        assert_eq!(val["tags"]["distinct"], 4);
+        assert_eq!(val["tags"]["total_elements"], 6);
+        assert!(val["tags"].get("mode").is_none());
+    }
+
+    #[pg_test]
+    fn test_arr_text_quoted_elements() {
+        // Quoted elements keep their commas, braces and spaces
+        let val = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT jsonb_stats_agg(jsonb_build_object('tags', jsonb_build_object('type', 'arr', 'value', a)))
+               FROM (VALUES ('{"a,b",c}'), ('{" c ","x\"y","{z}", c}')) AS t(a)"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(
+            val["tags"]["counts"],
+            serde_json::json!({"a,b": 1, "c": 2, " c ": 1, "x\"y": 1, "{z}": 1})
+        );
+        assert_eq!(val["tags"]["total_elements"], 6);
+    }
+
+    #[pg_test]
+    fn test_arr_text_empty_and_null_elements() {
+        // Empty unquoted elements and NULL are skipped; "" is a real empty string
+        let val = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT jsonb_stats_agg(jsonb_build_object('tags', jsonb_build_object('type', 'arr', 'value', a)))
+               FROM (VALUES ('{a,,b}'), ('{}'), ('{NULL,"NULL",""}')) AS t(a)"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["tags"]["count"], 3);
+        assert_eq!(
+            val["tags"]["counts"],
+            serde_json::json!({"a": 1, "b": 1, "NULL": 1, "": 1})
+        );
+    }
+
+    #[pg_test]
+    fn test_arr_text_matches_array_cast() {
+        // The text form counts the same elements as the array itself
+        let ok = Spi::get_one::<bool>(
+            r#"SELECT jsonb_stats_agg(jsonb_build_object('t', jsonb_build_object('type', 'arr', 'value', a::text)))
+                    = jsonb_stats_agg(jsonb_build_object('t', jsonb_build_object('type', 'arr', 'value', to_jsonb(a))))
+               FROM (VALUES (ARRAY['a,b', ' c', 'd"e', 'f\g']), (ARRAY['{x}', 'y', NULL])) AS t(a)"#,
+        );
+        assert_eq!(ok, Ok(Some(true)));
+    }
+
+    #[pg_test]
+    fn test_stat_of_text_array() {
+        let stat = Spi::get_one::<pgrx::JsonB>("SELECT stat(ARRAY['a', NULL, 'b'])")
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(stat, serde_json::json!({"type": "arr", "value": ["a", "b"]}));
+
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('tags', stat(a)))
+             FROM (VALUES (ARRAY['a', 'b']), (ARRAY['a', 'c']), (ARRAY['a'])) AS t(a)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["tags"]["type"], "arr_agg");
+        assert_eq!(val["tags"]["count"], 3);
+        assert_eq!(val["tags"]["counts"], serde_json::json!({"a": 3, "b": 1, "c": 1}));
+
+        // Elements convert like scalars: an int[] counts numbers
+        let stat = Spi::get_one::<pgrx::JsonB>("SELECT stat(ARRAY[3, 1])").unwrap().unwrap().0;
+        assert_eq!(stat, serde_json::json!({"type": "arr", "value": [3, 1]}));
+    }
+
+    #[pg_test]
+    fn test_arr_agg_mean_elements_per_row() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('tags', jsonb_build_object('type', 'arr', 'value', to_jsonb(a))))
+             FROM (VALUES (ARRAY['a', 'b']), (ARRAY['a', 'c', 'd', 'e'])) AS t(a)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["tags"]["total_elements"], 6);
+        assert_eq!(val["tags"]["mean_elements_per_row"].to_string(), "3.00");
+
+        // JSONB finalfunc path; no rows yet
+        let val = crate::jsonb_stats_final(pgrx::JsonB(serde_json::json!({
+            "t": {"type": "arr_agg", "count": 0, "counts": {}}
+        })))
+        .0;
+        assert!(val["t"]["mean_elements_per_row"].is_null());
+    }
+
+    #[pg_test]
+    fn test_distinct_matches_plpgsql() {
+        load_plpgsql_reference();
+        let state = "'{\"t\": {\"type\": \"arr_agg\", \"count\": 2, \"counts\": {\"x\": 2, \"y\": 1}}, \"f\": {\"type\": \"bool_agg\", \"counts\": {\"true\": 1, \"false\": 3}}}'::jsonb";
+        let ok = Spi::get_one::<bool>(&format!(
+            "SELECT jsonb_stats_final({state}) = jsonb_stats_final_plpgsql({state})"
+        ));
+        assert_eq!(ok, Ok(Some(true)));
+    }
+
+    // ── max_counts pruning ──
+
+    #[pg_test]
+    fn test_max_counts_prunes_to_top_n() {
+        Spi::run("SET LOCAL jsonb_stats.max_counts = 10").unwrap();
+        // s1..s10 appear 3 times, s11..s1000 once
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('c', jsonb_build_object('type', 'str', 'value', 's' || g)))
+             FROM generate_series(1, 1000) AS g,
+                  generate_series(1, CASE WHEN g <= 10 THEN 3 ELSE 1 END)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let counts = val["c"]["counts"].as_object().unwrap();
+        assert_eq!(counts.len(), 11);
+        for i in 1..=10 {
+            assert_eq!(counts[&format!("s{i}")], 3);
+        }
+        assert_eq!(counts["__other__"], 990);
+        assert_eq!(val["c"]["distinct"], 1000);
+    }
+
+    #[pg_test]
+    fn test_max_counts_jsonb_path_and_refinalize() {
+        Spi::run("SET LOCAL jsonb_stats.max_counts = 1").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_final('{\"c\": {\"type\": \"str_agg\", \"counts\": {\"a\": 3, \"b\": 2, \"__other__\": 4}}}'::jsonb)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["c"]["counts"], serde_json::json!({"a": 3, "__other__": 6}));
+        assert_eq!(val["c"]["distinct"], 2);
+        assert_eq!(val["c"]["mode"], "a");
+    }
+
+    #[pg_test]
+    fn test_max_counts_zero_is_unlimited() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('c', jsonb_build_object('type', 'str', 'value', 's' || g)))
+             FROM generate_series(1, 100) AS g",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["c"]["counts"].as_object().unwrap().len(), 100);
+        assert!(val["c"]["counts"].get("__other__").is_none());
+    }
+
+    #[pg_test]
+    fn test_guc_reading_functions_are_stable() {
+        // A SET changes their output, so they must not be folded as IMMUTABLE
+        let immutable = Spi::get_one::<i64>(
+            "SELECT count(*) FROM pg_proc
+             WHERE proname IN ('jsonb_stats_final', 'jsonb_stats_accum', 'stats', 'stats_diff', 'jsonb_stats_to_agg')
+               AND provolatile <> 's'",
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(immutable, 0);
+    }
+
+    // ── Timestamp stat type ──
+
+    #[pg_test]
+    fn test_stat_timestamp() {
+        let val = Spi::get_one::<pgrx::JsonB>("SELECT stat('2024-01-15 10:30:00'::timestamp)")
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(val, serde_json::json!({"type": "ts", "value": "2024-01-15T10:30:00"}));
+    }
+
+    #[pg_test]
+    fn test_stat_timestamptz_normalized_to_utc() {
+        Spi::run("SET LOCAL TimeZone = 'Europe/Oslo'").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>("SELECT stat('2024-01-15 10:30:00+01'::timestamptz)")
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(val, serde_json::json!({"type": "ts", "value": "2024-01-15T09:30:00Z"}));
+    }
+
+    #[pg_test]
+    fn test_ts_agg_pipeline() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('seen', stat(t)))
+             FROM (VALUES ('2024-03-01 12:00:00'::timestamp), ('2023-11-20 08:15:30'::timestamp)) AS v(t)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["seen"]["type"], "ts_agg");
+        assert_eq!(val["seen"]["min"], "2023-11-20T08:15:30");
+        assert_eq!(val["seen"]["max"], "2024-03-01T12:00:00");
+        assert_eq!(val["seen"]["counts"]["2024-03-01T12:00:00"], 1);
+        assert_eq!(val["seen"]["counts"]["2023-11-20T08:15:30"], 1);
+
+        // JSONB path agrees
+        let jsonb = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_final(jsonb_stats_accum(
+                jsonb_stats_accum('{}'::jsonb, jsonb_build_object('seen', stat('2024-03-01 12:00:00'::timestamp))),
+                jsonb_build_object('seen', stat('2023-11-20 08:15:30'::timestamp))))",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(jsonb["seen"]["min"], "2023-11-20T08:15:30");
+        assert_eq!(jsonb["seen"]["max"], "2024-03-01T12:00:00");
+    }
+
+    #[pg_test]
+    fn test_ts_agg_orders_fractional_seconds() {
+        // '.' sorts before 'Z', so a bytewise compare would put 10:30:00.5Z first
+        let (whole, frac) = ("2024-01-15T10:30:00Z", "2024-01-15T10:30:00.5Z");
+        let s = |ts: &str| format!("jsonb_build_object('t', stat('{}'::timestamptz))", ts);
+        for expr in [
+            format!(
+                "(SELECT jsonb_stats_agg(s) FROM (VALUES ({}), ({})) AS v(s))",
+                s(frac),
+                s(whole)
+            ),
+            format!(
+                "jsonb_stats_final(jsonb_stats_accum(jsonb_stats_accum('{{}}'::jsonb, {}), {}))",
+                s(frac),
+                s(whole)
+            ),
+            format!(
+                "jsonb_stats_merge((SELECT jsonb_stats_agg({})), (SELECT jsonb_stats_agg({})))",
+                s(frac),
+                s(whole)
+            ),
+            format!(
+                "(SELECT jsonb_stats_merge_agg(a) FROM (VALUES ((SELECT jsonb_stats_agg({}))), ((SELECT jsonb_stats_agg({})))) AS v(a))",
+                s(frac),
+                s(whole)
+            ),
+        ] {
+            let val = Spi::get_one::<pgrx::JsonB>(&format!("SELECT {}", expr))
+                .unwrap()
+                .unwrap()
+                .0;
+            assert_eq!(val["t"]["min"], whole, "{}", expr);
+            assert_eq!(val["t"]["max"], frac, "{}", expr);
+        }
+    }
+
+    #[pg_test]
+    fn test_ts_agg_merge_min_max() {
+        let a = "'{\"t\": {\"type\": \"ts_agg\", \"counts\": {\"2024-01-01T00:00:00\": 1}, \"min\": \"2024-01-01T00:00:00\", \"max\": \"2024-01-01T00:00:00\"}}'::jsonb";
+        let b = "'{\"t\": {\"type\": \"ts_agg\", \"counts\": {\"2023-06-01T12:00:00\": 1, \"2024-06-01T12:00:00\": 2}, \"min\": \"2023-06-01T12:00:00\", \"max\": \"2024-06-01T12:00:00\"}}'::jsonb";
+
+        let merged = Spi::get_one::<pgrx::JsonB>(&format!("SELECT jsonb_stats_merge({a}, {b})"))
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(merged["t"]["min"], "2023-06-01T12:00:00");
+        assert_eq!(merged["t"]["max"], "2024-06-01T12:00:00");
+
+        let merged = Spi::get_one::<pgrx::JsonB>(&format!(
+            "SELECT jsonb_stats_merge_agg(agg) FROM (VALUES ({a}), ({b})) AS t(agg)"
+        ))
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(merged["t"]["type"], "ts_agg");
+        assert_eq!(merged["t"]["min"], "2023-06-01T12:00:00");
+        assert_eq!(merged["t"]["max"], "2024-06-01T12:00:00");
+        assert_eq!(merged["t"]["counts"]["2024-06-01T12:00:00"], 2);
+    }
+
+    // ── Time stat type ──
+
+    #[pg_test]
+    fn test_stat_time_keeps_microseconds() {
+        let val = Spi::get_one::<pgrx::JsonB>("SELECT stat('09:05:03.000250'::time)")
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(val, serde_json::json!({"type": "time", "value": "09:05:03.000250"}));
+        let whole = Spi::get_one::<pgrx::JsonB>("SELECT stat('23:59:59'::time)")
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(whole["value"], "23:59:59.000000");
+    }
+
+    #[pg_test]
+    fn test_time_agg_pipeline() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('opens', stat(t)))
+             FROM (VALUES ('10:00:00.5'::time), ('9:30:00.123456'::time)) AS v(t)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["opens"]["type"], "time_agg");
+        assert_eq!(val["opens"]["min"], "09:30:00.123456");
+        assert_eq!(val["opens"]["max"], "10:00:00.500000");
+        assert_eq!(
+            val["opens"]["counts"],
+            serde_json::json!({"09:30:00.123456": 1, "10:00:00.500000": 1})
+        );
+
+        // JSONB path agrees, and a shorter fraction means the same time
+        let jsonb = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT jsonb_stats_final(jsonb_stats_accum(jsonb_stats_accum('{}'::jsonb,
+                   '{"opens": {"type": "time", "value": "10:00:00.5"}}'),
+                   jsonb_build_object('opens', stat('9:30:00.123456'::time))))"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(jsonb["opens"]["counts"], val["opens"]["counts"]);
+        assert_eq!(jsonb["opens"]["min"], "09:30:00.123456");
+        assert_eq!(jsonb["opens"]["max"], "10:00:00.500000");
+    }
+
+    #[pg_test(error = "jsonb_stats: time stat requires an HH:MM:SS[.ffffff] value, got '9:30'")]
+    fn test_time_stat_rejects_malformed_value() {
+        Spi::run(r#"SELECT jsonb_stats_agg('{"t": {"type": "time", "value": "9:30"}}'::jsonb)"#).unwrap();
+    }
+
+    // ── Nested stats ──
+
+    #[pg_test]
+    fn test_nested_stats_two_level_pipeline() {
+        // Level 1 summarizes each region, level 2 merges the regions
+        let merged = Spi::get_one::<pgrx::JsonB>(
+            "WITH data(region, city, zip, n) AS (VALUES
+                 ('north', 'Oslo', 150, 1), ('north', 'Oslo', 151, 2),
+                 ('south', 'Bergen', 5003, 3), ('south', 'Oslo', 152, 4)),
+             by_region AS (SELECT jsonb_stats_agg(jsonb_build_object(
+                               'n', stat(n),
+                               'addr', jsonb_build_object('type', 'stats', 'city', stat(city), 'zip', stat(zip)))) AS agg
+                           FROM data GROUP BY region)
+             SELECT jsonb_stats_merge_agg(agg) FROM by_region",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let addr = &merged["addr"];
+        assert_eq!(addr["type"], "stats_agg");
+        assert_eq!(addr["city"]["counts"], serde_json::json!({"Oslo": 3, "Bergen": 1}));
+        assert_eq!(addr["zip"]["type"], "int_agg");
+        assert_eq!(addr["zip"]["count"], 4);
+        assert_eq!(addr["zip"]["min"], 150);
+        assert_eq!(addr["zip"]["max"], 5003);
+        assert_eq!(merged["n"]["count"], 4);
+    }
+
+    #[pg_test]
+    fn test_nested_stats_jsonb_path_matches_internal() {
+        let (jsonb, internal) = Spi::get_two::<pgrx::JsonB, pgrx::JsonB>(
+            "WITH r(a, b, c) AS (SELECT
+                 jsonb_build_object('addr', jsonb_build_object('type', 'stats', 'city', stat('Oslo'::text), 'zip', stat(150))),
+                 jsonb_build_object('addr', jsonb_build_object('type', 'stats', 'city', stat('Bergen'::text), 'zip', stat(5003))),
+                 jsonb_build_object('addr', jsonb_build_object('type', 'stats', 'city', stat('Oslo'::text), 'zip', '{\"type\": \"int\", \"value\": null}'::jsonb)))
+             SELECT jsonb_stats_final(jsonb_stats_accum(jsonb_stats_accum(jsonb_stats_accum('{}', a), b), c)),
+                    (SELECT jsonb_stats_agg(s) FROM unnest(ARRAY[a, b, c]) AS s)
+             FROM r",
+        )
+        .unwrap();
+        let (jsonb, internal) = (jsonb.unwrap().0, internal.unwrap().0);
+        assert_eq!(jsonb["addr"]["zip"]["null_count"], 1);
+        assert_eq!(jsonb["addr"]["city"]["counts"], serde_json::json!({"Oslo": 2, "Bergen": 1}));
+        assert_eq!(jsonb, internal);
+    }
+
+    #[pg_test(error = "jsonb_stats: type mismatch for key 'addr': int_agg vs stats")]
+    fn test_nested_stats_type_mismatch() {
+        Spi::run(
+            "SELECT jsonb_stats_agg(s) FROM (VALUES
+                 (jsonb_build_object('addr', stat(1))),
+                 (jsonb_build_object('addr', jsonb_build_object('type', 'stats', 'city', stat('Oslo'::text))))) AS t(s)",
+        )
+        .unwrap();
+    }
+
+    // ── stat(value, unit) ──
+
+    #[pg_test]
+    fn test_unit_survives_full_pipeline() {
+        let result = Spi::get_one::<pgrx::JsonB>(
+            "WITH parts AS (
+                 SELECT jsonb_stats_agg(jsonb_build_object('headcount', stat(g, 'employees'))) AS agg
+                 FROM generate_series(1, 10) AS g GROUP BY g % 2)
+             SELECT jsonb_stats_merge_agg(agg) FROM parts",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(result["headcount"]["unit"], "employees");
+        assert_eq!(result["headcount"]["count"], 10);
+    }
+
+    #[pg_test]
+    fn test_unit_survives_jsonb_path() {
+        let result = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_final(jsonb_stats_merge(
+                 jsonb_stats_accum('{}', jsonb_build_object('temp', stat(21.5, 'celsius'))),
+                 jsonb_stats_accum('{}', jsonb_build_object('temp', stat(19.0)))))",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(result["temp"]["unit"], "celsius");
+        assert_eq!(result["temp"]["count"], 2);
+    }
+
+    // ── stats_build ──
+
+    #[pg_test]
+    fn test_stats_build_infers_types() {
+        let result = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT stats_build('{"num": 150, "ratio": 0.5, "ind": "tech", "ok": true, "tags": ["a", "b"], "gone": null}')"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(result["type"], "stats");
+        assert_eq!(result["num"], serde_json::json!({"type": "int", "value": 150}));
+        assert_eq!(result["ratio"]["type"], "float");
+        assert_eq!(result["ind"], serde_json::json!({"type": "str", "value": "tech"}));
+        assert_eq!(result["ok"]["type"], "bool");
+        assert_eq!(result["tags"]["type"], "arr");
+        assert_eq!(result["gone"], serde_json::Value::Null);
+
+        let agg = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT jsonb_stats_agg(stats_build(o)) FROM (VALUES
+                 ('{"num": 1, "ind": "tech"}'::jsonb), ('{"num": 3, "ind": "agri"}'::jsonb)) AS t(o)"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(agg["num"]["type"], "int_agg");
+        assert_eq!(agg["num"]["sum"], 4);
+        assert_eq!(agg["ind"]["counts"], serde_json::json!({"tech": 1, "agri": 1}));
+    }
+
+    // ── str_agg min/max ──
+
+    #[pg_test]
+    fn test_str_agg_min_max() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('c', jsonb_build_object('type', 'str', 'value', v)))
+             FROM unnest(ARRAY['b', 'a', 'c']) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["c"]["min"], "a");
+        assert_eq!(val["c"]["max"], "c");
+
+        // JSONB path agrees
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_final(jsonb_stats_accum(jsonb_stats_accum(jsonb_stats_accum('{}'::jsonb,
+                '{\"c\": {\"type\": \"str\", \"value\": \"b\"}}'::jsonb),
+                '{\"c\": {\"type\": \"str\", \"value\": \"a\"}}'::jsonb),
+                '{\"c\": {\"type\": \"str\", \"value\": \"c\"}}'::jsonb))",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["c"]["min"], "a");
+        assert_eq!(val["c"]["max"], "c");
+    }
+
+    #[pg_test]
+    fn test_str_agg_min_max_merge() {
+        let a = "'{\"c\": {\"type\": \"str_agg\", \"counts\": {\"b\": 1}, \"min\": \"b\", \"max\": \"b\"}}'::jsonb";
+        let b = "'{\"c\": {\"type\": \"str_agg\", \"counts\": {\"a\": 1, \"c\": 1}, \"min\": \"a\", \"max\": \"c\"}}'::jsonb";
+        let merged = Spi::get_one::<pgrx::JsonB>(&format!("SELECT jsonb_stats_merge({a}, {b})"))
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(merged["c"]["min"], "a");
+        assert_eq!(merged["c"]["max"], "c");
+
+        let merged = Spi::get_one::<pgrx::JsonB>(&format!(
+            "SELECT jsonb_stats_merge_agg(agg) FROM (VALUES ({a}), ({b})) AS t(agg)"
+        ))
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(merged["c"]["min"], "a");
+        assert_eq!(merged["c"]["max"], "c");
+
+        // bool_agg has no min/max
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('f', stat(v))) FROM unnest(ARRAY[true, false]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert!(val["f"].get("min").is_none());
+    }
+
+    #[pg_test]
+    fn test_str_agg_numeric_key_range() {
+        let sql = |values: &str| {
+            format!(
+                "SELECT jsonb_stats_agg(jsonb_build_object('zip', stat(v))) FROM unnest(ARRAY[{values}]) AS v"
+            )
+        };
+        let val = Spi::get_one::<pgrx::JsonB>(&sql("'10', '02', '30', '02'"))
+            .unwrap()
+            .unwrap()
+            .0;
+        // Lexical and numeric ranges differ only in how keys compare
+        assert_eq!(val["zip"]["min"], "02");
+        assert_eq!(val["zip"]["max"], "30");
+        assert_eq!(val["zip"]["min_numeric"], 2);
+        assert_eq!(val["zip"]["max_numeric"], 30);
+
+        // "9" sorts after "10" lexically, not numerically
+        let val = Spi::get_one::<pgrx::JsonB>(&sql("'9', '10', '-1.5'")).unwrap().unwrap().0;
+        assert_eq!(val["zip"]["max"], "9");
+        assert_eq!(val["zip"]["min_numeric"].to_string(), "-1.5");
+        assert_eq!(val["zip"]["max_numeric"], 10);
+
+        // One non-numeric key omits both fields
+        let val = Spi::get_one::<pgrx::JsonB>(&sql("'10', '02', 'n/a'")).unwrap().unwrap().0;
+        assert!(val["zip"].get("min_numeric").is_none());
+        assert!(val["zip"].get("max_numeric").is_none());
+
+        // JSONB finalfunc path agrees
+        let val = crate::jsonb_stats_final(pgrx::JsonB(serde_json::json!({
+            "zip": {"type": "str_agg", "counts": {"10": 1, "02": 2, "30": 1}}
+        })))
+        .0;
+        assert_eq!(val["zip"]["min_numeric"], 2);
+        assert_eq!(val["zip"]["max_numeric"], 30);
+    }
+
+    #[pg_test]
+    fn test_str_agg_weighted_median_and_mean() {
+        let val = crate::jsonb_stats_final(pgrx::JsonB(serde_json::json!({
+            "answer": {"type": "str_agg", "counts": {"1": 1, "2": 2, "3": 1}},
+            "even": {"type": "str_agg", "counts": {"1": 1, "4": 1}},
+            "label": {"type": "str_agg", "counts": {"1": 1, "n/a": 2}}
+        })))
+        .0;
+        assert_eq!(val["answer"]["weighted_median"], 2);
+        assert_eq!(val["answer"]["weighted_mean"], 2);
+        // An even total takes the midpoint of the two middle values
+        assert_eq!(val["even"]["weighted_median"].as_f64(), Some(2.5));
+        assert_eq!(val["even"]["weighted_mean"].as_f64(), Some(2.5));
+        assert!(val["label"].get("weighted_median").is_none());
+        assert!(val["label"].get("weighted_mean").is_none());
+
+        // The internal path agrees
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('answer', stat(v))) FROM unnest(ARRAY['1', '2', '2', '3']) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["answer"]["weighted_median"], 2);
+        assert_eq!(val["answer"]["weighted_mean"], 2);
+    }
+
+    #[pg_test]
+    fn test_str_agg_numeric_key_range_matches_plpgsql() {
+        load_plpgsql_reference();
+        let state = "'{\"z\": {\"type\": \"str_agg\", \"counts\": {\"10\": 1, \"02\": 2, \"-1.5\": 1}}, \"w\": {\"type\": \"str_agg\", \"counts\": {\"10\": 1, \"x\": 1}}}'::jsonb";
+        let ok = Spi::get_one::<bool>(&format!(
+            "SELECT jsonb_stats_final({state}) = jsonb_stats_final_plpgsql({state})"
+        ));
+        assert_eq!(ok, Ok(Some(true)));
+    }
+
+    // ── Date validation ──
+
+    #[pg_test(error = "jsonb_stats: date stat requires an ISO-8601 (YYYY-MM-DD) value, got '01/15/2024'")]
+    fn test_date_rejects_non_iso_jsonb_path() {
+        crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({"d": {"type": "date", "value": "01/15/2024"}})),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: date stat requires an ISO-8601 (YYYY-MM-DD) value, got '2023-02-29'")]
+    fn test_date_rejects_invalid_day_internal_path() {
+        Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg('{\"d\": {\"type\": \"date\", \"value\": \"2023-02-29\"}}'::jsonb)",
+        )
+        .unwrap();
+    }
+
+    #[pg_test]
+    fn test_date_accepts_leap_day() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('d', stat('2024-02-29'::date)))",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["d"]["min"], "2024-02-29");
+    }
+
+    #[pg_test]
+    fn test_date_is_iso_under_any_datestyle() {
+        Spi::run("SET LOCAL datestyle = 'SQL, DMY'").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('d', stat(d)))
+             FROM unnest(ARRAY['2024-01-15', '2023-06-01']::date[]) AS d",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["d"]["min"], "2023-06-01");
+        assert_eq!(val["d"]["max"], "2024-01-15");
+    }
+
+    // ── stats_agg_unnest ──
+
+    #[pg_test]
+    fn test_stats_agg_unnest_rows() {
+        let mismatches = Spi::get_one::<i64>(
+            "WITH agg AS (
+                SELECT jsonb_stats_agg(s) AS a FROM (VALUES
+                    ('{\"num\": {\"type\": \"int\", \"value\": 50}, \"name\": {\"type\": \"str\", \"value\": \"b\"}, \"d\": {\"type\": \"date\", \"value\": \"2024-01-15\"}}'::jsonb),
+                    ('{\"num\": {\"type\": \"int\", \"value\": 150}, \"name\": {\"type\": \"str\", \"value\": \"a\"}, \"d\": {\"type\": \"date\", \"value\": \"2023-06-01\"}}'::jsonb)
+                ) AS t(s)
+            ),
+            expected(key, agg_type, count, mean, stddev, min_text, max_text) AS (VALUES
+                ('num', 'int_agg', 2::bigint, 100::float8, 70.71::float8, '50', '150'),
+                ('name', 'str_agg', NULL, NULL, NULL, 'a', 'b'),
+                ('d', 'date_agg', NULL, NULL, NULL, '2023-06-01', '2024-01-15')
+            )
+            SELECT count(*) FROM (SELECT u.* FROM agg, stats_agg_unnest(agg.a) AS u) AS actual
+            FULL JOIN expected USING (key)
+            WHERE actual IS NULL OR expected IS NULL
+               OR (actual.agg_type, actual.count, actual.mean, actual.stddev, actual.min_text, actual.max_text)
+                  IS DISTINCT FROM
+                  (expected.agg_type, expected.count, expected.mean, expected.stddev, expected.min_text, expected.max_text)",
+        );
+        assert_eq!(mismatches, Ok(Some(0)));
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_agg_unnest expects a stats_agg, got type 'stats'")]
+    fn test_stats_agg_unnest_requires_stats_agg() {
+        Spi::run("SELECT * FROM stats_agg_unnest('{\"type\": \"stats\"}'::jsonb)").unwrap();
+    }
+
+    // ── stats_explain ──
+
+    #[pg_test]
+    fn test_stats_explain_snapshot() {
+        let line = crate::stats_explain(pgrx::JsonB(serde_json::json!({
+            "type": "stats_agg",
+            "num": {"type": "int_agg", "count": 1000, "sum": 52300, "min": 1, "max": 99,
+                    "mean": 52.3, "sum_sq_diff": 125318.8, "stddev": 11.2},
+            "ind": {"type": "str_agg", "counts": {"tech": 600, "finance": 400, "retail": 400, "energy": 5},
+                    "null_count": 2},
+            "tags": {"type": "arr_agg", "count": 3, "counts": {"a": 2, "b": 1}},
+            "once": {"type": "float_agg", "count": 1, "sum": 1.5, "min": 1.5, "max": 1.5,
+                     "mean": 1.5, "sum_sq_diff": 0, "stddev": null}
+        })));
+        assert_eq!(
+            line,
+            "ind: tech=600 finance=400 retail=400 nulls=2; num: n=1000 mean=52.3 sd=11.2; \
+             once: n=1 mean=1.5; tags: n=3 a=2 b=1"
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_explain expects a stats_agg, got type 'stats'")]
+    fn test_stats_explain_requires_stats_agg() {
+        Spi::run("SELECT stats_explain('{\"type\": \"stats\"}'::jsonb)").unwrap();
+    }
+
+    // ── stats_types ──
+
+    #[pg_test]
+    fn test_stats_types_of_mixed_agg() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT stats_types(jsonb_stats_agg(stats(jsonb_build_object(
+                 'n', stat(g), 'f', stat(g::float8), 's', stat(g::text), 'd', stat(DATE '2024-01-01' + g)))))
+             FROM generate_series(1, 3) AS g",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(
+            val,
+            serde_json::json!({"n": "int_agg", "f": "float_agg", "s": "str_agg", "d": "date_agg"})
+        );
+    }
+
+    #[pg_test]
+    fn test_stats_types_of_raw_stats() {
+        let val = crate::stats_types(pgrx::JsonB(serde_json::json!({
+            "type": "stats",
+            "n": {"type": "int", "value": 1},
+            "b": {"type": "bool", "value": true}
+        })))
+        .0;
+        assert_eq!(val, serde_json::json!({"n": "int", "b": "bool"}));
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_types expects a stats or stats_agg, got type 'int_agg'")]
+    fn test_stats_types_rejects_summary() {
+        crate::stats_types(pgrx::JsonB(serde_json::json!({"type": "int_agg", "count": 1})));
+    }
+
+    // ── stats_profile ──
+
+    #[pg_test]
+    fn test_stats_profile_of_mixed_agg() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT stats_profile(jsonb_stats_agg(stats(jsonb_build_object(
+                 'a', stat(g), 'b', stat(g * 2), 'c', stat(g * 3),
+                 's', stat(g::text), 't', stat((g * 2)::text), 'ok', stat(g > 1)))))
+             FROM generate_series(1, 3) AS g",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val, serde_json::json!({"int_agg": 3, "str_agg": 2, "bool_agg": 1}));
+    }
+
+    #[pg_test]
+    fn test_stats_profile_of_raw_stats() {
+        let val = crate::stats_profile(pgrx::JsonB(serde_json::json!({
+            "type": "stats",
+            "n": {"type": "int", "value": 1},
+            "m": {"type": "int", "value": 2},
+            "b": {"type": "bool", "value": true}
+        })))
+        .0;
+        assert_eq!(val, serde_json::json!({"int": 2, "bool": 1}));
+    }
+
+    // ── stats_flatten ──
+
+    #[pg_test]
+    fn test_stats_flatten_dotted_keys() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT stats_flatten(jsonb_stats_agg(stats(jsonb_build_object(
+                 'num', stat(g), 'ind', stat(CASE WHEN g % 4 = 0 THEN 'finance' ELSE 'tech' END)))))
+             FROM generate_series(1, 8) AS g",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let flat = val.as_object().unwrap();
+        assert_eq!(flat["num.count"], serde_json::json!(8));
+        assert_eq!(flat["num.mean"].as_f64(), Some(4.5));
+        assert_eq!(flat["num.min"], serde_json::json!(1));
+        assert_eq!(flat["ind.tech"], serde_json::json!(6));
+        assert_eq!(flat["ind.finance"], serde_json::json!(2));
+        assert!(flat.values().all(|v| !v.is_object() && !v.is_array()));
+    }
+
+    // ── stats_diff ──
+
+    #[pg_test]
+    fn test_stats_diff_of_two_finalized_aggs() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "WITH yesterday AS (
+                 SELECT jsonb_stats_agg(jsonb_build_object('n', stat(n), 's', stat(s), 'x', stat(true))) AS agg
+                 FROM (VALUES (1, 'a'), (2, 'a'), (3, 'b')) AS t(n, s)
+             ), today AS (
+                 SELECT jsonb_stats_agg(jsonb_build_object('n', stat(n), 's', stat(s), 'y', stat(1.5::float8))) AS agg
+                 FROM (VALUES (2, 'a'), (4, 'c'), (6, 'c'), (8, 'c')) AS t(n, s)
+             )
+             SELECT stats_diff(yesterday.agg, today.agg) FROM yesterday, today",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["n"]["type"], "int_agg");
+        assert_eq!(val["n"]["count_delta"], 1);
+        assert_eq!(val["n"]["mean_delta"].to_string(), "3.00");
+        assert_eq!(val["n"]["stddev_delta"].to_string(), "1.58");
+        assert_eq!(val["s"]["count_deltas"], serde_json::json!({"a": -1, "b": -1, "c": 3}));
+        assert_eq!(val["s"]["appeared"], serde_json::json!(["c"]));
+        assert_eq!(val["s"]["disappeared"], serde_json::json!(["b"]));
+        assert_eq!(val["x"], serde_json::json!({"only_in": "a"}));
+        assert_eq!(val["y"], serde_json::json!({"only_in": "b"}));
+    }
+
+    #[pg_test]
+    fn test_stats_diff_flags_type_change() {
+        let val = crate::stats_diff(
+            pgrx::JsonB(serde_json::json!({"type": "stats_agg", "k": {"type": "int_agg", "count": 1}})),
+            pgrx::JsonB(serde_json::json!({"type": "stats_agg", "k": {"type": "str_agg", "counts": {"a": 1}}})),
+        )
+        .0;
+        assert_eq!(val["k"], serde_json::json!({"type_changed": {"a": "int_agg", "b": "str_agg"}}));
+    }
+
+    // ── stats_agg_eq ──
+
+    #[pg_test]
+    fn test_stats_agg_eq_ignores_number_formatting_and_key_order() {
+        let ok = Spi::get_one::<bool>(
+            r#"SELECT stats_agg_eq(
+                '{"type": "stats_agg", "n": {"type": "int_agg", "count": 2, "mean": 100, "stddev": 1.414213}, "c": {"type": "str_agg", "counts": {"a": 1, "b": 2}}}'::jsonb,
+                '{"c": {"counts": {"b": 2, "a": 1}, "type": "str_agg"}, "n": {"stddev": 1.41, "mean": 100.00, "count": 2.0, "type": "int_agg"}, "type": "stats_agg"}'::jsonb,
+                0.01)"#,
+        );
+        assert_eq!(ok, Ok(Some(true)));
+
+        // Plain = sees the different formatting of stddev
+        let ok = Spi::get_one::<bool>(
+            r#"SELECT '{"type": "stats_agg", "n": {"stddev": 1.414213}}'::jsonb
+                    = '{"type": "stats_agg", "n": {"stddev": 1.41}}'::jsonb"#,
+        );
+        assert_eq!(ok, Ok(Some(false)));
+    }
+
+    #[pg_test]
+    fn test_stats_agg_eq_detects_differences() {
+        let a = serde_json::json!({"type": "stats_agg", "n": {"type": "int_agg", "mean": 2.0, "variance": null},
+            "c": {"type": "str_agg", "counts": {"a": 1}}});
+        let eq = |b: serde_json::Value, tol: f64| {
+            crate::stats_agg_eq(pgrx::JsonB(a.clone()), pgrx::JsonB(b), tol)
+        };
+        let with = |path: &[&str], v: serde_json::Value| {
+            let mut b = a.clone();
+            let (last, parents) = path.split_last().unwrap();
+            let mut obj = &mut b;
+            for p in parents {
+                obj = &mut obj[*p];
+            }
+            obj[*last] = v;
+            b
+        };
+        assert!(eq(a.clone(), 0.0));
+        assert!(eq(with(&["n", "mean"], serde_json::json!(2.004)), 0.01));
+        assert!(!eq(with(&["n", "mean"], serde_json::json!(2.004)), 0.001));
+        assert!(!eq(with(&["n", "variance"], serde_json::json!(0)), 1.0));
+        assert!(!eq(with(&["c", "counts", "b"], serde_json::json!(1)), 1.0));
+        assert!(!eq(with(&["c", "type"], serde_json::json!("uuid_agg")), 1.0));
+        // Integers past 2^53 are compared exactly, not after rounding to f64
+        let big = |n: u64| serde_json::json!({"type": "stats_agg", "n": {"type": "int_agg", "sum": n}});
+        let big_eq = |x: u64, y: u64, tol: f64| {
+            crate::stats_agg_eq(pgrx::JsonB(big(x)), pgrx::JsonB(big(y)), tol)
+        };
+        assert!(!big_eq(9007199254740993, 9007199254740992, 0.0));
+        assert!(big_eq(9007199254740993, 9007199254740992, 1.0));
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_agg_eq tolerance must be >= 0, got -1")]
+    fn test_stats_agg_eq_rejects_negative_tolerance() {
+        let agg = serde_json::json!({"type": "stats_agg"});
+        crate::stats_agg_eq(pgrx::JsonB(agg.clone()), pgrx::JsonB(agg), -1.0);
+    }
+
+    // ── stats_top ──
+
+    #[pg_test]
+    fn test_stats_top_two() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT stats_top('{"type": "stats_agg",
+                   "tags": {"type": "arr_agg", "count": 4, "counts": {"a": 5, "b": 3, "c": 1}}}'::jsonb, 'tags', 2)"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(
+            val,
+            serde_json::json!([{"value": "a", "count": 5}, {"value": "b", "count": 3}])
+        );
+    }
+
+    #[pg_test]
+    fn test_stats_top_ties_and_other_bucket() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT stats_top(jsonb_stats_agg(jsonb_build_object('c', stat(v))), 'c', 10)
+             FROM unnest(ARRAY['y', 'x', 'z', 'z']) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(
+            val,
+            serde_json::json!([{"value": "z", "count": 2}, {"value": "x", "count": 1}, {"value": "y", "count": 1}])
+        );
+        let pruned = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT stats_top('{"type": "stats_agg",
+                   "c": {"type": "str_agg", "counts": {"a": 2, "__other__": 9}}}'::jsonb, 'c', 5)"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(pruned, serde_json::json!([{"value": "a", "count": 2}]));
+        let absent = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT stats_top('{"type": "stats_agg"}'::jsonb, 'c', 5)"#,
+        );
+        assert!(absent.unwrap().is_none());
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_top key 'n' is int_agg, expected an aggregate with counts")]
+    fn test_stats_top_rejects_numeric() {
+        Spi::run("SELECT stats_top(jsonb_stats_agg(jsonb_build_object('n', stat(1))), 'n', 3)").unwrap();
+    }
+
+    // ── Geometric and harmonic means ──
+
+    #[pg_test]
+    fn test_float_agg_geometric_and_harmonic_mean() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v::float8)))
+             FROM unnest(ARRAY[1, 2, 4, 8]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["x"]["geometric_mean"].to_string(), "2.83");
+        assert_eq!(val["x"]["harmonic_mean"].to_string(), "2.13");
+        assert_eq!(val["x"]["nonpositive_count"], serde_json::json!(0));
+    }
+
+    #[pg_test]
+    fn test_log_means_skip_nonpositive_values() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v::numeric(10,2))))
+             FROM unnest(ARRAY[0, -3, 1, 2, 4, 8]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["x"]["count"], serde_json::json!(6));
+        assert_eq!(val["x"]["nonpositive_count"], serde_json::json!(2));
+        assert_eq!(val["x"]["geometric_mean"].to_string(), "2.83");
+    }
+
+    #[pg_test]
+    fn test_log_means_null_without_positive_values() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v::float8)))
+             FROM unnest(ARRAY[0, -1]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert!(val["x"]["geometric_mean"].is_null());
+        assert!(val["x"]["harmonic_mean"].is_null());
+    }
+
+    #[pg_test]
+    fn test_log_means_survive_merge() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_merge_agg(agg) FROM (
+                 SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v::float8))) AS agg
+                 FROM unnest(ARRAY[1, 2, 4, 8]) AS v
+                 GROUP BY v <= 2
+             ) parts",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["x"]["geometric_mean"].to_string(), "2.83");
+        assert_eq!(val["x"]["harmonic_mean"].to_string(), "2.13");
+    }
+
+    #[pg_test]
+    fn test_log_means_null_for_summary_without_log_sums() {
+        let old = r#"'{"x": {"type": "float_agg", "count": 2, "sum": 3, "min": 1, "max": 2, "mean": 1.5, "sum_sq_diff": 0.5}}'::jsonb"#;
+        for expr in [
+            format!("jsonb_stats_final({})", old),
+            format!("jsonb_stats_merge({0}, {0})", old),
+            format!("(SELECT jsonb_stats_merge_agg(a) FROM (VALUES ({0}), ({0})) AS t(a))", old),
+        ] {
+            let val = Spi::get_one::<pgrx::JsonB>(&format!("SELECT {}", expr))
+                .unwrap()
+                .unwrap()
+                .0;
+            assert!(val["x"]["geometric_mean"].is_null(), "{}", expr);
+            assert!(val["x"]["harmonic_mean"].is_null(), "{}", expr);
+            assert!(val["x"]["sum_log"].is_null(), "{}", expr);
+            assert_eq!(val["x"]["mean"].to_string(), "1.50", "{}", expr);
+        }
+    }
+
+    #[pg_test]
+    fn test_int_agg_has_no_log_means() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v)))
+             FROM unnest(ARRAY[1, 2, 4, 8]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert!(val["x"].get("geometric_mean").is_none());
+        assert!(val["x"].get("sum_log").is_none());
+    }
+
+    // ── NULL values ──
+
+    fn null_mix_sql() -> &'static str {
+        "SELECT jsonb_stats_agg(jsonb_build_object(
+             'n', jsonb_build_object('type', 'int', 'value', n),
+             's', jsonb_build_object('type', 'str', 'value', s)))
+         FROM (VALUES (NULL::int, 'a'), (10, NULL), (20, 'b'), (NULL, 'a')) AS t(n, s)"
+    }
+
+    #[pg_test]
+    fn test_null_values_counted_for_int_and_str() {
+        let val = Spi::get_one::<pgrx::JsonB>(null_mix_sql()).unwrap().unwrap().0;
+        // Nulls do not pollute the numeric summary
+        assert_eq!(val["n"]["count"], serde_json::json!(2));
+        assert_eq!(val["n"]["sum"], serde_json::json!(30));
+        assert_eq!(val["n"]["min"], serde_json::json!(10));
+        assert_eq!(val["n"]["mean"].to_string(), "15.00");
+        assert_eq!(val["n"]["null_count"], serde_json::json!(2));
+        // ...nor create a spurious count entry
+        assert_eq!(val["s"]["counts"], serde_json::json!({"a": 2, "b": 1}));
+        assert_eq!(val["s"]["null_count"], serde_json::json!(1));
+    }
+
+    #[pg_test]
+    fn test_null_count_jsonb_path_matches_internal() {
+        let ok = Spi::get_one::<bool>(&format!(
+            "WITH rows(stats) AS (
+                 SELECT jsonb_build_object(
+                     'n', jsonb_build_object('type', 'int', 'value', n),
+                     's', jsonb_build_object('type', 'str', 'value', s))
+                 FROM (VALUES (NULL::int, 'a'), (10, NULL), (20, 'b'), (NULL, 'a')) AS t(n, s)
+             ),
+             jsonb_path AS (
+                 SELECT jsonb_stats_final(
+                     (SELECT jsonb_stats_accum(
+                         jsonb_stats_accum(
+                             jsonb_stats_accum(jsonb_stats_accum('{{}}'::jsonb, s[1]), s[2]),
+                             s[3]),
+                         s[4])
+                      FROM (SELECT array_agg(stats) AS s FROM rows) a)) AS agg
+             )
+             SELECT agg = ({}) FROM jsonb_path",
+            null_mix_sql()
+        ));
+        assert_eq!(ok, Ok(Some(true)));
+    }
+
+    #[pg_test]
+    fn test_null_count_only_nulls() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('n', jsonb_build_object('type', 'float', 'value', NULL)))
+             FROM generate_series(1, 3)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["n"]["count"], serde_json::json!(0));
+        assert_eq!(val["n"]["null_count"], serde_json::json!(3));
+        assert!(val["n"]["mean"].is_null());
+        assert!(val["n"]["min"].is_null());
+    }
+
+    #[pg_test]
+    fn test_null_count_survives_merge() {
+        let val = Spi::get_one::<pgrx::JsonB>(&format!(
+            "SELECT jsonb_stats_merge_agg(agg) FROM (({}) UNION ALL ({})) parts(agg)",
+            null_mix_sql(),
+            null_mix_sql()
+        ))
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["n"]["count"], serde_json::json!(4));
+        assert_eq!(val["n"]["null_count"], serde_json::json!(4));
+        assert_eq!(val["s"]["null_count"], serde_json::json!(2));
+    }
+
+    #[pg_test]
+    fn test_no_null_count_without_nulls() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(stats('n', v)) FROM generate_series(1, 3) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert!(val["n"].get("null_count").is_none());
+    }
+
+    // ── jsonb_stats_unify_agg ──
+
+    #[pg_test]
+    fn test_unify_agg_mixes_raw_and_aggregated_rows() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT jsonb_stats_unify_agg(s) FROM (VALUES
+                ('{"type": "stats", "n": {"type": "int", "value": 10}, "s": {"type": "str", "value": "a"}}'::jsonb),
+                ((SELECT jsonb_stats_agg(stats(jsonb_build_object('n', stat(v), 's', stat('b'::text))))
+                  FROM unnest(ARRAY[2, 4]) AS v))) AS t(s)"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["type"], "stats_agg");
+        let n = &val["n"];
+        assert_eq!(n["type"], "int_agg");
+        assert_eq!(n["count"], 3);
+        assert_eq!(n["sum"], 16);
+        assert_eq!(n["min"], 2);
+        assert_eq!(n["max"], 10);
+        assert_eq!(val["s"]["counts"], serde_json::json!({"a": 1, "b": 2}));
+    }
+
+    #[pg_test(error = "jsonb_stats: type mismatch for key 'n': str_agg vs int")]
+    fn test_unify_agg_rejects_raw_stat_of_other_type() {
+        Spi::run(
+            r#"SELECT jsonb_stats_unify_agg(s) FROM (VALUES
+                ('{"type": "stats_agg", "n": {"type": "str_agg", "counts": {"a": 1}}}'::jsonb),
+                ('{"type": "stats", "n": {"type": "int", "value": 1}}'::jsonb)) AS t(s)"#,
+        )
+        .unwrap();
+    }
+
+    // ── jsonb_stats_ordered_agg ──
+
+    #[pg_test]
+    fn test_ordered_agg_first_last_out_of_order() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_ordered_agg(d, stats(jsonb_build_object('status', stat(s), 'n', stat(n))))
+             FROM (VALUES ('2024-03-01'::date, 'active', 2),
+                          ('2024-01-15'::date, 'pending', 1),
+                          ('2024-06-30'::date, 'closed', 3),
+                          ('2024-02-01'::date, 'active', 4)) AS t(d, s, n)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let status = &val["status"];
+        assert_eq!(status["first"], "pending");
+        assert_eq!(status["last"], "closed");
+        assert_eq!(status["counts"]["active"], 2);
+        // Numeric entries are summarized but not positioned
+        assert_eq!(val["n"]["count"], 4);
+        assert!(val["n"].get("first").is_none());
+    }
+
+    #[pg_test]
+    fn test_ordered_agg_skips_null_order_keys_and_values() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_ordered_agg(k, jsonb_build_object('s', jsonb_build_object('type', 'str', 'value', s)))
+             FROM (VALUES (NULL::int, 'z'), (10, 'b'), (2, NULL), (5, 'a')) AS t(k, s)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["s"]["first"], "a");
+        assert_eq!(val["s"]["last"], "b");
+        assert_eq!(val["s"]["counts"]["z"], 1);
+        assert_eq!(val["s"]["null_count"], 1);
+    }
+
+    #[pg_test]
+    fn test_plain_agg_has_no_first_last() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(stats(jsonb_build_object('s', stat('x'::text))))",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert!(val["s"].get("first").is_none());
+    }
+
+    // ── jsonb_stats_agg_watermarked ──
+
+    #[pg_test]
+    fn test_watermark_is_latest_input() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg_watermarked(ts, stats('n', n)) FROM (VALUES
+                 ('2024-03-01 12:00:00+00'::timestamptz, 1),
+                 ('2024-05-02 08:30:00+02'::timestamptz, 2),
+                 (NULL, 3),
+                 ('2024-01-15 00:00:00+00'::timestamptz, 4)) AS t(ts, n)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["_watermark"], "2024-05-02T06:30:00Z");
+        // The NULL-ts row is still summarized
+        assert_eq!(val["n"]["count"], 4);
+        assert!(crate::stats_validate(pgrx::JsonB(val.clone())).0["valid"].as_bool().unwrap());
+    }
+
+    #[pg_test]
+    fn test_watermark_survives_merges() {
+        let merged = Spi::get_one::<pgrx::JsonB>(
+            "WITH parts AS (
+                 SELECT jsonb_stats_agg_watermarked(ts, stats('n', n)) AS agg FROM (VALUES
+                     (1, '2024-01-01 00:00:00+00'::timestamptz, 1),
+                     (2, '2024-06-01 00:00:00+00'::timestamptz, 2)) AS t(g, ts, n)
+                 GROUP BY g)
+             SELECT jsonb_stats_merge_agg(agg) FROM parts",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(merged["_watermark"], "2024-06-01T00:00:00Z");
+
+        // JSONB merge: the later one wins whichever side it is on, comparing
+        // fractional seconds as digits
+        let agg = |w: &str| pgrx::JsonB(serde_json::json!({"type": "stats_agg", "_watermark": w}));
+        for (a, b) in [("2024-01-01T00:00:00Z", "2024-01-01T00:00:00.5Z"), ("2024-01-01T00:00:00.5Z", "2024-01-01T00:00:00Z")] {
+            let m = crate::jsonb_stats_merge(agg(a), agg(b)).0;
+            assert_eq!(m["_watermark"], "2024-01-01T00:00:00.5Z");
+        }
+        let finalized = crate::jsonb_stats_final(agg("2024-01-01T00:00:00Z")).0;
+        assert_eq!(finalized["_watermark"], "2024-01-01T00:00:00Z");
+    }
+
+    // ── jsonb_stats.compute_moments ──
+
+    #[pg_test]
+    fn test_compute_moments_off_omits_moment_fields() {
+        Spi::run("SET LOCAL jsonb_stats.compute_moments = off").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) FROM generate_series(1, 10) AS g",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let n = val["n"].as_object().unwrap();
+        assert_eq!(n["count"], 10);
+        assert_eq!(n["mean"].to_string(), "5.50");
+        assert_eq!(n["min"], 1);
+        assert_eq!(n["max"], 10);
+        for field in [
+            "sum_sq_diff",
+            "m3",
+            "m4",
+            "variance",
+            "stddev",
+            "coefficient_of_variation_pct",
+            "skewness",
+            "excess_kurtosis",
+        ] {
+            assert!(!n.contains_key(field), "{} should be absent", field);
+        }
+    }
+
+    #[pg_test]
+    fn test_compute_moments_off_state_merged_into_on_state() {
+        let on = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) FROM generate_series(1, 5) AS g",
+        )
+        .unwrap()
+        .unwrap();
+        Spi::run("SET LOCAL jsonb_stats.compute_moments = off").unwrap();
+        let off = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) FROM generate_series(6, 10) AS g",
+        )
+        .unwrap()
+        .unwrap();
+        let state_off = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_accum(jsonb_stats_accum('{}'::jsonb, jsonb_build_object('n', stat(1))),
+                                      jsonb_build_object('n', stat(5)))",
+        )
+        .unwrap()
+        .unwrap();
+        Spi::run("SET LOCAL jsonb_stats.compute_moments = on").unwrap();
+
+        let merged = crate::jsonb_stats_merge(on, off).0;
+        // Accumulated further with moments on, the skipped values still count
+        let resumed = crate::jsonb_stats_final(crate::jsonb_stats_accum(
+            state_off,
+            pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": 9}})),
+        ))
+        .0;
+        for n in [&merged["n"], &resumed["n"]] {
+            assert!(n["count"] == 10 || n["count"] == 3, "{}", n);
+            for field in ["variance", "stddev", "coefficient_of_variation_pct", "skewness", "excess_kurtosis"] {
+                assert!(n[field].is_null(), "{} should be NULL, not 0: {}", field, n);
+            }
+            assert!(n.get("sum_sq_diff").is_none(), "{}", n);
+        }
+        assert_eq!(merged["n"]["mean"].to_string(), "5.50");
+    }
+
+    // ── Decimal output ──
+
+    #[pg_test]
+    fn test_round_to_keeps_trailing_zeros() {
+        assert_eq!(crate::helpers::round_to(100.0, 2).to_string(), "100.00");
+        assert_eq!(crate::helpers::round_to(-0.5, 3).to_string(), "-0.500");
+        assert_eq!(crate::helpers::round_to(7.0, 0).to_string(), "7");
+        assert_eq!(crate::helpers::cents_value(-5).to_string(), "-0.05");
+        assert_eq!(crate::helpers::decimal_number("1.00e-9").to_string(), "1.00e-9");
+    }
+
+    #[pg_test(error = "jsonb_stats: invalid decimal '1.2.3': invalid number at line 1 column 4")]
+    fn test_decimal_number_rejects_malformed_text() {
+        crate::helpers::decimal_number("1.2.3");
+    }
+
+    // ── jsonb_stats.number_format ──
+
+    #[pg_test]
+    fn test_number_format_auto_keeps_tiny_mean() {
+        let query = "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v)))
+                     FROM (VALUES (1e-9::float8), (1e-9::float8)) AS t(v)";
+        let fixed = Spi::get_one::<pgrx::JsonB>(query).unwrap().unwrap().0;
+        assert_eq!(fixed["x"]["mean"].as_f64(), Some(0.0));
+
+        Spi::run("SET LOCAL jsonb_stats.number_format = 'auto'").unwrap();
+        let auto = Spi::get_one::<pgrx::JsonB>(query).unwrap().unwrap().0;
+        let mean = auto["x"]["mean"].as_f64().unwrap();
+        assert!((mean - 1e-9).abs() < 1e-20, "mean {}", mean);
+        // Raw mergeable fields are full precision in both formats
+        assert_eq!(auto["x"]["sum"], fixed["x"]["sum"]);
+        assert_eq!(auto["x"]["min"], fixed["x"]["min"]);
+    }
+
+    #[pg_test]
+    fn test_number_format_auto_leaves_ordinary_values_fixed() {
+        Spi::run("SET LOCAL jsonb_stats.number_format = 'auto'").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) FROM generate_series(1, 10) AS g",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["n"]["mean"].to_string(), "5.50");
+    }
+
+    // ── min_count / max_count ──
+
+    #[pg_test]
+    fn test_min_count_counts_ties_at_the_extremes() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "WITH parts AS (
+                 SELECT jsonb_stats_agg(jsonb_build_object('n', stat(v), 'd', stat(d))) AS agg
+                 FROM (VALUES (3, '2024-01-02'::date, 1), (1, '2024-01-01', 1), (5, '2024-01-03', 1),
+                              (1, '2024-01-01', 2), (5, '2024-01-03', 2), (5, '2024-01-02', 2)) AS t(v, d, part)
+                 GROUP BY part)
+             SELECT jsonb_stats_merge_agg(agg) FROM parts",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["n"]["min"], 1);
+        assert_eq!(val["n"]["min_count"], 2);
+        assert_eq!(val["n"]["max"], 5);
+        assert_eq!(val["n"]["max_count"], 3);
+        assert_eq!(val["d"]["min"], "2024-01-01");
+        assert_eq!(val["d"]["min_count"], 2);
+        assert_eq!(val["d"]["max_count"], 2);
+
+        // A new, smaller minimum resets the count
+        let state = crate::jsonb_stats_accum(
+            crate::jsonb_stats_accum(
+                pgrx::JsonB(serde_json::json!({})),
+                pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": 4}})),
+            ),
+            pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": 4}})),
+        );
+        assert_eq!(state.0["n"]["min_count"], 2);
+        let state = crate::jsonb_stats_accum(state, pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": 2}})));
+        assert_eq!(state.0["n"]["min_count"], 1);
+        assert_eq!(state.0["n"]["max_count"], 2);
+    }
+
+    #[pg_test]
+    fn test_min_count_survives_inverse_while_ties_remain() {
+        let stat = |v: i64| pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": v}}));
+        let mut state = pgrx::JsonB(serde_json::json!({}));
+        for v in [1, 1, 3] {
+            state = crate::jsonb_stats_accum(state, stat(v));
+        }
+        let state = crate::jsonb_stats_accum_inverse(state, stat(1));
+        assert_eq!(state.0["n"]["min"], 1);
+        assert_eq!(state.0["n"]["min_count"], 1);
+        let state = crate::jsonb_stats_accum_inverse(state, stat(1));
+        assert!(state.0["n"]["min"].is_null());
+        assert!(state.0["n"].get("min_count").is_none());
+    }
+
+    // ── jsonb_stats.track_extremes ──
+
+    #[pg_test]
+    fn test_track_extremes_off_omits_min_max() {
+        Spi::run("SET LOCAL jsonb_stats.track_extremes = off").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) FROM generate_series(1, 10) AS g",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let n = val["n"].as_object().unwrap();
+        assert_eq!(n["count"], 10);
+        assert_eq!(n["mean"].to_string(), "5.50");
+        assert_eq!(n["variance"].to_string(), "9.17");
+        for field in ["min", "max", "range"] {
+            assert!(!n.contains_key(field), "{} should be absent", field);
+        }
+    }
+
+    #[pg_test]
+    fn test_track_extremes_off_side_drops_them_on_merge() {
+        let tracked = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) FROM generate_series(1, 5) AS g",
+        )
+        .unwrap()
+        .unwrap();
+        Spi::run("SET LOCAL jsonb_stats.track_extremes = off").unwrap();
+        let untracked = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) FROM generate_series(6, 10) AS g",
+        )
+        .unwrap()
+        .unwrap();
+        let merged = crate::jsonb_stats_merge(tracked, untracked).0;
+        assert_eq!(merged["n"]["count"], 10);
+        assert!(merged["n"].get("min").is_none());
+        assert!(merged["n"].get("max").is_none());
+    }
+
+    // ── jsonb_stats.date_bucket ──
+
+    #[pg_test]
+    fn test_date_bucket_month() {
+        Spi::run("SET LOCAL jsonb_stats.date_bucket = 'month'").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(stats('d', d::date))
+             FROM generate_series('2024-01-01'::date, '2024-03-10'::date, '1 day') AS d",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let d = &val["d"];
+        assert_eq!(
+            d["counts"],
+            serde_json::json!({"2024-01-01": 31, "2024-02-01": 29, "2024-03-01": 10})
+        );
+        assert_eq!(d["min"], "2024-01-01");
+        assert_eq!(d["max"], "2024-03-10");
+        assert_eq!(d["distinct"], 3);
+    }
+
+    #[pg_test]
+    fn test_date_bucket_week_and_year_jsonb_path() {
+        let state = r#"'{"d": {"type": "date_agg", "counts": {"2024-03-10": 1, "2024-03-11": 2, "2024-03-17": 1, "2023-12-31": 4}}}'::jsonb"#;
+        Spi::run("SET LOCAL jsonb_stats.date_bucket = 'week'").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(&format!("SELECT jsonb_stats_final({})", state))
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(
+            val["d"]["counts"],
+            serde_json::json!({"2023-12-25": 4, "2024-03-04": 1, "2024-03-11": 3})
+        );
+        Spi::run("SET LOCAL jsonb_stats.date_bucket = 'year'").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(&format!("SELECT jsonb_stats_final({})", state))
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(val["d"]["counts"], serde_json::json!({"2023-01-01": 4, "2024-01-01": 4}));
+    }
+
+    // ── jsonb_stats.strict_input ──
+
+    #[pg_test]
+    fn test_non_object_input_passes_through_by_default() {
+        let arr = || pgrx::JsonB(serde_json::json!([1, 2]));
+        let state = crate::jsonb_stats_accum(pgrx::JsonB(serde_json::json!({})), arr()).0;
+        assert_eq!(state, serde_json::json!({}));
+        assert_eq!(crate::jsonb_stats_final(arr()).0, serde_json::json!([1, 2]));
+    }
+
+    #[pg_test(error = "jsonb_stats: expected a JSON object, got array")]
+    fn test_strict_input_accum_rejects_array() {
+        Spi::run("SET LOCAL jsonb_stats.strict_input = on").unwrap();
+        crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!([{"n": {"type": "int", "value": 1}}])),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: expected a JSON object, got array")]
+    fn test_strict_input_merge_rejects_array() {
+        Spi::run("SET LOCAL jsonb_stats.strict_input = on").unwrap();
+        crate::jsonb_stats_merge(
+            pgrx::JsonB(serde_json::json!({"type": "stats_agg"})),
+            pgrx::JsonB(serde_json::json!([])),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: expected a JSON object, got array")]
+    fn test_strict_input_final_rejects_array() {
+        Spi::run("SET LOCAL jsonb_stats.strict_input = on").unwrap();
+        Spi::get_one::<pgrx::JsonB>("SELECT jsonb_stats_final('[1, 2]'::jsonb)").unwrap();
+    }
+
+    // ── jsonb_stats.max_keys / max_array_elements ──
+
+    #[pg_test]
+    fn test_input_limits_allow_input_within_them() {
+        Spi::run("SET LOCAL jsonb_stats.max_keys = 2").unwrap();
+        Spi::run("SET LOCAL jsonb_stats.max_array_elements = 3").unwrap();
+        let agg = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('a', stat(1), 'tags', stat(ARRAY['x', 'y', 'z'])))",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(agg["tags"]["count"], 1);
+    }
+
+    #[pg_test]
+    fn test_max_keys_does_not_count_type_marker() {
+        Spi::run("SET LOCAL jsonb_stats.max_keys = 2").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(stats(jsonb_build_object('a', stat(1), 'b', stat(2))))",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["b"]["count"], 1);
+        let val = crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({
+                "type": "stats",
+                "a": {"type": "int", "value": 1},
+                "b": {"type": "int", "value": 2}
+            })),
+        )
+        .0;
+        assert_eq!(val["b"]["count"], 1);
+    }
+
+    #[pg_test(error = "jsonb_stats: stats object has 3 keys, more than jsonb_stats.max_keys (2)")]
+    fn test_max_keys_rejects_wide_stats() {
+        Spi::run("SET LOCAL jsonb_stats.max_keys = 2").unwrap();
+        Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('a', stat(1), 'b', stat(2), 'c', stat(3)))",
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: stats object has 3 keys, more than jsonb_stats.max_keys (2)")]
+    fn test_max_keys_rejects_wide_stats_in_jsonb_accum() {
+        Spi::run("SET LOCAL jsonb_stats.max_keys = 2").unwrap();
+        crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({
+                "a": {"type": "int", "value": 1},
+                "b": {"type": "int", "value": 2},
+                "c": {"type": "int", "value": 3}
+            })),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: value of 'tags' has 4 elements, more than jsonb_stats.max_array_elements (3)")]
+    fn test_max_array_elements_rejects_long_arr() {
+        Spi::run("SET LOCAL jsonb_stats.max_array_elements = 3").unwrap();
+        Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('tags', stat(ARRAY['a', 'b', 'c', 'd'])))",
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: value of 'tags' has 4 elements, more than jsonb_stats.max_array_elements (3)")]
+    fn test_max_array_elements_rejects_array_literal_in_jsonb_accum() {
+        Spi::run("SET LOCAL jsonb_stats.max_array_elements = 3").unwrap();
+        crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({"tags": {"type": "arr", "value": "{a,b,c,d}"}})),
+        );
+    }
+
+    // ── jsonb_stats.merge_prune_min ──
+
+    fn merge_with_rare_key_sql() -> &'static str {
+        r#"SELECT jsonb_stats_merge_agg(a) FROM (VALUES
+            ('{"x": {"type": "str_agg", "counts": {"a": 3, "b": 1}, "min": "a", "max": "b"}}'::jsonb),
+            ('{"x": {"type": "str_agg", "counts": {"a": 2}, "min": "a", "max": "a"}}'::jsonb)) AS t(a)"#
+    }
+
+    #[pg_test]
+    fn test_merge_prune_min_off_by_default() {
+        let val = Spi::get_one::<pgrx::JsonB>(merge_with_rare_key_sql()).unwrap().unwrap().0;
+        assert_eq!(val["x"]["counts"], serde_json::json!({"a": 5, "b": 1}));
+    }
+
+    #[pg_test]
+    fn test_merge_prune_min_folds_rare_keys() {
+        Spi::run("SET LOCAL jsonb_stats.merge_prune_min = 2").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(merge_with_rare_key_sql()).unwrap().unwrap().0;
+        let x = &val["x"];
+        assert_eq!(x["counts"], serde_json::json!({"a": 5, "__other__": 1}));
+        assert_eq!(x["distinct"], 1);
+        assert_eq!(x["max"], "b");
+    }
+
+    #[pg_test]
+    fn test_merge_prune_min_not_applied_by_combine() {
+        let partial = |agg: serde_json::Value| unsafe {
+            crate::jsonb_stats_merge_sfunc(pgrx::Internal::from(None), Some(pgrx::JsonB(agg)))
+        };
+        let s1 = partial(serde_json::json!({"x": {"type": "str_agg", "counts": {"a": 3, "b": 1}}}));
+        let s2 = partial(serde_json::json!({"x": {"type": "str_agg", "counts": {"a": 2}}}));
+        Spi::run("SET LOCAL jsonb_stats.merge_prune_min = 2").unwrap();
+        let combined = unsafe { crate::jsonb_stats_combine(s1, s2) };
+        let val = unsafe { crate::jsonb_stats_final_internal(combined) }.0;
+        assert_eq!(val["x"]["counts"], serde_json::json!({"a": 5, "b": 1}));
+    }
+
+    // ── Inverse transition (moving windows) ──
+
+    #[pg_test]
+    fn test_accum_inverse_restores_mean_and_sum() {
+        let before = crate::jsonb_stats_accum(
+            crate::jsonb_stats_accum(
+                pgrx::JsonB(serde_json::json!({})),
+                pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": 10}})),
+            ),
+            pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": 20}})),
+        );
+        let added = crate::jsonb_stats_accum(
+            pgrx::JsonB(before.0.clone()),
+            pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": 30}})),
+        );
+        let removed = crate::jsonb_stats_accum_inverse(
+            added,
+            pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": 30}})),
+        );
+        for field in ["count", "sum", "mean", "sum_sq_diff", "min"] {
+            assert_eq!(removed.0["n"][field], before.0["n"][field], "field {}", field);
+        }
+        // The removed value was the max, which cannot be recovered
+        assert!(removed.0["n"]["max"].is_null());
+    }
+
+    #[pg_test]
+    fn test_accum_inverse_count_maps() {
+        let mut state = pgrx::JsonB(serde_json::json!({}));
+        for v in ["b", "a", "c", "a"] {
+            state = crate::jsonb_stats_accum(
+                state,
+                pgrx::JsonB(serde_json::json!({"s": {"type": "str", "value": v}})),
+            );
+        }
+        let remove = |state, v: &str| {
+            crate::jsonb_stats_accum_inverse(
+                state,
+                pgrx::JsonB(serde_json::json!({"s": {"type": "str", "value": v}})),
+            )
+        };
+        state = remove(state, "c");
+        assert_eq!(state.0["s"]["counts"], serde_json::json!({"a": 2, "b": 1}));
+        assert_eq!(state.0["s"]["max"], "b");
+        state = remove(remove(remove(state, "a"), "b"), "a");
+        assert!(state.0.get("s").is_none(), "emptied summary is dropped");
+    }
+
+    #[pg_test]
+    fn test_accum_inverse_moving_window_aggregate() {
+        Spi::run(
+            "CREATE AGGREGATE jsonb_stats_moving_agg(jsonb) (
+                 sfunc = jsonb_stats_accum, stype = jsonb, initcond = '{}',
+                 finalfunc = jsonb_stats_final,
+                 msfunc = jsonb_stats_accum, minvfunc = jsonb_stats_accum_inverse,
+                 mstype = jsonb, minitcond = '{}', mfinalfunc = jsonb_stats_final
+             )",
+        )
+        .unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT agg FROM (
+                 SELECT v, jsonb_stats_moving_agg(stats('n', v))
+                        OVER (ORDER BY v ROWS BETWEEN 1 PRECEDING AND CURRENT ROW) AS agg
+                 FROM generate_series(1, 5) AS v
+             ) w WHERE v = 5",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["n"]["count"], serde_json::json!(2));
+        assert_eq!(val["n"]["sum"], serde_json::json!(9));
+        assert_eq!(val["n"]["mean"].to_string(), "4.50");
+        assert_eq!(val["n"]["max"], serde_json::json!(5));
+    }
+
+    #[pg_test(error = "jsonb_stats: cannot remove key 'n': not in state")]
+    fn test_accum_inverse_rejects_unknown_key() {
+        crate::jsonb_stats_accum_inverse(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": 1}})),
+        );
+    }
+
+    // ── Skewness and kurtosis ──
+
+    #[pg_test]
+    fn test_skewness_and_kurtosis_of_skewed_sample() {
+        // Matches scipy.stats.skew/kurtosis with bias=False
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v)))
+             FROM unnest(ARRAY[1, 2, 3, 4, 100]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["x"]["m3"].to_string(), "444600.00");
+        assert_eq!(val["x"]["m4"].to_string(), "37604834.00");
+        assert_eq!(val["x"]["skewness"].to_string(), "2.23");
+        assert_eq!(val["x"]["excess_kurtosis"].to_string(), "4.99");
+    }
+
+    #[pg_test]
+    fn test_skewness_and_kurtosis_survive_merge() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_merge_agg(agg) FROM (
+                 SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v))) AS agg
+                 FROM unnest(ARRAY[1, 2, 3, 4, 100]) AS v
+                 GROUP BY v % 2
+             ) parts",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["x"]["skewness"].to_string(), "2.23");
+        assert_eq!(val["x"]["excess_kurtosis"].to_string(), "4.99");
+    }
+
+    #[pg_test]
+    fn test_shape_stats_null_for_summary_without_moments() {
+        let old = r#"'{"x": {"type": "int_agg", "count": 4, "sum": 10, "min": 1, "max": 4, "mean": 2.5, "sum_sq_diff": 5}}'::jsonb"#;
+        for expr in [
+            format!("jsonb_stats_final({})", old),
+            format!("jsonb_stats_merge({0}, {0})", old),
+            format!("(SELECT jsonb_stats_merge_agg(a) FROM (VALUES ({0}), ({0})) AS t(a))", old),
+        ] {
+            let val = Spi::get_one::<pgrx::JsonB>(&format!("SELECT {}", expr))
+                .unwrap()
+                .unwrap()
+                .0;
+            assert!(val["x"]["skewness"].is_null(), "{}", expr);
+            assert!(val["x"]["excess_kurtosis"].is_null(), "{}", expr);
+            assert!(!val["x"]["variance"].is_null(), "{}", expr);
+        }
+    }
+
+    #[pg_test]
+    fn test_skewness_and_kurtosis_need_enough_values() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v)))
+             FROM unnest(ARRAY[1, 2, 10]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["x"]["skewness"].to_string(), "1.65");
+        assert!(val["x"]["excess_kurtosis"].is_null());
+
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('x', stat(v)))
+             FROM unnest(ARRAY[5, 5, 5, 5]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert!(val["x"]["skewness"].is_null(), "constant values have no shape");
+        assert!(val["x"]["excess_kurtosis"].is_null());
+    }
+
+    #[pg_test]
+    fn test_accum_inverse_reverses_moments() {
+        let mut state = pgrx::JsonB(serde_json::json!({}));
+        for v in [1, 2, 3, 4, 100] {
+            state = crate::jsonb_stats_accum(
+                state,
+                pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": v}})),
+            );
+        }
+        let removed = crate::jsonb_stats_accum_inverse(
+            state,
+            pgrx::JsonB(serde_json::json!({"n": {"type": "int", "value": 100}})),
+        );
+        // 1, 2, 3, 4: symmetric, so m3 = 0; m4 = 2 * (1.5^4 + 0.5^4)
+        let n = &removed.0["n"];
+        assert!(n["m3"].as_f64().unwrap().abs() < 1e-9, "m3 = {}", n["m3"]);
+        assert!((n["m4"].as_f64().unwrap() - 10.25).abs() < 1e-9, "m4 = {}", n["m4"]);
+    }
+
+    // ── bool_agg true_ratio ──
+
+    #[pg_test]
+    fn test_bool_agg_true_ratio() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('b', stat(v)))
+             FROM unnest(ARRAY[true, false, true, NULL, true]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["b"]["total"], 4);
+        assert_eq!(val["b"]["true_ratio"].to_string(), "0.75");
+
+        // JSONB path; a missing key counts as zero
+        let val = crate::jsonb_stats_final(pgrx::JsonB(serde_json::json!({
+            "b": {"type": "bool_agg", "counts": {"true": 3, "false": 1}},
+            "f": {"type": "bool_agg", "counts": {"false": 2}},
+            "n": {"type": "bool_agg", "counts": {}, "null_count": 2}
+        })))
+        .0;
+        assert_eq!(val["b"]["true_ratio"].to_string(), "0.75");
+        assert_eq!(val["f"]["total"], 2);
+        assert_eq!(val["f"]["true_ratio"].to_string(), "0.00");
+        assert_eq!(val["n"]["total"], 0);
+        assert!(val["n"]["true_ratio"].is_null());
+    }
+
+    #[pg_test]
+    fn test_bool_agg_true_ratio_matches_plpgsql() {
+        load_plpgsql_reference();
+        let state = "'{\"b\": {\"type\": \"bool_agg\", \"counts\": {\"true\": 3, \"false\": 1}}, \"f\": {\"type\": \"bool_agg\", \"counts\": {\"false\": 2}}}'::jsonb";
+        let ok = Spi::get_one::<bool>(&format!(
+            "SELECT jsonb_stats_final({state}) = jsonb_stats_final_plpgsql({state})"
+        ));
+        assert_eq!(ok, Ok(Some(true)));
+    }
+
+    // ── Entropy ──
+
+    #[pg_test]
+    fn test_bool_agg_even_split_has_one_bit() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('b', stat(g % 2 = 0)))
+             FROM generate_series(1, 10) AS g",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["b"]["entropy_bits"].as_f64(), Some(1.0));
+        assert_eq!(val["b"]["normalized_entropy"].as_f64(), Some(1.0));
+    }
+
+    #[pg_test]
+    fn test_entropy_of_constant_and_skewed_columns() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('c', stat('x'::text), 's', stat(s)))
+             FROM unnest(ARRAY['a', 'a', 'a', 'b']) AS s",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["c"]["entropy_bits"].to_string(), "0.00");
+        assert!(val["c"]["normalized_entropy"].is_null(), "one distinct value");
+        // -(0.75·log2 0.75 + 0.25·log2 0.25) = 0.811
+        assert_eq!(val["s"]["entropy_bits"].to_string(), "0.81");
+        assert_eq!(val["s"]["normalized_entropy"].to_string(), "0.81");
+    }
+
+    #[pg_test]
+    fn test_entropy_from_jsonb_state() {
+        let val = crate::jsonb_stats_final(pgrx::JsonB(serde_json::json!({
+            "tags": {"type": "arr_agg", "count": 2, "counts": {"a": 1, "b": 1, "c": 1, "d": 1}}
+        })))
+        .0;
+        assert_eq!(val["tags"]["entropy_bits"].to_string(), "2.00");
+        assert_eq!(val["tags"]["normalized_entropy"].to_string(), "1.00");
+    }
+
+    // ── Concentration (gini, top_share) ──
+
+    #[pg_test]
+    fn test_concentration_of_dominant_value() {
+        let val = crate::jsonb_stats_final(pgrx::JsonB(serde_json::json!({
+            "s": {"type": "str_agg", "counts": {"a": 90, "b": 10}}
+        })))
+        .0;
+        assert_eq!(val["s"]["top_share"].to_string(), "0.90");
+        // 1 - (0.9² + 0.1²)
+        assert_eq!(val["s"]["gini"].to_string(), "0.18");
+    }
+
+    #[pg_test]
+    fn test_concentration_in_date_agg() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('d', stat(d)))
+             FROM unnest(ARRAY['2024-01-01', '2024-01-01', '2024-01-01', '2024-01-02', '2024-01-03', '2024-01-04']::date[]) AS d",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["d"]["top_share"].to_string(), "0.50");
+        assert_eq!(val["d"]["gini"].to_string(), "0.67");
+    }
+
+    // ── jsonb_stats.on_unknown_type ──
+
+    fn unknown_type_rows_sql() -> &'static str {
+        r#"SELECT jsonb_stats_agg(s) FROM (VALUES
+            ('{"n": {"type": "int", "value": 1}}'::jsonb),
+            ('{"n": {"type": "int", "value": 3}, "x": {"type": "foo", "value": 1}}'::jsonb)) AS t(s)"#
+    }
+
+    #[pg_test(error = "jsonb_stats: unknown stat type 'foo'. Expected: int, float, dec2, nat, str, bool, arr, date, ts, time, uuid, inet, interval, stats")]
+    fn test_on_unknown_type_error_aborts_agg() {
+        Spi::run(unknown_type_rows_sql()).unwrap();
+    }
+
+    #[pg_test]
+    fn test_on_unknown_type_skip() {
+        Spi::run("SET LOCAL jsonb_stats.on_unknown_type = 'skip'").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(unknown_type_rows_sql()).unwrap().unwrap().0;
+        assert_eq!(val["n"]["count"], 2);
+        assert!(val.get("x").is_none());
+
+        // JSONB path, including a null value of the unknown type
+        let state = crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({
+                "n": {"type": "int", "value": 1},
+                "x": {"type": "foo", "value": 1},
+                "y": {"type": "foo", "value": null}
+            })),
+        )
+        .0;
+        assert_eq!(state["n"]["count"], 1);
+        assert!(state.get("x").is_none());
+        assert!(state.get("y").is_none());
+    }
+
+    // ── jsonb_stats.infer_type ──
+
+    fn untyped_rows_sql() -> &'static str {
+        r#"SELECT jsonb_stats_agg(s) FROM (VALUES
+            ('{"num": {"value": 150}, "name": {"value": "a"}, "ok": {"value": true}, "tags": {"value": ["x", "y"]}}'::jsonb),
+            ('{"num": {"value": 50.5}, "name": {"value": "b"}, "ok": {"value": false}, "tags": {"value": ["x"]}}'::jsonb),
+            ('{"num": {"value": null}, "n": {"type": "int", "value": 1}}'::jsonb)) AS t(s)"#
+    }
+
+    #[pg_test]
+    fn test_infer_type_off_skips_untyped_stats() {
+        let val = Spi::get_one::<pgrx::JsonB>(untyped_rows_sql()).unwrap().unwrap().0;
+        assert_eq!(val["n"]["count"], 1);
+        for key in ["num", "name", "ok", "tags"] {
+            assert!(val.get(key).is_none(), "key {}", key);
+        }
+    }
+
+    #[pg_test]
+    fn test_infer_type_from_value_kind() {
+        Spi::run("SET LOCAL jsonb_stats.infer_type = on").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(untyped_rows_sql()).unwrap().unwrap().0;
+        assert_eq!(val["num"]["type"], "float_agg");
+        assert_eq!(val["num"]["count"], 2);
+        assert_eq!(val["num"]["sum"].to_string(), "200.5");
+        // A null value says nothing about the type, so it is skipped too
+        assert!(val["num"].get("null_count").is_none());
+        assert_eq!(val["name"]["type"], "str_agg");
+        assert_eq!(val["name"]["counts"], serde_json::json!({"a": 1, "b": 1}));
+        assert_eq!(val["ok"]["type"], "bool_agg");
+        assert_eq!(val["tags"]["type"], "arr_agg");
+        assert_eq!(val["tags"]["counts"], serde_json::json!({"x": 2, "y": 1}));
+        assert_eq!(val["n"]["count"], 1);
+
+        // JSONB path agrees
+        let state = crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({"num": {"value": 150}, "name": {"value": "a"}})),
+        )
+        .0;
+        assert_eq!(state["num"]["type"], "float_agg");
+        assert_eq!(state["name"]["type"], "str_agg");
+    }
+
+    #[pg_test(error = "jsonb_stats: type mismatch for key 'num': float_agg vs int")]
+    fn test_infer_type_then_explicit_type_mismatch() {
+        Spi::run("SET LOCAL jsonb_stats.infer_type = on").unwrap();
+        Spi::run(
+            r#"SELECT jsonb_stats_agg(s) FROM (VALUES
+                ('{"num": {"value": 1}}'::jsonb),
+                ('{"num": {"type": "int", "value": 2}}'::jsonb)) AS t(s)"#,
+        )
+        .unwrap();
+    }
+
+    // ── Type consistency across accumulation ──
+
+    #[pg_test(error = "jsonb_stats: str stat requires a string 'value', got 1")]
+    fn test_agg_rejects_number_in_str_key() {
+        // "1" and 1 would otherwise both count under the key "1"
+        Spi::run(
+            r#"SELECT jsonb_stats_agg(s) FROM (VALUES
+                ('{"code": {"type": "str", "value": "1"}}'::jsonb),
+                ('{"code": {"type": "str", "value": 1}}'::jsonb)) AS t(s)"#,
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: str stat requires a string 'value', got 1")]
+    fn test_accum_rejects_number_in_str_key() {
+        let state = crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({"code": {"type": "str", "value": "1"}})),
+        );
+        crate::jsonb_stats_accum(
+            state,
+            pgrx::JsonB(serde_json::json!({"code": {"type": "str", "value": 1}})),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: bool stat requires a boolean 'value', got \"true\"")]
+    fn test_agg_rejects_string_in_bool_key() {
+        Spi::run(r#"SELECT jsonb_stats_agg('{"ok": {"type": "bool", "value": "true"}}'::jsonb)"#).unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: type mismatch for key 'num': int_agg vs str")]
+    fn test_agg_rejects_type_change_for_key() {
+        Spi::run(
+            r#"SELECT jsonb_stats_agg(s) FROM (VALUES
+                ('{"num": {"type": "int", "value": 1}}'::jsonb),
+                ('{"num": {"type": "str", "value": "a"}}'::jsonb)) AS t(s)"#,
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: type mismatch for key 'num': int_agg vs str")]
+    fn test_accum_rejects_type_change_for_key() {
+        let state = crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({"num": {"type": "int", "value": 1}})),
+        );
+        crate::jsonb_stats_accum(
+            state,
+            pgrx::JsonB(serde_json::json!({"num": {"type": "str", "value": "a"}})),
+        );
+    }
+
+    #[pg_test(error = "jsonb_stats: type mismatch for key 'num': int_agg vs str")]
+    fn test_agg_rejects_null_of_other_type() {
+        Spi::run(
+            r#"SELECT jsonb_stats_agg(s) FROM (VALUES
+                ('{"num": {"type": "int", "value": 1}}'::jsonb),
+                ('{"num": {"type": "str", "value": null}}'::jsonb)) AS t(s)"#,
+        )
+        .unwrap();
+    }
+
+    // ── Multi-value str stats ──
+
+    #[pg_test]
+    fn test_str_array_value_counts_each_element() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT jsonb_stats_agg(s) FROM (VALUES
+                ('{"tags": {"type": "str", "value": ["a", "a", "b"]}}'::jsonb)) AS t(s)"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let tags = &val["tags"];
+        assert_eq!(tags["type"], "str_agg");
+        assert_eq!(tags["counts"], serde_json::json!({"a": 2, "b": 1}));
+        assert_eq!(tags["min"], "a");
+        assert_eq!(tags["max"], "b");
+        assert!(tags.get("count").is_none());
+    }
+
+    #[pg_test]
+    fn test_str_array_mixes_with_scalar_values() {
+        let rows = [
+            serde_json::json!({"tags": {"type": "str", "value": ["b", "a", "b"]}}),
+            serde_json::json!({"tags": {"type": "str", "value": "c"}}),
+            serde_json::json!({"tags": {"type": "str", "value": []}}),
+        ];
+        let state = rows.into_iter().fold(pgrx::JsonB(serde_json::json!({})), |state, row| {
+            crate::jsonb_stats_accum(state, pgrx::JsonB(row))
+        });
+        let tags = &state.0["tags"];
+        assert_eq!(tags["counts"], serde_json::json!({"a": 1, "b": 2, "c": 1}));
+        assert_eq!(tags["min"], "a");
+        assert_eq!(tags["max"], "c");
+    }
+
+    #[pg_test(error = "jsonb_stats: str stat array elements must be strings, got null")]
+    fn test_str_array_rejects_null_element() {
+        crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({"tags": {"type": "str", "value": ["a", null]}})),
+        );
+    }
+
+    // ── uuid stats ──
+
+    #[pg_test]
+    fn test_stat_uuid() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT stat('A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11'::uuid)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val, serde_json::json!({"type": "uuid", "value": "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11"}));
+    }
+
+    #[pg_test]
+    fn test_uuid_agg_pipeline() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "WITH events(kind) AS (VALUES
+                ('a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'::uuid),
+                ('6ba7b810-9dad-11d1-80b4-00c04fd430c8'::uuid),
+                ('a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11'::uuid))
+             SELECT jsonb_stats_merge_agg(agg) FROM (
+                 SELECT jsonb_stats_agg(stats('kind', kind)) AS agg FROM events GROUP BY kind
+             ) per_kind",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let kind = &val["kind"];
+        assert_eq!(kind["type"], "uuid_agg");
+        assert_eq!(kind["counts"]["a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11"], 2);
+        assert_eq!(kind["counts"]["6ba7b810-9dad-11d1-80b4-00c04fd430c8"], 1);
+        assert_eq!(kind["distinct"], 2);
+        assert_eq!(kind["mode"], "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11");
+        assert!(kind.get("min").is_none());
+    }
+
+    #[pg_test]
+    fn test_uuid_counts_are_case_insensitive() {
+        let val = crate::jsonb_stats_accum(
+            crate::jsonb_stats_accum(
+                pgrx::JsonB(serde_json::json!({})),
+                pgrx::JsonB(serde_json::json!({"u": {"type": "uuid", "value": "A0EEBC99-9C0B-4EF8-BB6D-6BB9BD380A11"}})),
+            ),
+            pgrx::JsonB(serde_json::json!({"u": {"type": "uuid", "value": "a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11"}})),
+        )
+        .0;
+        assert_eq!(val["u"]["counts"], serde_json::json!({"a0eebc99-9c0b-4ef8-bb6d-6bb9bd380a11": 2}));
+    }
+
+    #[pg_test(error = "jsonb_stats: uuid stat requires a hyphenated UUID string 'value', got \"not-a-uuid\"")]
+    fn test_uuid_invalid_value() {
+        crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({"u": {"type": "uuid", "value": "not-a-uuid"}})),
+        );
+    }
+
+    // ── inet stats ──
+
+    #[pg_test]
+    fn test_stat_inet_and_cidr() {
+        let (host, net) = Spi::get_two::<pgrx::JsonB, pgrx::JsonB>(
+            "SELECT stat('192.168.1.5'::inet), stat('2001:DB8::/32'::cidr)",
+        )
+        .unwrap();
+        assert_eq!(host.unwrap().0, serde_json::json!({"type": "inet", "value": "192.168.1.5"}));
+        assert_eq!(net.unwrap().0, serde_json::json!({"type": "inet", "value": "2001:db8::/32"}));
+    }
+
+    #[pg_test]
+    fn test_inet_rollup_same_slash_24() {
+        let query = "SELECT jsonb_stats_agg(stats('ip', ip))
+                     FROM (VALUES ('10.1.2.3'::inet), ('10.1.2.77'::inet), ('10.1.2.200'::inet)) AS t(ip)";
+        let val = Spi::get_one::<pgrx::JsonB>(query).unwrap().unwrap().0;
+        assert_eq!(val["ip"]["type"], "inet_agg");
+        assert_eq!(val["ip"]["distinct"], 3);
+
+        Spi::run("SET LOCAL jsonb_stats.inet_rollup = on").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(query).unwrap().unwrap().0;
+        assert_eq!(val["ip"]["counts"], serde_json::json!({"10.1.2.0/24": 3}));
+        assert_eq!(val["ip"]["distinct"], 1);
+        assert_eq!(val["ip"]["mode"], "10.1.2.0/24");
+
+        // JSONB finalfunc path, IPv6 to /48
+        let val = crate::jsonb_stats_final(pgrx::JsonB(serde_json::json!({
+            "ip": {"type": "inet_agg", "counts": {"2001:db8:1:2::1": 2, "2001:db8:1:ff::9": 1, "10.0.0.0/8": 4}}
+        })))
+        .0;
+        assert_eq!(val["ip"]["counts"], serde_json::json!({"2001:db8:1::/48": 3, "10.0.0.0/8": 4}));
+    }
+
+    #[pg_test]
+    fn test_inet_agg_merge() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "WITH hits(region, ip) AS (VALUES ('eu', '10.0.0.1'::inet), ('eu', '10.0.0.2'::inet), ('us', '10.0.0.1'::inet))
+             SELECT jsonb_stats_merge_agg(agg) FROM (
+                 SELECT jsonb_stats_agg(stats('ip', ip)) AS agg FROM hits GROUP BY region
+             ) per_region",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["ip"]["counts"], serde_json::json!({"10.0.0.1": 2, "10.0.0.2": 1}));
+    }
+
+    #[pg_test]
+    fn test_inet_agg_counts_per_family() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(stats('ip', ip))
+             FROM (VALUES ('10.0.0.1'::inet), ('10.0.0.1'::inet), ('::1'::inet)) AS t(ip)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["ip"]["counts"], serde_json::json!({"10.0.0.1": 2, "::1": 1}));
+        assert_eq!(val["ip"]["ipv4_count"], 2);
+        assert_eq!(val["ip"]["ipv6_count"], 1);
+
+        // JSONB finalfunc path; merging drops the derived counts and recomputes them
+        let agg = || pgrx::JsonB(serde_json::json!({"ip": {"type": "inet_agg", "counts": {"10.0.0.1": 1, "::1": 3}}}));
+        let merged = crate::jsonb_stats_final(crate::jsonb_stats_merge(agg(), agg())).0;
+        assert_eq!(merged["ip"]["ipv4_count"], 2);
+        assert_eq!(merged["ip"]["ipv6_count"], 6);
+    }
+
+    #[pg_test(error = "jsonb_stats: inet stat requires an IP address 'value', got '10.0.0.300'")]
+    fn test_inet_invalid_value() {
+        crate::jsonb_stats_accum(
+            pgrx::JsonB(serde_json::json!({})),
+            pgrx::JsonB(serde_json::json!({"ip": {"type": "inet", "value": "10.0.0.300"}})),
+        );
+    }
+
+    // ── interval stats ──
+
+    #[pg_test]
+    fn test_stat_interval() {
+        let val = Spi::get_one::<pgrx::JsonB>("SELECT stat('1 day 00:00:01.5'::interval)")
+            .unwrap()
+            .unwrap()
+            .0;
+        assert_eq!(val["type"], "interval");
+        assert_eq!(val["value"].as_f64(), Some(86401.5));
+    }
+
+    #[pg_test]
+    fn test_interval_agg_mean() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(stats('d', d))
+             FROM unnest(ARRAY['1 hour', '3 hours']::interval[]) AS d",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let d = &val["d"];
+        assert_eq!(d["type"], "interval_agg");
+        assert_eq!(d["count"], 2);
+        assert_eq!(d["mean"].as_f64(), Some(7200.0));
+        assert_eq!(d["mean_interval"], "02:00:00");
+        let sql = format!("SELECT '{}'::interval = '2 hours'", d["mean_interval"].as_str().unwrap());
+        assert_eq!(Spi::get_one::<bool>(&sql), Ok(Some(true)));
+    }
+
+    // ── Large magnitudes ──
+
+    #[pg_test]
+    fn test_num_value_keeps_large_magnitudes() {
+        assert_eq!(crate::helpers::num_value(1e19).to_string(), "10000000000000000000");
+        assert_eq!(crate::helpers::num_value(-1.5e300).to_string(), "-1.5e300");
+        assert_eq!(crate::helpers::num_value(f64::INFINITY), serde_json::Value::Null);
+    }
+
+    #[pg_test]
+    fn test_float_agg_max_near_1e19() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT jsonb_stats_agg(s) FROM (VALUES
+                ('{"x": {"type": "float", "value": 1.0}}'::jsonb),
+                ('{"x": {"type": "float", "value": 9.9e18}}'::jsonb),
+                ('{"x": {"type": "float", "value": 1.5e19}}'::jsonb)) AS t(s)"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["x"]["max"].to_string(), "15000000000000000000");
+        assert_eq!(val["x"]["min"], 1);
+        assert!(val["x"]["sum"].is_number());
+    }
+
+    // ── stat_as ──
+
+    #[pg_test]
+    fn test_stat_as_coerces_text_to_int() {
+        let val = Spi::get_one::<pgrx::JsonB>("SELECT stat_as('42'::text, 'int')").unwrap().unwrap().0;
+        assert_eq!(val, serde_json::json!({"type": "int", "value": 42}));
+    }
+
+    #[pg_test]
+    fn test_stat_as_other_targets() {
+        let get = |sql: &str| Spi::get_one::<pgrx::JsonB>(sql).unwrap().unwrap().0;
+        assert_eq!(get("SELECT stat_as(7, 'str')"), serde_json::json!({"type": "str", "value": "7"}));
+        assert_eq!(get("SELECT stat_as('2.5'::text, 'float')")["value"].to_string(), "2.5");
+        assert_eq!(get("SELECT stat_as('19.99'::text, 'dec2')")["value"].to_string(), "19.99");
+        assert_eq!(get("SELECT stat_as('yes'::text, 'bool')")["value"], true);
+        assert_eq!(get("SELECT stat_as('2024-03-01'::text, 'date')")["value"], "2024-03-01");
+        assert_eq!(get("SELECT stat_as(DATE '2024-03-01', 'str')")["value"], "2024-03-01");
+    }
+
+    #[pg_test(error = "jsonb_stats: cannot coerce \"x\" to int")]
+    fn test_stat_as_rejects_non_numeric_int() {
+        Spi::get_one::<pgrx::JsonB>("SELECT stat_as('x'::text, 'int')").unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: cannot coerce \"-3\" to nat")]
+    fn test_stat_as_rejects_negative_nat() {
+        Spi::get_one::<pgrx::JsonB>("SELECT stat_as('-3'::text, 'nat')").unwrap();
+    }
+
+    // ── money stats ──
+
+    #[pg_test]
+    fn test_stat_money() {
+        let val = Spi::get_one::<pgrx::JsonB>("SELECT stat(19.99::money)").unwrap().unwrap().0;
+        assert_eq!(val, serde_json::json!({"type": "dec2", "value": 19.99}));
+        let neg = Spi::get_one::<pgrx::JsonB>("SELECT stat((-0.05)::money)").unwrap().unwrap().0;
+        assert_eq!(neg["value"].to_string(), "-0.05");
+    }
+
+    #[pg_test]
+    fn test_money_agg_as_dec2() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(stats('price', p))
+             FROM unnest(ARRAY[19.99, 0.01, 5.00]::money[]) AS p",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let price = &val["price"];
+        assert_eq!(price["type"], "dec2_agg");
+        assert_eq!(price["count"], 3);
+        assert_eq!(price["sum"].to_string(), "25.00");
+    }
+
+    // ── stats_validate ──
+
+    #[pg_test]
+    fn test_validate_reports_malformed_entries() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT stats_validate('{"type": "stats",
+                "a": {"type": "int", "value": 1},
+                "b": {"type": "nat", "value": -1},
+                "c": {"type": "date", "value": "01/15/2024"}}'::jsonb)"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["valid"], false);
+        let errors = val["errors"].as_array().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].as_str().unwrap().starts_with("key 'b': "));
+        assert!(errors[1].as_str().unwrap().starts_with("key 'c': "));
+    }
+
+    #[pg_test]
+    fn test_validate_accepts_aggregate() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT stats_validate(jsonb_stats_agg(stats(jsonb_build_object('n', stat(x), 's', stat(x::text)))))
+             FROM generate_series(1, 3) AS x",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val, serde_json::json!({"valid": true, "errors": []}));
+    }
+
+    #[pg_test]
+    fn test_validate_missing_value_and_unknown_type() {
+        let val = crate::stats_validate(pgrx::JsonB(serde_json::json!({
+            "type": "stats",
+            "a": {"type": "int"},
+            "b": {"type": "money", "value": 1}
+        })))
+        .0;
+        assert_eq!(val["errors"][0], "key 'a': int stat is missing 'value'");
+        assert!(val["errors"][1].as_str().unwrap().starts_with("key 'b': unknown type 'money'"));
+    }
+
+    // ── Weighted aggregation ──
+
+    #[pg_test]
+    fn test_wagg_weight_equals_repeated_values() {
+        let ok = Spi::get_one::<bool>(
+            "SELECT
+                 (SELECT jsonb_stats_wagg(jsonb_build_object('n', stat(v), 's', stat(s)), w)
+                  FROM (VALUES (10, 'a', 3), (4, 'b', 1)) AS t(v, s, w))
+               = (SELECT jsonb_stats_agg(jsonb_build_object('n', stat(v), 's', stat(s)))
+                  FROM (VALUES (10, 'a'), (10, 'a'), (10, 'a'), (4, 'b')) AS t(v, s))",
+        );
+        assert_eq!(ok, Ok(Some(true)));
+    }
+
+    #[pg_test]
+    fn test_wagg_weighted_variance() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_wagg(jsonb_build_object('x', stat(v::float8)), w)
+             FROM (VALUES (1, 2), (3, 2)) AS t(v, w)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        // Same as 1, 1, 3, 3: mean 2, sum_sq_diff 4, sample variance 4/3
+        assert_eq!(val["x"]["count"], serde_json::json!(4));
+        assert_eq!(val["x"]["sum"], serde_json::json!(8));
+        assert_eq!(val["x"]["sum_sq_diff"].to_string(), "4");
+        assert_eq!(val["x"]["variance"].to_string(), "1.33");
+    }
+
+    #[pg_test]
+    fn test_wagg_zero_and_null_weight_skipped() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_wagg(jsonb_build_object('s', stat(s)), w)
+             FROM (VALUES ('a', 2::float8), ('b', 0), ('c', NULL)) AS t(s, w)",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["s"]["counts"], serde_json::json!({"a": 2}));
+    }
+
+    #[pg_test(error = "jsonb_stats: weight must be a non-negative whole number, got 1.5")]
+    fn test_wagg_rejects_fractional_weight() {
+        Spi::get_one::<pgrx::JsonB>("SELECT jsonb_stats_wagg(stats('n', 1), 1.5)").unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: count overflow: 5000000000000000000 + 5000000000000000000 exceeds the i64 range")]
+    fn test_wagg_rejects_count_overflow() {
+        Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_wagg(stats('n', v), 5e18) FROM (VALUES (1), (2)) AS t(v)",
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "jsonb_stats: count overflow for key 'a'")]
+    fn test_wagg_rejects_count_map_overflow() {
+        Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_wagg(stats('s', 'a'::text), 5e18) FROM generate_series(1, 2)",
+        )
+        .unwrap();
+    }
+
+    // ── HyperLogLog distinct estimate ──
+
+    #[pg_test]
+    fn test_hll_distinct_estimate_within_5_pct() {
+        Spi::run("SET LOCAL jsonb_stats.hll = on").unwrap();
+        Spi::run("SET LOCAL jsonb_stats.max_counts = 10").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(stats('s', 'key-' || g)) FROM generate_series(1, 100000) AS g",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let s = &val["s"];
+        assert_eq!(s["counts"].as_object().unwrap().len(), 11);
+        let estimate = s["distinct_estimate"].to_string().parse::<f64>().unwrap();
+        assert!((estimate - 100000.0).abs() / 100000.0 < 0.05, "estimate {}", estimate);
+    }
+
+    #[pg_test]
+    fn test_hll_merges_across_groups() {
+        Spi::run("SET LOCAL jsonb_stats.hll = on").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_merge_agg(agg) FROM (
+                 SELECT jsonb_stats_agg(stats('s', 'key-' || g)) AS agg
+                 FROM generate_series(1, 2000) AS g GROUP BY g % 4) AS t",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let estimate = val["s"]["distinct_estimate"].to_string().parse::<f64>().unwrap();
+        assert!((estimate - 2000.0).abs() / 2000.0 < 0.05, "estimate {}", estimate);
+
+        // Same keys seen in every group count once
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_merge_agg(agg) FROM (
+                 SELECT jsonb_stats_agg(stats('s', 'key-' || (g % 50))) AS agg
+                 FROM generate_series(1, 2000) AS g GROUP BY g % 4) AS t",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["s"]["distinct"], 50);
+        assert_eq!(val["s"]["distinct_estimate"], 50);
+    }
+
+    #[pg_test]
+    fn test_hll_jsonb_path() {
+        Spi::run("SET LOCAL jsonb_stats.hll = on").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            r#"SELECT jsonb_stats_final(jsonb_stats_accum(jsonb_stats_accum('{}'::jsonb,
+                '{"s": {"type": "str", "value": ["a", "b"]}}'::jsonb),
+                '{"s": {"type": "str", "value": "c"}}'::jsonb))"#,
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["s"]["distinct_estimate"], 3);
+        assert!(val["s"]["hll"].is_string());
+    }
+
+    #[pg_test]
+    fn test_hll_off_by_default() {
+        let val = Spi::get_one::<pgrx::JsonB>("SELECT jsonb_stats_agg(stats('s', 'a'::text))")
+            .unwrap()
+            .unwrap()
+            .0;
+        assert!(val["s"].get("distinct_estimate").is_none());
+        assert!(val["s"].get("hll").is_none());
+    }
+
+    // ── Percentiles (t-digest) ──
+
+    fn median_1_to_100_sql() -> &'static str {
+        "SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) FROM generate_series(1, 100) AS g"
+    }
+
+    #[pg_test]
+    fn test_percentiles_median_1_to_100() {
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        Spi::run("SET LOCAL jsonb_stats.exact_quantile_limit = 0").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(median_1_to_100_sql()).unwrap().unwrap().0;
+        let get = |k: &str| val["n"][k].to_string().parse::<f64>().unwrap();
+        assert!((get("median") - 50.5).abs() < 1.0, "median {}", get("median"));
+        assert!((get("p25") - 25.75).abs() < 1.0, "p25 {}", get("p25"));
+        assert!((get("p75") - 75.25).abs() < 1.0, "p75 {}", get("p75"));
+        assert!((get("p95") - 95.05).abs() < 1.0, "p95 {}", get("p95"));
+        assert!((get("iqr") - 49.5).abs() < 2.0, "iqr {}", get("iqr"));
+        assert!(val["n"]["digest"].is_object());
+    }
+
+    #[pg_test]
+    fn test_percentiles_mad_robust_to_outlier() {
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        Spi::run("SET LOCAL jsonb_stats.exact_quantile_limit = 0").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('n', stat(v)))
+             FROM unnest(ARRAY[1, 2, 3, 4, 5, 6, 7, 8, 9, 10000]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let get = |k: &str| val["n"][k].to_string().parse::<f64>().unwrap();
+        assert!((get("mad") - 2.5).abs() < 1.0, "mad {}", get("mad"));
+        assert!(get("stddev") > 3000.0, "stddev {}", get("stddev"));
+        let mad = Spi::get_one::<f64>(&format!("SELECT stats_value('{}'::jsonb, 'n', 'mad')", val))
+            .unwrap()
+            .unwrap();
+        assert_eq!(mad, get("mad"));
+    }
+
+    #[pg_test]
+    fn test_percentiles_off_by_default() {
+        let val = Spi::get_one::<pgrx::JsonB>(median_1_to_100_sql()).unwrap().unwrap().0;
+        assert!(val["n"].get("median").is_none());
+        assert!(val["n"].get("digest").is_none());
+    }
+
+    #[pg_test]
+    fn test_percentiles_jsonb_path() {
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_final(jsonb_stats_accum(jsonb_stats_accum('{}'::jsonb,
+                '{\"n\": {\"type\": \"float\", \"value\": 1.5}}'::jsonb),
+                '{\"n\": {\"type\": \"float\", \"value\": 2.5}}'::jsonb))",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["n"]["median"].to_string(), "2.00");
+    }
+
+    #[pg_test]
+    fn test_percentiles_merge_agg() {
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "WITH parts AS (
+                SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) AS agg
+                FROM generate_series(1, 100) AS g
+                GROUP BY g % 3
+            )
+            SELECT jsonb_stats_merge_agg(agg) FROM parts",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let median = val["n"]["median"].to_string().parse::<f64>().unwrap();
+        assert!((median - 50.5).abs() < 1.0, "median {}", median);
+    }
+
+    #[pg_test]
+    fn test_percentiles_merge_without_digest_drops_it() {
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        let with = Spi::get_one::<pgrx::JsonB>(median_1_to_100_sql()).unwrap().unwrap();
+        let without = pgrx::JsonB(serde_json::json!({
+            "type": "stats_agg",
+            "n": {"type": "int_agg", "count": 1, "sum": 7, "min": 7, "max": 7, "mean": 7, "sum_sq_diff": 0}
+        }));
+        let merged = crate::jsonb_stats_merge(with, without).0;
+        assert_eq!(merged["n"]["count"], 101);
+        assert!(merged["n"].get("digest").is_none());
+    }
+
+    #[pg_test]
+    fn test_percentiles_digest_serde_roundtrip() {
+        use crate::state::{AggEntry, NumFields, StatsState};
+
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        let mut f = NumFields::init(1.0, 1);
+        for i in 2..=1000 {
+            f.update(i as f64, i);
+        }
+        let mut state = StatsState::default();
+        state.entries.insert("n".to_string(), AggEntry::IntAgg(f));
+
+        let bytes = serde_json::to_vec(&state).unwrap();
+        let roundtripped: StatsState = serde_json::from_slice(&bytes).unwrap();
+        let (AggEntry::IntAgg(a), AggEntry::IntAgg(b)) =
+            (&state.entries["n"], &roundtripped.entries["n"])
+        else {
+            panic!("expected int_agg");
+        };
+        let (da, db) = (a.digest.as_ref().unwrap(), b.digest.as_ref().unwrap());
+        assert_eq!(da.quantile(0.5), db.quantile(0.5));
+        assert!((db.quantile(0.5) - 500.5).abs() < 5.0);
+    }
+
+    // ── Exact quantiles ──
+
+    #[pg_test]
+    fn test_exact_median_of_seven_values() {
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(jsonb_build_object('n', stat(v)))
+             FROM unnest(ARRAY[12, 3, 7, 41, 7, 19, 2]) AS v",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert_eq!(val["n"]["median"].to_string(), "7.00");
+        // percentile_cont: p25 at rank 1.5 of 2, 3, 7, 7, 12, 19, 41
+        assert_eq!(val["n"]["p25"].to_string(), "5.00");
+        assert_eq!(val["n"]["p75"].to_string(), "15.50");
+        assert_eq!(val["n"]["raw_values"], serde_json::json!([2, 3, 7, 7, 12, 19, 41]));
+        let ok = Spi::get_one::<bool>(&format!(
+            "SELECT stats_percentile('{}'::jsonb, 'n', 0.95)
+                  = (SELECT percentile_cont(0.95) WITHIN GROUP (ORDER BY v)
+                     FROM unnest(ARRAY[12, 3, 7, 41, 7, 19, 2]) AS v)",
+            val
+        ));
+        assert_eq!(ok, Ok(Some(true)));
+    }
+
+    #[pg_test]
+    fn test_exact_quantiles_switch_to_digest_past_limit() {
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        Spi::run("SET LOCAL jsonb_stats.exact_quantile_limit = 50").unwrap();
+        let (small, large) = Spi::get_two::<pgrx::JsonB, pgrx::JsonB>(
+            "SELECT (SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) FROM generate_series(1, 50) AS g),
+                    (SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) FROM generate_series(1, 51) AS g)",
+        )
+        .unwrap();
+        let (small, large) = (small.unwrap().0, large.unwrap().0);
+        assert_eq!(small["n"]["raw_values"].as_array().unwrap().len(), 50);
+        assert!(large["n"].get("raw_values").is_none());
+        assert!(large["n"]["digest"].is_object());
+    }
+
+    #[pg_test]
+    fn test_exact_quantiles_merge_concatenates_until_limit() {
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        Spi::run("SET LOCAL jsonb_stats.exact_quantile_limit = 100").unwrap();
+        let (merged, direct, over) = Spi::get_three::<pgrx::JsonB, pgrx::JsonB, pgrx::JsonB>(
+            "WITH parts AS (
+                 SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) AS agg
+                 FROM generate_series(1, 100) AS g GROUP BY g % 3)
+             SELECT (SELECT jsonb_stats_merge_agg(agg) FROM parts),
+                    (SELECT jsonb_stats_agg(jsonb_build_object('n', stat(g))) FROM generate_series(1, 100) AS g),
+                    (SELECT jsonb_stats_merge_agg(agg) FROM (SELECT agg FROM parts UNION ALL SELECT agg FROM parts) AS twice)",
+        )
+        .unwrap();
+        let (merged, direct, over) = (merged.unwrap().0, direct.unwrap().0, over.unwrap().0);
+        for field in ["p25", "median", "p75", "p95", "mad", "raw_values"] {
+            assert_eq!(merged["n"][field], direct["n"][field], "{}", field);
+        }
+        assert!(over["n"].get("raw_values").is_none());
+        assert!(over["n"]["median"].is_number());
+    }
+
+    // ── Histograms ──
+
+    fn histogram_counts(h: &serde_json::Value) -> Vec<i64> {
+        h.as_array()
+            .unwrap()
+            .iter()
+            .map(|b| b["count"].as_i64().unwrap())
+            .collect()
+    }
+
+    #[pg_test]
+    fn test_histogram_uniform_range() {
+        Spi::run("SET LOCAL jsonb_stats.hist_bins = 10").unwrap();
+        // Order so that the first 100 values (which fix the range) include 1 and 1000
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(stats('n', g) ORDER BY g % 100, g) FROM generate_series(1, 1000) AS g",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let h = &val["n"]["histogram"];
+        assert_eq!(histogram_counts(h), vec![100; 10]);
+        assert_eq!(h[0]["lo"], serde_json::json!(1));
+        assert_eq!(h[9]["hi"], serde_json::json!(1000));
+    }
+
+    #[pg_test]
+    fn test_histogram_off_by_default() {
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_agg(stats('n', g)) FROM generate_series(1, 10) AS g",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        assert!(val["n"].get("histogram").is_none());
+    }
+
+    #[pg_test]
+    fn test_histogram_jsonb_path_matches_internal() {
+        Spi::run("SET LOCAL jsonb_stats.hist_bins = 8").unwrap();
+        Spi::run(
+            "CREATE AGGREGATE jsonb_accum_agg(jsonb) (
+                 sfunc = jsonb_stats_accum, stype = jsonb, initcond = '{}',
+                 finalfunc = jsonb_stats_final
+             )",
+        )
+        .unwrap();
+        // 150 ascending values: buffered, fixed at 100, then widened
+        let ok = Spi::get_one::<bool>(
+            "SELECT jsonb_accum_agg(stats('n', g) ORDER BY g) = jsonb_stats_agg(stats('n', g) ORDER BY g)
+             FROM generate_series(1, 150) AS g",
+        );
+        assert_eq!(ok, Ok(Some(true)));
+    }
+
+    #[pg_test]
+    fn test_histogram_survives_merge() {
+        Spi::run("SET LOCAL jsonb_stats.hist_bins = 5").unwrap();
+        let val = Spi::get_one::<pgrx::JsonB>(
+            "SELECT jsonb_stats_merge_agg(agg) FROM (
+                 SELECT jsonb_stats_agg(stats('n', g)) AS agg
+                 FROM generate_series(1, 300) AS g
+                 GROUP BY g % 2
+             ) parts",
+        )
+        .unwrap()
+        .unwrap()
+        .0;
+        let counts = histogram_counts(&val["n"]["histogram"]);
+        assert_eq!(counts.len(), 5);
+        assert_eq!(counts.iter().sum::<i64>(), 300);
+    }
+
+    #[pg_test]
+    fn test_histogram_serial_roundtrip() {
+        use crate::state::{AggEntry, NumFields, StatsState};
+
+        Spi::run("SET LOCAL jsonb_stats.hist_bins = 4").unwrap();
+        let mut f = NumFields::init(1.0, 1);
+        for i in 2..=200 {
+            f.update(i as f64, i);
+        }
+        let mut state = StatsState::default();
+        state.entries.insert("n".to_string(), AggEntry::IntAgg(f));
+
+        let bytes = serde_json::to_vec(&state).unwrap();
+        let roundtripped: StatsState = serde_json::from_slice(&bytes).unwrap();
+        match (&state.entries["n"], &roundtripped.entries["n"]) {
+            (AggEntry::IntAgg(a), AggEntry::IntAgg(b)) => {
+                let (ha, hb) = (a.hist.as_ref().unwrap(), b.hist.as_ref().unwrap());
+                assert_eq!(ha.counts, hb.counts);
+                assert_eq!((ha.lo, ha.hi), (hb.lo, hb.hi));
+            }
+            _ => panic!("expected int_agg entries"),
+        }
+    }
+
+    // ── stats_percentile / stats_cdf ──
+
+    /// 1..1000, ordered so that the histogram's warm-up sees both ends.
+    fn uniform_1000_sql() -> &'static str {
+        "SELECT jsonb_stats_agg(stats('n', g) ORDER BY g % 100, g) FROM generate_series(1, 1000) AS g"
+    }
+
+    fn sketch_query(agg: &serde_json::Value, f: &str, arg: f64) -> Option<f64> {
+        Spi::get_one::<f64>(&format!(
+            "SELECT {f}('{}'::jsonb, 'n', {arg})",
+            agg
+        ))
+        .unwrap()
+    }
+
+    #[pg_test]
+    fn test_stats_percentile_and_cdf_from_digest() {
+        Spi::run("SET LOCAL jsonb_stats.percentiles = on").unwrap();
+        Spi::run("SET LOCAL jsonb_stats.exact_quantile_limit = 0").unwrap();
+        let agg = Spi::get_one::<pgrx::JsonB>(uniform_1000_sql()).unwrap().unwrap().0;
+        for (p, expected) in [(0.1, 100.9), (0.5, 500.5), (0.9, 900.1)] {
+            let v = sketch_query(&agg, "stats_percentile", p).unwrap();
+            assert!((v - expected).abs() < 10.0, "p{} = {}", p, v);
+        }
+        assert_eq!(sketch_query(&agg, "stats_percentile", 0.0), Some(1.0));
+        assert_eq!(sketch_query(&agg, "stats_percentile", 1.0), Some(1000.0));
+        for (x, expected) in [(250.0, 0.25), (500.0, 0.5), (800.0, 0.8)] {
+            let f = sketch_query(&agg, "stats_cdf", x).unwrap();
+            assert!((f - expected).abs() < 0.01, "cdf({}) = {}", x, f);
+        }
+        assert_eq!(sketch_query(&agg, "stats_cdf", 0.0), Some(0.0));
+        assert_eq!(sketch_query(&agg, "stats_cdf", 1000.0), Some(1.0));
+        // The two are inverses of each other
+        let v = sketch_query(&agg, "stats_percentile", 0.3).unwrap();
+        assert!((sketch_query(&agg, "stats_cdf", v).unwrap() - 0.3).abs() < 1e-9);
+    }
+
+    #[pg_test]
+    fn test_stats_percentile_and_cdf_from_histogram() {
+        Spi::run("SET LOCAL jsonb_stats.hist_bins = 10").unwrap();
+        let agg = Spi::get_one::<pgrx::JsonB>(uniform_1000_sql()).unwrap().unwrap().0;
+        assert!(agg["n"].get("digest").is_none());
+        for (p, expected) in [(0.1, 100.9), (0.5, 500.5), (0.9, 900.1)] {
+            let v = sketch_query(&agg, "stats_percentile", p).unwrap();
+            assert!((v - expected).abs() < 1.0, "p{} = {}", p, v);
+        }
+        for (x, expected) in [(250.0, 0.25), (500.0, 0.5), (800.0, 0.8)] {
+            let f = sketch_query(&agg, "stats_cdf", x).unwrap();
+            assert!((f - expected).abs() < 0.01, "cdf({}) = {}", x, f);
+        }
+        assert_eq!(sketch_query(&agg, "stats_cdf", -5.0), Some(0.0));
+    }
+
+    #[pg_test]
+    fn test_stats_percentile_absent_key_is_null() {
+        let agg = serde_json::json!({"type": "stats_agg"});
+        assert_eq!(sketch_query(&agg, "stats_percentile", 0.5), None);
+        assert_eq!(sketch_query(&agg, "stats_cdf", 1.0), None);
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_percentile key 'n' has no digest or histogram; enable jsonb_stats.percentiles or jsonb_stats.hist_bins")]
+    fn test_stats_percentile_requires_sketch() {
+        let agg = Spi::get_one::<pgrx::JsonB>(uniform_1000_sql()).unwrap().unwrap().0;
+        sketch_query(&agg, "stats_percentile", 0.5);
+    }
+
+    #[pg_test(error = "jsonb_stats: stats_percentile p must be between 0 and 1, got 95")]
+    fn test_stats_percentile_rejects_p_out_of_range() {
+        crate::stats_percentile(pgrx::JsonB(serde_json::json!({"type": "stats_agg"})), "n", 95.0);
+    }
+
+    // ── Benchmarks: Rust vs PL/pgSQL ──
+    //
+    // pgrx tests run inside the PostgreSQL server process, so eprintln/warning
+    // output goes to PG's stderr (invisible to the test runner). We write
+    // benchmark results to /tmp/jsonb_stats_benchmarks.txt so they survive.
+
+    const BENCHMARK_FILE: &str = "/tmp/jsonb_stats_benchmarks.txt";
+
+    /// Time a SQL statement by running clock_timestamp() before/after via separate SPI calls.
+    /// Uses SELECT INTO to force materialization of aggregate results.
+    fn time_sql(query: &str) -> f64 {
+        let t1 = Spi::get_one::<f64>(
+            "SELECT extract(epoch from clock_timestamp())::float8",
+        )
+        .unwrap()
+        .unwrap();
+        Spi::run(query).unwrap();
+        let t2 = Spi::get_one::<f64>(
+            "SELECT extract(epoch from clock_timestamp())::float8",
+        )
         .unwrap()
         .unwrap();
         (t2 - t1) * 1000.0
@@ -1357,6 +5805,40 @@ mod tests {
             "{msg} — Rust should be faster"
         );
     }
+
+    #[pg_test]
+    fn test_benchmark_scalar_agg_10k() {
+        Spi::run(
+            "CREATE TEMP TABLE bench_scalar AS
+             SELECT i % 4 AS grp, (i * 37 % 1000)::int AS num, (i * 0.25)::float8 AS x,
+                    substr(md5(i::text), 1, 2) AS str, i % 3 = 0 AS ok
+             FROM generate_series(1, 10000) AS i",
+        )
+        .unwrap();
+
+        let scalar_ms = time_sql(
+            "SELECT grp, jsonb_stats_scalar_agg('num', num) AS num, jsonb_stats_scalar_agg('x', x) AS x,
+                    jsonb_stats_scalar_agg('str', str) AS str, jsonb_stats_scalar_agg('ok', ok) AS ok
+             INTO TEMP TABLE scalar_out FROM bench_scalar GROUP BY grp",
+        );
+        let wrapped_ms = time_sql(
+            "SELECT grp, jsonb_stats_agg(jsonb_build_object('num', stat(num))) AS num,
+                    jsonb_stats_agg(jsonb_build_object('x', stat(x))) AS x,
+                    jsonb_stats_agg(jsonb_build_object('str', stat(str))) AS str,
+                    jsonb_stats_agg(jsonb_build_object('ok', stat(ok))) AS ok
+             INTO TEMP TABLE wrapped_out FROM bench_scalar GROUP BY grp",
+        );
+        log_benchmark(&format!(
+            "BENCHMARK scalar agg 10K rows: scalar={:.0}ms, stat()-wrapped={:.0}ms",
+            scalar_ms, wrapped_ms
+        ));
+
+        let mismatches = Spi::get_one::<i64>(
+            "SELECT count(*) FROM scalar_out s JOIN wrapped_out w USING (grp)
+             WHERE (s.num, s.x, s.str, s.ok) IS DISTINCT FROM (w.num, w.x, w.str, w.ok)",
+        );
+        assert_eq!(mismatches, Ok(Some(0)), "scalar and stat()-wrapped results must match");
+    }
 }
 
 #[cfg(test)]
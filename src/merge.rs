@@ -4,39 +4,260 @@ use pgrx::prelude::*;
 use pgrx::{Internal, JsonB};
 use serde_json::{json, Map, Number, Value};
 
+use crate::accum::accum_into;
+use crate::guc;
 use crate::helpers::*;
-use crate::state::{AggEntry, NumFields, StatsState};
+use crate::hll::Hll;
+use crate::state::{has_log_means, AggEntry, NumFields, StatsState};
 
 /// Merge two stats_agg JSONB objects (Welford parallel merge for numeric aggs,
 /// count-map merging for str_agg/bool_agg/arr_agg/date_agg).
 ///
 /// Spec: dev/reference_plpgsql.sql lines 95-141
-#[pg_extern(immutable, parallel_safe, strict)]
+#[pg_extern(stable, parallel_safe, strict)]
 pub fn jsonb_stats_merge(a: JsonB, b: JsonB) -> JsonB {
+    check_object_input(&a.0);
+    check_object_input(&b.0);
     let mut merged: Map<String, Value> = match a.0 {
         Value::Object(m) => m,
         _ => Map::new(),
     };
+    reject_raw_stats(&merged);
+    merge_into(&mut merged, b.0);
+    JsonB(Value::Object(merged))
+}
 
-    let b_map = match b.0 {
+/// Strip a finalized `stats_agg` back to the running-state form that
+/// `jsonb_stats_accum` builds: numeric summaries keep only what merging
+/// needs (count, sums, min/max, mean, moments, digest, histogram),
+/// count-based summaries drop `mode`, `distinct`, `gini` and the other
+/// fields the finalfunc derives from `counts`, and the top-level
+/// `"type": "stats_agg"` goes. The result is smaller to store and still
+/// merges and finalizes to the same statistics.
+#[pg_extern(stable, parallel_safe, strict)]
+pub fn stats_strip(agg: JsonB) -> JsonB {
+    check_object_input(&agg.0);
+    let agg_map = match agg.0 {
         Value::Object(m) => m,
-        _ => return JsonB(Value::Object(merged)),
+        other => return JsonB(other),
     };
+    reject_raw_stats(&agg_map);
+    JsonB(Value::Object(strip_entries(agg_map)))
+}
+
+/// Strip every summary of a stats_agg, dropping its top-level type.
+fn strip_entries(agg_map: Map<String, Value>) -> Map<String, Value> {
+    let mut stripped = Map::new();
+    for (key, summary) in agg_map {
+        if key == "type" {
+            continue;
+        }
+        let unit = unit_of(&summary).map(str::to_string);
+        let mut summary = match summary {
+            Value::Object(obj) => strip_summary(obj),
+            other => other,
+        };
+        keep_unit(&mut summary, unit);
+        stripped.insert(key, summary);
+    }
+    stripped
+}
+
+fn strip_summary(mut obj: Map<String, Value>) -> Value {
+    match get_type(&obj) {
+        "int_agg" | "float_agg" | "dec2_agg" | "nat_agg" | "interval_agg" => {
+            NumFields::from_json(&obj).to_json(get_type(&obj))
+        }
+        "str_agg" | "bool_agg" | "arr_agg" | "date_agg" | "ts_agg" | "time_agg" | "uuid_agg" | "inet_agg" => {
+            strip_count_stats(&mut obj);
+            Value::Object(obj)
+        }
+        "stats_agg" => {
+            let mut nested = strip_entries(obj);
+            nested.insert("type".to_string(), json!("stats_agg"));
+            Value::Object(nested)
+        }
+        other => unknown_agg_type(other),
+    }
+}
+
+/// Remove stats_agg `b` from stats_agg `a`, where `b` summarizes a subset of
+/// the rows behind `a`: e.g. the total minus one region. Count maps lose
+/// `b`'s counts (clamped at 0, emptied keys dropped) and numeric summaries
+/// invert the Welford merge. Keys only in `a` are kept as they are; keys
+/// only in `b` are ignored. The result is finalized like `jsonb_stats_final`.
+#[pg_extern(stable, parallel_safe, strict)]
+pub fn stats_subtract(a: JsonB, b: JsonB) -> JsonB {
+    let mut state = parse_state(a.0);
+    subtract_state(&mut state, parse_state(b.0));
+    let opts = crate::final_fn::FinalOptions::from_gucs(1);
+    JsonB(Value::Object(crate::final_fn::finalize_entries(&state, &opts)))
+}
+
+/// Rename top-level keys of a `stats` or `stats_agg` by `mapping`
+/// (`{"old": "new"}`), e.g. to line up column names from two systems before
+/// merging. Unmapped keys, the `type` tag and a watermark are kept. Two summaries that
+/// land on one key are merged as by `jsonb_stats_merge`; two raw stats
+/// cannot be combined and raise.
+#[pg_extern(stable, parallel_safe, strict)]
+pub fn stats_rename(obj: JsonB, mapping: JsonB) -> JsonB {
+    check_object_input(&obj.0);
+    let obj = match obj.0 {
+        Value::Object(m) => m,
+        other => return JsonB(other),
+    };
+    let mapping = match mapping.0 {
+        Value::Object(m) => m,
+        other => pgrx::error!("jsonb_stats: stats_rename mapping must be a JSON object, got {}", json_kind(&other)),
+    };
+
+    let mut renamed = Map::new();
+    for (key, value) in obj {
+        if is_agg_metadata(&key, &value) {
+            renamed.insert(key, value);
+            continue;
+        }
+        let new_key = match mapping.get(&key) {
+            Some(Value::String(k)) if k == "type" => {
+                pgrx::error!("jsonb_stats: stats_rename cannot rename '{}' to 'type'", key)
+            }
+            Some(Value::String(k)) => k.clone(),
+            Some(other) => pgrx::error!(
+                "jsonb_stats: stats_rename mapping for '{}' must be a string, got {}",
+                key,
+                json_kind(other)
+            ),
+            None => key,
+        };
+        if !renamed.contains_key(&new_key) {
+            renamed.insert(new_key, value);
+        } else if is_summary(&value) && renamed.get(&new_key).is_some_and(is_summary) {
+            let mut incoming = Map::new();
+            incoming.insert(new_key, value);
+            merge_into(&mut renamed, Value::Object(incoming));
+        } else {
+            pgrx::error!("jsonb_stats: stats_rename maps more than one stat to '{}'", new_key);
+        }
+    }
+    JsonB(Value::Object(renamed))
+}
+
+/// Whether `v` is a `*_agg` summary rather than a raw stat.
+fn is_summary(v: &Value) -> bool {
+    v.as_object().is_some_and(|o| get_type(o).ends_with("_agg"))
+}
+
+/// A stats_agg object as a native state, for the functions that work on
+/// whole entries rather than JSON fields.
+fn parse_state(agg: Value) -> StatsState {
+    check_object_input(&agg);
+    let mut state = StatsState::default();
+    if let Value::Object(m) = agg {
+        reject_raw_stats(&m);
+        merge_into_state(&mut state, m);
+    }
+    state
+}
+
+/// Merge any number of stats_agg objects, e.g. rows collected into an array:
+/// `jsonb_stats_merge_all(a, b, c)` or `jsonb_stats_merge_all(VARIADIC arr)`.
+/// NULL elements are skipped; an empty array gives `{}`.
+#[pg_extern(stable, parallel_safe, strict)]
+pub fn jsonb_stats_merge_all(aggs: VariadicArray<'_, JsonB>) -> JsonB {
+    let mut merged: Option<Map<String, Value>> = None;
+    for agg in aggs.iter().flatten() {
+        match (&mut merged, agg.0) {
+            // The first object is the starting point, as `a` is for jsonb_stats_merge
+            (None, Value::Object(m)) => {
+                reject_raw_stats(&m);
+                merged = Some(m);
+            }
+            (Some(m), b) => merge_into(m, b),
+            (None, _) => {}
+        }
+    }
+    JsonB(Value::Object(merged.unwrap_or_default()))
+}
+
+/// Merge stats_agg `b` into `merged`, key by key.
+fn merge_into(merged: &mut Map<String, Value>, b: Value) {
+    let b_map = match b {
+        Value::Object(m) => m,
+        _ => return,
+    };
+    reject_raw_stats(&b_map);
 
     for (key, summary_b) in b_map {
         if key == "type" {
+            // Keep the tag whichever side carries it, so merge(a, b) = merge(b, a)
+            merged.entry(key).or_insert(summary_b);
+            continue;
+        }
+        if is_watermark(&key, &summary_b) {
+            // The later watermark wins
+            let keep_a = matches!(
+                (merged.get(&key), &summary_b),
+                (Some(Value::String(a)), Value::String(b)) if !iso_ts_later(b, a)
+            );
+            if !keep_a {
+                merged.insert(key, summary_b);
+            }
             continue;
         }
 
         if let Some(summary_a) = merged.remove(&key) {
-            merged.insert(key, merge_summaries(summary_a, summary_b));
+            let unit = merged_unit(&key, unit_of(&summary_a).map(str::to_string), unit_of(&summary_b));
+            let mut summary = merge_summaries(summary_a, summary_b);
+            keep_unit(&mut summary, unit);
+            merged.insert(key, summary);
         } else {
             // Key only in b — adopt directly
             merged.insert(key, summary_b);
         }
     }
+}
 
-    JsonB(Value::Object(merged))
+/// Every `*_agg` type a stats_agg may hold.
+const AGG_TYPES: &[&str] = &[
+    "int_agg", "float_agg", "dec2_agg", "nat_agg", "str_agg", "bool_agg", "arr_agg", "date_agg", "ts_agg",
+    "time_agg", "uuid_agg", "inet_agg", "interval_agg", "stats_agg",
+];
+
+fn unknown_agg_type(agg_type: &str) -> ! {
+    pgrx::error!(
+        "jsonb_stats: unknown aggregate type '{}'. Expected: {}",
+        agg_type,
+        AGG_TYPES.join(", ")
+    )
+}
+
+/// Reject raw stats (`{"type": "int", "value": 1}`) passed where `*_agg`
+/// summaries are expected: their missing fields would otherwise read as 0.
+/// An unknown type is rejected too, even on a key only one side has, as the
+/// Internal-state merge does.
+fn reject_raw_stats(agg: &Map<String, Value>) {
+    for (key, summary) in agg {
+        if let Value::Object(obj) = summary {
+            reject_raw_stat(obj, key);
+        }
+    }
+}
+
+fn reject_raw_stat(obj: &Map<String, Value>, key: &str) {
+    if matches!(
+        get_type(obj),
+        "int" | "float" | "dec2" | "nat" | "str" | "bool" | "arr" | "date" | "ts" | "time" | "uuid"
+            | "inet" | "interval" | "stats"
+    ) {
+        pgrx::error!(
+            "jsonb_stats: merge received a raw stat for key '{}'; did you mean jsonb_stats_accum?",
+            key
+        );
+    }
+    let agg_type = get_type(obj);
+    if !AGG_TYPES.contains(&agg_type) {
+        unknown_agg_type(agg_type);
+    }
 }
 
 fn merge_summaries(a: Value, b: Value) -> Value {
@@ -51,6 +272,11 @@ fn merge_summaries(a: Value, b: Value) -> Value {
 
     let a_type = get_type(&a_obj);
     let b_type = get_type(&b_obj);
+    if a_type != b_type && coerces_to_float(a_type, b_type) {
+        let mut merged = float_fields(parse_num_fields(&a_obj), a_type);
+        merged.merge(&float_fields(parse_num_fields(&b_obj), b_type));
+        return merged.to_json("float_agg");
+    }
     if a_type != b_type {
         pgrx::error!(
             "jsonb_stats: type mismatch in merge: '{}' vs '{}'",
@@ -59,50 +285,44 @@ fn merge_summaries(a: Value, b: Value) -> Value {
     }
 
     match a_type {
-        "int_agg" | "float_agg" | "dec2_agg" | "nat_agg" => merge_num_agg(a_obj, &b_obj),
-        "str_agg" | "bool_agg" => merge_count_agg(a_obj, &b_obj, false),
+        "int_agg" | "float_agg" | "dec2_agg" | "nat_agg" | "interval_agg" => merge_num_agg(a_obj, &b_obj),
+        "str_agg" | "bool_agg" | "uuid_agg" | "inet_agg" => merge_count_agg(a_obj, &b_obj, false),
         "arr_agg" => merge_count_agg(a_obj, &b_obj, true),
-        "date_agg" => merge_date_agg(a_obj, &b_obj),
-        other => pgrx::error!(
-            "jsonb_stats: unknown aggregate type '{}'. Expected: int_agg, float_agg, dec2_agg, nat_agg, str_agg, bool_agg, arr_agg, date_agg",
-            other
-        ),
+        "date_agg" | "ts_agg" | "time_agg" => merge_date_agg(a_obj, &b_obj),
+        "stats_agg" => {
+            let mut merged = a_obj;
+            merge_into(&mut merged, Value::Object(b_obj));
+            Value::Object(merged)
+        }
+        other => unknown_agg_type(other),
     }
 }
 
+/// Whether two differing agg types merge as float_agg under
+/// `jsonb_stats.numeric_merge_coerce`.
+fn coerces_to_float(a_type: &str, b_type: &str) -> bool {
+    let numeric = |t: &str| matches!(t, "int_agg" | "float_agg" | "dec2_agg" | "nat_agg");
+    numeric(a_type) && numeric(b_type) && guc::NUMERIC_MERGE_COERCE.get()
+}
+
+/// Numeric fields promoted to float_agg. int/nat summaries carry no log sums,
+/// so the promoted geometric/harmonic means become unknown.
+fn float_fields(mut f: NumFields, type_tag: &str) -> NumFields {
+    if !has_log_means(type_tag) {
+        f.forget_log_sums();
+    }
+    f
+}
+
 /// Welford parallel merge for any numeric agg summaries.
 /// Preserves the original type tag from a_obj.
 fn merge_num_agg(a: Map<String, Value>, b: &Map<String, Value>) -> Value {
-    let count_a = get_f64(&a, "count");
-    let count_b = get_f64(b, "count");
-    let total_count = count_a + count_b;
-
-    let mean_a = get_f64(&a, "mean");
-    let mean_b = get_f64(b, "mean");
-    let delta = mean_b - mean_a;
-
-    let new_mean = mean_a + (delta * count_b / total_count);
-    let new_ssd = get_f64(&a, "sum_sq_diff")
-        + get_f64(b, "sum_sq_diff")
-        + (delta * delta * count_a * count_b) / total_count;
-    let new_sum = get_f64(&a, "sum") + get_f64(b, "sum");
-    let new_min = get_f64(&a, "min").min(get_f64(b, "min"));
-    let new_max = get_f64(&a, "max").max(get_f64(b, "max"));
-
-    let type_tag = get_type(&a);
-
-    let mut result = Map::new();
-    result.insert("type".to_string(), json!(type_tag));
-    result.insert("count".to_string(), num_value(total_count));
-    result.insert("sum".to_string(), num_value(new_sum));
-    result.insert("min".to_string(), num_value(new_min));
-    result.insert("max".to_string(), num_value(new_max));
-    result.insert("mean".to_string(), num_value(new_mean));
-    result.insert("sum_sq_diff".to_string(), num_value(new_ssd));
-    Value::Object(result)
-}
-
-/// Merge count maps for str_agg, bool_agg, arr_agg.
+    let mut merged = parse_num_fields(&a);
+    merged.merge(&parse_num_fields(b));
+    merged.to_json(get_type(&a))
+}
+
+/// Merge count maps for str_agg, bool_agg, uuid_agg, inet_agg, arr_agg.
 /// For arr_agg, also sums the top-level "count" field.
 fn merge_count_agg(
     mut a_obj: Map<String, Value>,
@@ -110,14 +330,13 @@ fn merge_count_agg(
     is_arr: bool,
 ) -> Value {
     if is_arr {
-        let count_a = get_i64(&a_obj, "count");
-        let count_b = get_i64(b_obj, "count");
-        a_obj.insert(
-            "count".to_string(),
-            Value::Number(Number::from(count_a + count_b)),
-        );
+        let count = add_counts(get_count(&a_obj, "count"), get_count(b_obj, "count"));
+        a_obj.insert("count".to_string(), Value::Number(Number::from(count)));
     }
 
+    strip_count_stats(&mut a_obj);
+    merge_null_count(&mut a_obj, b_obj);
+
     // Remove counts from a so we can mutate it independently
     let mut counts_a: Map<String, Value> = a_obj
         .remove("counts")
@@ -128,28 +347,89 @@ fn merge_count_agg(
         .unwrap_or_default();
 
     if let Some(Value::Object(counts_b)) = b_obj.get("counts") {
-        for (k, v) in counts_b {
-            let v_int: i64 = match v {
-                Value::Number(n) => n.to_string().parse().unwrap_or(0),
-                _ => 0,
-            };
-            let existing: i64 = counts_a
-                .get(k)
-                .and_then(|v| match v {
-                    Value::Number(n) => n.to_string().parse().ok(),
-                    _ => None,
-                })
-                .unwrap_or(0);
-            counts_a.insert(k.clone(), Value::Number(Number::from(existing + v_int)));
+        for k in counts_b.keys() {
+            let merged = add_key_counts(k, get_count(&counts_a, k), get_count(counts_b, k));
+            counts_a.insert(k.clone(), Value::Number(Number::from(merged)));
         }
     }
 
     a_obj.insert("counts".to_string(), Value::Object(counts_a));
+    if !is_arr {
+        merge_str_range(&mut a_obj, b_obj);
+    }
+    if let Some(hll_a) = a_obj.remove("hll") {
+        let mut hll = Some(Hll::from_json(&hll_a));
+        merge_hll(&mut hll, b_obj.get("hll").map(Hll::from_json).as_ref());
+        if let Some(h) = hll {
+            a_obj.insert("hll".to_string(), h.to_json());
+        }
+    }
     Value::Object(a_obj)
 }
 
-/// Merge two date_agg objects: merge count maps + min/max dates.
+/// Add b's per-key counts into a's.
+fn add_count_map(ca: &mut HashMap<String, i64>, cb: HashMap<String, i64>) {
+    for (k, v) in cb {
+        match ca.get_mut(&k) {
+            Some(c) => *c = add_key_counts(&k, *c, v),
+            None => {
+                ca.insert(k, v);
+            }
+        }
+    }
+}
+
+/// Union b's distinct-count sketch into a's; a summary without one drops it.
+fn merge_hll(a: &mut Option<Hll>, b: Option<&Hll>) {
+    match (a.as_mut(), b) {
+        (Some(a), Some(b)) => a.merge(b),
+        _ => *a = None,
+    }
+}
+
+/// Drop fields the finalfunc derives from `counts`; they are stale once counts change.
+/// Keeping any of them would make the result depend on which side was `a`.
+/// "first"/"last" go too: without the ordering keys they cannot be merged.
+fn strip_count_stats(obj: &mut Map<String, Value>) {
+    for field in [
+        "mode",
+        "mode_count",
+        "distinct",
+        "distinct_estimate",
+        "total_elements",
+        "mean_elements_per_row",
+        "entropy_bits",
+        "normalized_entropy",
+        "gini",
+        "top_share",
+        "min_numeric",
+        "max_numeric",
+        "min_count",
+        "max_count",
+        "weighted_median",
+        "weighted_mean",
+        "total",
+        "true_ratio",
+        "ipv4_count",
+        "ipv6_count",
+        "first",
+        "last",
+    ] {
+        obj.remove(field);
+    }
+}
+
+/// Add b's "null_count" into a.
+fn merge_null_count(a_obj: &mut Map<String, Value>, b_obj: &Map<String, Value>) {
+    let null_count = add_counts(get_count(a_obj, "null_count"), get_count(b_obj, "null_count"));
+    insert_null_count(a_obj, null_count);
+}
+
+/// Merge two date_agg (or ts_agg, time_agg) objects: merge count maps + min/max.
 fn merge_date_agg(mut a_obj: Map<String, Value>, b_obj: &Map<String, Value>) -> Value {
+    strip_count_stats(&mut a_obj);
+    merge_null_count(&mut a_obj, b_obj);
+
     // Merge counts
     let mut counts_a: Map<String, Value> = a_obj
         .remove("counts")
@@ -160,27 +440,24 @@ fn merge_date_agg(mut a_obj: Map<String, Value>, b_obj: &Map<String, Value>) ->
         .unwrap_or_default();
 
     if let Some(Value::Object(counts_b)) = b_obj.get("counts") {
-        for (k, v) in counts_b {
-            let v_int: i64 = match v {
-                Value::Number(n) => n.to_string().parse().unwrap_or(0),
-                _ => 0,
-            };
-            let existing: i64 = counts_a
-                .get(k)
-                .and_then(|v| match v {
-                    Value::Number(n) => n.to_string().parse().ok(),
-                    _ => None,
-                })
-                .unwrap_or(0);
-            counts_a.insert(k.clone(), Value::Number(Number::from(existing + v_int)));
+        for k in counts_b.keys() {
+            let merged = add_key_counts(k, get_count(&counts_a, k), get_count(counts_b, k));
+            counts_a.insert(k.clone(), Value::Number(Number::from(merged)));
         }
     }
     a_obj.insert("counts".to_string(), Value::Object(counts_a));
+    merge_str_range(&mut a_obj, b_obj);
+    Value::Object(a_obj)
+}
 
-    // Merge min (lexicographic — ISO dates sort correctly)
+/// Merge "min"/"max" strings of b into a (date_agg, ts_agg, time_agg, str_agg),
+/// in `range_cmp` order.
+fn merge_str_range(a_obj: &mut Map<String, Value>, b_obj: &Map<String, Value>) {
+    let agg_type = get_type(a_obj).to_string();
+    // Merge min
     if let Some(b_min) = get_str(b_obj, "min") {
-        match get_str(&a_obj, "min") {
-            Some(a_min) if b_min < a_min => {
+        match get_str(a_obj, "min") {
+            Some(a_min) if range_cmp(&agg_type, b_min, a_min).is_lt() => {
                 a_obj.insert("min".to_string(), json!(b_min));
             }
             None => {
@@ -192,8 +469,8 @@ fn merge_date_agg(mut a_obj: Map<String, Value>, b_obj: &Map<String, Value>) ->
 
     // Merge max
     if let Some(b_max) = get_str(b_obj, "max") {
-        match get_str(&a_obj, "max") {
-            Some(a_max) if b_max > a_max => {
+        match get_str(a_obj, "max") {
+            Some(a_max) if range_cmp(&agg_type, b_max, a_max).is_gt() => {
                 a_obj.insert("max".to_string(), json!(b_max));
             }
             None => {
@@ -202,8 +479,6 @@ fn merge_date_agg(mut a_obj: Map<String, Value>, b_obj: &Map<String, Value>) ->
             _ => {}
         }
     }
-
-    Value::Object(a_obj)
 }
 
 // ── Internal-state merge sfunc (avoids serde_json round-trip on growing state) ──
@@ -211,7 +486,7 @@ fn merge_date_agg(mut a_obj: Map<String, Value>, b_obj: &Map<String, Value>) ->
 /// Merge sfunc using pgrx Internal state. Each input stats_agg JSONB is
 /// parsed once into native AggEntry types and merged into the HashMap state.
 /// The growing state is never serialized back to JSONB until the finalfunc.
-#[pg_extern(immutable, parallel_safe)]
+#[pg_extern(stable, parallel_safe)]
 pub unsafe fn jsonb_stats_merge_sfunc(internal: Internal, agg: Option<pgrx::JsonB>) -> Internal {
     let state_ptr: *mut StatsState = match internal.unwrap() {
         Some(datum) => datum.cast_mut_ptr::<StatsState>(),
@@ -224,94 +499,259 @@ pub unsafe fn jsonb_stats_merge_sfunc(internal: Internal, agg: Option<pgrx::Json
     };
 
     let state = unsafe { &mut *state_ptr };
+    if let Value::Object(agg_map) = agg.0 {
+        merge_into_state(state, agg_map);
+        prune_state(state);
+    }
 
-    let agg_map = match agg.0 {
-        Value::Object(m) => m,
-        _ => return Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize))),
+    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+}
+
+/// Sfunc for `jsonb_stats_merge_agg(stats_agg, source)`: merges like
+/// `jsonb_stats_merge_sfunc`, but raises when a `source` ID comes round a
+/// second time, catching a group merged twice. A NULL source is merged
+/// without the check.
+#[pg_extern(stable, parallel_safe)]
+pub unsafe fn jsonb_stats_merge_source_sfunc(
+    internal: Internal,
+    agg: Option<pgrx::JsonB>,
+    source: Option<String>,
+) -> Internal {
+    let state_ptr: *mut StatsState = match internal.unwrap() {
+        Some(datum) => datum.cast_mut_ptr::<StatsState>(),
+        None => Box::into_raw(Box::new(StatsState::default())),
     };
 
+    if let Some(agg) = agg {
+        let state = unsafe { &mut *state_ptr };
+        if let Some(source) = source {
+            state.claim_source(source);
+        }
+        if let Value::Object(agg_map) = agg.0 {
+            merge_into_state(state, agg_map);
+            prune_state(state);
+        }
+    }
+
+    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+}
+
+/// Sfunc for `jsonb_stats_unify_agg`: each input may be a raw `stats` or a
+/// `stats_agg`, told apart per key by its type. Raw stats are accumulated as
+/// by `jsonb_stats_agg` and summaries merged as by `jsonb_stats_merge_agg`,
+/// into the same state, so both kinds of input combine in one pass.
+#[pg_extern(stable, parallel_safe)]
+pub unsafe fn jsonb_stats_unify_sfunc(internal: Internal, input: Option<pgrx::JsonB>) -> Internal {
+    let state_ptr: *mut StatsState = match internal.unwrap() {
+        Some(datum) => datum.cast_mut_ptr::<StatsState>(),
+        None => Box::into_raw(Box::new(StatsState::default())),
+    };
+
+    if let Some(Value::Object(input_map)) = input.map(|i| i.0) {
+        let state = unsafe { &mut *state_ptr };
+        let (aggs, stats): (Map<String, Value>, Map<String, Value>) =
+            input_map.into_iter().partition(|(_, entry)| match entry {
+                Value::Object(e) => get_type(e).ends_with("_agg"),
+                _ => false,
+            });
+        accum_into(state, Value::Object(stats), 1);
+        merge_into_state(state, aggs);
+    }
+
+    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+}
+
+/// Merge each `*_agg` summary of a stats_agg object into the state.
+fn merge_into_state(state: &mut StatsState, agg_map: Map<String, Value>) {
     for (key, summary) in agg_map {
         if key == "type" {
             continue;
         }
+        if is_watermark(&key, &summary) {
+            if let Value::String(w) = &summary {
+                state.observe_watermark(w);
+            }
+            continue;
+        }
 
         let obj = match summary {
             Value::Object(m) => m,
             _ => continue,
         };
 
+        reject_raw_stat(&obj, &key);
+        state.note_unit(&key, get_str(&obj, "unit"));
         let incoming = parse_agg_entry(&obj);
-        match state.entries.get_mut(&key) {
-            Some(existing) => merge_agg_entries(existing, incoming, &key),
-            None => {
-                state.entries.insert(key, incoming);
-            }
+        merge_entry(state, key, incoming);
+    }
+}
+
+/// Merge one native entry into the state under `key`.
+pub fn merge_entry(state: &mut StatsState, key: String, incoming: AggEntry) {
+    match state.entries.get_mut(&key) {
+        Some(existing) => merge_agg_entries(existing, incoming, &key),
+        None => {
+            state.entries.insert(key, incoming);
         }
     }
+}
 
-    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+/// A `nat_agg`'s fields, refusing a hand-crafted summary that breaks the
+/// `nat >= 0` invariant `accum` enforces.
+fn parse_nat_fields(obj: &Map<String, Value>) -> NumFields {
+    let fields = NumFields::from_json(obj);
+    if fields.min < 0.0 || fields.sum < 0.0 || fields.sum_int < 0 {
+        pgrx::error!("jsonb_stats: corrupt nat_agg (negative value)");
+    }
+    fields
+}
+
+/// A numeric summary's fields, with the `nat_agg` invariant checked.
+fn parse_num_fields(obj: &Map<String, Value>) -> NumFields {
+    match get_type(obj) {
+        "nat_agg" => parse_nat_fields(obj),
+        _ => NumFields::from_json(obj),
+    }
 }
 
 /// Parse a JSONB *_agg object into a native AggEntry.
 fn parse_agg_entry(obj: &Map<String, Value>) -> AggEntry {
     match get_type(obj) {
-        "int_agg" => AggEntry::IntAgg(parse_num_fields(obj)),
-        "float_agg" => AggEntry::FloatAgg(parse_num_fields(obj)),
-        "dec2_agg" => AggEntry::Dec2Agg(parse_num_fields(obj)),
-        "nat_agg" => AggEntry::NatAgg(parse_num_fields(obj)),
+        "int_agg" => AggEntry::IntAgg(NumFields::from_json(obj)),
+        "float_agg" => AggEntry::FloatAgg(NumFields::from_json(obj)),
+        "dec2_agg" => AggEntry::Dec2Agg(NumFields::from_json(obj)),
+        "nat_agg" => AggEntry::NatAgg(parse_nat_fields(obj)),
+        "interval_agg" => AggEntry::IntervalAgg(NumFields::from_json(obj)),
         "str_agg" => AggEntry::StrAgg {
             counts: parse_counts(obj),
+            min: get_str(obj, "min").map(|s| s.to_string()),
+            max: get_str(obj, "max").map(|s| s.to_string()),
+            null_count: get_count(obj, "null_count"),
+            hll: obj.get("hll").map(Hll::from_json),
         },
         "bool_agg" => AggEntry::BoolAgg {
             counts: parse_counts(obj),
+            null_count: get_count(obj, "null_count"),
+        },
+        "uuid_agg" => AggEntry::UuidAgg {
+            counts: parse_counts(obj),
+            null_count: get_count(obj, "null_count"),
+        },
+        "inet_agg" => AggEntry::InetAgg {
+            counts: parse_counts(obj),
+            null_count: get_count(obj, "null_count"),
         },
         "arr_agg" => AggEntry::ArrAgg {
-            count: get_f64(obj, "count") as i64,
+            count: get_count(obj, "count"),
             counts: parse_counts(obj),
+            null_count: get_count(obj, "null_count"),
         },
         "date_agg" => AggEntry::DateAgg {
             counts: parse_counts(obj),
             min_date: get_str(obj, "min").map(|s| s.to_string()),
             max_date: get_str(obj, "max").map(|s| s.to_string()),
+            null_count: get_count(obj, "null_count"),
         },
-        other => pgrx::error!(
-            "jsonb_stats: unknown aggregate type '{}'. Expected: int_agg, float_agg, dec2_agg, nat_agg, str_agg, bool_agg, arr_agg, date_agg",
-            other
-        ),
-    }
-}
-
-fn parse_num_fields(obj: &Map<String, Value>) -> NumFields {
-    NumFields {
-        count: get_f64(obj, "count") as i64,
-        sum: get_f64(obj, "sum"),
-        min: get_f64(obj, "min"),
-        max: get_f64(obj, "max"),
-        mean: get_f64(obj, "mean"),
-        sum_sq_diff: get_f64(obj, "sum_sq_diff"),
+        "ts_agg" => AggEntry::TsAgg {
+            counts: parse_counts(obj),
+            min_ts: get_str(obj, "min").map(|s| s.to_string()),
+            max_ts: get_str(obj, "max").map(|s| s.to_string()),
+            null_count: get_count(obj, "null_count"),
+        },
+        "time_agg" => AggEntry::TimeAgg {
+            counts: parse_counts(obj),
+            min_time: get_str(obj, "min").map(|s| s.to_string()),
+            max_time: get_str(obj, "max").map(|s| s.to_string()),
+            null_count: get_count(obj, "null_count"),
+        },
+        "stats_agg" => {
+            let mut nested = StatsState::default();
+            merge_into_state(&mut nested, obj.clone());
+            AggEntry::NestedAgg(Box::new(nested))
+        }
+        other => unknown_agg_type(other),
     }
 }
 
 /// Parse the "counts" sub-object from a JSONB *_agg into a HashMap.
+/// A count that is not a non-negative whole number is an error.
 fn parse_counts(obj: &Map<String, Value>) -> HashMap<String, i64> {
     let mut result = HashMap::new();
     if let Some(Value::Object(counts)) = obj.get("counts") {
-        for (k, v) in counts {
-            let n: i64 = match v {
-                Value::Number(n) => n.to_string().parse().unwrap_or(0),
-                _ => 0,
-            };
-            result.insert(k.clone(), n);
+        for k in counts.keys() {
+            result.insert(k.clone(), get_count(counts, k));
         }
     }
     result
 }
 
+/// Fold rare `str_agg`, `bool_agg` and `arr_agg` keys into `OTHER_KEY` after
+/// a `jsonb_stats_merge_agg` step, nested stats included. Only the merge_agg
+/// sfuncs call this: parallel combine never prunes, so its result does not
+/// depend on the plan or on the order partial states meet.
+fn prune_state(state: &mut StatsState) {
+    let min = guc::MERGE_PRUNE_MIN.get() as i64;
+    if min <= 0 {
+        return;
+    }
+    for entry in state.entries.values_mut() {
+        prune_entry(entry, min);
+    }
+}
+
+fn prune_entry(entry: &mut AggEntry, min: i64) {
+    match entry {
+        AggEntry::StrAgg { counts, .. }
+        | AggEntry::BoolAgg { counts, .. }
+        | AggEntry::ArrAgg { counts, .. } => prune_rare_counts(counts, min),
+        AggEntry::NestedAgg(nested) => {
+            for e in nested.entries.values_mut() {
+                prune_entry(e, min);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Fold keys whose merged count is below `min` into `OTHER_KEY`, so count
+/// maps stay bounded across many merged groups. A key folded away early does
+/// not come back even if later groups would lift it over the threshold.
+fn prune_rare_counts(counts: &mut HashMap<String, i64>, min: i64) {
+    let mut other = 0;
+    counts.retain(|k, n| {
+        if k == OTHER_KEY || *n >= min {
+            return true;
+        }
+        other = add_counts(other, *n);
+        false
+    });
+    if other > 0 {
+        let c = counts.entry(OTHER_KEY.to_string()).or_insert(0);
+        *c = add_counts(*c, other);
+    }
+}
+
 /// Welford parallel merge and count-map merge on native AggEntry types.
 pub fn merge_agg_entries(existing: &mut AggEntry, incoming: AggEntry, key: &str) {
     // Fail fast on type mismatch
     let e_tag = existing.type_tag();
     let i_tag = incoming.type_tag();
+    if e_tag != i_tag && coerces_to_float(e_tag, i_tag) {
+        let placeholder = AggEntry::FloatAgg(NumFields::empty());
+        let (AggEntry::IntAgg(a) | AggEntry::FloatAgg(a) | AggEntry::Dec2Agg(a) | AggEntry::NatAgg(a)) =
+            std::mem::replace(existing, placeholder)
+        else {
+            unreachable!("coerces_to_float admits numeric aggs only")
+        };
+        let (AggEntry::IntAgg(b) | AggEntry::FloatAgg(b) | AggEntry::Dec2Agg(b) | AggEntry::NatAgg(b)) = incoming
+        else {
+            unreachable!("coerces_to_float admits numeric aggs only")
+        };
+        let mut merged = float_fields(a, e_tag);
+        merged.merge(&float_fields(b, i_tag));
+        *existing = AggEntry::FloatAgg(merged);
+        return;
+    }
     if e_tag != i_tag {
         pgrx::error!(
             "jsonb_stats: type mismatch for key '{}': existing {} vs incoming {}",
@@ -319,63 +759,317 @@ pub fn merge_agg_entries(existing: &mut AggEntry, incoming: AggEntry, key: &str)
         );
     }
 
+    if let (AggEntry::StrAgg { hll: hll_a, .. }, AggEntry::StrAgg { hll: hll_b, .. }) =
+        (&mut *existing, &incoming)
+    {
+        merge_hll(hll_a, hll_b.as_ref());
+    }
+
     match (existing, incoming) {
         // All numeric types: use NumFields::merge
         (AggEntry::IntAgg(a), AggEntry::IntAgg(b))
         | (AggEntry::FloatAgg(a), AggEntry::FloatAgg(b))
         | (AggEntry::Dec2Agg(a), AggEntry::Dec2Agg(b))
-        | (AggEntry::NatAgg(a), AggEntry::NatAgg(b)) => {
+        | (AggEntry::NatAgg(a), AggEntry::NatAgg(b))
+        | (AggEntry::IntervalAgg(a), AggEntry::IntervalAgg(b)) => {
             a.merge(&b);
         }
-        (AggEntry::StrAgg { counts: ca }, AggEntry::StrAgg { counts: cb })
-        | (AggEntry::BoolAgg { counts: ca }, AggEntry::BoolAgg { counts: cb }) => {
-            for (k, v) in cb {
-                *ca.entry(k).or_insert(0) += v;
-            }
+        (
+            AggEntry::BoolAgg {
+                counts: ca,
+                null_count: na,
+            },
+            AggEntry::BoolAgg {
+                counts: cb,
+                null_count: nb,
+            },
+        )
+        | (
+            AggEntry::UuidAgg {
+                counts: ca,
+                null_count: na,
+            },
+            AggEntry::UuidAgg {
+                counts: cb,
+                null_count: nb,
+            },
+        )
+        | (
+            AggEntry::InetAgg {
+                counts: ca,
+                null_count: na,
+            },
+            AggEntry::InetAgg {
+                counts: cb,
+                null_count: nb,
+            },
+        ) => {
+            *na = add_counts(*na, nb);
+            add_count_map(ca, cb);
         }
         (
             AggEntry::ArrAgg {
                 count: count_a,
                 counts: ca,
+                null_count: na,
             },
             AggEntry::ArrAgg {
                 count: count_b,
                 counts: cb,
+                null_count: nb,
             },
         ) => {
-            *count_a += count_b;
-            for (k, v) in cb {
-                *ca.entry(k).or_insert(0) += v;
-            }
+            *count_a = add_counts(*count_a, count_b);
+            *na = add_counts(*na, nb);
+            add_count_map(ca, cb);
         }
         (
             AggEntry::DateAgg {
                 counts: ca,
                 min_date: min_a,
                 max_date: max_a,
+                null_count: na,
             },
             AggEntry::DateAgg {
                 counts: cb,
                 min_date: min_b,
                 max_date: max_b,
+                null_count: nb,
+            },
+        )
+        | (
+            AggEntry::TsAgg {
+                counts: ca,
+                min_ts: min_a,
+                max_ts: max_a,
+                null_count: na,
+            },
+            AggEntry::TsAgg {
+                counts: cb,
+                min_ts: min_b,
+                max_ts: max_b,
+                null_count: nb,
+            },
+        )
+        | (
+            AggEntry::TimeAgg {
+                counts: ca,
+                min_time: min_a,
+                max_time: max_a,
+                null_count: na,
+            },
+            AggEntry::TimeAgg {
+                counts: cb,
+                min_time: min_b,
+                max_time: max_b,
+                null_count: nb,
+            },
+        )
+        | (
+            AggEntry::StrAgg {
+                counts: ca,
+                min: min_a,
+                max: max_a,
+                null_count: na,
+                ..
+            },
+            AggEntry::StrAgg {
+                counts: cb,
+                min: min_b,
+                max: max_b,
+                null_count: nb,
+                ..
             },
         ) => {
-            for (k, v) in cb {
-                *ca.entry(k).or_insert(0) += v;
-            }
+            *na = add_counts(*na, nb);
+            add_count_map(ca, cb);
             // Merge min
             match (&*min_a, &min_b) {
-                (Some(a), Some(b)) if b < a => *min_a = Some(b.clone()),
+                (Some(a), Some(b)) if range_cmp(e_tag, b, a).is_lt() => *min_a = Some(b.clone()),
                 (None, Some(_)) => *min_a = min_b,
                 _ => {}
             }
             // Merge max
             match (&*max_a, &max_b) {
-                (Some(a), Some(b)) if b > a => *max_a = Some(b.clone()),
+                (Some(a), Some(b)) if range_cmp(e_tag, b, a).is_gt() => *max_a = Some(b.clone()),
                 (None, Some(_)) => *max_a = max_b,
                 _ => {}
             }
         }
+        (AggEntry::NestedAgg(a), AggEntry::NestedAgg(b)) => {
+            for (k, entry) in b.entries {
+                merge_entry(a, k, entry);
+            }
+        }
+        _ => unreachable!(), // type_tag check above guarantees matching variants
+    }
+}
+
+/// Subtract every entry of `removed` from the matching entry of `state`.
+fn subtract_state(state: &mut StatsState, removed: StatsState) {
+    for (key, entry) in removed.entries {
+        if let Some(existing) = state.entries.get_mut(&key) {
+            subtract_agg_entries(existing, entry, &key);
+        }
+    }
+}
+
+/// Inverse of `merge_agg_entries`, for `stats_subtract`.
+fn subtract_agg_entries(existing: &mut AggEntry, removed: AggEntry, key: &str) {
+    let e_tag = existing.type_tag();
+    let r_tag = removed.type_tag();
+    if e_tag != r_tag {
+        pgrx::error!(
+            "jsonb_stats: type mismatch for key '{}': existing {} vs removed {}",
+            key, e_tag, r_tag
+        );
+    }
+
+    match (&mut *existing, removed) {
+        (AggEntry::IntAgg(a), AggEntry::IntAgg(b))
+        | (AggEntry::FloatAgg(a), AggEntry::FloatAgg(b))
+        | (AggEntry::Dec2Agg(a), AggEntry::Dec2Agg(b))
+        | (AggEntry::NatAgg(a), AggEntry::NatAgg(b))
+        | (AggEntry::IntervalAgg(a), AggEntry::IntervalAgg(b)) => {
+            a.subtract(&b);
+        }
+        (
+            AggEntry::BoolAgg {
+                counts: ca,
+                null_count: na,
+            },
+            AggEntry::BoolAgg {
+                counts: cb,
+                null_count: nb,
+            },
+        )
+        | (
+            AggEntry::UuidAgg {
+                counts: ca,
+                null_count: na,
+            },
+            AggEntry::UuidAgg {
+                counts: cb,
+                null_count: nb,
+            },
+        )
+        | (
+            AggEntry::InetAgg {
+                counts: ca,
+                null_count: na,
+            },
+            AggEntry::InetAgg {
+                counts: cb,
+                null_count: nb,
+            },
+        ) => {
+            *na = (*na - nb).max(0);
+            subtract_count_map(ca, cb);
+        }
+        (
+            AggEntry::ArrAgg {
+                count: count_a,
+                counts: ca,
+                null_count: na,
+            },
+            AggEntry::ArrAgg {
+                count: count_b,
+                counts: cb,
+                null_count: nb,
+            },
+        ) => {
+            *count_a = (*count_a - count_b).max(0);
+            *na = (*na - nb).max(0);
+            subtract_count_map(ca, cb);
+        }
+        (
+            AggEntry::DateAgg {
+                counts: ca,
+                min_date: min,
+                max_date: max,
+                null_count: na,
+            },
+            AggEntry::DateAgg {
+                counts: cb,
+                null_count: nb,
+                ..
+            },
+        )
+        | (
+            AggEntry::TsAgg {
+                counts: ca,
+                min_ts: min,
+                max_ts: max,
+                null_count: na,
+            },
+            AggEntry::TsAgg {
+                counts: cb,
+                null_count: nb,
+                ..
+            },
+        )
+        | (
+            AggEntry::TimeAgg {
+                counts: ca,
+                min_time: min,
+                max_time: max,
+                null_count: na,
+            },
+            AggEntry::TimeAgg {
+                counts: cb,
+                null_count: nb,
+                ..
+            },
+        )
+        | (
+            AggEntry::StrAgg {
+                counts: ca,
+                min,
+                max,
+                null_count: na,
+                ..
+            },
+            AggEntry::StrAgg {
+                counts: cb,
+                null_count: nb,
+                ..
+            },
+        ) => {
+            *na = (*na - nb).max(0);
+            let emptied = subtract_count_map(ca, cb);
+            // A bound whose key is gone moves to the nearest remaining key; a
+            // bound absent from the counts (pruned) is left alone
+            let remaining = || ca.keys().filter(|k| k.as_str() != OTHER_KEY);
+            let order = |a: &&String, b: &&String| range_cmp(e_tag, a, b);
+            if min.as_ref().is_some_and(|m| emptied.contains(m)) {
+                *min = remaining().min_by(order).cloned();
+            }
+            if max.as_ref().is_some_and(|m| emptied.contains(m)) {
+                *max = remaining().max_by(order).cloned();
+            }
+        }
+        (AggEntry::NestedAgg(a), AggEntry::NestedAgg(b)) => subtract_state(a, *b),
         _ => unreachable!(), // type_tag check above guarantees matching variants
     }
+
+    // The sketch cannot be reversed
+    if let AggEntry::StrAgg { hll, .. } = existing {
+        *hll = None;
+    }
+}
+
+/// Subtract `cb` from `ca` key by key, clamping at 0 and dropping keys that
+/// reach it. Keys only in `cb` are ignored. Returns the dropped keys.
+fn subtract_count_map(ca: &mut HashMap<String, i64>, cb: HashMap<String, i64>) -> Vec<String> {
+    let mut emptied = Vec::new();
+    for (k, n) in cb {
+        if let Some(c) = ca.get_mut(&k) {
+            *c -= n;
+            if *c <= 0 {
+                ca.remove(&k);
+                emptied.push(k);
+            }
+        }
+    }
+    emptied
 }
@@ -1,7 +1,10 @@
 use pgrx::prelude::*;
 use pgrx::Internal;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 
-use crate::merge::merge_agg_entries;
+use crate::guc;
+use crate::merge::merge_entry;
 use crate::state::StatsState;
 
 /// Combine two partial aggregate states (for parallel aggregation).
@@ -10,7 +13,7 @@ use crate::state::StatsState;
 /// Memory ownership:
 ///   state1: borrow (returned as the combined state)
 ///   state2: take ownership (freed after merging into state1)
-#[pg_extern(immutable, parallel_safe)]
+#[pg_extern(stable, parallel_safe)]
 pub unsafe fn jsonb_stats_combine(state1: Internal, state2: Internal) -> Internal {
     let ptr1: Option<*mut StatsState> = match state1.unwrap() {
         Some(datum) => Some(datum.cast_mut_ptr::<StatsState>()),
@@ -34,41 +37,121 @@ pub unsafe fn jsonb_stats_combine(state1: Internal, state2: Internal) -> Interna
             // Take ownership of state2 so it's freed when dropped
             let s2 = unsafe { Box::from_raw(p2) };
             for (key, entry) in s2.entries {
-                match s1.entries.get_mut(&key) {
-                    Some(existing) => merge_agg_entries(existing, entry, &key),
+                merge_entry(s1, key, entry);
+            }
+            for (key, fl) in s2.ordered {
+                match s1.ordered.get_mut(&key) {
+                    Some(existing) => existing.merge(fl),
                     None => {
-                        s1.entries.insert(key, entry);
+                        s1.ordered.insert(key, fl);
                     }
                 }
             }
+            for source in s2.sources {
+                s1.claim_source(source);
+            }
+            for (key, unit) in s2.units {
+                s1.note_unit(&key, Some(&unit));
+            }
+            if let Some(w) = s2.watermark {
+                s1.observe_watermark(&w);
+            }
             Internal::from(Some(pgrx::pg_sys::Datum::from(p1 as usize)))
         }
     }
 }
 
+/// Leading byte of every serialized state, naming the encoding of the rest:
+/// 1 = CBOR via serde_cbor, 2 = CBOR via ciborium. Bump it when the
+/// encoding or a state layout changes incompatibly, so a mismatch raises
+/// instead of misreading the bytes.
+const SERIAL_FORMAT_VERSION: u8 = 2;
+
 /// Serialize aggregate state to bytes for cross-worker IPC.
 /// Borrows state (does NOT free) — PG may call this multiple times.
-#[pg_extern(immutable, parallel_safe)]
+#[pg_extern(stable, parallel_safe)]
 pub unsafe fn jsonb_stats_serial(internal: Internal) -> Vec<u8> {
     let ptr: *mut StatsState = match internal.unwrap() {
         Some(datum) => datum.cast_mut_ptr::<StatsState>(),
-        None => return serde_json::to_vec(&StatsState::default()).unwrap_or_else(|e| {
-            pgrx::error!("jsonb_stats: serialization of empty state failed: {}", e)
-        }),
+        None => return serialize_state(&StatsState::default()),
     };
-    let state = unsafe { &*ptr };
-    serde_json::to_vec(state).unwrap_or_else(|e| {
-        pgrx::error!("jsonb_stats: serialization failed: {}", e)
-    })
+    serialize_state(unsafe { &*ptr })
 }
 
 /// Deserialize aggregate state from bytes received from a worker.
 /// The second `Internal` argument is required by PG but unused.
 #[pg_extern(immutable, parallel_safe)]
 pub unsafe fn jsonb_stats_deserial(bytes: Vec<u8>, _internal: Internal) -> Internal {
-    let state: StatsState = serde_json::from_slice(&bytes).unwrap_or_else(|e| {
-        pgrx::error!("jsonb_stats: deserialization failed: {}", e)
-    });
-    let ptr = Box::into_raw(Box::new(state));
+    let ptr = Box::into_raw(Box::new(deserialize_state(&bytes)));
     Internal::from(Some(pgrx::pg_sys::Datum::from(ptr as usize)))
 }
+
+pub(crate) fn serialize_state(state: &StatsState) -> Vec<u8> {
+    encode(state)
+}
+
+pub(crate) fn deserialize_state(bytes: &[u8]) -> StatsState {
+    decode(bytes)
+}
+
+/// The version byte followed by the CBOR encoding of `state`: binary, so
+/// much smaller and faster than JSON for large count maps, yet
+/// self-describing, so `#[serde(default)]` fields and embedded JSON values
+/// round-trip.
+///
+/// Raises once the encoding passes `jsonb_stats.max_serial_bytes`, before
+/// the rest of an oversized state is written.
+pub(crate) fn encode<T: Serialize>(state: &T) -> Vec<u8> {
+    let limit = guc::MAX_SERIAL_BYTES.get();
+    let mut out = LimitedWriter {
+        bytes: vec![SERIAL_FORMAT_VERSION],
+        limit: if limit > 0 { limit as usize } else { usize::MAX },
+        overflowed: false,
+    };
+    if let Err(e) = ciborium::into_writer(state, &mut out) {
+        if out.overflowed {
+            pgrx::error!(
+                "jsonb_stats: serialized state exceeds jsonb_stats.max_serial_bytes ({} bytes); \
+                 prune high-cardinality counts or run the query without parallel workers",
+                limit
+            );
+        }
+        pgrx::error!("jsonb_stats: serialization failed: {}", e);
+    }
+    out.bytes
+}
+
+/// A byte buffer that refuses to grow past `limit`.
+struct LimitedWriter {
+    bytes: Vec<u8>,
+    limit: usize,
+    overflowed: bool,
+}
+
+impl std::io::Write for LimitedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.bytes.len() + buf.len() > self.limit {
+            self.overflowed = true;
+            return Err(std::io::Error::other("size limit exceeded"));
+        }
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) fn decode<T: DeserializeOwned>(bytes: &[u8]) -> T {
+    match bytes.split_first() {
+        Some((&SERIAL_FORMAT_VERSION, rest)) => ciborium::from_reader(rest)
+            .unwrap_or_else(|e| pgrx::error!("jsonb_stats: deserialization failed: {}", e)),
+        Some((version, _)) => pgrx::error!(
+            "jsonb_stats: serialized state has format version {}, expected {}",
+            version,
+            SERIAL_FORMAT_VERSION
+        ),
+        None => pgrx::error!("jsonb_stats: deserialization failed: empty input"),
+    }
+}
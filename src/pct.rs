@@ -0,0 +1,108 @@
+use pgrx::prelude::*;
+use pgrx::{Internal, JsonB};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::digest::TDigest;
+use crate::guc;
+use crate::helpers::round_to;
+use crate::parallel::{decode, encode};
+
+/// Quantiles reported by `jsonb_stats_pct_agg`.
+const PCT_QUANTILES: &[(&str, f64)] = &[("p50", 0.50), ("p90", 0.90), ("p95", 0.95), ("p99", 0.99)];
+
+/// Running state of `jsonb_stats_pct_agg`: a t-digest and nothing else, so a
+/// wide table can track percentiles without the full numeric summary.
+/// None until the first non-NULL value.
+#[derive(Default, Serialize, Deserialize)]
+pub struct PctState {
+    pub digest: Option<TDigest>,
+}
+
+impl PctState {
+    pub fn update(&mut self, val: f64) {
+        match &mut self.digest {
+            Some(d) => d.add(val, 1),
+            None => self.digest = Some(TDigest::new(val, 1)),
+        }
+    }
+
+    pub fn merge(&mut self, other: PctState) {
+        match (&mut self.digest, other.digest) {
+            (Some(a), Some(b)) => a.merge(&b),
+            (None, b) => self.digest = b,
+            (_, None) => {}
+        }
+    }
+
+    /// `{"p50", "p90", "p95", "p99"}`, each NULL while no value was seen.
+    pub fn to_json(&self, digits: usize) -> Value {
+        let mut obj = Map::new();
+        for (name, q) in PCT_QUANTILES {
+            let v = self.digest.as_ref().map_or(Value::Null, |d| round_to(d.quantile(*q), digits));
+            obj.insert(name.to_string(), v);
+        }
+        Value::Object(obj)
+    }
+}
+
+/// Sfunc for `jsonb_stats_pct_agg(value)`. NULL values are skipped.
+#[pg_extern(immutable, parallel_safe)]
+pub unsafe fn jsonb_stats_pct_sfunc(internal: Internal, value: Option<f64>) -> Internal {
+    let state_ptr: *mut PctState = match internal.unwrap() {
+        Some(datum) => datum.cast_mut_ptr::<PctState>(),
+        None => Box::into_raw(Box::new(PctState::default())),
+    };
+
+    if let Some(v) = value {
+        if !v.is_finite() {
+            pgrx::error!("jsonb_stats: jsonb_stats_pct_agg value must be finite, got {}", v);
+        }
+        let state = unsafe { &mut *state_ptr };
+        state.update(v);
+    }
+
+    Internal::from(Some(pgrx::pg_sys::Datum::from(state_ptr as usize)))
+}
+
+/// Combine two partial digests; state2 is freed, as in `jsonb_stats_combine`.
+#[pg_extern(immutable, parallel_safe)]
+pub unsafe fn jsonb_stats_pct_combine(state1: Internal, state2: Internal) -> Internal {
+    let ptr1 = state1.unwrap().map(|d| d.cast_mut_ptr::<PctState>());
+    let ptr2 = state2.unwrap().map(|d| d.cast_mut_ptr::<PctState>());
+    let ptr = match (ptr1, ptr2) {
+        (None, None) => Box::into_raw(Box::new(PctState::default())),
+        (Some(p), None) | (None, Some(p)) => p,
+        (Some(p1), Some(p2)) => {
+            let s2 = unsafe { Box::from_raw(p2) };
+            unsafe { &mut *p1 }.merge(*s2);
+            p1
+        }
+    };
+    Internal::from(Some(pgrx::pg_sys::Datum::from(ptr as usize)))
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub unsafe fn jsonb_stats_pct_serial(internal: Internal) -> Vec<u8> {
+    match internal.unwrap() {
+        Some(datum) => encode(unsafe { &*datum.cast_mut_ptr::<PctState>() }),
+        None => encode(&PctState::default()),
+    }
+}
+
+#[pg_extern(immutable, parallel_safe)]
+pub unsafe fn jsonb_stats_pct_deserial(bytes: Vec<u8>, _internal: Internal) -> Internal {
+    let ptr = Box::into_raw(Box::new(decode::<PctState>(&bytes)));
+    Internal::from(Some(pgrx::pg_sys::Datum::from(ptr as usize)))
+}
+
+/// Finalfunc for `jsonb_stats_pct_agg`, rounded to `jsonb_stats.round_digits`.
+/// Borrows the state, as `jsonb_stats_final_internal` does.
+#[pg_extern(stable, parallel_safe)]
+pub unsafe fn jsonb_stats_pct_final(internal: Internal) -> JsonB {
+    let digits = guc::ROUND_DIGITS.get() as usize;
+    match internal.unwrap() {
+        Some(datum) => JsonB(unsafe { &*datum.cast_mut_ptr::<PctState>() }.to_json(digits)),
+        None => JsonB(PctState::default().to_json(digits)),
+    }
+}
@@ -1,25 +1,136 @@
+use std::num::NonZeroUsize;
+
 use pgrx::prelude::*;
-use pgrx::{AnyElement, JsonB};
+use pgrx::datum::ToIsoString;
+use pgrx::{AnyElement, JsonB, PgBox, PgTupleDesc};
 use serde_json::{json, Map, Value};
 
+use crate::helpers::{cents_value, field_key, is_iso_date, TYPE_FIELD_KEY};
+use crate::state::OrderKey;
+
 /// Create a typed `stat` JSONB from any scalar value.
 /// Returns: {"type": "<type_name>", "value": <value>}
 ///
 /// Type mapping:
 ///   int4 -> "int", float8 -> "float", bool -> "bool",
-///   text -> "str", date -> "date", numeric -> "dec2"
+///   text -> "str", date -> "date", numeric -> "dec2", money -> "dec2",
+///   timestamp -> "ts", timestamptz -> "ts" (normalized to UTC, with a "Z" suffix),
+///   time -> "time" (HH:MM:SS.ffffff, microseconds always written out),
+///   uuid -> "uuid" (lowercase hyphenated), interval -> "interval" (total seconds),
+///   inet, cidr -> "inet" (address text, with the prefix length of a network),
+///   any array -> "arr" (the non-NULL elements, each converted as above)
 #[pg_extern(immutable, parallel_safe, strict)]
 pub fn stat(value: AnyElement) -> JsonB {
-    let oid = value.oid();
-    let datum = value.datum();
+    JsonB(unsafe { stat_json(value.oid(), value.datum()) })
+}
+
+/// `stat(value)` labelled with a unit that aggregation carries through to
+/// the `*_agg` summary: `stat(150, 'employees')` is
+/// `{"type": "int", "value": 150, "unit": "employees"}`.
+#[pg_extern(name = "stat", immutable, parallel_safe, strict)]
+pub fn stat_with_unit(value: AnyElement, unit: &str) -> JsonB {
+    let mut stat = unsafe { stat_json(value.oid(), value.datum()) };
+    if let Value::Object(m) = &mut stat {
+        m.insert("unit".to_string(), json!(unit));
+    }
+    JsonB(stat)
+}
+
+/// Create a stat of a caller-chosen type, coercing the value to it:
+/// `stat_as('150'::text, 'int')` is `{"type": "int", "value": 150}`, and
+/// `stat_as(7, 'str')` is `{"type": "str", "value": "7"}`. Raises when the
+/// value does not convert. Targets: int, float, dec2, nat, str, bool, date.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stat_as(value: AnyElement, type_name: &str) -> JsonB {
+    let stat = unsafe { stat_json(value.oid(), value.datum()) };
+    let coerced = coerce_value(&stat["value"], type_name).unwrap_or_else(|| {
+        pgrx::error!("jsonb_stats: cannot coerce {} to {}", stat["value"], type_name)
+    });
+    JsonB(json!({"type": type_name, "value": coerced}))
+}
+
+/// `value` as a stat value of type `type_name`, or None if it does not convert.
+fn coerce_value(value: &Value, type_name: &str) -> Option<Value> {
+    let text = match value {
+        Value::String(s) => s.trim().to_string(),
+        other => other.to_string(),
+    };
+    match type_name {
+        "int" | "nat" => {
+            let n = text.parse::<i64>().ok()?;
+            (type_name == "int" || n >= 0).then(|| json!(n))
+        }
+        "float" => {
+            let f = text.parse::<f64>().ok().filter(|f| f.is_finite())?;
+            Some(json!(f))
+        }
+        "dec2" => match serde_json::from_str::<Value>(&text) {
+            Ok(n @ Value::Number(_)) => Some(n),
+            _ => None,
+        },
+        "str" => Some(match value {
+            Value::String(s) => json!(s),
+            other => json!(other.to_string()),
+        }),
+        "bool" => match text.to_ascii_lowercase().as_str() {
+            "true" | "t" | "yes" | "y" | "on" | "1" => Some(json!(true)),
+            "false" | "f" | "no" | "n" | "off" | "0" => Some(json!(false)),
+            _ => None,
+        },
+        "date" => is_iso_date(&text).then(|| json!(text)),
+        other => pgrx::error!(
+            "jsonb_stats: stat_as type must be one of int, float, dec2, nat, str, bool, date, got '{}'",
+            other
+        ),
+    }
+}
+
+/// Build a `stats` object from a row of a composite type, one `stat()` per
+/// column keyed by column name: `SELECT stats_row(t) FROM legal_unit t`.
+/// NULL columns are left out, as `stat()` of NULL is NULL.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_row(value: AnyElement) -> JsonB {
+    let mut obj = Map::new();
+    obj.insert("type".to_string(), json!("stats"));
+    unsafe {
+        if !pg_sys::type_is_rowtype(value.oid()) {
+            pgrx::error!("jsonb_stats: stats_row expects a row of a composite type");
+        }
+        let htup = pg_sys::pg_detoast_datum(value.datum().cast_mut_ptr()) as pg_sys::HeapTupleHeader;
+        let tupdesc = PgTupleDesc::from_pg(pg_sys::lookup_rowtype_tupdesc(
+            pgrx::heap_tuple_header_get_type_id(htup),
+            pgrx::heap_tuple_header_get_typmod(htup),
+        ));
+        let mut tuple = PgBox::<pg_sys::HeapTupleData>::alloc0();
+        tuple.t_len = pgrx::heap_tuple_header_get_datum_length(htup) as u32;
+        tuple.t_data = htup;
+
+        for (i, att) in tupdesc.iter().enumerate() {
+            if att.is_dropped() {
+                continue;
+            }
+            let attno = NonZeroUsize::new(i + 1).unwrap();
+            if let Some(datum) = pgrx::heap_getattr_raw(tuple.as_ptr(), attno, tupdesc.as_ptr()) {
+                obj.insert(field_key(att.name().to_string()), stat_json(att.type_oid().value(), datum));
+            }
+        }
+    }
+    JsonB(Value::Object(obj))
+}
 
+/// The `{"type", "value"}` stat for a datum of type `oid`.
+pub(crate) unsafe fn stat_json(oid: pg_sys::Oid, datum: pg_sys::Datum) -> Value {
     let (type_name, json_value) = unsafe {
         if oid == pg_sys::INT4OID {
             let v = i32::from_datum(datum, false).unwrap_or(0);
             ("int", json!(v))
         } else if oid == pg_sys::FLOAT8OID {
             let v = f64::from_datum(datum, false).unwrap_or(0.0);
-            ("float", serde_json::Number::from_f64(v).map(Value::Number).unwrap_or(Value::Null))
+            // JSON has no NaN or Infinity; they would otherwise turn into null
+            if !v.is_finite() {
+                pgrx::error!("jsonb_stats: float value must be finite");
+            }
+            ("float", json!(v))
         } else if oid == pg_sys::BOOLOID {
             let v = bool::from_datum(datum, false).unwrap_or(false);
             ("bool", json!(v))
@@ -27,11 +138,44 @@ pub fn stat(value: AnyElement) -> JsonB {
             let v = String::from_datum(datum, false).unwrap_or_default();
             ("str", json!(v))
         } else if oid == pg_sys::DATEOID {
-            let v = pgrx::datum::Date::from_datum(datum, false);
-            match v {
-                Some(d) => ("date", json!(d.to_string())),
+            // ISO regardless of the session DateStyle, as the accumulators require
+            match pgrx::datum::Date::from_datum(datum, false) {
+                Some(d) => ("date", json!(d.to_iso_string())),
                 None => ("date", Value::Null),
             }
+        } else if oid == pg_sys::TIMESTAMPOID {
+            match pgrx::datum::Timestamp::from_datum(datum, false) {
+                Some(ts) => ("ts", json!(ts.to_iso_string())),
+                None => ("ts", Value::Null),
+            }
+        } else if oid == pg_sys::TIMESTAMPTZOID {
+            // Normalize to UTC so lexical min/max is independent of the session TimeZone
+            match pgrx::datum::TimestampWithTimeZone::from_datum(datum, false) {
+                Some(ts) => ("ts", json!(timestamptz_text(ts))),
+                None => ("ts", Value::Null),
+            }
+        } else if oid == pg_sys::TIMEOID {
+            // TimeADT is microseconds since midnight
+            match i64::from_datum(datum, false) {
+                Some(micros) => ("time", json!(time_text(micros))),
+                None => ("time", Value::Null),
+            }
+        } else if oid == pg_sys::UUIDOID {
+            match pgrx::Uuid::from_datum(datum, false) {
+                Some(u) => ("uuid", json!(u.to_string().to_ascii_lowercase())),
+                None => ("uuid", Value::Null),
+            }
+        } else if oid == pg_sys::INETOID || oid == pg_sys::CIDROID {
+            ("inet", json!(output_text(oid, datum)))
+        } else if oid == pg_sys::INTERVALOID {
+            match pgrx::datum::Interval::from_datum(datum, false) {
+                Some(iv) => ("interval", json!(interval_seconds(&iv))),
+                None => ("interval", Value::Null),
+            }
+        } else if oid == pg_sys::CASHOID {
+            // money is stored as a count of cents, whatever `lc_monetary` says
+            let cents = i64::from_datum(datum, false).unwrap_or(0);
+            ("dec2", cents_value(cents as i128))
         } else if oid == pg_sys::NUMERICOID {
             let v = pgrx::AnyNumeric::from_datum(datum, false);
             match v {
@@ -43,6 +187,8 @@ pub fn stat(value: AnyElement) -> JsonB {
                 }
                 None => ("dec2", Value::Null),
             }
+        } else if let Some(elem_oid) = array_element_type(oid) {
+            ("arr", Value::Array(array_values(datum, elem_oid)))
         } else {
             // Fallback: convert to string representation
             let v = String::from_datum(datum, false).unwrap_or_default();
@@ -53,20 +199,159 @@ pub fn stat(value: AnyElement) -> JsonB {
     let mut obj = Map::new();
     obj.insert("type".to_string(), json!(type_name));
     obj.insert("value".to_string(), json_value);
-    JsonB(Value::Object(obj))
+    Value::Object(obj)
+}
+
+/// The element type of an array type, or None for any other type.
+fn array_element_type(oid: pg_sys::Oid) -> Option<pg_sys::Oid> {
+    let elem_oid = unsafe { pg_sys::get_element_type(oid) };
+    (elem_oid != pg_sys::Oid::INVALID).then_some(elem_oid)
+}
+
+/// The non-NULL elements of an array datum as stat values, in storage order
+/// (a multidimensional array is flattened).
+unsafe fn array_values(datum: pg_sys::Datum, elem_oid: pg_sys::Oid) -> Vec<Value> {
+    let mut typlen: i16 = 0;
+    let mut typbyval = false;
+    let mut typalign: std::ffi::c_char = 0;
+    let mut elems: *mut pg_sys::Datum = std::ptr::null_mut();
+    let mut nulls: *mut bool = std::ptr::null_mut();
+    let mut n: i32 = 0;
+    unsafe {
+        let array = pg_sys::pg_detoast_datum(datum.cast_mut_ptr()) as *mut pg_sys::ArrayType;
+        pg_sys::get_typlenbyvalalign(elem_oid, &mut typlen, &mut typbyval, &mut typalign);
+        pg_sys::deconstruct_array(
+            array,
+            elem_oid,
+            typlen as i32,
+            typbyval,
+            typalign,
+            &mut elems,
+            &mut nulls,
+            &mut n,
+        );
+        (0..n as usize)
+            .filter(|&i| !*nulls.add(i))
+            .map(|i| match stat_json(elem_oid, *elems.add(i)) {
+                Value::Object(mut stat) => stat.remove("value").unwrap_or(Value::Null),
+                _ => Value::Null,
+            })
+            .collect()
+    }
+}
+
+/// The text form of a datum, from the type's output function.
+unsafe fn output_text(oid: pg_sys::Oid, datum: pg_sys::Datum) -> String {
+    let mut typoutput = pg_sys::Oid::INVALID;
+    let mut is_varlena = false;
+    unsafe {
+        pg_sys::getTypeOutputInfo(oid, &mut typoutput, &mut is_varlena);
+        let cstr = pg_sys::OidOutputFunctionCall(typoutput, datum);
+        std::ffi::CStr::from_ptr(cstr).to_string_lossy().into_owned()
+    }
+}
+
+/// The position of an order key for `jsonb_stats_ordered_agg`, taken from
+/// its `stat()` value: numbers (and booleans) compare numerically, dates,
+/// timestamps and text lexically.
+pub(crate) fn order_position(value: &AnyElement) -> OrderKey {
+    let stat = unsafe { stat_json(value.oid(), value.datum()) };
+    match &stat["value"] {
+        Value::Number(n) => OrderKey::Num(n.as_f64().unwrap_or(0.0)),
+        Value::Bool(b) => OrderKey::Num(*b as i64 as f64),
+        Value::String(s) => OrderKey::Text(s.clone()),
+        _ => pgrx::error!("jsonb_stats: order key of type '{}' is not supported", stat["type"]),
+    }
+}
+
+/// A timestamptz as UTC ISO text with a trailing `Z`.
+pub(crate) fn timestamptz_text(ts: pgrx::datum::TimestampWithTimeZone) -> String {
+    format!("{}Z", ts.to_utc().to_iso_string())
+}
+
+/// `HH:MM:SS.ffffff` for a time of day in microseconds since midnight.
+fn time_text(micros: i64) -> String {
+    let secs = micros / 1_000_000;
+    format!(
+        "{:02}:{:02}:{:02}.{:06}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+        micros % 1_000_000
+    )
+}
+
+/// Total seconds of an interval, counting a month as 1/12 of 365.25 days
+/// like `extract(epoch from interval)`.
+fn interval_seconds(iv: &pgrx::datum::Interval) -> f64 {
+    const SECS_PER_DAY: f64 = 86_400.0;
+    const SECS_PER_MONTH: f64 = 365.25 / 12.0 * SECS_PER_DAY;
+    iv.months() as f64 * SECS_PER_MONTH + iv.days() as f64 * SECS_PER_DAY + iv.micros() as f64 / 1e6
 }
 
 /// Add "type": "stats" to a JSONB object containing stat entries.
-#[pg_extern(name = "stats", immutable, parallel_safe, strict)]
+/// Each entry must be a stat object with a "type" (which may be left out
+/// under `jsonb_stats.infer_type`) or JSON null, as `stat(NULL)` gives.
+/// A stat keyed "type" is kept under `__type__`.
+#[pg_extern(name = "stats", stable, parallel_safe, strict)]
 pub fn stats_from_jsonb(input: JsonB) -> JsonB {
     let mut obj = match input.0 {
         Value::Object(m) => m,
-        _ => return input,
+        _ => pgrx::error!("jsonb_stats: stats() expects a JSON object of code->stat entries"),
     };
+    if let Some(field @ Value::Object(_)) = obj.remove("type") {
+        obj.insert(TYPE_FIELD_KEY.to_string(), field);
+    }
+    for (code, entry) in &obj {
+        let is_stat = match entry {
+            Value::Null => true,
+            Value::Object(e) => e.get("type").is_some_and(Value::is_string) || crate::guc::INFER_TYPE.get(),
+            _ => false,
+        };
+        if code != "type" && !is_stat {
+            pgrx::error!(
+                "jsonb_stats: stats() entry '{}' must be a stat object with a \"type\", got {}",
+                code,
+                entry
+            );
+        }
+    }
     obj.insert("type".to_string(), json!("stats"));
     JsonB(Value::Object(obj))
 }
 
+/// Build a `stats` object from a plain JSONB object of code->scalar,
+/// inferring each stat's type from the kind of its value: whole numbers
+/// become int, other numbers float, strings str, booleans bool and arrays
+/// arr. A JSON null stays null, like `stat(NULL)`.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_build(obj: JsonB) -> JsonB {
+    let input = match obj.0 {
+        Value::Object(m) => m,
+        _ => pgrx::error!("jsonb_stats: stats_build expects a JSON object of code->value"),
+    };
+    let mut out = Map::new();
+    out.insert("type".to_string(), json!("stats"));
+    for (code, value) in input {
+        let type_name = match &value {
+            Value::Null => {
+                out.insert(field_key(code), Value::Null);
+                continue;
+            }
+            Value::Number(n) if n.is_i64() => "int",
+            Value::Number(_) => "float",
+            Value::String(_) => "str",
+            Value::Bool(_) => "bool",
+            Value::Array(_) => "arr",
+            Value::Object(_) => {
+                pgrx::error!("jsonb_stats: stats_build cannot infer a stat type for the object at '{}'", code)
+            }
+        };
+        out.insert(field_key(code), json!({"type": type_name, "value": value}));
+    }
+    JsonB(Value::Object(out))
+}
+
 /// State transition function for jsonb_stats_agg(text, jsonb).
 /// Inserts code->stat into the state object, adding "type":"stats" on first call.
 #[pg_extern(immutable, parallel_safe, strict)]
@@ -76,7 +361,7 @@ pub fn jsonb_stats_sfunc(state: JsonB, code: &str, stat_val: JsonB) -> JsonB {
         _ => Map::new(),
     };
 
-    obj.insert(code.to_string(), stat_val.0);
+    obj.insert(field_key(code.to_string()), stat_val.0);
 
     if !obj.contains_key("type") {
         obj.insert("type".to_string(), json!("stats"));
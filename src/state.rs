@@ -1,72 +1,710 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Number, Value};
+
+use crate::digest::TDigest;
+use crate::guc;
+use crate::histogram::Histogram;
+use crate::hll::Hll;
+use crate::helpers::*;
 
 /// Common fields for all numeric aggregates (int, float, dec2, nat).
 /// Welford online algorithm methods live here — written once, used by all.
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct NumFields {
     pub count: i64,
     pub sum: f64,
+    /// Exact scaled sum: whole units for int/nat, cents for dec2. Kept
+    /// alongside the f64 `sum` because f64 silently loses precision once the
+    /// running total passes 2^53, and drifts on repeated decimal additions.
+    pub sum_int: i128,
+    /// NaN once unknown: a moving-window inverse removed the extreme value.
     pub min: f64,
     pub max: f64,
+    /// Observations equal to `min`/`max`; 0 once unknown, as for a summary
+    /// written before these were tracked.
+    #[serde(default)]
+    pub min_count: i64,
+    #[serde(default)]
+    pub max_count: i64,
+    /// Whether `min`/`max` are maintained (`jsonb_stats.track_extremes`);
+    /// when not, they are meaningless and left out of the JSON form.
+    #[serde(default = "extremes_tracked")]
+    pub extremes: bool,
     pub mean: f64,
+    /// Whether `sum_sq_diff`, `m3` and `m4` are maintained
+    /// (`jsonb_stats.compute_moments`); when not, they are meaningless and
+    /// left out of the JSON form, so variance and the shape stats are NULL.
+    #[serde(default = "moments_tracked")]
+    pub moments: bool,
     pub sum_sq_diff: f64,
+    /// Third and fourth central moment sums, Σ(x − mean)³ and Σ(x − mean)⁴,
+    /// for skewness and kurtosis.
+    pub m3: f64,
+    pub m4: f64,
+    /// Σ ln(x) and Σ 1/x over positive values, for the geometric and harmonic
+    /// means of float/dec2. Non-positive values are skipped and counted.
+    pub sum_log: f64,
+    pub sum_reciprocal: f64,
+    pub nonpositive_count: i64,
+    /// Stats whose `value` was JSON null; not part of `count`.
+    pub null_count: i64,
+    /// Quantile sketch, present only when created under `jsonb_stats.percentiles`.
+    pub digest: Option<TDigest>,
+    /// Equal-width histogram, present only when created under `jsonb_stats.hist_bins`.
+    pub hist: Option<Histogram>,
+    /// The values themselves, in no particular order, for exact quantiles:
+    /// present only when created under `jsonb_stats.percentiles`, and
+    /// dropped once `count` passes `jsonb_stats.exact_quantile_limit`.
+    #[serde(default)]
+    pub raw_values: Option<Vec<f64>>,
 }
 
 impl NumFields {
-    /// Initialize from a single value.
-    pub fn init(val: f64) -> Self {
-        NumFields {
-            count: 1,
-            sum: val,
+    /// Initialize from a single value. `exact` is the value in the type's
+    /// exact scale (units for int/nat, cents for dec2), or 0 for float.
+    pub fn init(val: f64, exact: i128) -> Self {
+        Self::init_weighted(val, exact, 1)
+    }
+
+    /// Initialize from `w` identical observations of a value.
+    pub fn init_weighted(val: f64, exact: i128, w: i64) -> Self {
+        let mut f = NumFields {
+            count: w,
+            sum: val * w as f64,
+            sum_int: exact * w as i128,
             min: val,
             max: val,
+            min_count: w,
+            max_count: w,
+            extremes: guc::TRACK_EXTREMES.get(),
             mean: val,
+            moments: guc::COMPUTE_MOMENTS.get(),
+            sum_sq_diff: 0.0,
+            m3: 0.0,
+            m4: 0.0,
+            sum_log: 0.0,
+            sum_reciprocal: 0.0,
+            nonpositive_count: 0,
+            null_count: 0,
+            digest: guc::PERCENTILES.get().then(|| TDigest::new(val, w as u64)),
+            hist: (guc::HIST_BINS.get() > 0)
+                .then(|| Histogram::new(guc::HIST_BINS.get() as usize, val, w as u64)),
+            raw_values: (guc::PERCENTILES.get() && w <= exact_quantile_limit()).then(|| vec![val; w as usize]),
+        };
+        f.add_log_terms(val, w);
+        f
+    }
+
+    /// A summary with no values yet, for a key that has only seen nulls.
+    pub fn empty() -> Self {
+        NumFields {
+            count: 0,
+            sum: 0.0,
+            sum_int: 0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            min_count: 0,
+            max_count: 0,
+            extremes: guc::TRACK_EXTREMES.get(),
+            mean: 0.0,
+            moments: guc::COMPUTE_MOMENTS.get(),
             sum_sq_diff: 0.0,
+            m3: 0.0,
+            m4: 0.0,
+            sum_log: 0.0,
+            sum_reciprocal: 0.0,
+            nonpositive_count: 0,
+            null_count: 0,
+            digest: None,
+            hist: None,
+            raw_values: None,
+        }
+    }
+
+    fn add_log_terms(&mut self, val: f64, w: i64) {
+        if val > 0.0 {
+            self.sum_log += val.ln() * w as f64;
+            self.sum_reciprocal += w as f64 / val;
+        } else {
+            self.nonpositive_count = add_counts(self.nonpositive_count, w);
         }
     }
 
     /// Welford single-value update.
-    pub fn update(&mut self, val: f64) {
-        self.count += 1;
+    pub fn update(&mut self, val: f64, exact: i128) {
+        self.update_weighted(val, exact, 1);
+    }
+
+    /// Weighted Welford update: `val` counted as `w` identical observations.
+    /// Equivalent to merging a partial aggregate of count `w`, mean `val`
+    /// and zero spread.
+    pub fn update_weighted(&mut self, val: f64, exact: i128, w: i64) {
+        if self.count == 0 {
+            *self = NumFields {
+                null_count: self.null_count,
+                ..Self::init_weighted(val, exact, w)
+            };
+            return;
+        }
+        let na = self.count as f64;
+        let wf = w as f64;
+        let n = na + wf;
         let delta = val - self.mean;
-        self.mean += delta / (self.count as f64);
-        self.sum_sq_diff += delta * (val - self.mean);
-        self.sum += val;
-        if val < self.min {
-            self.min = val;
+        // Skipping even one value leaves the moments describing only some
+        self.moments &= guc::COMPUTE_MOMENTS.get();
+        let moments = self.moments;
+        if moments {
+            // Higher moments first: each uses the lower moments before this value
+            self.m4 += delta.powi(4) * na * wf * (na * na - na * wf + wf * wf) / n.powi(3)
+                + 6.0 * delta * delta * wf * wf * self.sum_sq_diff / (n * n)
+                - 4.0 * delta * wf * self.m3 / n;
+            self.m3 += delta.powi(3) * na * wf * (na - wf) / (n * n) - 3.0 * delta * wf * self.sum_sq_diff / n;
+        }
+        self.count = add_counts(self.count, w);
+        self.mean += delta * wf / n;
+        if moments {
+            self.sum_sq_diff += delta * (val - self.mean) * w as f64;
+        }
+        self.sum += val * w as f64;
+        self.sum_int += exact * w as i128;
+        if self.extremes {
+            if val < self.min {
+                self.min = val;
+                self.min_count = w;
+            } else if val == self.min {
+                self.min_count = add_tie_counts(self.min_count, w);
+            }
+            if val > self.max {
+                self.max = val;
+                self.max_count = w;
+            } else if val == self.max {
+                self.max_count = add_tie_counts(self.max_count, w);
+            }
         }
-        if val > self.max {
-            self.max = val;
+        self.add_log_terms(val, w);
+        if let Some(d) = &mut self.digest {
+            d.add(val, w as u64);
+        }
+        if let Some(h) = &mut self.hist {
+            h.add(val, w as u64);
+        }
+        match &mut self.raw_values {
+            Some(raw) if self.count <= exact_quantile_limit() => raw.resize(raw.len() + w as usize, val),
+            _ => self.raw_values = None,
         }
     }
 
     /// Welford parallel merge.
     pub fn merge(&mut self, other: &NumFields) {
+        self.null_count = add_counts(self.null_count, other.null_count);
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = NumFields {
+                null_count: self.null_count,
+                ..other.clone()
+            };
+            return;
+        }
         let ca = self.count as f64;
         let cb = other.count as f64;
         let total = ca + cb;
         let delta = other.mean - self.mean;
+        // Pébay's pairwise update for the higher moments, from the pre-merge
+        // lower moments
+        self.m4 += other.m4
+            + delta.powi(4) * ca * cb * (ca * ca - ca * cb + cb * cb) / total.powi(3)
+            + 6.0 * delta * delta * (ca * ca * other.sum_sq_diff + cb * cb * self.sum_sq_diff) / (total * total)
+            + 4.0 * delta * (ca * other.m3 - cb * self.m3) / total;
+        self.m3 += other.m3
+            + delta.powi(3) * ca * cb * (ca - cb) / (total * total)
+            + 3.0 * delta * (ca * other.sum_sq_diff - cb * self.sum_sq_diff) / total;
         self.mean += delta * cb / total;
         self.sum_sq_diff += other.sum_sq_diff + (delta * delta * ca * cb) / total;
-        self.count += other.count;
+        self.count = add_counts(self.count, other.count);
         self.sum += other.sum;
-        if other.min < self.min {
+        self.sum_int += other.sum_int;
+        self.sum_log += other.sum_log;
+        self.sum_reciprocal += other.sum_reciprocal;
+        self.nonpositive_count += other.nonpositive_count;
+        // Extremes and moments only describe all values if both sides tracked them
+        self.extremes &= other.extremes;
+        self.moments &= other.moments;
+        // f64::min/max would skip a NaN (unknown) side instead of propagating it
+        if other.min < self.min || other.min.is_nan() {
             self.min = other.min;
+            self.min_count = other.min_count;
+        } else if other.min == self.min {
+            self.min_count = add_tie_counts(self.min_count, other.min_count);
         }
-        if other.max > self.max {
+        if other.max > self.max || other.max.is_nan() {
             self.max = other.max;
+            self.max_count = other.max_count;
+        } else if other.max == self.max {
+            self.max_count = add_tie_counts(self.max_count, other.max_count);
+        }
+        // A sketch only describes all values if both sides carried one
+        match (&mut self.digest, &other.digest) {
+            (Some(a), Some(b)) => a.merge(b),
+            _ => self.digest = None,
+        }
+        match (&mut self.hist, &other.hist) {
+            (Some(a), Some(b)) => a.merge(b),
+            _ => self.hist = None,
+        }
+        match (&mut self.raw_values, &other.raw_values) {
+            (Some(a), Some(b)) if self.count <= exact_quantile_limit() => a.extend_from_slice(b),
+            _ => self.raw_values = None,
+        }
+    }
+
+    /// Reverse Welford update for moving-window inverse transitions. min/max
+    /// cannot be recovered when the removed value was the only one at the
+    /// extreme, so they become NaN (NULL in JSON); the digest cannot be reversed and is dropped,
+    /// while the raw values simply lose the removed one.
+    pub fn remove(&mut self, val: f64, exact: i128) {
+        if self.count <= 1 {
+            *self = NumFields {
+                null_count: self.null_count,
+                ..Self::empty()
+            };
+            return;
+        }
+        let prev_mean = self.mean;
+        self.count -= 1;
+        self.mean -= (val - prev_mean) / (self.count as f64);
+        self.sum_sq_diff = (self.sum_sq_diff - (val - self.mean) * (val - prev_mean)).max(0.0);
+        // Invert the single-value moment update, now that mean and
+        // sum_sq_diff describe the remaining values
+        let na = self.count as f64;
+        let n = na + 1.0;
+        let delta = val - self.mean;
+        self.m3 -= delta.powi(3) * na * (na - 1.0) / (n * n) - 3.0 * delta * self.sum_sq_diff / n;
+        self.m4 -= delta.powi(4) * na * (na * na - na + 1.0) / n.powi(3)
+            + 6.0 * delta * delta * self.sum_sq_diff / (n * n)
+            - 4.0 * delta * self.m3 / n;
+        self.sum -= val;
+        self.sum_int -= exact;
+        if val == self.min && self.min_count > 1 {
+            self.min_count -= 1;
+        } else if val <= self.min {
+            self.min = f64::NAN;
+            self.min_count = 0;
+        }
+        if val == self.max && self.max_count > 1 {
+            self.max_count -= 1;
+        } else if val >= self.max {
+            self.max = f64::NAN;
+            self.max_count = 0;
+        }
+        if val > 0.0 {
+            self.sum_log -= val.ln();
+            self.sum_reciprocal -= 1.0 / val;
+        } else {
+            self.nonpositive_count -= 1;
+        }
+        self.digest = None;
+        self.hist = None;
+        let pos = self.raw_values.as_ref().and_then(|raw| raw.iter().position(|v| *v == val));
+        match (&mut self.raw_values, pos) {
+            (Some(raw), Some(i)) => {
+                raw.swap_remove(i);
+            }
+            _ => self.raw_values = None,
+        }
+    }
+
+    /// Inverse of `merge`: remove `other`, taken to summarize a subset of
+    /// this summary's values. The moments invert Pébay's pairwise update;
+    /// min/max survive only when `other` provably left some values at the
+    /// extreme, and the digest and histogram cannot be reversed.
+    pub fn subtract(&mut self, other: &NumFields) {
+        self.null_count = (self.null_count - other.null_count).max(0);
+        if other.count == 0 {
+            return;
+        }
+        if other.count >= self.count {
+            *self = NumFields {
+                null_count: self.null_count,
+                ..Self::empty()
+            };
+            return;
+        }
+        let total = self.count as f64;
+        let cb = other.count as f64;
+        let ca = total - cb;
+        let mean = (total * self.mean - cb * other.mean) / ca;
+        let delta = other.mean - mean;
+        let m2 = (self.sum_sq_diff - other.sum_sq_diff - delta * delta * ca * cb / total).max(0.0);
+        let m3 = self.m3
+            - other.m3
+            - delta.powi(3) * ca * cb * (ca - cb) / (total * total)
+            - 3.0 * delta * (ca * other.sum_sq_diff - cb * m2) / total;
+        self.m4 -= other.m4
+            + delta.powi(4) * ca * cb * (ca * ca - ca * cb + cb * cb) / total.powi(3)
+            + 6.0 * delta * delta * (ca * ca * other.sum_sq_diff + cb * cb * m2) / (total * total)
+            + 4.0 * delta * (ca * other.m3 - cb * m3) / total;
+        self.m3 = m3;
+        self.sum_sq_diff = m2;
+        self.mean = mean;
+        self.count -= other.count;
+        self.sum -= other.sum;
+        self.sum_int -= other.sum_int;
+        self.sum_log -= other.sum_log;
+        self.sum_reciprocal -= other.sum_reciprocal;
+        self.nonpositive_count = (self.nonpositive_count - other.nonpositive_count).max(0);
+        self.moments &= other.moments;
+        if !other.extremes {
+            self.min = f64::NAN;
+            self.max = f64::NAN;
+            self.min_count = 0;
+            self.max_count = 0;
+        }
+        if other.min == self.min && other.min_count > 0 && other.min_count < self.min_count {
+            self.min_count -= other.min_count;
+        } else if other.min <= self.min || other.min.is_nan() {
+            self.min = f64::NAN;
+            self.min_count = 0;
+        }
+        if other.max == self.max && other.max_count > 0 && other.max_count < self.max_count {
+            self.max_count -= other.max_count;
+        } else if other.max >= self.max || other.max.is_nan() {
+            self.max = f64::NAN;
+            self.max_count = 0;
+        }
+        self.digest = None;
+        self.hist = None;
+        self.raw_values = match (self.raw_values.take(), &other.raw_values) {
+            (Some(mut raw), Some(gone)) => gone
+                .iter()
+                .all(|v| match raw.iter().position(|x| x == v) {
+                    Some(i) => {
+                        raw.swap_remove(i);
+                        true
+                    }
+                    None => false,
+                })
+                .then_some(raw),
+            _ => None,
+        };
+    }
+
+    /// The raw values in ascending order, if still kept.
+    pub fn sorted_raw_values(&self) -> Option<Vec<f64>> {
+        let mut sorted = self.raw_values.clone()?;
+        sorted.sort_by(f64::total_cmp);
+        Some(sorted)
+    }
+
+    /// "min"/"max" and how many observations equal each; a count of 0
+    /// (unknown) is left out.
+    pub fn insert_extremes(&self, obj: &mut Map<String, Value>) {
+        obj.insert("min".to_string(), num_value(self.min));
+        obj.insert("max".to_string(), num_value(self.max));
+        for (field, count) in [("min_count", self.min_count), ("max_count", self.max_count)] {
+            if count > 0 {
+                obj.insert(field.to_string(), Value::Number(Number::from(count)));
+            }
+        }
+    }
+
+    /// Running sums behind the geometric/harmonic means (float/dec2 only).
+    pub fn insert_log_sums(&self, obj: &mut Map<String, Value>) {
+        obj.insert("sum_log".to_string(), num_value(self.sum_log));
+        obj.insert("sum_reciprocal".to_string(), num_value(self.sum_reciprocal));
+        obj.insert(
+            "nonpositive_count".to_string(),
+            Value::Number(Number::from(self.nonpositive_count)),
+        );
+    }
+
+    /// Geometric and harmonic means over the positive values, if any and
+    /// if the log sums are known.
+    pub fn log_means(&self) -> Option<(f64, f64)> {
+        let n = (self.count - self.nonpositive_count) as f64;
+        let known = !self.sum_log.is_nan() && !self.sum_reciprocal.is_nan();
+        (n > 0.0 && known).then(|| ((self.sum_log / n).exp(), n / self.sum_reciprocal))
+    }
+
+    /// Mark the log sums unknown (NaN, JSON null), e.g. for an int_agg
+    /// promoted to float_agg: int summaries do not carry them.
+    pub fn forget_log_sums(&mut self) {
+        self.sum_log = f64::NAN;
+        self.sum_reciprocal = f64::NAN;
+    }
+
+    /// The `sum` as a JSON number: exact for int/nat/dec2, f64 for float.
+    pub fn sum_value(&self, type_tag: &str) -> Value {
+        match type_tag {
+            "int_agg" | "nat_agg" => int_value(self.sum_int),
+            "dec2_agg" => cents_value(self.sum_int),
+            _ => num_value(self.sum),
+        }
+    }
+
+    /// Parse the running-state fields of a JSONB *_agg summary. The mean is
+    /// recomputed from `sum`, which unlike the rounded `mean` of finalized
+    /// output is exact.
+    pub fn from_json(obj: &Map<String, Value>) -> Self {
+        let sum_int = match get_type(obj) {
+            "int_agg" | "nat_agg" => get_i128(obj, "sum"),
+            "dec2_agg" => get_cents(obj, "sum"),
+            _ => 0,
+        };
+        let null_count = get_count(obj, "null_count");
+        let count = get_count(obj, "count");
+        if count == 0 {
+            return NumFields {
+                null_count,
+                ..Self::empty()
+            };
+        }
+        NumFields {
+            count,
+            sum: get_checked_f64(obj, "sum"),
+            sum_int,
+            min: get_extreme(obj, "min"),
+            max: get_extreme(obj, "max"),
+            min_count: get_count(obj, "min_count"),
+            max_count: get_count(obj, "max_count"),
+            extremes: obj.contains_key("min"),
+            moments: obj.contains_key("sum_sq_diff"),
+            mean: match get_type(obj) {
+                "int_agg" | "nat_agg" => sum_int as f64 / count as f64,
+                "dec2_agg" => sum_int as f64 / 100.0 / count as f64,
+                _ => get_checked_f64(obj, "sum") / count as f64,
+            },
+            sum_sq_diff: get_checked_f64(obj, "sum_sq_diff"),
+            m3: get_unknown_if_absent(obj, "m3"),
+            m4: get_unknown_if_absent(obj, "m4"),
+            sum_log: get_unknown_if_absent(obj, "sum_log"),
+            sum_reciprocal: get_unknown_if_absent(obj, "sum_reciprocal"),
+            nonpositive_count: get_count(obj, "nonpositive_count"),
+            null_count,
+            digest: obj.get("digest").map(TDigest::from_json),
+            hist: obj.get("histogram").map(Histogram::from_json),
+            raw_values: obj.get("raw_values").and_then(|v| parse_raw_values(v, count)),
+        }
+    }
+
+    /// Serialize to the running-state (pre-finalization) JSONB form.
+    pub fn to_json(&self, type_tag: &str) -> Value {
+        let mut obj = Map::new();
+        obj.insert("type".to_string(), json!(type_tag));
+        obj.insert("count".to_string(), Value::Number(Number::from(self.count)));
+        obj.insert("sum".to_string(), self.sum_value(type_tag));
+        if self.extremes {
+            self.insert_extremes(&mut obj);
+        }
+        obj.insert("mean".to_string(), num_value(self.mean));
+        if self.moments {
+            obj.insert("sum_sq_diff".to_string(), num_value(self.sum_sq_diff));
+            obj.insert("m3".to_string(), num_value(self.m3));
+            obj.insert("m4".to_string(), num_value(self.m4));
+        }
+        if has_log_means(type_tag) {
+            self.insert_log_sums(&mut obj);
+        }
+        insert_null_count(&mut obj, self.null_count);
+        if let Some(d) = &self.digest {
+            obj.insert("digest".to_string(), d.to_json());
+        }
+        if let Some(h) = &self.hist {
+            obj.insert("histogram".to_string(), h.to_json());
+        }
+        if let Some(sorted) = self.sorted_raw_values() {
+            obj.insert("raw_values".to_string(), raw_values_json(&sorted));
         }
+        Value::Object(obj)
     }
 }
 
+/// Add to the count of observations at an extreme; unknown (0) on either
+/// side stays unknown.
+fn add_tie_counts(a: i64, b: i64) -> i64 {
+    if a == 0 || b == 0 {
+        0
+    } else {
+        add_counts(a, b)
+    }
+}
+
+/// Serde default for `NumFields::extremes`.
+fn extremes_tracked() -> bool {
+    true
+}
+
+/// Serde default for `NumFields::moments`.
+fn moments_tracked() -> bool {
+    true
+}
+
+/// `jsonb_stats.exact_quantile_limit` as a count.
+fn exact_quantile_limit() -> i64 {
+    guc::EXACT_QUANTILE_LIMIT.get() as i64
+}
+
+/// The "raw_values" array of a summary. An array that does not hold exactly
+/// `count` values no longer describes them all and is ignored.
+fn parse_raw_values(v: &Value, count: i64) -> Option<Vec<f64>> {
+    let values: Vec<f64> = v
+        .as_array()
+        .unwrap_or_else(|| pgrx::error!("jsonb_stats: corrupt agg field 'raw_values'"))
+        .iter()
+        .map(|n| match n {
+            Value::Number(n) => n.to_string().parse().unwrap_or(f64::NAN),
+            _ => pgrx::error!("jsonb_stats: corrupt agg field 'raw_values'"),
+        })
+        .collect();
+    (values.len() as i64 == count).then_some(values)
+}
+
+/// Ascending raw values as a JSON array.
+pub fn raw_values_json(sorted: &[f64]) -> Value {
+    Value::Array(sorted.iter().map(|v| num_value(*v)).collect())
+}
+
+/// A numeric min/max; JSON null means unknown (NaN) rather than 0.
+fn get_extreme(obj: &Map<String, Value>, key: &str) -> f64 {
+    match obj.get(key) {
+        Some(Value::Null) => f64::NAN,
+        _ => get_checked_f64(obj, key),
+    }
+}
+
+/// A higher moment or log sum, which summaries written before it existed
+/// lack: absent or null means unknown (NaN), so the shape stats and log
+/// means derived from it come out NULL.
+fn get_unknown_if_absent(obj: &Map<String, Value>, key: &str) -> f64 {
+    match obj.get(key) {
+        None => f64::NAN,
+        _ => get_extreme(obj, key),
+    }
+}
+
+/// Whether an agg type reports geometric/harmonic means.
+pub fn has_log_means(type_tag: &str) -> bool {
+    matches!(type_tag, "float_agg" | "dec2_agg")
+}
+
 /// Native Rust state for the jsonb_stats_agg aggregate.
 /// By keeping this as a Rust struct (via pgrx Internal), we avoid
 /// serde_json serialization/deserialization on every sfunc call.
 #[derive(Default, Serialize, Deserialize)]
 pub struct StatsState {
     pub entries: HashMap<String, AggEntry>,
+    /// Per-key first/last categorical values, filled only by
+    /// `jsonb_stats_ordered_agg`.
+    #[serde(default)]
+    pub ordered: HashMap<String, FirstLast>,
+    /// Source IDs merged so far, filled only by
+    /// `jsonb_stats_merge_agg(stats_agg, source)`.
+    #[serde(default)]
+    pub sources: HashSet<String>,
+    /// Per-key unit labels from `stat(value, unit)`, the first seen kept.
+    #[serde(default)]
+    pub units: HashMap<String, String>,
+    /// Latest input timestamp (UTC ISO text), filled only by
+    /// `jsonb_stats_agg_watermarked` and by merging its output.
+    #[serde(default)]
+    pub watermark: Option<String>,
+}
+
+impl StatsState {
+    /// Record that `source` has been merged, raising if it already was:
+    /// merging one group twice would silently double its counts.
+    pub fn claim_source(&mut self, source: String) {
+        if self.sources.contains(&source) {
+            pgrx::error!("jsonb_stats: duplicate source '{}' in merge", source);
+        }
+        self.sources.insert(source);
+    }
+
+    /// Keep `unit` for `key` unless another was seen first.
+    pub fn note_unit(&mut self, key: &str, unit: Option<&str>) {
+        if unit.is_none() {
+            return;
+        }
+        let existing = self.units.remove(key);
+        if let Some(unit) = merged_unit(key, existing, unit) {
+            self.units.insert(key.to_string(), unit);
+        }
+    }
+
+    /// Keep the later of the current watermark and `ts`.
+    pub fn observe_watermark(&mut self, ts: &str) {
+        match &self.watermark {
+            Some(w) if !iso_ts_later(ts, w) => {}
+            _ => self.watermark = Some(ts.to_string()),
+        }
+    }
+
+    /// Every entry in its running-state JSONB form, without the top-level type.
+    pub fn entries_json(&self) -> Map<String, Value> {
+        self.entries
+            .iter()
+            .map(|(key, entry)| {
+                let mut summary = entry.to_json();
+                keep_unit(&mut summary, self.units.get(key).cloned());
+                (key.clone(), summary)
+            })
+            .collect()
+    }
+}
+
+/// A position in `jsonb_stats_ordered_agg`'s ordering: the order key's
+/// `stat()` value, numeric or ISO text (so dates and timestamps sort
+/// lexically). One aggregate call only ever sees one variant.
+#[derive(Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum OrderKey {
+    Num(f64),
+    Text(String),
+}
+
+/// The values seen at the smallest and largest order key. Ties keep the
+/// value seen first.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FirstLast {
+    pub first_key: OrderKey,
+    pub first: Value,
+    pub last_key: OrderKey,
+    pub last: Value,
+}
+
+impl FirstLast {
+    pub fn new(key: OrderKey, value: Value) -> Self {
+        FirstLast {
+            first_key: key.clone(),
+            first: value.clone(),
+            last_key: key,
+            last: value,
+        }
+    }
+
+    pub fn observe(&mut self, key: OrderKey, value: Value) {
+        if key < self.first_key {
+            self.first_key = key.clone();
+            self.first = value.clone();
+        }
+        if key > self.last_key {
+            self.last_key = key;
+            self.last = value;
+        }
+    }
+
+    pub fn merge(&mut self, other: FirstLast) {
+        if other.first_key < self.first_key {
+            self.first_key = other.first_key;
+            self.first = other.first;
+        }
+        if other.last_key > self.last_key {
+            self.last_key = other.last_key;
+            self.last = other.last;
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -75,21 +713,53 @@ pub enum AggEntry {
     FloatAgg(NumFields),
     Dec2Agg(NumFields),
     NatAgg(NumFields),
+    IntervalAgg(NumFields),
     StrAgg {
         counts: HashMap<String, i64>,
+        min: Option<String>,
+        max: Option<String>,
+        null_count: i64,
+        /// Distinct-count sketch, present only when created under `jsonb_stats.hll`.
+        #[serde(default)]
+        hll: Option<Hll>,
     },
     BoolAgg {
         counts: HashMap<String, i64>,
+        null_count: i64,
     },
     ArrAgg {
         count: i64,
         counts: HashMap<String, i64>,
+        null_count: i64,
     },
     DateAgg {
         counts: HashMap<String, i64>,
         min_date: Option<String>,
         max_date: Option<String>,
+        null_count: i64,
     },
+    TsAgg {
+        counts: HashMap<String, i64>,
+        min_ts: Option<String>,
+        max_ts: Option<String>,
+        null_count: i64,
+    },
+    TimeAgg {
+        counts: HashMap<String, i64>,
+        min_time: Option<String>,
+        max_time: Option<String>,
+        null_count: i64,
+    },
+    UuidAgg {
+        counts: HashMap<String, i64>,
+        null_count: i64,
+    },
+    InetAgg {
+        counts: HashMap<String, i64>,
+        null_count: i64,
+    },
+    /// A `stats`-typed entry's own keys, summarized as a nested `stats_agg`.
+    NestedAgg(Box<StatsState>),
 }
 
 impl AggEntry {
@@ -99,10 +769,87 @@ impl AggEntry {
             AggEntry::FloatAgg(_) => "float_agg",
             AggEntry::Dec2Agg(_) => "dec2_agg",
             AggEntry::NatAgg(_) => "nat_agg",
+            AggEntry::IntervalAgg(_) => "interval_agg",
             AggEntry::StrAgg { .. } => "str_agg",
             AggEntry::BoolAgg { .. } => "bool_agg",
             AggEntry::ArrAgg { .. } => "arr_agg",
             AggEntry::DateAgg { .. } => "date_agg",
+            AggEntry::TsAgg { .. } => "ts_agg",
+            AggEntry::TimeAgg { .. } => "time_agg",
+            AggEntry::UuidAgg { .. } => "uuid_agg",
+            AggEntry::InetAgg { .. } => "inet_agg",
+            AggEntry::NestedAgg(_) => "stats_agg",
+        }
+    }
+
+    /// Serialize to the running-state (pre-finalization) JSONB form, the
+    /// shape `jsonb_stats_accum` builds and `jsonb_stats_merge` reads back.
+    pub fn to_json(&self) -> Value {
+        let (counts, range, null_count) = match self {
+            AggEntry::IntAgg(f)
+            | AggEntry::FloatAgg(f)
+            | AggEntry::Dec2Agg(f)
+            | AggEntry::NatAgg(f)
+            | AggEntry::IntervalAgg(f) => return f.to_json(self.type_tag()),
+            AggEntry::StrAgg { counts, min, max, null_count, .. }
+            | AggEntry::DateAgg { counts, min_date: min, max_date: max, null_count }
+            | AggEntry::TsAgg { counts, min_ts: min, max_ts: max, null_count }
+            | AggEntry::TimeAgg { counts, min_time: min, max_time: max, null_count } => {
+                (counts, Some((min, max)), null_count)
+            }
+            AggEntry::BoolAgg { counts, null_count }
+            | AggEntry::ArrAgg { counts, null_count, .. }
+            | AggEntry::UuidAgg { counts, null_count }
+            | AggEntry::InetAgg { counts, null_count } => (counts, None, null_count),
+            AggEntry::NestedAgg(nested) => {
+                let mut obj = nested.entries_json();
+                obj.insert("type".to_string(), json!("stats_agg"));
+                return Value::Object(obj);
+            }
+        };
+        let mut obj = Map::new();
+        obj.insert("type".to_string(), json!(self.type_tag()));
+        if let AggEntry::ArrAgg { count, .. } = self {
+            obj.insert("count".to_string(), Value::Number(Number::from(*count)));
+        }
+        let counts: Map<String, Value> = counts
+            .iter()
+            .map(|(k, v)| (k.clone(), Value::Number(Number::from(*v))))
+            .collect();
+        obj.insert("counts".to_string(), Value::Object(counts));
+        if let Some((min, max)) = range {
+            if let Some(min) = min {
+                obj.insert("min".to_string(), json!(min));
+            }
+            if let Some(max) = max {
+                obj.insert("max".to_string(), json!(max));
+            }
+        }
+        insert_null_count(&mut obj, *null_count);
+        if let AggEntry::StrAgg { hll: Some(h), .. } = self {
+            obj.insert("hll".to_string(), h.to_json());
+        }
+        Value::Object(obj)
+    }
+
+    /// Number of stats with a JSON null `value` folded into this entry.
+    /// A nested `stats` has no value of its own, so has no null count.
+    pub fn null_count_mut(&mut self) -> Option<&mut i64> {
+        match self {
+            AggEntry::IntAgg(f)
+            | AggEntry::FloatAgg(f)
+            | AggEntry::Dec2Agg(f)
+            | AggEntry::NatAgg(f)
+            | AggEntry::IntervalAgg(f) => Some(&mut f.null_count),
+            AggEntry::StrAgg { null_count, .. }
+            | AggEntry::BoolAgg { null_count, .. }
+            | AggEntry::ArrAgg { null_count, .. }
+            | AggEntry::DateAgg { null_count, .. }
+            | AggEntry::TsAgg { null_count, .. }
+            | AggEntry::TimeAgg { null_count, .. }
+            | AggEntry::UuidAgg { null_count, .. }
+            | AggEntry::InetAgg { null_count, .. } => Some(null_count),
+            AggEntry::NestedAgg(_) => None,
         }
     }
 }
@@ -0,0 +1,128 @@
+use pgrx::prelude::*;
+use pgrx::JsonB;
+use serde_json::{json, Map, Value};
+
+use crate::helpers::*;
+
+/// Check a `stats` or `stats_agg` object without raising: returns
+/// `{"valid": bool, "errors": [...]}` with one message per problem found.
+/// Validates each entry's `type`, the fields that type requires, and value
+/// kinds (integers for int/nat, ISO dates, nat >= 0, ...). A JSON null
+/// `value` is valid: it is counted as a null.
+#[pg_extern(immutable, parallel_safe, strict)]
+pub fn stats_validate(input: JsonB) -> JsonB {
+    let mut errors = Vec::new();
+    match &input.0 {
+        Value::Object(obj) => {
+            for (key, entry) in obj {
+                if is_type_tag(key, entry) {
+                    if !matches!(entry.as_str(), Some("stats" | "stats_agg")) {
+                        errors.push(format!("'type' must be \"stats\" or \"stats_agg\", got {}", entry));
+                    }
+                    continue;
+                }
+                if is_watermark(key, entry) {
+                    continue;
+                }
+                match entry {
+                    Value::Object(e) => validate_entry(key, e, &mut errors),
+                    other => errors.push(format!("key '{}': expected an object, got {}", key, other)),
+                }
+            }
+        }
+        other => errors.push(format!("expected a JSON object, got {}", other)),
+    }
+    JsonB(json!({"valid": errors.is_empty(), "errors": errors}))
+}
+
+fn validate_entry(key: &str, entry: &Map<String, Value>, errors: &mut Vec<String>) {
+    let mut err = |msg: String| errors.push(format!("key '{}': {}", key, msg));
+    let entry_type = match entry.get("type") {
+        Some(Value::String(t)) => t.as_str(),
+        _ => return err("missing 'type'".to_string()),
+    };
+    match entry_type {
+        "int" | "float" | "dec2" | "nat" | "str" | "bool" | "arr" | "date" | "ts" | "time" | "uuid"
+        | "inet" | "interval" => {
+            let value = match entry.get("value") {
+                Some(Value::Null) => return,
+                Some(v) => v,
+                None => return err(format!("{} stat is missing 'value'", entry_type)),
+            };
+            if let Some(expected) = expected_value_kind(entry_type, value) {
+                err(format!("{} stat value must be {}, got {}", entry_type, expected, value));
+            }
+        }
+        "int_agg" | "float_agg" | "dec2_agg" | "nat_agg" | "interval_agg" => {
+            for field in ["count", "sum", "mean"] {
+                if !entry.get(field).is_some_and(Value::is_number) {
+                    err(format!("{} requires a numeric '{}'", entry_type, field));
+                }
+            }
+            // Absent when jsonb_stats.compute_moments was off
+            if entry.get("sum_sq_diff").is_some_and(|v| !v.is_number()) {
+                err(format!("{} requires a numeric 'sum_sq_diff'", entry_type));
+            }
+            // Both absent when jsonb_stats.track_extremes was off
+            let extremes = entry.contains_key("min") || entry.contains_key("max");
+            for field in ["min", "max"].into_iter().filter(|_| extremes) {
+                if !entry.get(field).is_some_and(|v| v.is_number() || v.is_null()) {
+                    err(format!("{} requires a numeric or null '{}'", entry_type, field));
+                }
+            }
+        }
+        "str_agg" | "bool_agg" | "arr_agg" | "date_agg" | "ts_agg" | "time_agg" | "uuid_agg" | "inet_agg" => {
+            if entry_type == "arr_agg" && !entry.get("count").is_some_and(Value::is_number) {
+                err("arr_agg requires a numeric 'count'".to_string());
+            }
+            match entry.get("counts") {
+                Some(Value::Object(counts)) => {
+                    for (k, n) in counts {
+                        if !n.is_u64() {
+                            err(format!("count for '{}' must be a non-negative integer, got {}", k, n));
+                        }
+                    }
+                }
+                _ => err(format!("{} requires a 'counts' object", entry_type)),
+            }
+        }
+        "stats" | "stats_agg" => {
+            // Nested keys are reported by their dotted path
+            for (sub_key, sub) in entry.iter().filter(|(k, _)| k.as_str() != "type") {
+                let path = format!("{}.{}", key, sub_key);
+                match sub {
+                    Value::Object(e) => validate_entry(&path, e, errors),
+                    other => errors.push(format!("key '{}': expected an object, got {}", path, other)),
+                }
+            }
+        }
+        other => err(format!(
+            "unknown type '{}'. Expected a stat type (int, float, dec2, nat, str, bool, arr, date, ts, time, uuid, inet, interval, stats) or its *_agg",
+            other
+        )),
+    }
+}
+
+/// The kind of value a stat type expects, if `value` is not of that kind.
+fn expected_value_kind(stat_type: &str, value: &Value) -> Option<&'static str> {
+    // Numeric stats accept a quoted number, as the accumulators do
+    let quoted = quoted_number(value).map(Value::Number);
+    let number = quoted.as_ref().unwrap_or(value);
+    let (ok, expected) = match stat_type {
+        "int" => (number.is_i64() || number.is_u64(), "an integer"),
+        "nat" => (number.is_u64(), "an integer >= 0"),
+        "float" | "dec2" | "interval" => (number.is_number(), "a number"),
+        "str" => (
+            value.is_string() || value.as_array().is_some_and(|a| a.iter().all(Value::is_string)),
+            "a string or an array of strings",
+        ),
+        "ts" => (value.is_string(), "a string"),
+        "time" => (value.as_str().and_then(iso_time).is_some(), "an HH:MM:SS[.ffffff] string"),
+        "bool" => (value.is_boolean(), "a boolean"),
+        "arr" => (value.is_array() || value.is_string(), "an array"),
+        "uuid" => (value.as_str().is_some_and(is_uuid), "a hyphenated UUID string"),
+        "inet" => (value.as_str().and_then(parse_inet).is_some(), "an IP address string"),
+        _ => (value.as_str().is_some_and(is_iso_date), "an ISO-8601 (YYYY-MM-DD) string"),
+    };
+    (!ok).then_some(expected)
+}